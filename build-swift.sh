@@ -42,3 +42,6 @@ swift-bridge-cli create-package \
 
 
 #--simulator target/universal-ios/"$MODE"/libdt_swift.a \
+
+# Hand-written swift code which goes alongside the generated bindings.
+cp ./crates/dt-swift/swift/*.swift target/dt-swift/Sources/DiamondTypes/
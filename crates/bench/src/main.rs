@@ -208,6 +208,38 @@ fn paper_benchmarks(c: &mut Criterion) {
     }
 }
 
+#[cfg(any(feature = "listmerge2", feature = "parallel"))]
+fn merge_algorithm_benchmarks(c: &mut Criterion) {
+    use diamond_types::list::{ListBranch, MergeAlgorithm};
+
+    for name in PAPER_DATASETS {
+        let mut group = c.benchmark_group("dt");
+        let bytes = std::fs::read(format!("benchmark_data/{name}.dt")).unwrap();
+        let oplog = ListOpLog::load_from(&bytes).unwrap();
+        group.throughput(Throughput::Elements(oplog.len() as _));
+
+        let algorithms = [
+            ("merge_tracker", MergeAlgorithm::Tracker),
+            #[cfg(feature = "listmerge2")]
+            ("merge_index_forking", MergeAlgorithm::IndexForking),
+            #[cfg(feature = "parallel")]
+            ("merge_parallel", MergeAlgorithm::Parallel),
+        ];
+
+        for (alg_name, alg) in algorithms {
+            group.bench_function(BenchmarkId::new(alg_name, name), |b| {
+                b.iter(|| {
+                    let mut branch = ListBranch::new();
+                    branch.merge_with(&oplog, oplog.local_frontier_ref(), alg);
+                    black_box(branch);
+                });
+            });
+        }
+
+        group.finish();
+    }
+}
+
 fn opt_load_time_benchmark(c: &mut Criterion) {
     for &name in PAPER_DATASETS {
         let mut group = c.benchmark_group("dt");
@@ -254,6 +286,8 @@ fn main() {
     encoding_nodecc_benchmarks(&mut c);
     // idxtrace_benchmarks(&mut c);
     paper_benchmarks(&mut c);
+    #[cfg(any(feature = "listmerge2", feature = "parallel"))]
+    merge_algorithm_benchmarks(&mut c);
     opt_load_time_benchmark(&mut c);
     c.final_summary();
 }
\ No newline at end of file
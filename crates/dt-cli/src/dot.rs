@@ -8,6 +8,33 @@ use std::ffi::OsString;
 use std::fmt::{Display, Formatter};
 use std::io::Write as _;
 use std::process::{Command, Stdio};
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Render an SVG using graphviz's `dot` command.
+    Graphviz,
+    /// DOT source, for rendering with graphviz later. (The same as --no-render).
+    Dot,
+    /// SVG rendered with a simple built-in layout. This doesn't need graphviz, but its only
+    /// readable for small graphs.
+    Svg,
+    /// GraphML, for graph tools like yEd, Gephi or networkx.
+    Graphml,
+    /// JSON list of nodes and edges (eg for d3).
+    Json,
+}
+
+impl GraphFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            GraphFormat::Graphviz | GraphFormat::Svg => "svg",
+            GraphFormat::Dot => "dot",
+            GraphFormat::Graphml => "graphml",
+            GraphFormat::Json => "json",
+        }
+    }
+}
 
 // pub fn name_of(time: LV) -> String {
 //     if time == LV::MAX { panic!("Should not see ROOT_TIME here"); }
@@ -13,11 +13,13 @@ use std::cmp::Ordering;
 
 use std::collections::{BinaryHeap, HashMap};
 use std::ffi::OsString;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
 use chrono::{DateTime, FixedOffset, SubsecRound};
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, IgnoredAny, SeqAccess, Visitor};
 use serde::ser::SerializeTupleStruct;
 use smallvec::{SmallVec, smallvec};
 use smartstring::alias::String as SmartString;
@@ -35,9 +37,9 @@ use rle::take_max_iter::TakeMaxFns;
 // practice given the whole operation is unitary.
 #[derive(Clone, Debug)]
 pub struct SimpleTextOp {
-    pos: usize,
-    del_len: usize,
-    ins_content: SmartString,
+    pub pos: usize,
+    pub del_len: usize,
+    pub ins_content: SmartString,
 }
 
 impl MergableSpan for SimpleTextOp {
@@ -70,6 +72,49 @@ impl Serialize for SimpleTextOp {
     }
 }
 
+impl<'de> Deserialize<'de> for SimpleTextOp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        // Mirrors the tuple serialization above. Any trailing fields (eg timestamps) are ignored.
+        struct TupleVisitor;
+
+        impl<'de> Visitor<'de> for TupleVisitor {
+            type Value = SimpleTextOp;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an array of [pos, del_len, ins_content, ...]")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let pos = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let del_len = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let ins_content = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+
+                Ok(SimpleTextOp { pos, del_len, ins_content })
+            }
+        }
+
+        deserializer.deserialize_seq(TupleVisitor)
+    }
+}
+
+impl SimpleTextOp {
+    /// Convert this patch into the equivalent list of text operations. Unlike the `Into` impl
+    /// below, this allows patches which both delete and insert content at the same position. (As
+    /// used in the editing traces format).
+    pub fn push_ops_into(&self, ops: &mut Vec<TextOperation>) {
+        if self.del_len > 0 {
+            ops.push(TextOperation::new_delete(self.pos..self.pos + self.del_len));
+        }
+        if !self.ins_content.is_empty() {
+            ops.push(TextOperation::new_insert(self.pos, &self.ins_content));
+        }
+    }
+}
+
 impl From<TextOperation> for SimpleTextOp {
     fn from(op: TextOperation) -> Self {
         match op.kind {
@@ -488,23 +533,23 @@ pub fn export_trace_to_json(oplog: &ListOpLog, timestamp_filename: Option<OsStri
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DTExportTxn {
     /// The LV span of the txn. Note the agent seq span is not exported.
-    span: DTRange,
-    parents: SmallVec<usize, 2>,
-    agent: SmartString,
-    seq_start: usize,
+    pub span: DTRange,
+    pub parents: SmallVec<usize, 2>,
+    pub agent: SmartString,
+    pub seq_start: usize,
     // op: TextOperation,
-    ops: SmallVec<SimpleTextOp, 2>,
+    pub ops: SmallVec<SimpleTextOp, 2>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DTExport {
-    txns: Vec<DTExportTxn>,
-    end_content: String,
+    pub txns: Vec<DTExportTxn>,
+    pub end_content: String,
 }
 
 fn export_oplog_to_json(oplog: &ListOpLog) -> Vec<DTExportTxn> {
@@ -0,0 +1,124 @@
+//! Reporting for `dt fsck`. The checks themselves live in diamond-types
+//! (ListOpLog::verify_encoded). This file just formats the result for humans and for JSON.
+
+use serde::Serialize;
+use diamond_types::list::encoding::{VerifyProblem, VerifyReport};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkJson {
+    name: String,
+    offset: usize,
+    len: usize,
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProblemJson {
+    /// Machine readable name for the kind of problem.
+    kind: &'static str,
+    message: String,
+    /// Byte offset in the file, for problems with the file's structure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<String>,
+    /// The range of local versions (or agent sequence numbers) which are affected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<(usize, usize)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agent: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FsckJson {
+    ok: bool,
+    file_len: usize,
+    ops_read: usize,
+    valid_ops: usize,
+    chunks: Vec<ChunkJson>,
+    problems: Vec<ProblemJson>,
+}
+
+fn problem_json(p: &VerifyProblem, ops_read: usize) -> ProblemJson {
+    let mut result = ProblemJson {
+        kind: "",
+        message: p.to_string(),
+        offset: None,
+        chunk: None,
+        range: None,
+        agent: None,
+    };
+
+    match p {
+        VerifyProblem::Structure { offset, .. } => {
+            result.kind = "structure";
+            result.offset = Some(*offset);
+        }
+        VerifyProblem::MissingChecksum => { result.kind = "missingChecksum"; }
+        VerifyProblem::ChecksumMismatch { .. } => { result.kind = "checksum"; }
+        VerifyProblem::Unreadable(_) => { result.kind = "unreadable"; }
+        VerifyProblem::Decode { chunk, .. } => {
+            result.kind = "decode";
+            result.chunk = Some(chunk.clone());
+        }
+        VerifyProblem::CausalGraph { range, .. } => {
+            result.kind = "causalGraph";
+            result.range = Some((range.start, range.end));
+        }
+        VerifyProblem::AgentSeq { agent, seq_range, .. } => {
+            result.kind = "agentSeq";
+            result.agent = Some(agent.clone());
+            result.range = Some((seq_range.start, seq_range.end));
+        }
+        VerifyProblem::ReplayFailed { ops_ok } => {
+            result.kind = "replay";
+            result.range = Some((*ops_ok, ops_read));
+        }
+        VerifyProblem::ContentMismatch { .. } => { result.kind = "contentMismatch"; }
+        _ => { result.kind = "other"; }
+    }
+
+    result
+}
+
+pub fn fsck_json(report: &VerifyReport) -> FsckJson {
+    FsckJson {
+        ok: report.is_ok(),
+        file_len: report.file_len,
+        ops_read: report.ops_read,
+        valid_ops: report.valid_ops,
+        chunks: report.chunks.iter().map(|c| ChunkJson {
+            name: c.name.clone(),
+            offset: c.offset,
+            len: c.len,
+            depth: c.depth,
+        }).collect(),
+        problems: report.problems.iter().map(|p| problem_json(p, report.ops_read)).collect(),
+    }
+}
+
+pub fn print_fsck_report(report: &VerifyReport, verbose: bool) {
+    if verbose {
+        println!("File size {} bytes", report.file_len);
+        for c in report.chunks.iter() {
+            println!("{:indent$}{} at {} ({} bytes)", "", c.name, c.offset, c.len, indent = c.depth * 2);
+        }
+    }
+
+    for p in report.problems.iter() {
+        println!("{p}");
+    }
+
+    if report.valid_ops == report.ops_read {
+        println!("{} operations read", report.ops_read);
+    } else {
+        println!("{} operations read. {} are valid", report.ops_read, report.valid_ops);
+    }
+
+    if report.is_ok() {
+        println!("OK");
+    }
+}
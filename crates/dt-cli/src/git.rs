@@ -3,12 +3,13 @@
 
 // #![allow(unused_imports)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use anyhow::Context;
-use git2::{BranchType, Commit, Oid, Repository};
+use git2::{BranchType, Commit, Delta, DiffFindOptions, ObjectType, Oid, Reference, Repository, Signature, Tree, TreeWalkMode, TreeWalkResult};
 use git2::ObjectType::Blob;
 use similar::{ChangeTag, TextDiff};
 use similar::utils::TextDiffRemapper;
@@ -17,6 +18,8 @@ use indicatif::ProgressBar;
 use std::io::{BufWriter, Write};
 
 use diamond_types::list::*;
+use diamond_types::list::operation::TextOperation;
+use diamond_types::{CRDTKind, CreateValue, Frontier, HasLength, LV, OpLog, Primitive, ROOT_CRDT_ID};
 
 /// In the git repository for linux, there are commits (maybe just one commit?) with the same commit
 /// named twice in the parents list. Its this commit: 13e652800d1644dfedcd0d59ac95ef0beb7f3165
@@ -59,9 +62,71 @@ impl<'commit> UniqParentIds<'commit> {
     }
 }
 
-pub fn extract_from_git(mut input_path: PathBuf, branch: Option<String>, quiet: bool, map_out: Option<PathBuf>) -> anyhow::Result<ListOpLog> {
-    // let mut args: Args = argh::from_env();
+/// The parent / child relationships between all the commits reachable from some head commit.
+struct CommitGraph {
+    commit_parents: HashMap<Oid, SmallVec<Oid, 3>>,
+    commit_children: HashMap<Oid, SmallVec<Oid, 3>>,
+    /// Commits with no parents. Commits are processed in causal order starting from here.
+    fwd_frontier: Vec<Oid>,
+}
+
+impl CommitGraph {
+    fn scan(repo: &Repository, head: &Reference) -> anyhow::Result<Self> {
+        let mut scan_frontier = Vec::new();
+        let mut fwd_frontier = Vec::new();
+
+        // let mut commits_seen = HashSet::new();
+        let mut commit_children = HashMap::<Oid, SmallVec<Oid, 3>>::new();
+        let mut commit_parents = HashMap::<Oid, SmallVec<Oid, 3>>::new();
+
+        let c = head.peel_to_commit()?;
+        scan_frontier.push(c.id());
+        // Mark the final change as having no children.
+        commit_children.insert(c.id(), smallvec![]);
+
+        while let Some(c_id) = scan_frontier.pop() {
+            // println!("cc: {} / cp: {} / sf {} / ff {}", commit_children.len(), commit_parents.len(), scan_frontier.len(), fwd_frontier.len());
+            if commit_parents.contains_key(&c_id) { continue; }
+
+            // println!("Scanning {:?}", c);
+
+            let commit = repo.find_commit(c_id)?;
+
+            commit_parents.insert(c_id, UniqParentIds::new(&commit).collect());
+            for p_id in UniqParentIds::new(&commit) {
+                scan_frontier.push(p_id);
+
+                commit_children.entry(p_id).or_default()
+                    .push(c_id);
+            }
+
+            if commit.parent_count() == 0 {
+                fwd_frontier.push(commit.id());
+            }
+        }
+
+        Ok(Self { commit_parents, commit_children, fwd_frontier })
+    }
+
+}
 
+/// Diamond types only allows agent IDs up to 50 bytes long. We'll trim the name down to 30 bytes,
+/// just to be on the safe side.
+fn agent_name_for<'a>(sig: &'a Signature) -> &'a str {
+    let mut author = sig.name().unwrap_or("unknown");
+
+    if author.len() > 30 {
+        let mut end = 30;
+        // Make sure we cut at a unicode-safe boundary.
+        while !author.is_char_boundary(end) { end -= 1; }
+        author = &author[..end];
+    }
+    author
+}
+
+/// Find the repository containing the passed path. Returns the repository and the input path
+/// relative to the root of the repository.
+fn open_repo_containing(mut input_path: PathBuf) -> anyhow::Result<(Repository, PathBuf)> {
     if input_path.is_relative() {
         input_path = std::env::current_dir()?.join(input_path);
     }
@@ -75,12 +140,17 @@ pub fn extract_from_git(mut input_path: PathBuf, branch: Option<String>, quiet:
         repo_path = repo_path.parent().unwrap().to_path_buf();
     }
     // dbg!(&input_path, &repo_path);
-    let file_path = input_path.strip_prefix(&repo_path)?;
+    let file_path = input_path.strip_prefix(&repo_path)?.to_path_buf();
 
     // dbg!(&repo_path, &file_path);
 
     let repo = Repository::open(&repo_path)?;
-    let file = file_path;
+    Ok((repo, file_path))
+}
+
+pub fn extract_from_git(input_path: PathBuf, branch: Option<String>, quiet: bool, map_out: Option<PathBuf>) -> anyhow::Result<ListOpLog> {
+    // let mut args: Args = argh::from_env();
+    let (repo, file) = open_repo_containing(input_path)?;
     let branch = branch.unwrap_or_else(|| "master".into());
 
     let path = Path::new(&file);
@@ -90,47 +160,10 @@ pub fn extract_from_git(mut input_path: PathBuf, branch: Option<String>, quiet:
     // let head = repo.head().unwrap();
     let head = repo.find_branch(&branch, BranchType::Local).unwrap().into_reference();
 
-    let mut scan_frontier = Vec::new();
-    let mut fwd_frontier = Vec::new();
-
-    // Could wrap this stuff up in a struct or something, but its not a big deal.
-    // let mut commits_seen = HashSet::new();
-    let mut commit_children = HashMap::<Oid, SmallVec<Oid, 3>>::new();
-    let mut commit_parents = HashMap::<Oid, SmallVec<Oid, 3>>::new();
-
-    // (parents, children).
-    // let mut commit_info = HashMap::<Oid, (SmallVec<[Oid; 3]>, SmallVec<[Oid; 3]>)>::new();
-
-    let c = head.peel_to_commit().unwrap();
-    scan_frontier.push(c.id());
-    // Mark the final change as having no children.
-    commit_children.insert(c.id(), smallvec![]);
-
     let start = std::time::SystemTime::now();
 
     if !quiet { println!("Scanning frontier..."); }
-    while let Some(c_id) = scan_frontier.pop() {
-        // println!("cc: {} / cp: {} / sf {} / ff {}", commit_children.len(), commit_parents.len(), scan_frontier.len(), fwd_frontier.len());
-        if commit_parents.contains_key(&c_id) { continue; }
-
-        // println!("Scanning {:?}", c);
-
-        let commit = repo.find_commit(c_id)?;
-
-        commit_parents.insert(c_id, UniqParentIds::new(&commit).collect());
-        for p_id in UniqParentIds::new(&commit) {
-            scan_frontier.push(p_id);
-
-            commit_children.entry(p_id).or_insert_with(|| SmallVec::new())
-                .push(c_id);
-        }
-
-        if commit.parent_count() == 0 {
-            fwd_frontier.push(commit.id());
-        }
-    }
-
-    drop(scan_frontier);
+    let CommitGraph { commit_parents, commit_children, mut fwd_frontier } = CommitGraph::scan(&repo, &head)?;
 
     let scan_commits_time = std::time::SystemTime::now();
 
@@ -272,17 +305,7 @@ pub fn extract_from_git(mut input_path: PathBuf, branch: Option<String>, quiet:
                 if branch.content() != &new {
                     git_bytes_read += new.len();
                     let sig = commit.author();
-                    let mut author = sig.name().unwrap_or("unknown");
-
-                    // Diamond types only allows agent IDs up to 50 bytes long. We'll trim the
-                    // name down to 30 bytes, just to be on the safe side.
-                    if author.len() > 30 {
-                        let mut end = 30;
-                        // Make sure we cut at a unicode-safe boundary.
-                        while !author.is_char_boundary(end) { end -= 1; }
-                        author = &author[..end];
-                    }
-                    let agent = oplog.get_or_create_agent_id(author);
+                    let agent = oplog.get_or_create_agent_id(agent_name_for(&sig));
 
                     let branch_string = branch.content().to_string();
                     let old = branch_string.as_str();
@@ -382,3 +405,418 @@ pub fn extract_from_git(mut input_path: PathBuf, branch: Option<String>, quiet:
 
     Ok(oplog)
 }
+
+/// Convert the difference between two strings into a list of text operations which, applied in
+/// order, turn old into new.
+fn diff_to_ops(old: &str, new: &str) -> Vec<TextOperation> {
+    let diff = TextDiff::from_chars(old, new);
+    let remapper = TextDiffRemapper::from_text_diff(&diff, old, new);
+
+    let mut ops = vec![];
+    let mut pos = 0;
+    for (tag, str) in diff.ops().iter()
+        .flat_map(move |x| remapper.iter_slices(x)) {
+        let len = str.chars().count();
+        match tag {
+            ChangeTag::Equal => pos += len,
+            ChangeTag::Delete => ops.push(TextOperation::new_delete(pos..pos + len)),
+            ChangeTag::Insert => {
+                ops.push(TextOperation::new_insert(pos, str));
+                pos += len;
+            }
+        }
+    }
+    ops
+}
+
+/// The state of a file (text CRDT) in the document at some commit.
+#[derive(Debug, Clone)]
+struct FileState {
+    /// The text CRDT containing the file's content.
+    crdt: LV,
+    /// The git blob the content came from. This is zero if the content is the result of a CRDT
+    /// merge, and doesn't match any blob in git.
+    blob: Oid,
+    /// The version of the text CRDT's content.
+    text_version: Frontier,
+    content: Rc<str>,
+}
+
+/// The state of a key (path) in the root map at some commit.
+#[derive(Debug, Clone)]
+struct KeyState {
+    /// The version(s) of the most recent map operations on this key. This only has multiple
+    /// entries if the key was set concurrently on different branches.
+    set_version: Frontier,
+    /// The file stored at this key, or None if the file has been deleted or the key is conflicted.
+    file: Option<FileState>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RepoState {
+    version: Frontier,
+    files: BTreeMap<String, KeyState>,
+}
+
+/// Merge the states of a commit's parents into the starting state for the commit.
+fn merge_repo_states(oplog: &OpLog, mut parents: Vec<RepoState>) -> RepoState {
+    if parents.len() == 1 { return parents.pop().unwrap(); }
+
+    let mut version = Frontier::root();
+    for p in parents.iter() {
+        version.merge_union(p.version.as_ref(), &oplog.cg.graph);
+    }
+
+    let all_keys: BTreeSet<&String> = parents.iter().flat_map(|p| p.files.keys()).collect();
+    let mut files = BTreeMap::new();
+
+    for key in all_keys {
+        let entries: Vec<&KeyState> = parents.iter().filter_map(|p| p.files.get(key)).collect();
+
+        let mut set_version = Frontier::root();
+        for e in entries.iter() {
+            set_version.merge_union(e.set_version.as_ref(), &oplog.cg.graph);
+        }
+
+        // If one of the parents dominates the others, the map register takes its value.
+        let winner = entries.iter().find(|e| e.set_version == set_version);
+
+        let file = winner.and_then(|winner| {
+            let f = winner.file.as_ref()?;
+
+            // The other parents might have made concurrent edits to the same text CRDT.
+            let mut text_version = Frontier::root();
+            for e in entries.iter() {
+                if let Some(f2) = e.file.as_ref().filter(|f2| f2.crdt == f.crdt) {
+                    text_version.merge_union(f2.text_version.as_ref(), &oplog.cg.graph);
+                }
+            }
+
+            Some(if text_version == f.text_version {
+                f.clone()
+            } else {
+                FileState {
+                    crdt: f.crdt,
+                    blob: Oid::zero(),
+                    content: oplog.checkout_text_at(f.crdt, version.as_ref()).to_string().into(),
+                    text_version,
+                }
+            })
+        });
+
+        files.insert(key.clone(), KeyState { set_version, file });
+    }
+
+    RepoState { version, files }
+}
+
+/// List all the (utf8) text files in the tree, below the optional subtree prefix. Binary files,
+/// symlinks and submodules are ignored.
+fn files_in_tree(repo: &Repository, tree: &Tree, subtree: &Path) -> anyhow::Result<BTreeMap<String, Oid>> {
+    let mut result = BTreeMap::new();
+
+    let tree = if subtree.as_os_str().is_empty() {
+        tree.clone()
+    } else {
+        match tree.get_path(subtree) {
+            Ok(entry) if entry.kind() == Some(ObjectType::Tree) => entry.to_object(repo)?.peel_to_tree()?,
+            _ => return Ok(result), // The subtree doesn't exist at this commit.
+        }
+    };
+
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        // Symlinks are stored as blobs with a special file mode.
+        if entry.kind() == Some(Blob) && entry.filemode() != 0o120000 {
+            if let Some(name) = entry.name() {
+                result.insert(format!("{root}{name}"), entry.id());
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    Ok(result)
+}
+
+/// Find the files renamed by a commit, relative to its first parent. Git doesn't store renames, so
+/// (like `git log --follow`) we detect them by content similarity. The result maps each new path
+/// to the path it was renamed from. Paths are relative to the subtree.
+fn renames_in_commit(repo: &Repository, commit: &Commit, subtree: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let mut result = HashMap::new();
+    let Ok(parent) = commit.parent(0) else { return Ok(result); };
+
+    let mut diff = repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&commit.tree()?), None)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+    let relative_path = |path: Option<&Path>| -> Option<String> {
+        Some(path?.strip_prefix(subtree).ok()?.to_str()?.to_string())
+    };
+
+    for delta in diff.deltas().filter(|d| d.status() == Delta::Renamed) {
+        if let (Some(old), Some(new)) = (relative_path(delta.old_file().path()), relative_path(delta.new_file().path())) {
+            result.insert(new, old);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Import the history of every file in a git repository (or a subtree of it) into a single
+/// diamond types document.
+///
+/// The document's root map contains an entry for each file path, mapping to a text CRDT with the
+/// file's contents. Each git commit is mapped onto one run of operations in the causal graph, with
+/// parents corresponding to the commit's parents:
+///
+/// - Creating a file sets the path in the root map to a new text CRDT
+/// - Modifying a file edits its text CRDT
+/// - Deleting a file sets the path to nil
+///
+/// Renamed files are detected using git's similarity heuristics. The document model has no way to
+/// move a text CRDT to a different key, so a rename sets the old path to nil and creates a new text
+/// CRDT at the new path. The new CRDT starts with the old file's content, and any changes made
+/// along with the rename are imported as edits to that content. Files which aren't valid UTF8 are
+/// ignored.
+pub fn extract_repo_from_git(input_path: PathBuf, branch: Option<String>, quiet: bool, map_out: Option<PathBuf>) -> anyhow::Result<OpLog> {
+    let (repo, subtree) = open_repo_containing(input_path)?;
+    let branch = branch.unwrap_or_else(|| "master".into());
+
+    if !quiet { println!("Loading {:?} from {:?}", subtree, repo.path()); }
+
+    let head = repo.find_branch(&branch, BranchType::Local)?.into_reference();
+
+    if !quiet { println!("Scanning frontier..."); }
+    let CommitGraph { commit_parents, commit_children, mut fwd_frontier } = CommitGraph::scan(&repo, &head)?;
+
+    if !quiet { println!("Scanning commits..."); }
+    let mut oplog = OpLog::new();
+
+    // (Document state after the commit, number of remaining children.)
+    let mut state_at_oid = HashMap::<Oid, (RepoState, usize)>::new();
+
+    let mut map_file = map_out.map(|map_path| File::create(map_path).map(BufWriter::new)).transpose()?;
+
+    let bar = if quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(commit_parents.len() as _)
+    };
+
+    while let Some(commit_id) = fwd_frontier.pop() {
+        bar.inc(1);
+
+        let commit = repo.find_commit(commit_id)?;
+
+        let parent_states = commit_parents[&commit_id].iter().map(|p_id| {
+            let (state, num_children) = state_at_oid.get_mut(p_id).unwrap();
+            debug_assert!(*num_children >= 1);
+            if *num_children == 1 {
+                state_at_oid.remove(p_id).unwrap().0
+            } else {
+                *num_children -= 1;
+                state.clone()
+            }
+        }).collect::<Vec<_>>();
+        let mut state = merge_repo_states(&oplog, parent_states);
+
+        let tree_files = files_in_tree(&repo, &commit.tree()?, &subtree)?;
+        let agent = oplog.cg.get_or_create_agent_id(agent_name_for(&commit.author()));
+
+        // The content of each renamed file before the rename, keyed by its new path.
+        let renamed_from: HashMap<String, Rc<str>> = renames_in_commit(&repo, &commit, &subtree)?
+            .into_iter()
+            .filter_map(|(new_path, old_path)| {
+                if tree_files.contains_key(&old_path) { return None; } // Copied, not renamed.
+                let content = state.files.get(&old_path)?.file.as_ref()?.content.clone();
+                Some((new_path, content))
+            })
+            .collect();
+
+        // Each change made in this commit is appended (linearly) after the commit's parents.
+        let mut version = state.version.clone();
+        let push_text_ops = |oplog: &mut OpLog, version: &mut Frontier, crdt: LV, ops: Vec<TextOperation>| {
+            for op in ops {
+                let v_range = oplog.cg.assign_local_op_with_parents(version.as_ref(), agent, op.len());
+                oplog.remote_text_op(crdt, v_range, op);
+                *version = Frontier::new_1(v_range.last());
+            }
+        };
+
+        // Deleted files. If the key was set concurrently in different branches (so it has multiple
+        // set versions), we also need to explicitly delete it to resolve the conflict.
+        for (path, key_state) in state.files.iter_mut() {
+            if (key_state.file.is_some() || key_state.set_version.len() > 1) && !tree_files.contains_key(path) {
+                let v = oplog.cg.assign_local_op_with_parents(version.as_ref(), agent, 1).start;
+                oplog.remote_map_set(ROOT_CRDT_ID, v, path, CreateValue::Primitive(Primitive::Nil));
+                version = Frontier::new_1(v);
+                key_state.set_version = version.clone();
+                key_state.file = None;
+            }
+        }
+
+        // Created and modified files.
+        for (path, blob_id) in tree_files {
+            let key_state = state.files.get(&path);
+            if key_state.and_then(|k| k.file.as_ref()).map(|f| f.blob) == Some(blob_id) {
+                continue; // Unchanged.
+            }
+
+            let blob = repo.find_blob(blob_id)?;
+            let Ok(new) = std::str::from_utf8(blob.content()) else {
+                continue; // Binary file.
+            };
+
+            match key_state.and_then(|k| k.file.clone()) {
+                Some(mut file) => {
+                    if file.content.as_ref() != new {
+                        push_text_ops(&mut oplog, &mut version, file.crdt, diff_to_ops(&file.content, new));
+                        file.text_version = version.clone();
+                        file.content = new.into();
+                    }
+                    file.blob = blob_id;
+                    state.files.get_mut(&path).unwrap().file = Some(file);
+                }
+                None => {
+                    let crdt = oplog.cg.assign_local_op_with_parents(version.as_ref(), agent, 1).start;
+                    oplog.remote_map_set(ROOT_CRDT_ID, crdt, &path, CreateValue::NewCRDT(CRDTKind::Text));
+                    version = Frontier::new_1(crdt);
+                    let set_version = version.clone();
+
+                    let ops = match renamed_from.get(&path) {
+                        Some(old) if !old.is_empty() => {
+                            let mut ops = vec![TextOperation::new_insert(0, old)];
+                            ops.extend(diff_to_ops(old, new));
+                            ops
+                        }
+                        _ if !new.is_empty() => vec![TextOperation::new_insert(0, new)],
+                        _ => vec![],
+                    };
+                    push_text_ops(&mut oplog, &mut version, crdt, ops);
+
+                    state.files.insert(path, KeyState {
+                        set_version,
+                        file: Some(FileState {
+                            crdt,
+                            blob: blob_id,
+                            text_version: version.clone(),
+                            content: new.into(),
+                        }),
+                    });
+                }
+            }
+        }
+
+        if let Some(map_file) = map_file.as_mut() {
+            let rv = oplog.cg.agent_assignment.local_to_remote_frontier(version.as_ref());
+            writeln!(map_file, "{},{}", commit.id(), serde_json::to_string(&rv)?)?;
+        }
+
+        state.version = version;
+        let children = commit_children.get(&commit_id).unwrap();
+        state_at_oid.insert(commit_id, (state, children.len()));
+
+        for c in children {
+            if !state_at_oid.contains_key(c) {
+                let processed_all = commit_parents[c].iter()
+                    .all(|p_id| state_at_oid.contains_key(p_id));
+                if processed_all {
+                    fwd_frontier.push(*c);
+                }
+            }
+        }
+    }
+    bar.finish();
+
+    Ok(oplog)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Time;
+    use diamond_types::DTValue;
+    use super::*;
+
+    /// Write a (possibly nested) tree containing the named files.
+    fn write_tree(repo: &Repository, files: &[(&str, &str)]) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        let mut dirs = BTreeMap::<&str, Vec<(&str, &str)>>::new();
+
+        for (path, content) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, content)),
+                None => {
+                    let blob = repo.blob(content.as_bytes()).unwrap();
+                    builder.insert(path, blob, 0o100644).unwrap();
+                }
+            }
+        }
+        for (dir, files) in dirs {
+            builder.insert(dir, write_tree(repo, &files), 0o040000).unwrap();
+        }
+        builder.write().unwrap()
+    }
+
+    fn commit(repo: &Repository, author: &str, t: i64, files: &[(&str, &str)], parents: &[Oid]) -> Oid {
+        let sig = Signature::new(author, &format!("{author}@example.com"), &Time::new(t, 0)).unwrap();
+        let tree = repo.find_tree(write_tree(repo, files)).unwrap();
+        let parents = parents.iter().map(|p| repo.find_commit(*p).unwrap()).collect::<Vec<_>>();
+        let parent_refs = parents.iter().collect::<Vec<_>>();
+        repo.commit(None, &sig, &sig, "commit", &tree, &parent_refs).unwrap()
+    }
+
+    fn text_at(oplog: &OpLog, path: &str) -> String {
+        oplog.checkout_text(oplog.text_at_path(&[path])).to_string()
+    }
+
+    #[test]
+    fn import_repo() {
+        let dir = std::env::temp_dir().join(format!("dt-git-import-{}", std::process::id()));
+        let repo = Repository::init(&dir).unwrap();
+
+        let readme = "This file is long enough for git to notice when it is renamed.\nLine 2\nLine 3\n";
+        let renamed = "This file is long enough for git to notice when it is renamed.\nLine 2\nLine 3!\n";
+
+        let base = commit(&repo, "alice", 1000, &[
+            ("readme.txt", readme),
+            ("src/lib/code.txt", "fn main() {}\n"),
+        ], &[]);
+
+        // On one branch the readme is renamed (and edited). On the other, code.txt is edited.
+        let a = commit(&repo, "alice", 2000, &[
+            ("docs/readme.txt", renamed),
+            ("src/lib/code.txt", "fn main() {}\n"),
+        ], &[base]);
+        let b = commit(&repo, "bob", 2000, &[
+            ("readme.txt", readme),
+            ("src/lib/code.txt", "fn main() { println!(\"hi\"); }\n"),
+        ], &[base]);
+
+        let merge = commit(&repo, "alice", 3000, &[
+            ("docs/readme.txt", renamed),
+            ("src/lib/code.txt", "fn main() { println!(\"hi\"); }\n"),
+        ], &[a, b]);
+        repo.branch("master", &repo.find_commit(merge).unwrap(), true).unwrap();
+
+        let oplog = extract_repo_from_git(dir.clone(), None, true, None).unwrap();
+
+        let files = oplog.checkout();
+        assert_eq!(files.keys().map(|k| k.as_str()).collect::<Vec<_>>(),
+            vec!["docs/readme.txt", "readme.txt", "src/lib/code.txt"]);
+        assert_eq!(text_at(&oplog, "docs/readme.txt"), renamed);
+        assert_eq!(text_at(&oplog, "src/lib/code.txt"), "fn main() { println!(\"hi\"); }\n");
+
+        // The old path is deleted.
+        assert!(matches!(files["readme.txt"].as_ref(), DTValue::Primitive(Primitive::Nil)));
+
+        // The renamed file starts with the old file's content, then gets edited.
+        let crdt = oplog.text_at_path(&["docs/readme.txt"]);
+        let ops = oplog.text_ops_since(crdt, &[]).unwrap();
+        assert_eq!(ops[0].1.content.as_deref(), Some(readme));
+        assert!(ops.len() > 1);
+
+        // The merge commit doesn't change anything itself, so the document ends up with both
+        // branches' versions in its frontier.
+        assert_eq!(oplog.cg.version.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
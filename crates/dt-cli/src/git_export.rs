@@ -0,0 +1,216 @@
+//! This contains the code to replay the history in a diamond types document into git commits. Its
+//! the inverse of git.rs, and mostly exists so DT history can be reviewed using ordinary git tools.
+//!
+//! Each run of operations in the causal graph becomes a commit, authored by the run's agent. Runs
+//! are split wherever some other operation names a version in the middle of the run as a parent,
+//! so every parent version has a commit of its own. Concurrent edits end up on concurrent git
+//! branches, and are joined by merge commits. Each commit's tree contains a single file with the
+//! document's content at that version.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use anyhow::{anyhow, bail};
+use git2::{Oid, Repository, Signature, Time};
+use diamond_types::{DTRange, Frontier, HasLength, LV};
+use diamond_types::causalgraph::agent_assignment::remote_ids::RemoteVersionSpan;
+use diamond_types::list::{ListBranch, ListOpLog};
+
+/// A single commit which will be written to git.
+#[derive(Debug, Clone)]
+struct PlannedCommit {
+    span: DTRange,
+    parents: Frontier,
+}
+
+/// Split the causal graph into commits. Commits are returned in causal order.
+fn plan_commits(oplog: &ListOpLog) -> Vec<PlannedCommit> {
+    // Every version named as a parent needs to be the last version in some commit.
+    let cut_points: BTreeSet<LV> = oplog.cg.iter()
+        .flat_map(|entry| entry.parents.0.into_iter())
+        .collect();
+
+    let mut commits = vec![];
+    for entry in oplog.cg.iter() {
+        let span: DTRange = (entry.start..entry.start + entry.len()).into();
+        let mut parents = entry.parents;
+        let mut start = span.start;
+
+        let cuts_here = cut_points.range(span.start..span.last());
+        for end in cuts_here.copied().map(|lv| lv + 1).chain(std::iter::once(span.end)) {
+            commits.push(PlannedCommit {
+                span: (start..end).into(),
+                parents: std::mem::replace(&mut parents, Frontier::new_1(end - 1)),
+            });
+            start = end;
+        }
+    }
+
+    commits
+}
+
+/// The result of exporting. Each commit is listed along with the document version it contains.
+#[derive(Debug, Clone)]
+pub struct GitExport {
+    pub head: Oid,
+    pub commits: Vec<(Oid, Frontier)>,
+}
+
+fn write_commit(repo: &Repository, filename: &str, content: &str, sig: &Signature, message: &str, parents: &[Oid]) -> anyhow::Result<Oid> {
+    let blob = repo.blob(content.as_bytes())?;
+    let mut builder = repo.treebuilder(None)?;
+    builder.insert(filename, blob, 0o100644)?;
+    let tree = repo.find_tree(builder.write()?)?;
+
+    let parents = parents.iter()
+        .map(|p| repo.find_commit(*p))
+        .collect::<Result<Vec<_>, _>>()?;
+    let parent_refs: Vec<_> = parents.iter().collect();
+
+    Ok(repo.commit(None, sig, sig, message, &tree, &parent_refs)?)
+}
+
+/// Git signatures need a name and an email address, neither of which can contain angle brackets.
+/// We synthesize both from the DT agent name.
+fn signature_for(agent: &str, time: usize) -> anyhow::Result<Signature<'static>> {
+    let name: String = agent.chars().filter(|c| *c != '<' && *c != '>').collect();
+    let name = if name.trim().is_empty() { "unknown".into() } else { name };
+    let email_user: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+
+    Ok(Signature::new(&name, &format!("{email_user}@diamond-types"), &Time::new(time as i64, 0))?)
+}
+
+/// Export the history of the oplog into commits in the git repository at repo_path. The repository
+/// is created if it doesn't already exist. The named branch is pointed at the final version of the
+/// document.
+///
+/// DT doesn't store timestamps, so commit times are synthesized from local versions. This makes
+/// the output deterministic.
+pub fn export_to_git(oplog: &ListOpLog, repo_path: &Path, branch: &str, filename: &str, force: bool) -> anyhow::Result<GitExport> {
+    if oplog.is_empty() {
+        bail!("Document has no history to export");
+    }
+
+    let repo = if repo_path.exists() {
+        Repository::open(repo_path)?
+    } else {
+        Repository::init(repo_path)?
+    };
+
+    let ref_name = format!("refs/heads/{branch}");
+    if !force && repo.find_reference(&ref_name).is_ok() {
+        bail!("Branch '{branch}' already exists. Overwrite by passing -f");
+    }
+
+    let planned = plan_commits(oplog);
+
+    // Count how many times each commit is used as a parent, so we know when we can throw away the
+    // branch (checkout) at that version.
+    let mut remaining_children = HashMap::<LV, usize>::new();
+    for c in planned.iter() {
+        for p in c.parents.iter() {
+            *remaining_children.entry(*p).or_default() += 1;
+        }
+    }
+
+    let mut oid_for_lv = HashMap::<LV, Oid>::new();
+    let mut branch_at_lv = HashMap::<LV, ListBranch>::new();
+    let mut commits = Vec::with_capacity(planned.len());
+
+    for PlannedCommit { span, parents } in planned {
+        let mut branch = match parents.0.first() {
+            None => ListBranch::new(),
+            Some(p) => {
+                if remaining_children[p] == 1 && parents.len() == 1 {
+                    branch_at_lv.remove(p).unwrap()
+                } else {
+                    branch_at_lv[p].clone()
+                }
+            }
+        };
+        branch.merge(oplog, &[span.last()]);
+
+        let RemoteVersionSpan(agent, seq_range) = oplog.cg.agent_assignment.local_to_remote_version_span(span);
+        let sig = signature_for(agent, span.start)?;
+        let message = format!("{agent} {}..{}", seq_range.start, seq_range.end);
+
+        let parent_oids: Vec<Oid> = parents.iter().map(|p| oid_for_lv[p]).collect();
+        let oid = write_commit(&repo, filename, &branch.content().to_string(), &sig, &message, &parent_oids)?;
+
+        for p in parents.iter() {
+            let count = remaining_children.get_mut(p).unwrap();
+            *count -= 1;
+            if *count == 0 { branch_at_lv.remove(p); }
+        }
+
+        oid_for_lv.insert(span.last(), oid);
+        if remaining_children.contains_key(&span.last()) {
+            branch_at_lv.insert(span.last(), branch);
+        }
+        commits.push((oid, Frontier::new_1(span.last())));
+    }
+
+    let frontier = oplog.local_frontier();
+    let head = if let Some(v) = frontier.try_get_single_entry() {
+        oid_for_lv[&v]
+    } else {
+        // The document ends with concurrent changes. Merge them together in a final commit.
+        let content = oplog.checkout_tip().content().to_string();
+        let sig = signature_for("diamond-types", oplog.len())?;
+        let parent_oids: Vec<Oid> = frontier.iter().map(|v| oid_for_lv[v]).collect();
+        let oid = write_commit(&repo, filename, &content, &sig, "Merge", &parent_oids)?;
+        commits.push((oid, frontier));
+        oid
+    };
+
+    repo.reference(&ref_name, head, force, "dt git-export")?;
+    if repo.head().is_err() {
+        // The repository is empty. Point HEAD at the new branch.
+        repo.set_head(&ref_name)?;
+    }
+
+    Ok(GitExport { head, commits })
+}
+
+/// Check that every exported commit contains the document content at the corresponding version.
+pub fn check_git_export(oplog: &ListOpLog, repo_path: &Path, filename: &str, export: &GitExport) -> anyhow::Result<()> {
+    let repo = Repository::open(repo_path)?;
+    for (oid, version) in export.commits.iter() {
+        let tree = repo.find_commit(*oid)?.tree()?;
+        let entry = tree.get_name(filename).ok_or_else(|| anyhow!("Commit {oid} is missing {filename}"))?;
+        let blob = repo.find_blob(entry.id())?;
+        let expected = oplog.checkout(version.as_ref()).content().to_string();
+        if blob.content() != expected.as_bytes() {
+            bail!("Content of commit {oid} does not match version {:?}", version);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diamond_types::list::gen_oplog;
+    use super::*;
+
+    #[test]
+    fn export_random_oplogs() {
+        for seed in 0..5 {
+            let oplog = gen_oplog(seed, 10, true, true);
+
+            let dir = std::env::temp_dir().join(format!("dt-git-export-{}-{seed}", std::process::id()));
+            let export = export_to_git(&oplog, &dir, "master", "doc.txt", false).unwrap();
+            check_git_export(&oplog, &dir, "doc.txt", &export).unwrap();
+
+            let repo = Repository::open(&dir).unwrap();
+            assert_eq!(repo.head().unwrap().target(), Some(export.head));
+
+            // Exporting again should fail unless forced.
+            assert!(export_to_git(&oplog, &dir, "master", "doc.txt", false).is_err());
+            let export_2 = export_to_git(&oplog, &dir, "master", "doc.txt", true).unwrap();
+            assert_eq!(export.head, export_2.head);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
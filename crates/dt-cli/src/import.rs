@@ -0,0 +1,393 @@
+//! This file contains the inverse of export.rs. It reads editing traces and JSON exports (in any of
+//! the formats produced by `dt export`, `dt export-trace`, `dt export-trace-simple` and
+//! `dt gen-conformance`) and rebuilds a diamond types oplog from them.
+//!
+//! Note the JSON formats discard some information, so importing isn't always byte-for-byte
+//! identical with the original file:
+//!
+//! - Editing traces don't store agent names. Agents are named by their index in the trace.
+//! - None of the formats store the fwd / backwards direction of deletes.
+//!
+//! But the resulting oplog should always have the same content and (for the DT export format) the
+//! same versions as the original.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+
+use diamond_types::Frontier;
+use diamond_types::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+use diamond_types::list::ListOpLog;
+use diamond_types::list::operation::TextOperation;
+use crate::export::{DTExport, SimpleTextOp};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// Guess the format based on the fields present in the data.
+    Auto,
+    /// Linear editing trace (as produced by `dt export-trace-simple`).
+    Simple,
+    /// Concurrent editing trace with parents (as produced by `dt export-trace`).
+    Concurrent,
+    /// Nonlinear dataset with (agent, seq) IDs and parents (as loaded by crdt-testdata).
+    Nl,
+    /// Full diamond types JSON export (as produced by `dt export`).
+    Dt,
+    /// Line-delimited list of DT exports (as produced by `dt gen-conformance`).
+    Conformance,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SimpleTraceData {
+    #[serde(default)]
+    start_content: String,
+    end_content: String,
+    txns: Vec<SimpleTraceTxn>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SimpleTraceTxn {
+    patches: Vec<SimpleTextOp>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConcurrentTraceData {
+    end_content: String,
+    num_agents: usize,
+    txns: Vec<ConcurrentTraceTxn>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ConcurrentTraceTxn {
+    parents: Vec<usize>,
+    agent: usize,
+    patches: Vec<SimpleTextOp>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+struct NLId {
+    agent: u32,
+    seq: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct NLPatch {
+    id: NLId,
+    parents: Vec<NLId>,
+    patch: SimpleTextOp,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NLDataset {
+    #[serde(default)]
+    start_content: String,
+    ops: Vec<NLPatch>,
+}
+
+/// Editing traces name agents by number. We still need agent names in diamond types, and concurrent
+/// edits are ordered by agent name. Zero padding the names makes the lexicographic order of the
+/// names match the numeric order used by the trace.
+fn numbered_agent_names(num_agents: usize) -> Vec<String> {
+    let width = num_agents.saturating_sub(1).to_string().len();
+    (0..num_agents).map(|i| format!("{:0width$}", i)).collect()
+}
+
+fn patches_to_ops<'a, I: IntoIterator<Item = &'a SimpleTextOp>>(patches: I) -> Vec<TextOperation> {
+    let mut ops = vec![];
+    for p in patches {
+        p.push_ops_into(&mut ops);
+    }
+    ops
+}
+
+fn union_frontiers<'a, I: IntoIterator<Item = &'a Frontier>>(oplog: &ListOpLog, frontiers: I) -> Frontier {
+    frontiers.into_iter().fold(Frontier::root(), |acc, f| {
+        oplog.version_union(acc.as_ref(), f.as_ref())
+    })
+}
+
+fn import_simple(data: SimpleTraceData, agent_name: &str) -> ListOpLog {
+    let mut oplog = ListOpLog::new();
+    let agent = oplog.get_or_create_agent_id(agent_name);
+
+    if !data.start_content.is_empty() {
+        oplog.add_insert(agent, 0, &data.start_content);
+    }
+
+    for txn in &data.txns {
+        let ops = patches_to_ops(&txn.patches);
+        if !ops.is_empty() {
+            oplog.add_operations(agent, &ops);
+        }
+    }
+
+    oplog
+}
+
+fn import_concurrent(data: ConcurrentTraceData) -> Result<ListOpLog, anyhow::Error> {
+    let mut oplog = ListOpLog::new();
+    let agents: Vec<_> = numbered_agent_names(data.num_agents).iter()
+        .map(|name| oplog.get_or_create_agent_id(name))
+        .collect();
+
+    // The version of the document after each txn. Txns with no patches (like the final merge txn
+    // emitted by export-trace) don't correspond to any operations, so they're just the union of
+    // their parents.
+    let mut txn_versions: Vec<Frontier> = Vec::with_capacity(data.txns.len());
+
+    for (i, txn) in data.txns.iter().enumerate() {
+        if let Some(p) = txn.parents.iter().find(|p| **p >= i) {
+            bail!("Txn {i} has parent {p} which does not come before it");
+        }
+        let agent = *agents.get(txn.agent)
+            .ok_or_else(|| anyhow!("Txn {i} has agent {} but numAgents is {}", txn.agent, data.num_agents))?;
+
+        let parents = union_frontiers(&oplog, txn.parents.iter().map(|p| &txn_versions[*p]));
+        let ops = patches_to_ops(&txn.patches);
+
+        let version = if ops.is_empty() {
+            parents
+        } else {
+            Frontier::new_1(oplog.add_operations_at(agent, parents.as_ref(), &ops))
+        };
+        txn_versions.push(version);
+    }
+
+    Ok(oplog)
+}
+
+fn import_nl(data: NLDataset, agent_name: &str) -> Result<ListOpLog, anyhow::Error> {
+    let mut oplog = ListOpLog::new();
+
+    // The start content isn't part of any agent's history. It goes first, and everything else
+    // comes after it.
+    let base = if data.start_content.is_empty() {
+        Frontier::root()
+    } else {
+        let agent = oplog.get_or_create_agent_id(agent_name);
+        Frontier::new_1(oplog.add_insert(agent, 0, &data.start_content))
+    };
+
+    let num_agents = data.ops.iter().map(|op| op.id.agent as usize + 1).max().unwrap_or(0);
+    let names = numbered_agent_names(num_agents);
+
+    let mut versions: HashMap<NLId, Frontier> = HashMap::new();
+
+    for op in &data.ops {
+        let parents = if op.parents.is_empty() {
+            base.clone()
+        } else {
+            let parents = op.parents.iter().map(|p| {
+                versions.get(p).ok_or_else(|| anyhow!("Op {:?} has unknown parent {:?}", op.id, p))
+            }).collect::<Result<Vec<_>, _>>()?;
+            union_frontiers(&oplog, parents)
+        };
+
+        let agent = oplog.get_or_create_agent_id(&names[op.id.agent as usize]);
+        let ops = patches_to_ops([&op.patch]);
+        let version = if ops.is_empty() {
+            parents
+        } else {
+            Frontier::new_1(oplog.add_operations_at(agent, parents.as_ref(), &ops))
+        };
+
+        if versions.insert(op.id, version).is_some() {
+            bail!("Duplicate op ID {:?}", op.id);
+        }
+    }
+
+    Ok(oplog)
+}
+
+fn import_dt(data: &DTExport) -> Result<ListOpLog, anyhow::Error> {
+    let mut oplog = ListOpLog::new();
+
+    for txn in &data.txns {
+        // Parents are stored as local versions, so the txns have to be replayed in order.
+        if txn.span.start != oplog.len() {
+            bail!("Txn with span {:?} out of order (expected start {})", txn.span, oplog.len());
+        }
+        if let Some(p) = txn.parents.iter().find(|p| **p >= txn.span.start) {
+            bail!("Txn with span {:?} has invalid parent {p}", txn.span);
+        }
+
+        let agent = oplog.get_or_create_agent_id(&txn.agent);
+        let ops = patches_to_ops(&txn.ops);
+        let span = oplog.add_operations_remote(agent, txn.parents.as_slice(), txn.seq_start, &ops);
+        if span != txn.span {
+            bail!("Txn ({}, {}) was assigned span {:?}, but the export says {:?}",
+                txn.agent, txn.seq_start, span, txn.span);
+        }
+    }
+
+    Ok(oplog)
+}
+
+fn detect_format(input: &str) -> Result<ImportFormat, anyhow::Error> {
+    let mut docs = serde_json::Deserializer::from_str(input).into_iter::<Value>();
+    let first = docs.next().ok_or_else(|| anyhow!("Input is empty"))??;
+    if docs.next().is_some() {
+        return Ok(ImportFormat::Conformance);
+    }
+
+    let obj = first.as_object().ok_or_else(|| anyhow!("Input is not a JSON object"))?;
+
+    Ok(if obj.contains_key("kind") || obj.contains_key("numAgents") {
+        ImportFormat::Concurrent
+    } else if obj.contains_key("ops") {
+        ImportFormat::Nl
+    } else if obj.contains_key("startContent") {
+        ImportFormat::Simple
+    } else if obj.contains_key("txns") {
+        ImportFormat::Dt
+    } else {
+        bail!("Could not detect the format of the input data");
+    })
+}
+
+/// Import the passed JSON data into a new oplog. The end content in the data (if any) is checked
+/// against the content of the resulting document.
+///
+/// The agent name is only used for formats which don't name their agents. `index` selects which
+/// example to import from line-delimited conformance data.
+pub fn import_from_json(input: &str, format: ImportFormat, agent_name: &str, index: Option<usize>) -> Result<ListOpLog, anyhow::Error> {
+    let format = if format == ImportFormat::Auto {
+        detect_format(input)?
+    } else { format };
+
+    let (oplog, end_content) = match format {
+        ImportFormat::Auto => unreachable!(),
+        ImportFormat::Simple => {
+            let data: SimpleTraceData = serde_json::from_str(input)?;
+            let end_content = data.end_content.clone();
+            (import_simple(data, agent_name), Some(end_content))
+        }
+        ImportFormat::Concurrent => {
+            let data: ConcurrentTraceData = serde_json::from_str(input)?;
+            let end_content = data.end_content.clone();
+            (import_concurrent(data)?, Some(end_content))
+        }
+        ImportFormat::Nl => {
+            let data: NLDataset = serde_json::from_str(input)?;
+            (import_nl(data, agent_name)?, None)
+        }
+        ImportFormat::Dt => {
+            let data: DTExport = serde_json::from_str(input)?;
+            (import_dt(&data)?, Some(data.end_content))
+        }
+        ImportFormat::Conformance => {
+            let index = index.unwrap_or(0);
+            let data: DTExport = serde_json::Deserializer::from_str(input)
+                .into_iter::<DTExport>()
+                .nth(index)
+                .ok_or_else(|| anyhow!("Conformance data has no example at index {index}"))?
+                .context("Could not parse conformance data")?;
+            (import_dt(&data)?, Some(data.end_content))
+        }
+    };
+
+    // Positions in the input haven't been checked yet, and replaying an out of range operation
+    // would panic.
+    if let Some(lv) = oplog.find_out_of_bounds_op() {
+        let RemoteVersion(agent, seq) = oplog.cg.agent_assignment.local_to_remote_version(lv);
+        bail!("Operation ({agent}, {seq}) is out of range in the document at its parent version");
+    }
+
+    if let Some(end_content) = end_content {
+        let content = oplog.checkout_tip().content().to_string();
+        if content != end_content {
+            bail!("Imported document content does not match the expected end content");
+        }
+    }
+
+    Ok(oplog)
+}
+
+#[cfg(test)]
+mod tests {
+    use diamond_types::list::gen_oplog;
+    use crate::export::{export_full_to_json, export_trace_to_json, export_transformed};
+    use super::*;
+
+    #[test]
+    fn dt_export_round_trips() {
+        for seed in 0..10 {
+            let oplog = gen_oplog(seed, 10, true, true);
+            let json = serde_json::to_string(&export_full_to_json(&oplog)).unwrap();
+
+            let imported = import_from_json(&json, ImportFormat::Auto, "x", None).unwrap();
+            assert_eq!(imported.cg.remote_frontier_owned(), oplog.cg.remote_frontier_owned());
+            assert_eq!(imported.len(), oplog.len());
+
+            // Exporting the imported oplog should produce identical data.
+            let json_2 = serde_json::to_string(&export_full_to_json(&imported)).unwrap();
+            assert_eq!(json, json_2);
+        }
+    }
+
+    #[test]
+    fn traces_round_trip() {
+        for seed in 0..10 {
+            let oplog = gen_oplog(seed, 10, true, false);
+            let content = oplog.checkout_tip().content().to_string();
+
+            let simple = serde_json::to_string(&export_transformed(&oplog, None, false)).unwrap();
+            let imported = import_from_json(&simple, ImportFormat::Auto, "x", None).unwrap();
+            assert_eq!(imported.checkout_tip().content().to_string(), content);
+
+            if !oplog.has_conflicts_when_merging() {
+                let trace = serde_json::to_string(&export_trace_to_json(&oplog, None, false)).unwrap();
+                let imported = import_from_json(&trace, ImportFormat::Auto, "x", None).unwrap();
+                assert_eq!(imported.checkout_tip().content().to_string(), content);
+            }
+        }
+    }
+
+    #[test]
+    fn patches_with_trailing_fields() {
+        // Some editing traces store a timestamp after each patch. It should be ignored.
+        let op: SimpleTextOp = serde_json::from_str(r#"[1, 2, "hi", "2022-01-01T00:00:00Z"]"#).unwrap();
+        assert_eq!((op.pos, op.del_len, op.ins_content.as_str()), (1, 2, "hi"));
+
+        let trace = r#"{
+            "endContent": "hi",
+            "txns": [
+                {"patches": [[0, 0, "hey", 1640995200]]},
+                {"patches": [[1, 2, "i", 1640995201]]}
+            ]
+        }"#;
+        let imported = import_from_json(trace, ImportFormat::Simple, "x", None).unwrap();
+        assert_eq!(imported.checkout_tip().content().to_string(), "hi");
+
+        assert!(serde_json::from_str::<SimpleTextOp>(r#"[1, 2]"#).is_err());
+    }
+
+    #[test]
+    fn out_of_range_positions_rejected() {
+        let simple = r#"{
+            "endContent": "",
+            "txns": [{"patches": [[0, 0, "hi"]]}, {"patches": [[1, 5, ""]]}]
+        }"#;
+        let err = import_from_json(simple, ImportFormat::Simple, "x", None).unwrap_err();
+        assert!(err.to_string().contains("(x, 2)"), "{err}");
+
+        // Concurrent edits are checked against the document at their own parents.
+        let concurrent = r#"{
+            "kind": "concurrent",
+            "endContent": "",
+            "numAgents": 2,
+            "txns": [
+                {"parents": [], "agent": 0, "patches": [[0, 0, "hi"]]},
+                {"parents": [], "agent": 1, "patches": [[1, 0, "x"]]}
+            ]
+        }"#;
+        let err = import_from_json(concurrent, ImportFormat::Concurrent, "x", None).unwrap_err();
+        assert!(err.to_string().contains("(1, 0)"), "{err}");
+    }
+}
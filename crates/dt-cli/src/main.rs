@@ -1,8 +1,13 @@
 mod export;
+mod import;
 mod dot;
+mod fsck;
+mod stats;
 
 #[cfg(feature = "git")]
 mod git;
+#[cfg(feature = "git")]
+mod git_export;
 
 use std::ffi::OsString;
 use std::fs;
@@ -21,11 +26,16 @@ use diamond_types::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned
 use diamond_types::Frontier;
 use diamond_types::list::{gen_oplog, ListBranch, ListOpLog};
 use diamond_types::list::encoding::{ENCODE_FULL, EncodeOptions};
-use crate::dot::{generate_svg_with_dot};
+use crate::dot::{generate_svg_with_dot, GraphFormat};
 use crate::export::{check_trace_invariants, export_full_to_json, export_trace_to_json, export_transformed};
+use crate::import::{import_from_json, ImportFormat};
+use crate::fsck::{fsck_json, print_fsck_report};
+use crate::stats::get_detailed_stats;
 
 #[cfg(feature = "git")]
-use crate::git::extract_from_git;
+use crate::git::{extract_from_git, extract_repo_from_git};
+#[cfg(feature = "git")]
+use crate::git_export::{check_git_export, export_to_git};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -75,13 +85,21 @@ enum Commands {
         version: Option<Version>,
     },
 
+    /// Print statistics about a diamond types file
     Stats {
         /// Diamond types file to read
-        #[arg(value_name = "filename", value_parser = parse_dt_oplog)]
-        oplog: ListOpLog,
+        #[arg(value_name = "filename")]
+        dt_filename: PathBuf,
 
-        // #[arg(short, long)]
-        // json: bool,
+        /// Output a detailed breakdown instead. This includes bytes per chunk type, per-agent
+        /// operation counts, concurrency metrics, RLE run lengths and the document length over
+        /// time.
+        #[arg(short, long, alias = "json")]
+        detailed: bool,
+
+        /// Number of samples to take of the document length over time (with --detailed)
+        #[arg(long, default_value_t = 100)]
+        samples: usize,
 
         /// Output contents to the named file instead of stdout
         #[arg(short, long)]
@@ -125,6 +143,32 @@ enum Commands {
         oplog: ListOpLog,
     },
 
+    /// Check a diamond types file for corruption.
+    ///
+    /// This checks the file's chunk structure and checksum, the causal graph and agent
+    /// assignments, and that the operations can be replayed (matching the stored end content, if
+    /// any). Exits with a non-zero status if any problems are found.
+    Fsck {
+        /// Diamond types file to check
+        dt_filename: PathBuf,
+
+        /// Output the report in JSON format
+        #[arg(short, long)]
+        json: bool,
+
+        /// Also print the chunk structure of the file
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Save the longest valid causal prefix of operations in the file to this filename
+        #[arg(short, long)]
+        salvage: Option<PathBuf>,
+
+        /// Force overwrite the salvage file if it exists
+        #[arg(short, long)]
+        force: bool,
+    },
+
     /// Set the contents of a DT file by applying a diff
     Set {
         /// Diamond types file to modify
@@ -198,6 +242,13 @@ enum Commands {
         #[arg(long)]
         no_deleted_content: bool,
 
+        /// Save the file as a stream of messages, each containing at most this many operations.
+        /// Streams can be merged incrementally as they're read, without buffering the whole file
+        /// (see `ListOpLog::load_from_reader`). Note streams can only be read by the streaming
+        /// decoder.
+        #[arg(long, value_name = "OPS_PER_MESSAGE")]
+        stream: Option<usize>,
+
         /// Suppress all output to stdout
         #[arg(short, long)]
         quiet: bool,
@@ -271,6 +322,42 @@ enum Commands {
         shatter: bool
     },
 
+    /// Import an editing trace or JSON export back into a diamond types file. This is the inverse of
+    /// export, export-trace and export-trace-simple. It also reads conformance data generated by
+    /// gen-conformance.
+    ///
+    /// If the data names the expected document content, the imported document is checked against
+    /// it.
+    Import {
+        /// JSON file to import. Use - to read from stdin.
+        input: OsString,
+
+        /// Output filename. Defaults to the input filename with a .dt extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Format of the input data. By default the format is detected from the data itself.
+        #[arg(long, value_enum, default_value_t = ImportFormat::Auto)]
+        format: ImportFormat,
+
+        /// Which example to import from conformance data. Defaults to the first.
+        #[arg(long)]
+        index: Option<usize>,
+
+        /// Agent name for edits in formats which don't name agents. If not specified, a random
+        /// name is chosen.
+        #[arg(short, long)]
+        agent: Option<String>,
+
+        /// Force overwrite the file which exists with the same name.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress all output to stdout
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
     /// Generate and export testing data for multi-implementation conformance testing.
     GenConformance {
         /// Output the result to the specified filename. If missing, output is printed to stdout.
@@ -306,8 +393,9 @@ enum Commands {
 
     /// Generate a diagram of the causal graph contained in a diamond types' file.
     ///
-    /// This depends on having the `dot` tool from [graphviz](https://graphviz.org/download/)
-    /// installed on your computer.
+    /// By default this depends on having the `dot` tool from
+    /// [graphviz](https://graphviz.org/download/) installed on your computer. Use `--format` to
+    /// pick a format which doesn't need graphviz.
     ///
     /// By default, we will execute `dot` in the system path to render graphs. But this can be
     /// overridden using `--dot-path="xxx/dot"`.
@@ -318,8 +406,12 @@ enum Commands {
         #[arg(short, long)]
         no_render: bool,
 
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = GraphFormat::Graphviz)]
+        format: GraphFormat,
+
         /// Output the result to the specified filename. If missing, output is saved to
-        /// (dt file).svg / .dot.
+        /// (dt file).svg / .dot / .graphml / .json.
         ///
         /// Use -o- to output to stdout instead.
         #[arg(short, long)]
@@ -356,6 +448,71 @@ enum Commands {
         map_out: Option<PathBuf>,
     },
 
+    /// Import & convert the history of every file in a git repository (or a directory inside it)
+    /// into a single multi-file diamond types document.
+    ///
+    /// The document's root map maps each file path to a text CRDT. The result is saved as JSON.
+    #[cfg(feature = "git")]
+    GitImportRepo {
+        /// Path to the repository, or a directory inside the repository to import.
+        path: PathBuf,
+
+        /// branch to be read. Defaults to 'master'.
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// Quiet mode
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Output filename
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Output an extra file containing mapping from git commits <-> DT versions.
+        #[arg(short, long)]
+        map_out: Option<PathBuf>,
+    },
+
+    /// Export the history of a diamond types file into commits in a git repository.
+    ///
+    /// Each run of changes becomes a commit authored by the change's agent. Concurrent changes
+    /// become concurrent git branches, joined by merge commits. The repository is created if it
+    /// doesn't exist.
+    #[cfg(feature = "git")]
+    GitExport {
+        /// Diamond types file to read
+        #[arg(value_name = "filename", value_parser = parse_dt_oplog)]
+        oplog: ListOpLog,
+
+        /// Path to the git repository to write to
+        repo: PathBuf,
+
+        /// Branch to point at the final version. Defaults to 'master'.
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// Name of the file stored in each commit. Defaults to 'content.txt'.
+        #[arg(long)]
+        filename: Option<String>,
+
+        /// Overwrite the branch if it already exists.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Check that every exported commit contains the expected content.
+        #[arg(long)]
+        check: bool,
+
+        /// Quiet mode
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Output an extra file containing mapping from git commits <-> DT versions.
+        #[arg(short, long)]
+        map_out: Option<PathBuf>,
+    },
+
     /// Duplicate an operation log some integer number of times.
     BenchDuplicate {
         /// File
@@ -444,15 +601,23 @@ fn main() -> Result<(), anyhow::Error> {
             }
         }
 
-        Commands::Stats { oplog, output } => {
-            let stats = oplog.get_stats();
-            let json = serde_json::to_string_pretty(&stats).unwrap();
+        Commands::Stats { dt_filename, detailed, samples, output } => {
+            let data = fs::read(&dt_filename)?;
+            let oplog = ListOpLog::load_from(&data)?;
 
-            if let Some(output) = output {
-                let mut file = File::create(output)?;
-                write!(&mut file, "{json}")?;
+            if detailed {
+                let stats = get_detailed_stats(&oplog, &data, samples)?;
+                write_serde_data(output, true, &stats)?;
             } else {
-                print!("{}", json);
+                let stats = oplog.get_stats();
+                let json = serde_json::to_string_pretty(&stats).unwrap();
+
+                if let Some(output) = output {
+                    let mut file = File::create(output)?;
+                    write!(&mut file, "{json}")?;
+                } else {
+                    print!("{}", json);
+                }
             }
         }
 
@@ -526,6 +691,31 @@ fn main() -> Result<(), anyhow::Error> {
             println!("{version}");
         }
 
+        Commands::Fsck { dt_filename, json, verbose, salvage, force } => {
+            let data = fs::read(&dt_filename)?;
+            let (report, salvaged) = ListOpLog::verify_encoded(&data);
+
+            if json {
+                write_serde_data(None, true, fsck_json(&report))?;
+            } else {
+                print_fsck_report(&report, verbose);
+            }
+
+            if let Some(salvage) = salvage {
+                let Some(salvaged) = salvaged else {
+                    anyhow::bail!("Nothing can be salvaged from this file");
+                };
+                maybe_overwrite(&salvage, &salvaged.encode(&ENCODE_FULL), force)?;
+                if !json {
+                    println!("Saved {} operations to {}", salvaged.len(), salvage.display());
+                }
+            }
+
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+
         Commands::Set { dt_filename, target_content_file, version, quiet, agent } => {
             let data = fs::read(&dt_filename)?;
 
@@ -590,7 +780,7 @@ fn main() -> Result<(), anyhow::Error> {
             fs::write(&dt_filename, out_data)?;
         }
 
-        Commands::Repack { dt_filename, output, force, uncompressed, version, truncate, patch, no_inserted_content, no_deleted_content, quiet } => {
+        Commands::Repack { dt_filename, output, force, uncompressed, version, truncate, patch, no_inserted_content, no_deleted_content, stream, quiet } => {
             let data = fs::read(&dt_filename)?;
             let mut oplog = ListOpLog::load_from(&data)?;
 
@@ -625,7 +815,15 @@ fn main() -> Result<(), anyhow::Error> {
                 .store_deleted_content(!no_deleted_content)
                 .compress_content(!uncompressed);
             
-            let new_data = oplog.encode_from(&opts, from_version.as_ref());
+            let new_data = match stream {
+                Some(0) => anyhow::bail!("Stream messages must contain at least 1 operation"),
+                Some(ops_per_message) => {
+                    let mut new_data = vec![];
+                    oplog.encode_stream(&mut new_data, &opts, from_version.as_ref(), ops_per_message)?;
+                    new_data
+                }
+                None => oplog.encode_from(&opts, from_version.as_ref()),
+            };
 
             let lossy = no_inserted_content || no_deleted_content || !from_version.is_empty();
             if output.is_none() && !force && lossy {
@@ -707,6 +905,35 @@ fn main() -> Result<(), anyhow::Error> {
             write_serde_data(output, pretty, &result)?;
         }
 
+        Commands::Import { input, output, format, index, agent, force, quiet } => {
+            let json = if input == "-" {
+                let mut s = String::new();
+                std::io::stdin().read_to_string(&mut s)?;
+                s
+            } else {
+                fs::read_to_string(&input)?
+            };
+
+            let agent_name = agent.unwrap_or_else(random_agent_name);
+            let oplog = import_from_json(&json, format, &agent_name, index)?;
+
+            let out_filename = match output {
+                Some(output) => output,
+                None if input == "-" => {
+                    anyhow::bail!("Output filename must be specified when reading from stdin");
+                }
+                None => PathBuf::from(&input).with_extension("dt"),
+            };
+
+            let data = oplog.encode(&ENCODE_FULL);
+            maybe_overwrite(&out_filename, &data, force)?;
+
+            if !quiet {
+                println!("Imported {} operations. {} bytes written to {}",
+                         oplog.len(), data.len(), out_filename.display());
+            }
+        }
+
         Commands::GenConformance { output, num, steps, seed, pretty, unicode, simple } => {
             let num = num.unwrap_or(100);
             let steps = steps.unwrap_or(if pretty { 1 } else { 50 });
@@ -723,38 +950,32 @@ fn main() -> Result<(), anyhow::Error> {
             }))?;
         }
 
-        Commands::Dot { dt_filename, no_render, output, dot_path, truncate } => {
+        Commands::Dot { dt_filename, no_render, format, output, dot_path, truncate } => {
             let data = fs::read(&dt_filename)?;
             let oplog = ListOpLog::load_from(&data)?;
 
-            let dot_input = if let Some(t) = truncate {
-                // There's probably a way to do this using nice rust constructs, but the borrow checker is hard.
-                oplog.cg.to_dot_graph(Some(&[t]))
-            } else {
-                oplog.cg.to_dot_graph(None)
-            };
-            // println!("{dot_input}");
+            let truncate_at = truncate.map(|t| [t]);
+            let version = truncate_at.as_ref().map(|t| &t[..]);
 
-            let render = !no_render;
+            let format = if no_render { GraphFormat::Dot } else { format };
 
-            if render {
-                let svg_contents = generate_svg_with_dot(dot_input, dot_path)
-                    .expect("Error running DOT");
-                let out_filename = get_filename_from(&dt_filename, output, "svg");
-                if let Some(out_filename) = out_filename {
-                    fs::write(&out_filename, svg_contents)?;
-                    println!("Wrote SVG to {}", out_filename.to_string_lossy());
-                } else {
-                    println!("{svg_contents}");
+            let contents = match format {
+                GraphFormat::Graphviz => {
+                    generate_svg_with_dot(oplog.cg.to_dot_graph(version), dot_path)
+                        .expect("Error running DOT")
                 }
+                GraphFormat::Dot => oplog.cg.to_dot_graph(version),
+                GraphFormat::Svg => oplog.cg.export_graph(version).to_svg(true),
+                GraphFormat::Graphml => oplog.cg.export_graph(version).to_graphml(),
+                GraphFormat::Json => oplog.cg.export_graph(version).to_json(),
+            };
+
+            let out_filename = get_filename_from(&dt_filename, output, format.extension());
+            if let Some(out_filename) = out_filename {
+                fs::write(&out_filename, contents)?;
+                println!("Wrote {} to {}", format.extension(), out_filename.to_string_lossy());
             } else {
-                let out_filename = get_filename_from(&dt_filename, output, "dot");
-                if let Some(out_filename) = out_filename {
-                    fs::write(&out_filename, dot_input)?;
-                    println!("Wrote dot to {}", out_filename.to_string_lossy());
-                } else {
-                    println!("{dot_input}");
-                }
+                println!("{contents}");
             }
         }
 
@@ -776,6 +997,49 @@ fn main() -> Result<(), anyhow::Error> {
             }
         }
 
+        #[cfg(feature = "git")]
+        Commands::GitImportRepo { path, branch, quiet, out, map_out } => {
+            let oplog = extract_repo_from_git(path.clone(), branch, quiet, map_out)?;
+
+            let out_filename = out.unwrap_or_else(|| {
+                let path = path.canonicalize().unwrap_or(path);
+                let stem = path.file_name().expect("Invalid path");
+                let mut path = PathBuf::from(stem);
+                path.set_extension("json");
+                path
+            });
+
+            let data = serde_json::to_vec(&oplog)?;
+            fs::write(&out_filename, &data)?;
+            if !quiet {
+                println!("{} operations ({} bytes) written to {}", oplog.cg.len(), data.len(), out_filename.display());
+            }
+        }
+
+        #[cfg(feature = "git")]
+        Commands::GitExport { oplog, repo, branch, filename, force, check, quiet, map_out } => {
+            let branch = branch.unwrap_or_else(|| "master".into());
+            let filename = filename.unwrap_or_else(|| "content.txt".into());
+
+            let export = export_to_git(&oplog, &repo, &branch, &filename, force)?;
+            if check {
+                check_git_export(&oplog, &repo, &filename, &export)?;
+            }
+
+            if let Some(map_out) = map_out {
+                let mut map_file = BufWriter::new(File::create(map_out)?);
+                for (oid, version) in export.commits.iter() {
+                    let rv = oplog.cg.agent_assignment.local_to_remote_frontier(version.as_ref());
+                    writeln!(map_file, "{},{}", oid, serde_json::to_string(&rv)?)?;
+                }
+            }
+
+            if !quiet {
+                println!("Wrote {} commits to {}. Branch '{branch}' is at {}",
+                         export.commits.len(), repo.display(), export.head);
+            }
+        }
+
         Commands::BenchDuplicate { path, output, force, number, quiet } => {
             let data = fs::read(&path)?;
             let orig_oplog = ListOpLog::load_from(&data)?;
@@ -0,0 +1,189 @@
+//! Detailed statistics about a diamond types file, for `dt stats --detailed`.
+
+use std::collections::BTreeMap;
+use serde::Serialize;
+use diamond_types::HasLength;
+use diamond_types::list::encoding::read_chunk_tree;
+use diamond_types::list::ListOpLog;
+use diamond_types::list::oplog::ListOpLogStats;
+use diamond_types::list::operation::ListOpKind;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentStats {
+    /// Number of operations (keystrokes) by this agent.
+    ops: usize,
+    inserted_chars: usize,
+    deleted_chars: usize,
+    /// Number of runs of consecutive operations in the causal graph attributed to this agent.
+    runs: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunLengths {
+    runs: usize,
+    items: usize,
+    mean: f64,
+    max: usize,
+}
+
+impl RunLengths {
+    fn from_lengths<I: Iterator<Item = usize>>(iter: I) -> Self {
+        let mut result = Self::default();
+        for len in iter {
+            result.runs += 1;
+            result.items += len;
+            result.max = result.max.max(len);
+        }
+        if result.runs > 0 {
+            result.mean = result.items as f64 / result.runs as f64;
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Concurrency {
+    num_merges: usize,
+    /// Number of times the merge algorithm needs to clear its state when replaying the whole
+    /// history.
+    ff_clears: usize,
+    /// Number of operations which need to be transformed when replaying the whole history.
+    ff_normal_ops: usize,
+    /// Number of operations which can be fast-forwarded (applied without transformation).
+    ff_ops: usize,
+    /// True if any concurrent inserts land at the same location in the document.
+    has_conflicts: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetailedStats {
+    summary: ListOpLogStats,
+    file_bytes: usize,
+    /// Total bytes used by each chunk type in the file, including chunk headers of children.
+    chunk_bytes: BTreeMap<String, usize>,
+    agents: BTreeMap<String, AgentStats>,
+    concurrency: Concurrency,
+    operation_runs: RunLengths,
+    history_runs: RunLengths,
+    agent_assignment_runs: RunLengths,
+    /// Pairs of (ops, document length in characters) sampled while replaying the history, where
+    /// ops is the number of operations which have been merged so far.
+    doc_length: Vec<(usize, usize)>,
+}
+
+fn doc_length_series(oplog: &ListOpLog, samples: usize) -> Vec<(usize, usize)> {
+    let step = (oplog.len() / samples.max(1)).max(1);
+    let mut result = vec![(0, 0)];
+    let mut applied = 0;
+    let mut doc_len = 0;
+    let mut next_sample = step;
+
+    // Applying the transformed operations in order gives us the document state after each prefix
+    // of the merge. Note the operations aren't visited in local version order, so we count how
+    // many operations have been applied so far instead.
+    for (range, op) in oplog.iter_xf_operations() {
+        applied += range.len();
+        if let Some(op) = op {
+            match op.kind {
+                ListOpKind::Ins => doc_len += op.len(),
+                ListOpKind::Del => doc_len -= op.len(),
+            }
+        }
+        if applied >= next_sample && applied < oplog.len() {
+            result.push((applied, doc_len));
+            next_sample = applied + step;
+        }
+    }
+
+    if applied > 0 {
+        result.push((applied, doc_len));
+    }
+
+    result
+}
+
+pub fn get_detailed_stats(oplog: &ListOpLog, data: &[u8], samples: usize) -> Result<DetailedStats, anyhow::Error> {
+    let mut chunk_bytes = BTreeMap::<String, usize>::new();
+    for chunk in read_chunk_tree(data)? {
+        *chunk_bytes.entry(chunk.name).or_default() += chunk.len;
+    }
+
+    let mut agents = BTreeMap::<String, AgentStats>::new();
+    for entry in oplog.cg.iter() {
+        let stats = agents.entry(oplog.get_agent_name(entry.span.agent).into()).or_default();
+        stats.ops += entry.len();
+        stats.runs += 1;
+        for op in oplog.iter_ops_range((entry.start..entry.start + entry.len()).into()) {
+            match op.kind {
+                ListOpKind::Ins => stats.inserted_chars += op.len(),
+                ListOpKind::Del => stats.deleted_chars += op.len(),
+            }
+        }
+    }
+
+    let (ff_clears, ff_normal_ops, ff_ops) = oplog.get_ff_stats();
+
+    Ok(DetailedStats {
+        summary: oplog.get_stats(),
+        file_bytes: data.len(),
+        chunk_bytes,
+        agents,
+        concurrency: Concurrency {
+            num_merges: oplog.iter_history().filter(|e| e.parents.len() >= 2).count(),
+            ff_clears,
+            ff_normal_ops,
+            ff_ops,
+            has_conflicts: oplog.has_conflicts_when_merging(),
+        },
+        operation_runs: RunLengths::from_lengths(oplog.iter_ops().map(|op| op.len())),
+        history_runs: RunLengths::from_lengths(oplog.iter_history().map(|e| e.span.len())),
+        agent_assignment_runs: RunLengths::from_lengths(oplog.iter_remote_mappings().map(|m| m.1.len())),
+        doc_length: doc_length_series(oplog, samples),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use diamond_types::list::encoding::ENCODE_FULL;
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn detailed_stats_fields() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert_at(seph, &[], 0, "hello");
+        oplog.add_insert_at(mike, &[], 0, "abc");
+        oplog.add_delete_at(seph, &[4, 7], 0..2);
+
+        let data = oplog.encode(&ENCODE_FULL);
+        let stats = serde_json::to_value(get_detailed_stats(&oplog, &data, 2).unwrap()).unwrap();
+
+        assert_eq!(stats["fileBytes"], data.len());
+        assert_eq!(stats["chunkBytes"], json!({
+            "FileInfo": 12,
+            "AgentNames": 10,
+            "StartBranch": 0,
+            "Patches": 41,
+            "OpVersions": 6,
+            "OpTypeAndPosition": 5,
+            "OpParents": 7,
+            "PatchContent": 15,
+            "Crc": 4,
+        }));
+        assert_eq!(stats["agents"], json!({
+            "seph": { "ops": 7, "insertedChars": 5, "deletedChars": 2, "runs": 2 },
+            "mike": { "ops": 3, "insertedChars": 3, "deletedChars": 0, "runs": 1 },
+        }));
+        assert_eq!(stats["concurrency"]["numMerges"], 1);
+        assert_eq!(stats["historyRuns"], json!({ "runs": 3, "items": 10, "mean": 10.0 / 3.0, "max": 5 }));
+        // Sampled at most every 5 ops (10 ops / 2 samples), plus the start and end of the history.
+        assert_eq!(stats["docLength"], json!([[0, 0], [8, 8], [10, 6]]));
+        assert_eq!(stats["summary"]["final_doc_len_chars"], 6);
+    }
+}
@@ -0,0 +1,24 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Could not read cbindgen.toml");
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=DT_FFI_UPDATE_HEADER");
+
+    let bindings = cbindgen::generate_with_config(&crate_dir, config)
+        .expect("Unable to generate C bindings");
+
+    // Builds only write the header to OUT_DIR. The copy in include/ is checked in, and is only
+    // updated on request. The tests check it matches.
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    bindings.write_to_file(out_dir.join("diamond_types.h"));
+
+    if env::var_os("DT_FFI_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(crate_dir.join("include/diamond_types.h"));
+    }
+}
@@ -0,0 +1,190 @@
+#ifndef DIAMOND_TYPES_H
+#define DIAMOND_TYPES_H
+
+/* This file is generated by cbindgen from crates/dt-ffi. Do not edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result codes returned by fallible functions. The numeric values are stable.
+typedef enum DTResult {
+  DT_OK = 0,
+  // A required pointer argument was NULL.
+  DT_ERR_NULL_POINTER = 1,
+  // A string argument was not valid UTF-8.
+  DT_ERR_INVALID_UTF8_ARG = 2,
+  // A position or range is outside the document.
+  DT_ERR_OUT_OF_BOUNDS = 3,
+  // A remote version names an agent which is not known to the oplog.
+  DT_ERR_UNKNOWN_AGENT = 4,
+  // A remote version names a sequence number which is not known to the oplog.
+  DT_ERR_SEQ_IN_FUTURE = 5,
+  // The branch's version isn't in the oplog. This happens when a branch is used with an oplog
+  // other than the one it was created from.
+  DT_ERR_BRANCH_VERSION_UNKNOWN = 6,
+  // Diamond types panicked. This is a bug. The objects passed to the function may have been left
+  // in an inconsistent state, and should be freed. This is only returned by builds which unwind
+  // on panic. Builds with `panic = "abort"` abort instead.
+  DT_ERR_INTERNAL = 7,
+  DT_ERR_INVALID_MAGIC = 100,
+  DT_ERR_UNSUPPORTED_PROTOCOL_VERSION = 101,
+  DT_ERR_DOC_ID_MISMATCH = 102,
+  DT_ERR_BASE_VERSION_UNKNOWN = 103,
+  DT_ERR_UNKNOWN_CHUNK = 104,
+  DT_ERR_LZ4_DECODER_NEEDED = 105,
+  DT_ERR_LZ4_DECOMPRESSION_ERROR = 106,
+  DT_ERR_COMPRESSED_DATA_MISSING = 107,
+  DT_ERR_INVALID_CHUNK_HEADER = 108,
+  DT_ERR_MISSING_CHUNK = 109,
+  DT_ERR_INVALID_LENGTH = 110,
+  DT_ERR_UNEXPECTED_EOF = 111,
+  DT_ERR_INVALID_UTF8 = 112,
+  DT_ERR_INVALID_REMOTE_ID = 113,
+  DT_ERR_INVALID_VAR_INT = 114,
+  DT_ERR_INVALID_CONTENT = 115,
+  DT_ERR_GENERIC_INVALID_DATA = 116,
+  DT_ERR_CHECKSUM_FAILED = 117,
+  DT_ERR_DATA_MISSING = 118,
+  DT_ERR_OPERATION_REJECTED = 119,
+  DT_ERR_INVALID_SIGNATURE = 120,
+  DT_ERR_MISSING_SIGNATURE = 121,
+  DT_ERR_EQUIVOCATION = 122,
+  DT_ERR_ID_REUSED = 123,
+  DT_ERR_OPERATION_OUT_OF_BOUNDS = 124,
+  // Some other problem with the data which doesn't have its own code yet.
+  DT_ERR_PARSE_OTHER = 199,
+} DTResult;
+
+// A checkout of a document at some version.
+typedef struct DTBranch DTBranch;
+
+// An operation log. This stores the full history of a document.
+typedef struct DTOpLog DTOpLog;
+
+// A version expressed as a list of (agent name, sequence number) pairs. Unlike local versions,
+// these can be sent to remote peers.
+typedef struct DTVersion DTVersion;
+
+// A buffer of bytes owned by diamond types. Free it with `dt_bytes_free`.
+typedef struct DTBytes {
+  uint8_t *ptr;
+  size_t len;
+} DTBytes;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Get a human readable, static description of a result code. The returned string must not be
+// freed.
+const char *dt_result_str(enum DTResult result);
+
+// Create a new, empty oplog. Local edits will be attributed to the named agent. If agent_name is
+// NULL, a random agent name is generated.
+struct DTOpLog *dt_oplog_new(const char *agent_name);
+
+// Load an oplog from bytes created by `dt_oplog_to_bytes`. On success, the new oplog is written
+// to *out. If agent_name is NULL, a random agent name is generated.
+//
+// The operations are replayed to check their positions, so corrupt data is rejected with
+// `DT_ERR_OPERATION_OUT_OF_BOUNDS` rather than crashing later.
+enum DTResult dt_oplog_from_bytes(const uint8_t *bytes,
+                                  size_t len,
+                                  const char *agent_name,
+                                  struct DTOpLog **out);
+
+void dt_oplog_free(struct DTOpLog *oplog);
+
+// Set the agent used for subsequent local edits.
+enum DTResult dt_oplog_set_agent(struct DTOpLog *oplog, const char *agent_name);
+
+// Get the number of operations in the oplog.
+size_t dt_oplog_len(const struct DTOpLog *oplog);
+
+// Get the current version of the oplog. The result must be freed with `dt_version_free`. Returns
+// NULL if oplog is NULL.
+struct DTVersion *dt_oplog_get_remote_version(const struct DTOpLog *oplog);
+
+// Encode the entire oplog. The result must be freed with `dt_bytes_free`.
+enum DTResult dt_oplog_to_bytes(const struct DTOpLog *oplog, struct DTBytes *out);
+
+// Encode the operations in the oplog since the named version. This is usually used to send
+// changes to a remote peer. The result must be freed with `dt_bytes_free`.
+enum DTResult dt_oplog_get_patch_since(const struct DTOpLog *oplog,
+                                       const struct DTVersion *from_version,
+                                       struct DTBytes *out);
+
+// Merge encoded operations (from `dt_oplog_to_bytes` or `dt_oplog_get_patch_since`) into the
+// oplog. The new operations' positions are checked first, as in `dt_oplog_from_bytes`. If the
+// data is rejected, the oplog is left unchanged.
+enum DTResult dt_oplog_add_from_bytes(struct DTOpLog *oplog, const uint8_t *bytes, size_t len);
+
+// Create a new, empty branch at the start of history.
+struct DTBranch *dt_branch_new(void);
+
+// Create a branch with all the changes in the oplog merged in. Returns NULL if oplog is NULL.
+struct DTBranch *dt_branch_checkout(const struct DTOpLog *oplog);
+
+void dt_branch_free(struct DTBranch *branch);
+
+// Merge changes from the oplog into the branch. If version is NULL, all changes are merged.
+//
+// A branch must only ever be used with the oplog it was created from (or first used with). With
+// any other oplog, this returns `DT_ERR_BRANCH_VERSION_UNKNOWN`.
+enum DTResult dt_branch_merge(struct DTBranch *branch,
+                              const struct DTOpLog *oplog,
+                              const struct DTVersion *version);
+
+// Insert content at the named position in the branch. The change is recorded in the oplog, using
+// the oplog's current agent.
+enum DTResult dt_branch_ins(struct DTBranch *branch,
+                            struct DTOpLog *oplog,
+                            size_t pos,
+                            const char *content);
+
+// Delete len characters starting at pos from the branch. The change is recorded in the oplog,
+// using the oplog's current agent.
+enum DTResult dt_branch_del(struct DTBranch *branch, struct DTOpLog *oplog, size_t pos, size_t len);
+
+// Get the length of the branch's content, in unicode characters.
+size_t dt_branch_len(const struct DTBranch *branch);
+
+// Get the content of the branch as a NUL terminated UTF-8 string. The result must be freed with
+// `dt_string_free`. Returns NULL if branch is NULL.
+char *dt_branch_get(const struct DTBranch *branch);
+
+// Get the version of the branch. The result must be freed with `dt_version_free`. Returns NULL
+// if either argument is NULL, or if the branch belongs to a different oplog.
+struct DTVersion *dt_branch_get_remote_version(const struct DTBranch *branch,
+                                               const struct DTOpLog *oplog);
+
+// Create a new empty version. The empty version refers to the start of history.
+struct DTVersion *dt_version_new(void);
+
+void dt_version_free(struct DTVersion *version);
+
+// Add an (agent, seq) pair to the version.
+enum DTResult dt_version_push(struct DTVersion *version, const char *agent_name, size_t seq);
+
+// Get the number of (agent, seq) pairs in the version.
+size_t dt_version_len(const struct DTVersion *version);
+
+// Get the agent name of the version's idx-th entry. The returned string is owned by the version,
+// and is valid until the version is freed. Returns NULL if idx is out of bounds.
+const char *dt_version_agent(const struct DTVersion *version, size_t idx);
+
+// Get the sequence number of the version's idx-th entry. Returns 0 if idx is out of bounds.
+size_t dt_version_seq(const struct DTVersion *version, size_t idx);
+
+void dt_bytes_free(struct DTBytes bytes);
+
+void dt_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DIAMOND_TYPES_H */
@@ -0,0 +1,635 @@
+//! C ABI for diamond types. The generated header is checked in at `include/diamond_types.h`.
+//!
+//! All objects are passed to C as opaque pointers, created with a `dt_*_new` (or similar) function
+//! and released with the matching `dt_*_free` function. Functions which can fail return a
+//! [`DTResult`], and return any values through out parameters.
+//!
+//! Strings passed in are NUL terminated UTF-8. Positions and lengths in documents are measured in
+//! unicode characters (codepoints), the same as the rust API.
+//!
+//! Every argument is validated before it's passed to diamond types: pointers, UTF-8, positions
+//! against the branch's length, versions against the oplog, and the operations in any data being
+//! loaded. A branch is tied to the oplog it was first used with, and using it with any other oplog
+//! returns `DT_ERR_BRANCH_VERSION_UNKNOWN`.
+//!
+//! This validation is what keeps bad input from crashing the host. Release builds use
+//! `panic = "abort"`, so a panic there aborts the process. In builds which unwind, any remaining
+//! panic (which would be a bug) is caught at the boundary and reported as `DT_ERR_INTERNAL`, or as
+//! a NULL / zero result.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr::null_mut;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use diamond_types::{AgentId, Frontier};
+use diamond_types::causalgraph::agent_assignment::remote_ids::{RemoteVersion, VersionConversionError};
+use diamond_types::list::{ListBranch, ListOpLog};
+use diamond_types::list::encoding::{DecodeOptions, ENCODE_FULL, ENCODE_PATCH};
+use diamond_types::list::encoding::ParseError;
+
+/// Result codes returned by fallible functions. The numeric values are stable.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DTResult {
+    DT_OK = 0,
+
+    /// A required pointer argument was NULL.
+    DT_ERR_NULL_POINTER = 1,
+    /// A string argument was not valid UTF-8.
+    DT_ERR_INVALID_UTF8_ARG = 2,
+    /// A position or range is outside the document.
+    DT_ERR_OUT_OF_BOUNDS = 3,
+    /// A remote version names an agent which is not known to the oplog.
+    DT_ERR_UNKNOWN_AGENT = 4,
+    /// A remote version names a sequence number which is not known to the oplog.
+    DT_ERR_SEQ_IN_FUTURE = 5,
+    /// The branch's version isn't in the oplog. This happens when a branch is used with an oplog
+    /// other than the one it was created from.
+    DT_ERR_BRANCH_VERSION_UNKNOWN = 6,
+    /// Diamond types panicked. This is a bug. The objects passed to the function may have been left
+    /// in an inconsistent state, and should be freed. This is only returned by builds which unwind
+    /// on panic. Builds with `panic = "abort"` abort instead.
+    DT_ERR_INTERNAL = 7,
+
+    // These are mapped from the parse errors returned when decoding data.
+    DT_ERR_INVALID_MAGIC = 100,
+    DT_ERR_UNSUPPORTED_PROTOCOL_VERSION = 101,
+    DT_ERR_DOC_ID_MISMATCH = 102,
+    DT_ERR_BASE_VERSION_UNKNOWN = 103,
+    DT_ERR_UNKNOWN_CHUNK = 104,
+    DT_ERR_LZ4_DECODER_NEEDED = 105,
+    DT_ERR_LZ4_DECOMPRESSION_ERROR = 106,
+    DT_ERR_COMPRESSED_DATA_MISSING = 107,
+    DT_ERR_INVALID_CHUNK_HEADER = 108,
+    DT_ERR_MISSING_CHUNK = 109,
+    DT_ERR_INVALID_LENGTH = 110,
+    DT_ERR_UNEXPECTED_EOF = 111,
+    DT_ERR_INVALID_UTF8 = 112,
+    DT_ERR_INVALID_REMOTE_ID = 113,
+    DT_ERR_INVALID_VAR_INT = 114,
+    DT_ERR_INVALID_CONTENT = 115,
+    DT_ERR_GENERIC_INVALID_DATA = 116,
+    DT_ERR_CHECKSUM_FAILED = 117,
+    DT_ERR_DATA_MISSING = 118,
+    DT_ERR_OPERATION_REJECTED = 119,
+    DT_ERR_INVALID_SIGNATURE = 120,
+    DT_ERR_MISSING_SIGNATURE = 121,
+    DT_ERR_EQUIVOCATION = 122,
+    DT_ERR_ID_REUSED = 123,
+    DT_ERR_OPERATION_OUT_OF_BOUNDS = 124,
+    /// Some other problem with the data which doesn't have its own code yet.
+    DT_ERR_PARSE_OTHER = 199,
+}
+
+use DTResult::*;
+
+impl From<ParseError> for DTResult {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::InvalidMagic => DT_ERR_INVALID_MAGIC,
+            ParseError::UnsupportedProtocolVersion => DT_ERR_UNSUPPORTED_PROTOCOL_VERSION,
+            ParseError::DocIdMismatch => DT_ERR_DOC_ID_MISMATCH,
+            ParseError::BaseVersionUnknown => DT_ERR_BASE_VERSION_UNKNOWN,
+            ParseError::UnknownChunk => DT_ERR_UNKNOWN_CHUNK,
+            ParseError::LZ4DecoderNeeded => DT_ERR_LZ4_DECODER_NEEDED,
+            ParseError::LZ4DecompressionError => DT_ERR_LZ4_DECOMPRESSION_ERROR,
+            ParseError::CompressedDataMissing => DT_ERR_COMPRESSED_DATA_MISSING,
+            ParseError::InvalidChunkHeader => DT_ERR_INVALID_CHUNK_HEADER,
+            ParseError::MissingChunk(_) => DT_ERR_MISSING_CHUNK,
+            ParseError::InvalidLength => DT_ERR_INVALID_LENGTH,
+            ParseError::UnexpectedEOF => DT_ERR_UNEXPECTED_EOF,
+            ParseError::InvalidUTF8 => DT_ERR_INVALID_UTF8,
+            ParseError::InvalidRemoteID(_) => DT_ERR_INVALID_REMOTE_ID,
+            ParseError::InvalidVarInt => DT_ERR_INVALID_VAR_INT,
+            ParseError::InvalidContent => DT_ERR_INVALID_CONTENT,
+            ParseError::GenericInvalidData => DT_ERR_GENERIC_INVALID_DATA,
+            ParseError::ChecksumFailed => DT_ERR_CHECKSUM_FAILED,
+            ParseError::DataMissing => DT_ERR_DATA_MISSING,
+            ParseError::OperationRejected => DT_ERR_OPERATION_REJECTED,
+            ParseError::InvalidSignature => DT_ERR_INVALID_SIGNATURE,
+            ParseError::MissingSignature => DT_ERR_MISSING_SIGNATURE,
+            ParseError::Equivocation => DT_ERR_EQUIVOCATION,
+            ParseError::IdReused { .. } => DT_ERR_ID_REUSED,
+            ParseError::OperationOutOfBounds => DT_ERR_OPERATION_OUT_OF_BOUNDS,
+            _ => DT_ERR_PARSE_OTHER,
+        }
+    }
+}
+
+impl From<VersionConversionError> for DTResult {
+    fn from(err: VersionConversionError) -> Self {
+        match err {
+            VersionConversionError::UnknownAgent => DT_ERR_UNKNOWN_AGENT,
+            VersionConversionError::SeqInFuture => DT_ERR_SEQ_IN_FUTURE,
+        }
+    }
+}
+
+/// Get a human readable, static description of a result code. The returned string must not be
+/// freed.
+#[no_mangle]
+pub extern "C" fn dt_result_str(result: DTResult) -> *const c_char {
+    let s: &'static CStr = match result {
+        DT_OK => c"ok",
+        DT_ERR_NULL_POINTER => c"null pointer passed as argument",
+        DT_ERR_INVALID_UTF8_ARG => c"string argument is not valid UTF-8",
+        DT_ERR_OUT_OF_BOUNDS => c"position out of bounds",
+        DT_ERR_UNKNOWN_AGENT => c"unknown agent in version",
+        DT_ERR_SEQ_IN_FUTURE => c"unknown sequence number in version",
+        DT_ERR_BRANCH_VERSION_UNKNOWN => c"branch version is not in the oplog",
+        DT_ERR_INTERNAL => c"internal error",
+        DT_ERR_INVALID_MAGIC => c"invalid magic bytes",
+        DT_ERR_UNSUPPORTED_PROTOCOL_VERSION => c"unsupported protocol version",
+        DT_ERR_DOC_ID_MISMATCH => c"document id mismatch",
+        DT_ERR_BASE_VERSION_UNKNOWN => c"base version of patch is unknown",
+        DT_ERR_UNKNOWN_CHUNK => c"unknown chunk",
+        DT_ERR_LZ4_DECODER_NEEDED => c"lz4 decoder needed",
+        DT_ERR_LZ4_DECOMPRESSION_ERROR => c"lz4 decompression error",
+        DT_ERR_COMPRESSED_DATA_MISSING => c"compressed data missing",
+        DT_ERR_INVALID_CHUNK_HEADER => c"invalid chunk header",
+        DT_ERR_MISSING_CHUNK => c"missing chunk",
+        DT_ERR_INVALID_LENGTH => c"invalid length",
+        DT_ERR_UNEXPECTED_EOF => c"unexpected end of data",
+        DT_ERR_INVALID_UTF8 => c"invalid UTF-8 in data",
+        DT_ERR_INVALID_REMOTE_ID => c"invalid remote id in data",
+        DT_ERR_INVALID_VAR_INT => c"invalid varint",
+        DT_ERR_INVALID_CONTENT => c"invalid content",
+        DT_ERR_GENERIC_INVALID_DATA => c"invalid data",
+        DT_ERR_CHECKSUM_FAILED => c"checksum failed",
+        DT_ERR_DATA_MISSING => c"data missing",
+        DT_ERR_OPERATION_REJECTED => c"operations rejected by validator",
+        DT_ERR_INVALID_SIGNATURE => c"invalid signature",
+        DT_ERR_MISSING_SIGNATURE => c"missing signature",
+        DT_ERR_EQUIVOCATION => c"agent signed conflicting operations",
+        DT_ERR_ID_REUSED => c"operation ids reused for different operations",
+        DT_ERR_OPERATION_OUT_OF_BOUNDS => c"operation position outside the document",
+        DT_ERR_PARSE_OTHER => c"could not parse data",
+    };
+    s.as_ptr()
+}
+
+/// An operation log. This stores the full history of a document.
+pub struct DTOpLog {
+    inner: ListOpLog,
+    agent: AgentId,
+    /// Unique for each oplog created through this API. Branches remember the ID of their oplog.
+    id: u64,
+}
+
+/// A checkout of a document at some version.
+pub struct DTBranch {
+    inner: ListBranch,
+    /// The ID of the oplog this branch is used with. None until the branch is first used.
+    oplog_id: Option<u64>,
+}
+
+/// A version expressed as a list of (agent name, sequence number) pairs. Unlike local versions,
+/// these can be sent to remote peers.
+pub struct DTVersion(Vec<(CString, usize)>);
+
+/// A buffer of bytes owned by diamond types. Free it with `dt_bytes_free`.
+#[repr(C)]
+pub struct DTBytes {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+
+impl From<Vec<u8>> for DTBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let ptr = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        DTBytes { ptr, len }
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, DTResult> {
+    if s.is_null() { return Err(DT_ERR_NULL_POINTER); }
+    CStr::from_ptr(s).to_str().map_err(|_| DT_ERR_INVALID_UTF8_ARG)
+}
+
+unsafe fn bytes_arg<'a>(bytes: *const u8, len: usize) -> Result<&'a [u8], DTResult> {
+    if len == 0 { return Ok(&[]); }
+    if bytes.is_null() { return Err(DT_ERR_NULL_POINTER); }
+    Ok(slice::from_raw_parts(bytes, len))
+}
+
+/// Panics must not unwind into C. This runs f, returning `on_panic` if it panics.
+///
+/// This only helps in builds which unwind on panic. With `panic = "abort"` (as in our release
+/// profile), the process aborts before this can run, so callers must validate their arguments
+/// rather than relying on this.
+fn ffi_guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+fn ffi_result(f: impl FnOnce() -> Result<(), DTResult>) -> DTResult {
+    match ffi_guard(Err(DT_ERR_INTERNAL), f) {
+        Ok(()) => DT_OK,
+        Err(e) => e,
+    }
+}
+
+static NEXT_OPLOG_ID: AtomicU64 = AtomicU64::new(0);
+
+fn new_oplog(inner: ListOpLog, agent: AgentId) -> *mut DTOpLog {
+    let id = NEXT_OPLOG_ID.fetch_add(1, Ordering::Relaxed);
+    Box::into_raw(Box::new(DTOpLog { inner, agent, id }))
+}
+
+/// Check the branch belongs to the oplog. Positions in the oplog's operations are only valid for
+/// a branch made from the same oplog, so using a branch with another oplog could panic.
+fn check_branch(branch: &DTBranch, oplog: &DTOpLog) -> Result<(), DTResult> {
+    let same_oplog = branch.oplog_id.is_none_or(|id| id == oplog.id);
+    if same_oplog && branch.inner.local_frontier_ref().iter().all(|v| *v < oplog.inner.len()) {
+        Ok(())
+    } else {
+        Err(DT_ERR_BRANCH_VERSION_UNKNOWN)
+    }
+}
+
+/// Like [`check_branch`], but also ties an unused branch to the oplog.
+fn bind_branch(branch: &mut DTBranch, oplog: &DTOpLog) -> Result<(), DTResult> {
+    check_branch(branch, oplog)?;
+    branch.oplog_id = Some(oplog.id);
+    Ok(())
+}
+
+/// Data from C could have been corrupted or written by a malicious peer. Check the operations'
+/// positions, so merging them into a branch can't panic.
+fn untrusted_data_opts<'a>() -> DecodeOptions<'a> {
+    DecodeOptions { check_positions: true, ..Default::default() }
+}
+
+fn create_agent(oplog: &mut ListOpLog, agent_name: Option<&str>) -> AgentId {
+    match agent_name {
+        Some(name) => oplog.get_or_create_agent_id(name),
+        None => {
+            let name: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(8)
+                .map(char::from)
+                .collect();
+            oplog.get_or_create_agent_id(&name)
+        }
+    }
+}
+
+fn remote_version(oplog: &ListOpLog, frontier: &[usize]) -> *mut DTVersion {
+    let version = oplog.cg.agent_assignment.local_to_remote_frontier(frontier)
+        .into_iter()
+        .map(|RemoteVersion(name, seq)| {
+            // Agent names can't contain NUL bytes when they come from C, but they could in data
+            // from elsewhere. Those names are truncated.
+            let name = CString::new(name).unwrap_or_else(|err| {
+                let pos = err.nul_position();
+                CString::new(&name.as_bytes()[..pos]).unwrap()
+            });
+            (name, seq)
+        })
+        .collect();
+
+    Box::into_raw(Box::new(DTVersion(version)))
+}
+
+fn local_frontier(oplog: &ListOpLog, version: &DTVersion) -> Result<Frontier, DTResult> {
+    let mut frontier = vec![];
+    for (name, seq) in version.0.iter() {
+        let name = name.to_str().map_err(|_| DT_ERR_INVALID_UTF8_ARG)?;
+        frontier.push(oplog.cg.agent_assignment.try_remote_to_local_version(RemoteVersion(name, *seq))?);
+    }
+    // The parents need to be sorted and must not contain redundant versions.
+    Ok(oplog.cg.graph.find_dominators(&frontier))
+}
+
+// *** OpLog
+
+/// Create a new, empty oplog. Local edits will be attributed to the named agent. If agent_name is
+/// NULL, a random agent name is generated.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_new(agent_name: *const c_char) -> *mut DTOpLog {
+    ffi_guard(null_mut(), || {
+        let agent_name = if agent_name.is_null() { None } else {
+            match str_arg(agent_name) {
+                Ok(name) => Some(name),
+                Err(_) => return null_mut(),
+            }
+        };
+
+        let mut inner = ListOpLog::new();
+        let agent = create_agent(&mut inner, agent_name);
+        new_oplog(inner, agent)
+    })
+}
+
+/// Load an oplog from bytes created by `dt_oplog_to_bytes`. On success, the new oplog is written
+/// to *out. If agent_name is NULL, a random agent name is generated.
+///
+/// The operations are replayed to check their positions, so corrupt data is rejected with
+/// `DT_ERR_OPERATION_OUT_OF_BOUNDS` rather than crashing later.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_from_bytes(bytes: *const u8, len: usize, agent_name: *const c_char, out: *mut *mut DTOpLog) -> DTResult {
+    ffi_result(|| {
+        if out.is_null() { return Err(DT_ERR_NULL_POINTER); }
+        let data = bytes_arg(bytes, len)?;
+        let agent_name = if agent_name.is_null() { None } else { Some(str_arg(agent_name)?) };
+
+        let mut inner = ListOpLog::load_from_opts(data, untrusted_data_opts())?;
+        let agent = create_agent(&mut inner, agent_name);
+        *out = new_oplog(inner, agent);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_free(oplog: *mut DTOpLog) {
+    ffi_guard((), || {
+        if !oplog.is_null() {
+            drop(Box::from_raw(oplog));
+        }
+    })
+}
+
+/// Set the agent used for subsequent local edits.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_set_agent(oplog: *mut DTOpLog, agent_name: *const c_char) -> DTResult {
+    ffi_result(|| {
+        let oplog = oplog.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        let name = str_arg(agent_name)?;
+        oplog.agent = oplog.inner.get_or_create_agent_id(name);
+        Ok(())
+    })
+}
+
+/// Get the number of operations in the oplog.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_len(oplog: *const DTOpLog) -> usize {
+    ffi_guard(0, || {
+        oplog.as_ref().map_or(0, |oplog| oplog.inner.len())
+    })
+}
+
+/// Get the current version of the oplog. The result must be freed with `dt_version_free`. Returns
+/// NULL if oplog is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_get_remote_version(oplog: *const DTOpLog) -> *mut DTVersion {
+    ffi_guard(null_mut(), || {
+        match oplog.as_ref() {
+            Some(oplog) => remote_version(&oplog.inner, oplog.inner.local_frontier_ref()),
+            None => null_mut(),
+        }
+    })
+}
+
+/// Encode the entire oplog. The result must be freed with `dt_bytes_free`.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_to_bytes(oplog: *const DTOpLog, out: *mut DTBytes) -> DTResult {
+    ffi_result(|| {
+        let oplog = oplog.as_ref().ok_or(DT_ERR_NULL_POINTER)?;
+        let out = out.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        *out = oplog.inner.encode(&ENCODE_FULL).into();
+        Ok(())
+    })
+}
+
+/// Encode the operations in the oplog since the named version. This is usually used to send
+/// changes to a remote peer. The result must be freed with `dt_bytes_free`.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_get_patch_since(oplog: *const DTOpLog, from_version: *const DTVersion, out: *mut DTBytes) -> DTResult {
+    ffi_result(|| {
+        let oplog = oplog.as_ref().ok_or(DT_ERR_NULL_POINTER)?;
+        let from_version = from_version.as_ref().ok_or(DT_ERR_NULL_POINTER)?;
+        let out = out.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+
+        let from = local_frontier(&oplog.inner, from_version)?;
+        *out = oplog.inner.encode_from(&ENCODE_PATCH, from.as_ref()).into();
+        Ok(())
+    })
+}
+
+/// Merge encoded operations (from `dt_oplog_to_bytes` or `dt_oplog_get_patch_since`) into the
+/// oplog. The new operations' positions are checked first, as in `dt_oplog_from_bytes`. If the
+/// data is rejected, the oplog is left unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn dt_oplog_add_from_bytes(oplog: *mut DTOpLog, bytes: *const u8, len: usize) -> DTResult {
+    ffi_result(|| {
+        let oplog = oplog.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        let data = bytes_arg(bytes, len)?;
+        oplog.inner.decode_and_add_opts(data, untrusted_data_opts())?;
+        Ok(())
+    })
+}
+
+// *** Branch
+
+/// Create a new, empty branch at the start of history.
+#[no_mangle]
+pub extern "C" fn dt_branch_new() -> *mut DTBranch {
+    ffi_guard(null_mut(), || {
+        Box::into_raw(Box::new(DTBranch { inner: ListBranch::new(), oplog_id: None }))
+    })
+}
+
+/// Create a branch with all the changes in the oplog merged in. Returns NULL if oplog is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_checkout(oplog: *const DTOpLog) -> *mut DTBranch {
+    ffi_guard(null_mut(), || {
+        match oplog.as_ref() {
+            Some(oplog) => Box::into_raw(Box::new(DTBranch {
+                inner: oplog.inner.checkout_tip(),
+                oplog_id: Some(oplog.id),
+            })),
+            None => null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_free(branch: *mut DTBranch) {
+    ffi_guard((), || {
+        if !branch.is_null() {
+            drop(Box::from_raw(branch));
+        }
+    })
+}
+
+/// Merge changes from the oplog into the branch. If version is NULL, all changes are merged.
+///
+/// A branch must only ever be used with the oplog it was created from (or first used with). With
+/// any other oplog, this returns `DT_ERR_BRANCH_VERSION_UNKNOWN`.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_merge(branch: *mut DTBranch, oplog: *const DTOpLog, version: *const DTVersion) -> DTResult {
+    ffi_result(|| {
+        let branch = branch.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        let oplog = oplog.as_ref().ok_or(DT_ERR_NULL_POINTER)?;
+        bind_branch(branch, oplog)?;
+
+        match version.as_ref() {
+            Some(version) => {
+                let frontier = local_frontier(&oplog.inner, version)?;
+                branch.inner.merge(&oplog.inner, frontier.as_ref());
+            }
+            None => {
+                branch.inner.merge(&oplog.inner, oplog.inner.local_frontier_ref());
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Insert content at the named position in the branch. The change is recorded in the oplog, using
+/// the oplog's current agent.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_ins(branch: *mut DTBranch, oplog: *mut DTOpLog, pos: usize, content: *const c_char) -> DTResult {
+    ffi_result(|| {
+        let branch = branch.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        let oplog = oplog.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        bind_branch(branch, oplog)?;
+        let content = str_arg(content)?;
+        if pos > branch.inner.len() { return Err(DT_ERR_OUT_OF_BOUNDS); }
+        if !content.is_empty() {
+            branch.inner.insert(&mut oplog.inner, oplog.agent, pos, content);
+        }
+        Ok(())
+    })
+}
+
+/// Delete len characters starting at pos from the branch. The change is recorded in the oplog,
+/// using the oplog's current agent.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_del(branch: *mut DTBranch, oplog: *mut DTOpLog, pos: usize, len: usize) -> DTResult {
+    ffi_result(|| {
+        let branch = branch.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        let oplog = oplog.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        bind_branch(branch, oplog)?;
+        let end = pos.checked_add(len).ok_or(DT_ERR_OUT_OF_BOUNDS)?;
+        if end > branch.inner.len() { return Err(DT_ERR_OUT_OF_BOUNDS); }
+        if len > 0 {
+            branch.inner.delete(&mut oplog.inner, oplog.agent, pos..end);
+        }
+        Ok(())
+    })
+}
+
+/// Get the length of the branch's content, in unicode characters.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_len(branch: *const DTBranch) -> usize {
+    ffi_guard(0, || {
+        branch.as_ref().map_or(0, |branch| branch.inner.len())
+    })
+}
+
+/// Get the content of the branch as a NUL terminated UTF-8 string. The result must be freed with
+/// `dt_string_free`. Returns NULL if branch is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_get(branch: *const DTBranch) -> *mut c_char {
+    ffi_guard(null_mut(), || {
+        let Some(branch) = branch.as_ref() else { return null_mut(); };
+        let content = branch.inner.content().to_string();
+        // Documents can contain NUL characters. C strings can't, so they're stripped out here.
+        let content = CString::new(content).unwrap_or_else(|err| {
+            let mut bytes = err.into_vec();
+            bytes.retain(|b| *b != 0);
+            CString::new(bytes).unwrap()
+        });
+        content.into_raw()
+    })
+}
+
+/// Get the version of the branch. The result must be freed with `dt_version_free`. Returns NULL
+/// if either argument is NULL, or if the branch belongs to a different oplog.
+#[no_mangle]
+pub unsafe extern "C" fn dt_branch_get_remote_version(branch: *const DTBranch, oplog: *const DTOpLog) -> *mut DTVersion {
+    ffi_guard(null_mut(), || {
+        match (branch.as_ref(), oplog.as_ref()) {
+            (Some(branch), Some(oplog)) if check_branch(branch, oplog).is_ok() => {
+                remote_version(&oplog.inner, branch.inner.local_frontier_ref())
+            }
+            _ => null_mut(),
+        }
+    })
+}
+
+// *** Versions
+
+/// Create a new empty version. The empty version refers to the start of history.
+#[no_mangle]
+pub extern "C" fn dt_version_new() -> *mut DTVersion {
+    ffi_guard(null_mut(), || {
+        Box::into_raw(Box::new(DTVersion(vec![])))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dt_version_free(version: *mut DTVersion) {
+    ffi_guard((), || {
+        if !version.is_null() {
+            drop(Box::from_raw(version));
+        }
+    })
+}
+
+/// Add an (agent, seq) pair to the version.
+#[no_mangle]
+pub unsafe extern "C" fn dt_version_push(version: *mut DTVersion, agent_name: *const c_char, seq: usize) -> DTResult {
+    ffi_result(|| {
+        let version = version.as_mut().ok_or(DT_ERR_NULL_POINTER)?;
+        let name = str_arg(agent_name)?;
+        version.0.push((CString::new(name).unwrap(), seq));
+        Ok(())
+    })
+}
+
+/// Get the number of (agent, seq) pairs in the version.
+#[no_mangle]
+pub unsafe extern "C" fn dt_version_len(version: *const DTVersion) -> usize {
+    ffi_guard(0, || {
+        version.as_ref().map_or(0, |v| v.0.len())
+    })
+}
+
+/// Get the agent name of the version's idx-th entry. The returned string is owned by the version,
+/// and is valid until the version is freed. Returns NULL if idx is out of bounds.
+#[no_mangle]
+pub unsafe extern "C" fn dt_version_agent(version: *const DTVersion, idx: usize) -> *const c_char {
+    ffi_guard(std::ptr::null(), || {
+        version.as_ref()
+            .and_then(|v| v.0.get(idx))
+            .map_or(std::ptr::null(), |(name, _)| name.as_ptr())
+    })
+}
+
+/// Get the sequence number of the version's idx-th entry. Returns 0 if idx is out of bounds.
+#[no_mangle]
+pub unsafe extern "C" fn dt_version_seq(version: *const DTVersion, idx: usize) -> usize {
+    ffi_guard(0, || {
+        version.as_ref()
+            .and_then(|v| v.0.get(idx))
+            .map_or(0, |(_, seq)| *seq)
+    })
+}
+
+// *** Memory
+
+#[no_mangle]
+pub unsafe extern "C" fn dt_bytes_free(bytes: DTBytes) {
+    ffi_guard((), || {
+        if !bytes.ptr.is_null() {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(bytes.ptr, bytes.len)));
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dt_string_free(s: *mut c_char) {
+    ffi_guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
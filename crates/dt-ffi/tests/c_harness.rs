@@ -0,0 +1,49 @@
+//! Compile tests/harness.c against the checked in header and the dt_ffi shared library, and run
+//! it. This needs a C compiler, found through $CC (or `cc`).
+
+#![cfg(unix)]
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn c_harness() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    // Integration tests live in target/<profile>/deps. The shared library is built next to deps.
+    let lib_dir = env::current_exe().unwrap()
+        .parent().unwrap()
+        .parent().unwrap()
+        .to_path_buf();
+
+    let exe = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("dt_ffi_harness");
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".into());
+
+    let status = Command::new(&cc)
+        .arg(crate_dir.join("tests/harness.c"))
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I").arg(crate_dir.join("include"))
+        .arg("-L").arg(&lib_dir)
+        .arg("-ldt_ffi")
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-o").arg(&exe)
+        .status()
+        .expect("Could not run C compiler");
+    assert!(status.success(), "Compiling the C harness failed");
+
+    let output = Command::new(&exe).output().unwrap();
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "C harness failed");
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let generated = std::fs::read_to_string(PathBuf::from(env!("OUT_DIR")).join("diamond_types.h")).unwrap();
+    let checked_in = std::fs::read_to_string(crate_dir.join("include/diamond_types.h")).unwrap();
+    assert!(generated == checked_in,
+        "include/diamond_types.h is out of date. Regenerate it with DT_FFI_UPDATE_HEADER=1 cargo build -p dt-ffi");
+}
@@ -0,0 +1,193 @@
+// C test harness for the diamond types C API. This is compiled and run by tests/c_harness.rs.
+
+#include <stdio.h>
+#include <string.h>
+#include "diamond_types.h"
+
+static int failures = 0;
+
+#define CHECK(cond) do { \
+    if (!(cond)) { \
+        fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+        failures++; \
+    } \
+} while (0)
+
+#define CHECK_OK(expr) do { \
+    DTResult r_ = (expr); \
+    if (r_ != DT_OK) { \
+        fprintf(stderr, "%s:%d: %s returned %d (%s)\n", __FILE__, __LINE__, #expr, r_, dt_result_str(r_)); \
+        failures++; \
+    } \
+} while (0)
+
+static void check_content(const DTBranch *branch, const char *expected) {
+    char *content = dt_branch_get(branch);
+    if (strcmp(content, expected) != 0) {
+        fprintf(stderr, "expected content '%s', got '%s'\n", expected, content);
+        failures++;
+    }
+    dt_string_free(content);
+}
+
+static void test_edit_and_reload(void) {
+    DTOpLog *oplog = dt_oplog_new("seph");
+    DTBranch *branch = dt_branch_new();
+
+    CHECK_OK(dt_branch_ins(branch, oplog, 0, "hello world"));
+    CHECK_OK(dt_branch_del(branch, oplog, 5, 6));
+    CHECK_OK(dt_branch_ins(branch, oplog, 5, " \xc3\xa9t\xc3\xa9"));
+    CHECK(dt_branch_len(branch) == 9);
+    CHECK(dt_oplog_len(oplog) == 21);
+    check_content(branch, "hello \xc3\xa9t\xc3\xa9");
+
+    DTVersion *v = dt_branch_get_remote_version(branch, oplog);
+    CHECK(dt_version_len(v) == 1);
+    CHECK(strcmp(dt_version_agent(v, 0), "seph") == 0);
+    CHECK(dt_version_seq(v, 0) == 20);
+    CHECK(dt_version_agent(v, 1) == NULL);
+    dt_version_free(v);
+
+    DTBytes bytes;
+    CHECK_OK(dt_oplog_to_bytes(oplog, &bytes));
+
+    DTOpLog *loaded = NULL;
+    CHECK_OK(dt_oplog_from_bytes(bytes.ptr, bytes.len, NULL, &loaded));
+    CHECK(dt_oplog_len(loaded) == 21);
+    DTBranch *checkout = dt_branch_checkout(loaded);
+    check_content(checkout, "hello \xc3\xa9t\xc3\xa9");
+
+    dt_branch_free(checkout);
+    dt_oplog_free(loaded);
+    dt_bytes_free(bytes);
+    dt_branch_free(branch);
+    dt_oplog_free(oplog);
+}
+
+// Send everything since the common version from one peer to the other.
+static void sync_patch(DTOpLog *from, DTOpLog *to, const DTVersion *common) {
+    DTBytes patch;
+    DTResult r = dt_oplog_get_patch_since(from, common, &patch);
+    CHECK_OK(r);
+    if (r != DT_OK) return;
+    CHECK_OK(dt_oplog_add_from_bytes(to, patch.ptr, patch.len));
+    dt_bytes_free(patch);
+}
+
+static void test_concurrent_merge(void) {
+    DTOpLog *a = dt_oplog_new("alice");
+    DTBranch *a_branch = dt_branch_new();
+    CHECK_OK(dt_branch_ins(a_branch, a, 0, "abc"));
+
+    // Bob starts with a copy of alice's document.
+    DTBytes bytes;
+    CHECK_OK(dt_oplog_to_bytes(a, &bytes));
+    DTOpLog *b = NULL;
+    CHECK_OK(dt_oplog_from_bytes(bytes.ptr, bytes.len, "bob", &b));
+    dt_bytes_free(bytes);
+    DTBranch *b_branch = dt_branch_checkout(b);
+    DTVersion *common = dt_oplog_get_remote_version(a);
+
+    // Concurrent edits.
+    CHECK_OK(dt_branch_ins(a_branch, a, 0, "X"));
+    CHECK_OK(dt_branch_del(b_branch, b, 1, 1));
+    CHECK_OK(dt_branch_ins(b_branch, b, 2, "Y"));
+
+    sync_patch(a, b, common);
+    sync_patch(b, a, common);
+    dt_version_free(common);
+    CHECK(dt_oplog_len(a) == dt_oplog_len(b));
+
+    CHECK_OK(dt_branch_merge(a_branch, a, NULL));
+    CHECK_OK(dt_branch_merge(b_branch, b, NULL));
+    check_content(a_branch, "XacY");
+    check_content(b_branch, "XacY");
+
+    // Checking out an older version.
+    DTVersion *v = dt_version_new();
+    CHECK_OK(dt_version_push(v, "alice", 2));
+    DTBranch *old = dt_branch_new();
+    CHECK_OK(dt_branch_merge(old, b, v));
+    check_content(old, "abc");
+    dt_branch_free(old);
+    dt_version_free(v);
+
+    dt_branch_free(a_branch);
+    dt_branch_free(b_branch);
+    dt_oplog_free(a);
+    dt_oplog_free(b);
+}
+
+static void test_errors(void) {
+    DTOpLog *oplog = dt_oplog_new(NULL);
+    DTBranch *branch = dt_branch_new();
+
+    const uint8_t garbage[] = "not a diamond types file";
+    DTOpLog *out = NULL;
+    CHECK(dt_oplog_from_bytes(garbage, sizeof(garbage), NULL, &out) == DT_ERR_INVALID_MAGIC);
+    CHECK(out == NULL);
+    CHECK(dt_oplog_add_from_bytes(oplog, garbage, sizeof(garbage)) == DT_ERR_INVALID_MAGIC);
+
+    CHECK(dt_branch_ins(branch, oplog, 1, "x") == DT_ERR_OUT_OF_BOUNDS);
+    CHECK_OK(dt_branch_ins(branch, oplog, 0, "x"));
+    CHECK(dt_branch_del(branch, oplog, 0, 2) == DT_ERR_OUT_OF_BOUNDS);
+    CHECK(dt_branch_ins(branch, oplog, 0, "\xff") == DT_ERR_INVALID_UTF8_ARG);
+    CHECK(dt_branch_ins(NULL, oplog, 0, "x") == DT_ERR_NULL_POINTER);
+
+    DTVersion *v = dt_version_new();
+    CHECK_OK(dt_version_push(v, "nobody", 0));
+    DTBytes patch;
+    CHECK(dt_oplog_get_patch_since(oplog, v, &patch) == DT_ERR_UNKNOWN_AGENT);
+    dt_version_free(v);
+
+    // Using a branch with an oplog other than the one it was created from.
+    DTOpLog *other = dt_oplog_new("other");
+    DTBranch *other_branch = dt_branch_new();
+    CHECK_OK(dt_branch_ins(other_branch, other, 0, "some longer text"));
+    CHECK(dt_branch_merge(other_branch, oplog, NULL) == DT_ERR_BRANCH_VERSION_UNKNOWN);
+    CHECK(dt_branch_ins(other_branch, oplog, 0, "y") == DT_ERR_BRANCH_VERSION_UNKNOWN);
+    CHECK(dt_branch_del(other_branch, oplog, 0, 1) == DT_ERR_BRANCH_VERSION_UNKNOWN);
+    CHECK(dt_branch_get_remote_version(other_branch, oplog) == NULL);
+    dt_branch_free(other_branch);
+
+    // Even when the other oplog's versions happen to be in range.
+    DTBranch *small_branch = dt_branch_new();
+    CHECK_OK(dt_branch_ins(small_branch, other, 0, "y"));
+    CHECK(dt_branch_ins(small_branch, oplog, 0, "y") == DT_ERR_BRANCH_VERSION_UNKNOWN);
+    CHECK(dt_branch_merge(small_branch, oplog, NULL) == DT_ERR_BRANCH_VERSION_UNKNOWN);
+    dt_branch_free(small_branch);
+    dt_oplog_free(other);
+
+    // A file containing a delete past the end of the document.
+    const uint8_t bad_ops[] = {
+        0x44, 0x4d, 0x4e, 0x44, 0x54, 0x59, 0x50, 0x53, 0x00, 0x01, 0x06, 0x03, 0x04, 0x03, 0x62, 0x61,
+        0x64, 0x0a, 0x00, 0x14, 0x18, 0x18, 0x09, 0x00, 0x0d, 0x03, 0x04, 0x68, 0x69, 0x19, 0x01, 0x05,
+        0x15, 0x02, 0x02, 0x06, 0x16, 0x03, 0x11, 0x4f, 0x03, 0x17, 0x02, 0x06, 0x01, 0x64, 0x04, 0x7e,
+        0x85, 0x8a, 0xee,
+    };
+    CHECK(dt_oplog_from_bytes(bad_ops, sizeof(bad_ops), NULL, &out) == DT_ERR_OPERATION_OUT_OF_BOUNDS);
+    CHECK(out == NULL);
+    size_t len_before = dt_oplog_len(oplog);
+    CHECK(dt_oplog_add_from_bytes(oplog, bad_ops, sizeof(bad_ops)) == DT_ERR_OPERATION_OUT_OF_BOUNDS);
+    CHECK(dt_oplog_len(oplog) == len_before);
+
+    CHECK(strcmp(dt_result_str(DT_ERR_OUT_OF_BOUNDS), "position out of bounds") == 0);
+    CHECK(strcmp(dt_result_str(DT_ERR_ID_REUSED), "operation ids reused for different operations") == 0);
+
+    check_content(branch, "x");
+    dt_branch_free(branch);
+    dt_oplog_free(oplog);
+}
+
+int main(void) {
+    test_edit_and_reload();
+    test_concurrent_merge();
+    test_errors();
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("All C harness checks passed\n");
+    return 0;
+}
@@ -0,0 +1,10 @@
+//! A language server which lets any editor with LSP support collaboratively edit text documents.
+//!
+//! Each open document is backed by a diamond types oplog. Edits from the editor are added to the
+//! oplog, and changes made by other peers are merged in and sent back to the editor as
+//! `workspace/applyEdit` requests. Peers share their changes through a [session
+//! directory](session::SessionDir).
+
+pub mod text;
+pub mod session;
+pub mod server;
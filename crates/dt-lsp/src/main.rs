@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use clap::Parser;
+use lsp_server::Connection;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use dt_lsp::server::run;
+use dt_lsp::session::SessionDir;
+
+/// Language server for collaboratively editing documents with diamond types.
+///
+/// Talks LSP over stdin / stdout.
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Cli {
+    /// Directory shared with other peers. Each peer's changes are written here.
+    #[arg(long)]
+    session: PathBuf,
+
+    /// Agent name for edits. If not specified, a random name is chosen.
+    #[arg(short, long)]
+    agent: Option<String>,
+
+    /// How often (in milliseconds) to check the session for changes from other peers.
+    #[arg(long, default_value_t = 200)]
+    poll_ms: u64,
+}
+
+fn random_agent_name() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect()
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let agent = cli.agent.unwrap_or_else(random_agent_name);
+
+    let (conn, io_threads) = Connection::stdio();
+    run(&conn, SessionDir::new(cli.session, agent), Duration::from_millis(cli.poll_ms))?;
+    drop(conn);
+    io_threads.join()?;
+    Ok(())
+}
@@ -0,0 +1,385 @@
+//! The language server. This keeps a branch for each open document, holding the document's
+//! content as the editor sees it, at the version of the oplog the editor's content corresponds to.
+//!
+//! Edits from the editor (`textDocument/didChange`) are made on that branch. Changes merged from
+//! other peers are sent to the editor one transformed operation at a time, using
+//! `workspace/applyEdit`. The editor echoes each applied edit back to us in a `didChange`, which
+//! moves the branch forward.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use crossbeam_channel::RecvTimeoutError;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::{ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentChanges, InitializeParams, OneOf, OptionalVersionedTextDocumentIdentifier, Position, PositionEncodingKind, Range, ServerCapabilities, ServerInfo, TextDocumentContentChangeEvent, TextDocumentEdit, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Exit, Notification as _};
+use lsp_types::request::{ApplyWorkspaceEdit, Request as _};
+use diamond_types::{AgentId, Frontier};
+use diamond_types::list::{ListBranch, ListOpLog};
+use diamond_types::list::operation::ListOpKind;
+use diamond_types::list::pos_unit::PosUnit;
+use crate::session::{DocFiles, SessionDir};
+use crate::text::{diff, offset_to_position, position_to_offset, replace_chars};
+
+/// An edit we've sent to the editor, which we expect to be echoed back in a `didChange`.
+#[derive(Debug, Clone)]
+struct SentEdit {
+    range: Range,
+    text: String,
+    /// The oplog version of the editor's content once the edit has been applied.
+    version_after: Frontier,
+}
+
+#[derive(Debug)]
+struct Doc {
+    files: DocFiles,
+    oplog: ListOpLog,
+    agent: AgentId,
+
+    /// The document's content as the editor sees it. Edits from the editor are made on this
+    /// branch, and its version is the oplog version the editor's content corresponds to.
+    branch: ListBranch,
+    /// If the editor's content didn't match the session when the document was opened, this holds
+    /// the editor's content until the editor has been sent the session's content. `branch` isn't
+    /// used until then.
+    stale: Option<String>,
+    /// The editor's version number for the document.
+    editor_version: i32,
+
+    sent: Option<SentEdit>,
+    /// True while we're waiting for a response to a `workspace/applyEdit` request.
+    in_flight: bool,
+    /// Set when the editor rejects an edit. We wait for something to change before retrying.
+    rejected: bool,
+}
+
+impl Doc {
+    fn position_to_offset(&self, pos: Position, unit: PosUnit) -> usize {
+        match self.stale.as_ref() {
+            Some(text) => position_to_offset(text.chars(), pos, unit),
+            None => position_to_offset(self.branch.content().borrow().chars(), pos, unit),
+        }
+    }
+
+    /// Add an edit made in the editor to the oplog, and apply it to our copy of the editor's
+    /// content.
+    fn local_change(&mut self, range: std::ops::Range<usize>, content: &str) {
+        if let Some(text) = self.stale.as_mut() {
+            replace_chars(text, range, content);
+            return;
+        }
+        if !range.is_empty() {
+            self.branch.delete(&mut self.oplog, self.agent, range.clone());
+        }
+        if !content.is_empty() {
+            self.branch.insert(&mut self.oplog, self.agent, range.start, content);
+        }
+    }
+
+    fn apply_change(&mut self, change: TextDocumentContentChangeEvent, unit: PosUnit) {
+        let range = match change.range {
+            Some(range) => self.position_to_offset(range.start, unit)..self.position_to_offset(range.end, unit),
+            None => {
+                // The whole document was replaced. Only record what changed.
+                let old = match self.stale.as_ref() {
+                    Some(text) => text.clone(),
+                    None => self.branch.content().to_string(),
+                };
+                let (range, content) = diff(&old, &change.text);
+                self.check_echo(None, &change.text);
+                return self.local_change(range, &content);
+            }
+        };
+
+        if let Some(version) = self.check_echo(change.range, &change.text) {
+            // The editor applied the edit we sent. Catch the branch up to match.
+            self.stale = None;
+            self.branch.merge(&self.oplog, version.as_ref());
+        } else {
+            self.local_change(range, &change.text);
+        }
+    }
+
+    /// If the change is the edit we sent, returns the version of the editor's content after the
+    /// edit. Otherwise the editor's content has moved on, and the edit we sent is forgotten.
+    fn check_echo(&mut self, range: Option<Range>, text: &str) -> Option<Frontier> {
+        let sent = self.sent.take()?;
+        if range == Some(sent.range) && text == sent.text {
+            Some(sent.version_after)
+        } else { None }
+    }
+
+    /// The next edit to send to the editor, to bring its content up to date with the oplog.
+    fn next_edit(&mut self, unit: PosUnit) -> Option<SentEdit> {
+        let tip = self.oplog.local_frontier_ref();
+
+        if let Some(text) = self.stale.as_ref() {
+            // Replace the editor's content with the session's content.
+            let end = offset_to_position(text.chars(), usize::MAX, unit);
+            return Some(SentEdit {
+                range: Range::new(Default::default(), end),
+                text: self.oplog.checkout_tip().content().to_string(),
+                version_after: tip.into(),
+            });
+        }
+        let synced = self.branch.local_frontier_ref();
+        if synced == tip { return None; }
+
+        let mut version: Frontier = synced.into();
+        for (lvs, op) in self.oplog.iter_xf_operations_from(synced, tip) {
+            version = self.oplog.cg.graph.find_dominators_2(version.as_ref(), &[lvs.last()]);
+            let Some(op) = op else { continue; };
+
+            let content = self.branch.content().borrow();
+            let start = offset_to_position(content.chars(), op.start(), unit);
+            let (range, text) = match op.kind {
+                ListOpKind::Ins => {
+                    let content = op.content_as_str().unwrap_or_default();
+                    // Reversed inserts store their content in the order it was typed.
+                    let content = if op.loc.fwd { content.to_string() } else { content.chars().rev().collect() };
+                    (Range::new(start, start), content)
+                }
+                ListOpKind::Del => (Range::new(start, offset_to_position(content.chars(), op.end(), unit)), String::new()),
+            };
+            return Some(SentEdit { range, text, version_after: version });
+        }
+
+        // None of the remaining operations change the document.
+        self.branch.merge(&self.oplog, version.as_ref());
+        None
+    }
+}
+
+struct Server<'a> {
+    conn: &'a Connection,
+    session: SessionDir,
+    unit: PosUnit,
+    root: Option<PathBuf>,
+    docs: HashMap<Url, Doc>,
+    in_flight: HashMap<RequestId, Url>,
+    next_id: i32,
+    poll_interval: Duration,
+    last_poll: Instant,
+}
+
+/// Pick the position encoding. We use the first one the client lists, or UTF-16 (which every
+/// client must support).
+fn negotiate_encoding(params: &InitializeParams) -> PosUnit {
+    let encodings = params.capabilities.general.as_ref()
+        .and_then(|g| g.position_encodings.as_ref());
+
+    encodings.into_iter().flatten()
+        .find_map(|e| match e.as_str() {
+            "utf-8" => Some(PosUnit::Utf8),
+            "utf-16" => Some(PosUnit::Utf16),
+            "utf-32" => Some(PosUnit::Chars),
+            _ => None,
+        })
+        .unwrap_or(PosUnit::Utf16)
+}
+
+fn encoding_kind(unit: PosUnit) -> PositionEncodingKind {
+    match unit {
+        PosUnit::Chars => PositionEncodingKind::UTF32,
+        PosUnit::Utf16 => PositionEncodingKind::UTF16,
+        PosUnit::Utf8 => PositionEncodingKind::UTF8,
+    }
+}
+
+/// Run the language server on the connection until the client shuts it down. Changes are read
+/// from the session directory every `poll_interval`.
+pub fn run(conn: &Connection, session: SessionDir, poll_interval: Duration) -> Result<()> {
+    let (id, params) = conn.initialize_start()?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+    let unit = negotiate_encoding(&params);
+
+    let capabilities = ServerCapabilities {
+        position_encoding: Some(encoding_kind(unit)),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::INCREMENTAL)),
+        ..Default::default()
+    };
+    let server_info = ServerInfo {
+        name: "dt-lsp".into(),
+        version: Some(env!("CARGO_PKG_VERSION").into()),
+    };
+    conn.initialize_finish(id, serde_json::json!({
+        "capabilities": capabilities,
+        "serverInfo": server_info,
+    }))?;
+
+    #[allow(deprecated)]
+    let root = params.workspace_folders.as_ref()
+        .and_then(|folders| folders.first())
+        .map(|folder| &folder.uri)
+        .or(params.root_uri.as_ref())
+        .and_then(|uri| uri.to_file_path().ok());
+
+    let mut server = Server {
+        conn,
+        session,
+        unit,
+        root,
+        docs: HashMap::new(),
+        in_flight: HashMap::new(),
+        next_id: 0,
+        poll_interval,
+        last_poll: Instant::now(),
+    };
+    server.main_loop()
+}
+
+impl<'a> Server<'a> {
+    fn main_loop(&mut self) -> Result<()> {
+        loop {
+            match self.conn.receiver.recv_timeout(self.poll_interval) {
+                Ok(Message::Request(req)) => {
+                    if self.conn.handle_shutdown(&req)? { return Ok(()); }
+                    self.conn.sender.send(Response::new_err(req.id, ErrorCode::MethodNotFound as i32,
+                        format!("Unsupported request {}", req.method)).into())?;
+                }
+                Ok(Message::Notification(not)) => {
+                    if not.method == Exit::METHOD { return Ok(()); }
+                    self.handle_notification(not)?;
+                }
+                Ok(Message::Response(resp)) => self.handle_response(resp),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            if self.last_poll.elapsed() >= self.poll_interval {
+                self.pull()?;
+                self.last_poll = Instant::now();
+            }
+            self.flush()?;
+        }
+    }
+
+    /// The name of a document's folder in the session directory.
+    fn doc_name(&self, uri: &Url) -> PathBuf {
+        let Ok(path) = uri.to_file_path() else {
+            return uri.as_str().replace(|c: char| !c.is_alphanumeric() && c != '.', "_").into();
+        };
+        match self.root.as_ref().and_then(|root| path.strip_prefix(root).ok()) {
+            Some(rel) => rel.into(),
+            None => path.file_name().map(PathBuf::from).unwrap_or_default(),
+        }
+    }
+
+    fn handle_notification(&mut self, not: Notification) -> Result<()> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+                self.open(params)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+                if let Some(doc) = self.docs.get_mut(&params.text_document.uri) {
+                    for change in params.content_changes {
+                        doc.apply_change(change, self.unit);
+                    }
+                    doc.editor_version = params.text_document.version;
+                    doc.rejected = false;
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+                if let Some(mut doc) = self.docs.remove(&params.text_document.uri) {
+                    doc.files.push(&doc.oplog)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, params: DidOpenTextDocumentParams) -> Result<()> {
+        let item = params.text_document;
+        let mut oplog = ListOpLog::new();
+        let mut files = self.session.open(&self.doc_name(&item.uri), &mut oplog)?;
+        files.pull(&mut oplog)?;
+        let agent = oplog.get_or_create_agent_id(self.session.agent());
+
+        let mut branch = oplog.checkout_tip();
+        let stale = if oplog.is_empty() {
+            // We're the first peer to open the document.
+            if !item.text.is_empty() {
+                branch.insert(&mut oplog, agent, 0, &item.text);
+            }
+            None
+        } else if *branch.content() == item.text {
+            None
+        } else {
+            Some(item.text)
+        };
+
+        self.docs.insert(item.uri, Doc {
+            files,
+            oplog,
+            agent,
+            branch,
+            stale,
+            editor_version: item.version,
+            sent: None,
+            in_flight: false,
+            rejected: false,
+        });
+        Ok(())
+    }
+
+    fn handle_response(&mut self, resp: Response) {
+        let Some(uri) = self.in_flight.remove(&resp.id) else { return; };
+        let Some(doc) = self.docs.get_mut(&uri) else { return; };
+        doc.in_flight = false;
+
+        let applied = resp.result
+            .and_then(|r| serde_json::from_value::<ApplyWorkspaceEditResponse>(r).ok())
+            .is_some_and(|r| r.applied);
+        if !applied {
+            doc.sent = None;
+            doc.rejected = true;
+        }
+    }
+
+    /// Merge changes from other peers.
+    fn pull(&mut self) -> Result<()> {
+        for doc in self.docs.values_mut() {
+            if doc.files.pull(&mut doc.oplog)? {
+                doc.rejected = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Save local changes to the session, and send remote changes to the editor.
+    fn flush(&mut self) -> Result<()> {
+        for (uri, doc) in self.docs.iter_mut() {
+            doc.files.push(&doc.oplog)?;
+
+            if doc.in_flight || doc.rejected || doc.sent.is_some() { continue; }
+            let Some(edit) = doc.next_edit(self.unit) else { continue; };
+
+            let params = ApplyWorkspaceEditParams {
+                label: Some("diamond types".into()),
+                edit: WorkspaceEdit {
+                    document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri: uri.clone(),
+                            version: Some(doc.editor_version),
+                        },
+                        edits: vec![OneOf::Left(TextEdit::new(edit.range, edit.text.clone()))],
+                    }])),
+                    ..Default::default()
+                },
+            };
+
+            let id = RequestId::from(self.next_id);
+            self.next_id += 1;
+            self.conn.sender.send(Request::new(id.clone(), ApplyWorkspaceEdit::METHOD.into(), params).into())?;
+            self.in_flight.insert(id, uri.clone());
+            doc.in_flight = true;
+            doc.sent = Some(edit);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Peers share changes through a session directory. Each document has a folder in the session
+//! directory (named after the document's path relative to the workspace), and each peer writes its
+//! copy of the document's oplog to `<agent>.dt` in that folder. Peers merge changes by reading
+//! each other's files.
+//!
+//! Every file is only ever written by one peer. Files are streams of messages (see
+//! [`diamond_types::list::encoding::stream`]). Each time a peer saves, it appends a patch with the
+//! changes since its last save, so other peers only need to read the bytes added since they last
+//! looked. The directory can be shared between machines using any file syncing tool.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use diamond_types::Frontier;
+use diamond_types::list::ListOpLog;
+use diamond_types::list::encoding::{EncodeOptions, StreamDecoder};
+
+#[derive(Debug, Clone)]
+pub struct SessionDir {
+    root: PathBuf,
+    agent: String,
+}
+
+impl SessionDir {
+    pub fn new(root: impl Into<PathBuf>, agent: impl Into<String>) -> Self {
+        Self { root: root.into(), agent: agent.into() }
+    }
+
+    pub fn agent(&self) -> &str {
+        &self.agent
+    }
+
+    /// Open (creating if needed) the folder for the named document. Changes in our own file (from
+    /// before a restart) are merged into `oplog`, which should be empty.
+    pub fn open(&self, name: &Path, oplog: &mut ListOpLog) -> io::Result<DocFiles> {
+        let dir = self.root.join(name);
+        fs::create_dir_all(&dir)?;
+        let own = dir.join(format!("{}.dt", self.agent));
+
+        let was_empty = oplog.is_empty();
+        let saved = match File::open(&own) {
+            // If our file is damaged (eg we crashed partway through a save), it gets rewritten on
+            // the next push.
+            Ok(file) => oplog.decode_and_add_from_reader(file, |_| {}).ok()
+                .filter(|_| was_empty)
+                .map(|_| oplog.local_frontier()),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(DocFiles { dir, own, saved, readers: HashMap::new() })
+    }
+}
+
+/// How far we've read through another peer's file.
+#[derive(Debug, Default)]
+struct FileReader {
+    decoder: StreamDecoder,
+    /// The number of bytes of the file we've read.
+    offset: u64,
+    /// Set if the file couldn't be decoded.
+    failed: bool,
+}
+
+/// The files for one document in a session.
+#[derive(Debug)]
+pub struct DocFiles {
+    dir: PathBuf,
+    own: PathBuf,
+
+    /// The version of the oplog stored in our own file. None if the file needs to be (re)written
+    /// from scratch.
+    saved: Option<Frontier>,
+    readers: HashMap<PathBuf, FileReader>,
+}
+
+impl DocFiles {
+    /// Merge changes which other peers have added to their files since they were last read.
+    /// Returns true if any changes were merged.
+    pub fn pull(&mut self, oplog: &mut ListOpLog) -> io::Result<bool> {
+        let len_before = oplog.len();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path == self.own || path.extension().is_none_or(|ext| ext != "dt") { continue; }
+
+            let len = fs::metadata(&path)?.len();
+            let reader = self.readers.entry(path.clone()).or_default();
+            if len < reader.offset || (reader.failed && len != reader.offset) {
+                // The file has been replaced. Read it again from the start.
+                *reader = FileReader::default();
+            }
+            if len == reader.offset { continue; }
+
+            let mut file = File::open(&path)?;
+            file.seek(SeekFrom::Start(reader.offset))?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            reader.offset += data.len() as u64;
+
+            // A message which is still being written is held by the decoder until the rest of it
+            // arrives. A parse error means the file is corrupt. Skip it rather than stopping the
+            // session.
+            if reader.decoder.push(oplog, &data).is_err() {
+                reader.failed = true;
+            }
+        }
+
+        Ok(oplog.len() != len_before)
+    }
+
+    /// Save the oplog to our own file in the session. Changes since the last save are appended to
+    /// the file as a patch.
+    pub fn push(&mut self, oplog: &ListOpLog) -> io::Result<()> {
+        match &self.saved {
+            Some(saved) if saved.as_ref() == oplog.local_frontier_ref() => return Ok(()),
+            Some(saved) => {
+                let patch = oplog.encode_from(&EncodeOptions::patch(), saved.as_ref());
+                OpenOptions::new().append(true).open(&self.own)?.write_all(&patch)?;
+            }
+            None => {
+                let tmp = self.own.with_extension("dt.tmp");
+                fs::write(&tmp, oplog.encode(&EncodeOptions::full()))?;
+                fs::rename(&tmp, &self.own)?;
+            }
+        }
+        self.saved = Some(oplog.local_frontier());
+        Ok(())
+    }
+}
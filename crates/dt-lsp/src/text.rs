@@ -0,0 +1,149 @@
+//! Conversions between LSP positions (line, character) and character offsets in a document.
+//!
+//! LSP measures `character` in the position encoding negotiated with the client (UTF-16 unless the
+//! client says otherwise). Lines are split on `\n`, `\r\n` and `\r`.
+//!
+//! The conversions take the document as an iterator of characters, so they can read straight from
+//! a branch's rope.
+
+use std::ops::Range;
+use lsp_types::Position;
+use diamond_types::list::pos_unit::PosUnit;
+
+/// The length of `c` in `unit`.
+fn char_len(c: char, unit: PosUnit) -> usize {
+    match unit {
+        PosUnit::Chars => 1,
+        PosUnit::Utf16 => c.len_utf16(),
+        PosUnit::Utf8 => c.len_utf8(),
+    }
+}
+
+/// Convert an LSP position to a character offset in the document. Positions past the end of a
+/// line are clamped to the end of the line (as the LSP spec requires), and positions inside a
+/// character are rounded down to the start of the character. Lines past the end of the document
+/// are treated as an empty line at the end of the document.
+///
+/// Only the document's characters up to the position are read.
+pub fn position_to_offset(chars: impl Iterator<Item = char>, pos: Position, unit: PosUnit) -> usize {
+    let mut chars = chars.peekable();
+    let mut offset = 0;
+
+    let mut line = 0;
+    while line < pos.line {
+        match chars.next() {
+            None => return offset,
+            Some('\n') => line += 1,
+            Some('\r') => {
+                line += 1;
+                if chars.next_if_eq(&'\n').is_some() { offset += 1; }
+            }
+            Some(_) => {}
+        }
+        offset += 1;
+    }
+
+    let mut character = 0;
+    while let Some(c) = chars.next_if(|&c| c != '\n' && c != '\r') {
+        character += char_len(c, unit);
+        if character > pos.character as usize { break; }
+        offset += 1;
+    }
+    offset
+}
+
+/// Convert a character offset in the document to an LSP position. Only the document's characters
+/// up to the offset are read.
+pub fn offset_to_position(chars: impl Iterator<Item = char>, offset: usize, unit: PosUnit) -> Position {
+    let mut chars = chars.peekable();
+    let mut pos = Position::new(0, 0);
+    // The position at the end of the previous line.
+    let mut line_end = pos;
+    let mut after_cr = false;
+
+    for _ in 0..offset {
+        let Some(c) = chars.next() else { break; };
+        match c {
+            // The \r already ended the line.
+            '\n' if after_cr => {}
+            '\n' | '\r' => {
+                line_end = pos;
+                pos = Position::new(pos.line + 1, 0);
+            }
+            c => pos.character += char_len(c, unit) as u32,
+        }
+        after_cr = c == '\r';
+    }
+
+    // Between \r and \n. Treat this as the end of the line.
+    if after_cr && chars.peek() == Some(&'\n') { line_end } else { pos }
+}
+
+/// Replace the characters in `range` with `content`.
+pub fn replace_chars(text: &mut String, range: Range<usize>, content: &str) {
+    let byte_pos = |offset: usize| text.char_indices().nth(offset).map_or(text.len(), |(i, _)| i);
+    let bytes = byte_pos(range.start)..byte_pos(range.end);
+    text.replace_range(bytes, content);
+}
+
+/// Find the smallest character range in `old` which needs to be replaced to turn it into `new`.
+/// Returns the range in `old` and the content to replace it with.
+pub fn diff(old: &str, new: &str) -> (Range<usize>, String) {
+    let prefix = old.chars().zip(new.chars()).take_while(|(a, b)| a == b).count();
+    let old_rest: Vec<char> = old.chars().skip(prefix).collect();
+    let new_rest: Vec<char> = new.chars().skip(prefix).collect();
+    let suffix = old_rest.iter().rev().zip(new_rest.iter().rev()).take_while(|(a, b)| a == b).count();
+
+    let content = new_rest[..new_rest.len() - suffix].iter().collect();
+    (prefix..prefix + old_rest.len() - suffix, content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UNITS: [PosUnit; 3] = [PosUnit::Chars, PosUnit::Utf16, PosUnit::Utf8];
+
+    #[test]
+    fn positions_round_trip() {
+        let text = "a😀b\r\n\nc\rd😀\n";
+        for unit in UNITS {
+            for offset in 0..=text.chars().count() {
+                let pos = offset_to_position(text.chars(), offset, unit);
+                let chars: Vec<char> = text.chars().collect();
+                // The offset between \r and \n maps to the end of the line.
+                if offset > 0 && chars[offset - 1] == '\r' && chars.get(offset) == Some(&'\n') { continue; }
+                assert_eq!(position_to_offset(text.chars(), pos, unit), offset, "{unit:?} {offset} {pos:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn position_units() {
+        let text = "x\na😀b";
+        assert_eq!(offset_to_position(text.chars(), 4, PosUnit::Utf16), Position::new(1, 3));
+        assert_eq!(offset_to_position(text.chars(), 4, PosUnit::Utf8), Position::new(1, 5));
+        assert_eq!(offset_to_position(text.chars(), 4, PosUnit::Chars), Position::new(1, 2));
+        assert_eq!(position_to_offset(text.chars(), Position::new(1, 3), PosUnit::Utf16), 4);
+
+        // Clamped to the end of the line, or rounded down inside a character.
+        assert_eq!(position_to_offset(text.chars(), Position::new(0, 10), PosUnit::Utf16), 1);
+        assert_eq!(position_to_offset(text.chars(), Position::new(1, 2), PosUnit::Utf16), 3);
+        assert_eq!(position_to_offset(text.chars(), Position::new(5, 0), PosUnit::Utf16), 5);
+
+        // Between \r and \n is the end of the line.
+        assert_eq!(offset_to_position("ab\r\nc".chars(), 3, PosUnit::Chars), Position::new(0, 2));
+    }
+
+    #[test]
+    fn diff_finds_changed_range() {
+        assert_eq!(diff("hello world", "hello there world"), (6..6, "there ".into()));
+        assert_eq!(diff("abc", "ac"), (1..2, "".into()));
+        assert_eq!(diff("aaa", "aaaa"), (3..3, "a".into()));
+        assert_eq!(diff("😀x", "😀y"), (1..2, "y".into()));
+
+        let mut s = "😀x".to_string();
+        replace_chars(&mut s, 1..2, "yz");
+        assert_eq!(s, "😀yz");
+    }
+}
@@ -0,0 +1,251 @@
+//! Drives two in-process language servers sharing a session directory with scripted clients.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::{ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, DocumentChanges, OneOf, Position, Range};
+use lsp_types::notification::{DidChangeTextDocument, DidOpenTextDocument, Exit, Initialized, Notification as _};
+use lsp_types::request::{ApplyWorkspaceEdit, Initialize, Request as _, Shutdown};
+use serde_json::json;
+use diamond_types::list::ListOpLog;
+use diamond_types::list::encoding::EncodeOptions;
+use diamond_types::list::pos_unit::PosUnit;
+use dt_lsp::server::run;
+use dt_lsp::session::SessionDir;
+use dt_lsp::text::{position_to_offset, replace_chars};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pretend editor with a single open document.
+struct Editor {
+    conn: Connection,
+    server: Option<JoinHandle<()>>,
+    next_id: i32,
+    uri: String,
+    text: String,
+    version: i32,
+}
+
+impl Editor {
+    fn start(session: &Path, agent: &str) -> Self {
+        let (server_conn, conn) = Connection::memory();
+        let session = SessionDir::new(session, agent);
+        let server = std::thread::spawn(move || {
+            run(&server_conn, session, Duration::from_millis(10)).unwrap();
+        });
+
+        let mut editor = Self { conn, server: Some(server), next_id: 0, uri: String::new(), text: String::new(), version: 0 };
+        let result = editor.request(Initialize::METHOD, json!({
+            "capabilities": { "general": { "positionEncodings": ["utf-16"] } },
+            "rootUri": "file:///work",
+        }));
+        assert_eq!(result["capabilities"]["positionEncoding"], "utf-16");
+        editor.notify(Initialized::METHOD, json!({}));
+        editor
+    }
+
+    fn notify(&self, method: &str, params: serde_json::Value) {
+        self.conn.sender.send(Notification::new(method.into(), params).into()).unwrap();
+    }
+
+    /// Send a request and wait for the response. Requests from the server are handled while
+    /// waiting.
+    fn request(&mut self, method: &str, params: serde_json::Value) -> serde_json::Value {
+        let id = RequestId::from(self.next_id);
+        self.next_id += 1;
+        self.conn.sender.send(Request::new(id.clone(), method.into(), params).into()).unwrap();
+        loop {
+            match self.conn.receiver.recv_timeout(TIMEOUT).unwrap() {
+                Message::Response(resp) if resp.id == id => {
+                    return resp.result.unwrap_or_else(|| json!({ "error": resp.error.unwrap().message }));
+                }
+                Message::Request(req) => self.handle_request(req),
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait until the server has processed everything we've sent.
+    fn sync(&mut self) {
+        let result = self.request("dt/sync", json!(null));
+        assert!(result["error"].is_string());
+    }
+
+    fn open(&mut self, path: &str, text: &str) {
+        self.uri = format!("file:///work/{path}");
+        self.text = text.into();
+        self.notify(DidOpenTextDocument::METHOD, json!({
+            "textDocument": { "uri": self.uri, "languageId": "plaintext", "version": 0, "text": text },
+        }));
+        self.sync();
+    }
+
+    fn edit(&mut self, range: Range, text: &str) {
+        let start = position_to_offset(self.text.chars(), range.start, PosUnit::Utf16);
+        let end = position_to_offset(self.text.chars(), range.end, PosUnit::Utf16);
+        replace_chars(&mut self.text, start..end, text);
+        self.version += 1;
+        self.notify(DidChangeTextDocument::METHOD, json!({
+            "textDocument": { "uri": self.uri, "version": self.version },
+            "contentChanges": [{ "range": range, "text": text }],
+        }));
+    }
+
+    fn handle_request(&mut self, req: Request) {
+        assert_eq!(req.method, ApplyWorkspaceEdit::METHOD);
+        let params: ApplyWorkspaceEditParams = serde_json::from_value(req.params).unwrap();
+        let Some(DocumentChanges::Edits(edits)) = params.edit.document_changes else { panic!("Expected edits") };
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].text_document.uri.as_str(), self.uri);
+
+        let applied = edits[0].text_document.version == Some(self.version);
+        let resp = ApplyWorkspaceEditResponse { applied, failure_reason: None, failed_change: None };
+        self.conn.sender.send(Response::new_ok(req.id, resp).into()).unwrap();
+
+        if applied {
+            for edit in edits[0].edits.iter() {
+                let OneOf::Left(edit) = edit else { panic!("Unexpected annotated edit") };
+                self.edit(edit.range, &edit.new_text);
+            }
+        }
+    }
+
+    /// Handle requests from the server until the document has the expected content.
+    fn wait_for(&mut self, expected: &str) {
+        while self.text != expected {
+            match self.conn.receiver.recv_timeout(TIMEOUT).unwrap() {
+                Message::Request(req) => self.handle_request(req),
+                msg => panic!("Unexpected message {msg:?}"),
+            }
+        }
+    }
+
+    fn stop(mut self) {
+        self.request(Shutdown::METHOD, json!(null));
+        self.notify(Exit::METHOD, json!(null));
+        self.server.take().unwrap().join().unwrap();
+    }
+}
+
+fn session_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dt-lsp-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn pos(line: u32, character: u32) -> Position {
+    Position::new(line, character)
+}
+
+#[test]
+fn edits_are_shared_between_editors() {
+    let dir = session_dir("shared");
+    let mut a = Editor::start(&dir, "alice");
+    let mut b = Editor::start(&dir, "bob");
+
+    a.open("notes.txt", "hello\nworld");
+    b.open("notes.txt", "hello\nworld");
+
+    // Positions after the emoji are 2 UTF-16 code units further along.
+    a.edit(Range::new(pos(0, 0), pos(0, 0)), "😀");
+    b.wait_for("😀hello\nworld");
+    a.edit(Range::new(pos(0, 7), pos(0, 7)), "!");
+    b.wait_for("😀hello!\nworld");
+
+    b.edit(Range::new(pos(1, 0), pos(1, 5)), "there");
+    a.wait_for("😀hello!\nthere");
+
+    a.stop();
+    b.stop();
+
+    let mut oplog = ListOpLog::new();
+    for peer in ["alice", "bob"] {
+        let file = File::open(dir.join("notes.txt").join(format!("{peer}.dt"))).unwrap();
+        oplog.decode_and_add_from_reader(file, |_| {}).unwrap();
+    }
+    assert_eq!(oplog.checkout_tip().content().to_string(), "😀hello!\nthere");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn concurrent_edits_converge() {
+    let dir = session_dir("concurrent");
+    let mut a = Editor::start(&dir, "alice");
+    let mut b = Editor::start(&dir, "bob");
+
+    a.open("doc.txt", "abc");
+    b.open("doc.txt", "abc");
+
+    a.edit(Range::new(pos(0, 3), pos(0, 3)), "ü");
+    b.edit(Range::new(pos(0, 0), pos(0, 1)), "");
+    a.wait_for("bcü");
+    b.wait_for("bcü");
+
+    a.stop();
+    b.stop();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stale_editor_content_is_replaced() {
+    let dir = session_dir("stale");
+    let mut a = Editor::start(&dir, "alice");
+    a.open("doc.txt", "from the session");
+
+    let mut b = Editor::start(&dir, "bob");
+    b.open("doc.txt", "old");
+    b.wait_for("from the session");
+
+    b.edit(Range::new(pos(0, 0), pos(0, 4)), "From");
+    a.wait_for("From the session");
+
+    a.stop();
+    b.stop();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn saves_append_to_session_files() {
+    let dir = session_dir("append");
+    let alice = SessionDir::new(&dir, "alice");
+    let path = dir.join("doc.txt").join("alice.dt");
+
+    let mut a = ListOpLog::new();
+    let mut a_files = alice.open(Path::new("doc.txt"), &mut a).unwrap();
+    let mut b = ListOpLog::new();
+    let mut b_files = SessionDir::new(&dir, "bob").open(Path::new("doc.txt"), &mut b).unwrap();
+
+    let agent = a.get_or_create_agent_id("alice");
+    a.add_insert(agent, 0, "hello");
+    a_files.push(&a).unwrap();
+    let saved = std::fs::read(&path).unwrap();
+    assert!(b_files.pull(&mut b).unwrap());
+
+    a.add_insert(agent, 5, " world");
+    a_files.push(&a).unwrap();
+    let appended = std::fs::read(&path).unwrap();
+    assert!(appended.len() > saved.len() && appended.starts_with(&saved));
+    assert!(b_files.pull(&mut b).unwrap());
+    assert!(!b_files.pull(&mut b).unwrap());
+    assert_eq!(b.checkout_tip().content().to_string(), "hello world");
+
+    // Alice restarts and keeps appending to her file. Bob sees a patch which is only partly
+    // written once the rest of it arrives.
+    let mut a = ListOpLog::new();
+    let _ = alice.open(Path::new("doc.txt"), &mut a).unwrap();
+    assert_eq!(a.checkout_tip().content().to_string(), "hello world");
+    let version = a.local_frontier();
+    a.add_insert(agent, 11, "!");
+    let patch = a.encode_from(&EncodeOptions::patch(), version.as_ref());
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&patch[..patch.len() / 2]).unwrap();
+    assert!(!b_files.pull(&mut b).unwrap());
+    file.write_all(&patch[patch.len() / 2..]).unwrap();
+    assert!(b_files.pull(&mut b).unwrap());
+    assert_eq!(b.checkout_tip().content().to_string(), "hello world!");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
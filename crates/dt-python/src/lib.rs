@@ -0,0 +1,459 @@
+//! Python bindings for diamond types' plain text CRDT.
+//!
+//! The module is called `diamond_types`. It exposes [`ListOpLog`] and [`ListBranch`] as python
+//! classes, along with [`EncodeOptions`] for saving. All positions are in unicode characters
+//! (python string indexes). Local versions are lists of integers, and remote versions are lists
+//! of `(agent, seq)` tuples.
+//!
+//! Decoding errors raise `diamond_types.ParseError`. Unknown versions raise `ValueError`, and
+//! positions past the end of the document raise `IndexError`.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::ops::Range;
+use crdt_testdata::TestData;
+use flate2::bufread::GzDecoder;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use diamond_types::list::{ListBranch as DTListBranch, ListOpLog as DTListOpLog};
+use diamond_types::list::encoding::{EncodeOptions as DTEncodeOptions, ParseError as DTParseError};
+use diamond_types::list::operation::{ListOpKind, TextOperation};
+use diamond_types::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+use diamond_types::{Frontier, LV};
+
+create_exception!(diamond_types, ParseError, PyException, "Raised when diamond types data can't be decoded.");
+
+fn parse_err(e: DTParseError) -> PyErr {
+    // The message is the name of the error variant, eg "InvalidMagic" or "ChecksumFailed".
+    ParseError::new_err(format!("{:?}", e))
+}
+
+/// Check that every entry in a version passed in from python is known by the oplog, and reduce it
+/// to its dominators.
+fn local_version(oplog: &DTListOpLog, version: &[LV]) -> PyResult<Frontier> {
+    match version.iter().find(|v| **v >= oplog.len()) {
+        Some(v) => Err(PyValueError::new_err(format!("Unknown version {v} (oplog length {})", oplog.len()))),
+        None => Ok(oplog.cg.graph.find_dominators(version)),
+    }
+}
+
+fn check_range(range: &Range<usize>, doc_len: usize) -> PyResult<()> {
+    if range.start > range.end || range.end > doc_len {
+        Err(PyIndexError::new_err(format!("Range {}..{} is out of bounds (document length {doc_len})", range.start, range.end)))
+    } else {
+        Ok(())
+    }
+}
+
+fn op_to_py<'py>(py: Python<'py>, op: &TextOperation) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("kind", match op.kind {
+        ListOpKind::Ins => "ins",
+        ListOpKind::Del => "del",
+    })?;
+    dict.set_item("start", op.start())?;
+    dict.set_item("end", op.end())?;
+    dict.set_item("fwd", op.loc.fwd)?;
+    dict.set_item("content", op.content_as_str())?;
+    Ok(dict)
+}
+
+/// Read an editing trace in the crdt-testdata format. IO errors raise `OSError`, and files which
+/// aren't valid traces raise `ValueError`.
+fn read_trace(path: &str) -> PyResult<TestData> {
+    let mut json = vec![];
+    GzDecoder::new(BufReader::new(File::open(path)?))
+        .read_to_end(&mut json)
+        .map_err(|e| PyValueError::new_err(format!("Could not decompress trace: {e}")))?;
+    serde_json::from_slice(&json)
+        .map_err(|e| PyValueError::new_err(format!("Invalid trace: {e}")))
+}
+
+/// A transformed operation from `iter_xf_operations_from`.
+type XfOp<'py> = (LV, LV, Option<Bound<'py, PyDict>>);
+
+/// Options for `ListOpLog.encode()` and `ListOpLog.save()`. The defaults store everything needed
+/// to load the document again.
+#[pyclass(module = "diamond_types")]
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    #[pyo3(get, set)]
+    pub user_data: Option<Vec<u8>>,
+    #[pyo3(get, set)]
+    pub store_start_branch_content: bool,
+    #[pyo3(get, set)]
+    pub store_inserted_content: bool,
+    #[pyo3(get, set)]
+    pub store_deleted_content: bool,
+    #[pyo3(get, set)]
+    pub compress_content: bool,
+    #[pyo3(get, set)]
+    pub verbose: bool,
+}
+
+impl EncodeOptions {
+    fn to_dt(&self) -> DTEncodeOptions<'_> {
+        let opts = DTEncodeOptions::full()
+            .store_start_branch_content(self.store_start_branch_content)
+            .store_inserted_content(self.store_inserted_content)
+            .store_deleted_content(self.store_deleted_content)
+            .compress_content(self.compress_content)
+            .verbose(self.verbose);
+
+        match &self.user_data {
+            Some(data) => opts.user_data(data),
+            None => opts,
+        }
+    }
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+#[pymethods]
+impl EncodeOptions {
+    #[new]
+    #[pyo3(signature = (*, user_data=None, store_start_branch_content=true, store_inserted_content=true, store_deleted_content=false, compress_content=true, verbose=false))]
+    fn new(user_data: Option<Vec<u8>>, store_start_branch_content: bool, store_inserted_content: bool, store_deleted_content: bool, compress_content: bool, verbose: bool) -> Self {
+        Self { user_data, store_start_branch_content, store_inserted_content, store_deleted_content, compress_content, verbose }
+    }
+
+    /// Options for a full save of a document.
+    #[staticmethod]
+    pub fn full() -> Self {
+        Self::new(None, true, true, false, true, false)
+    }
+
+    /// Options for a patch to send to a peer which already has some of the document.
+    #[staticmethod]
+    pub fn patch() -> Self {
+        Self::new(None, false, true, false, true, false)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// The history of a document. This stores every change made to the document, and can produce the
+/// document's contents at any version.
+#[pyclass(module = "diamond_types", unsendable)]
+#[derive(Debug, Default)]
+pub struct ListOpLog {
+    pub inner: DTListOpLog,
+
+    /// A checkout of the oplog at some version, used to bounds check edits. This is moved to the
+    /// version being edited on demand.
+    branch: DTListBranch,
+}
+
+impl ListOpLog {
+    fn encode_opts(&self, opts: Option<PyRef<EncodeOptions>>, from: &[LV]) -> PyResult<Vec<u8>> {
+        let from = local_version(&self.inner, from)?;
+        Ok(match opts {
+            Some(opts) => self.inner.encode_from(&opts.to_dt(), from.as_ref()),
+            None => self.inner.encode_from(&DTEncodeOptions::full(), from.as_ref()),
+        })
+    }
+
+    fn wrap(inner: DTListOpLog) -> Self {
+        Self { inner, branch: DTListBranch::new() }
+    }
+
+    /// The length of the document at `version`. When `version` is ahead of the cached branch (eg
+    /// when editing at the current version), the branch is merged forward, which only replays the
+    /// new operations. Otherwise the document is checked out from scratch.
+    fn len_at(&mut self, version: &[LV]) -> usize {
+        let branch_version = self.branch.local_frontier_ref();
+        if branch_version != version {
+            if self.inner.cg.graph.frontier_contains_frontier(version, branch_version) {
+                self.branch.merge(&self.inner, version);
+            } else {
+                self.branch = self.inner.checkout(version);
+            }
+        }
+        self.branch.len()
+    }
+
+    fn parents_or_tip(&self, parents: Option<Vec<LV>>) -> PyResult<Frontier> {
+        match parents {
+            Some(parents) => local_version(&self.inner, &parents),
+            None => Ok(self.inner.local_frontier_ref().into()),
+        }
+    }
+}
+
+#[pymethods]
+impl ListOpLog {
+    #[new]
+    pub fn new() -> Self {
+        Self::wrap(DTListOpLog::new())
+    }
+
+    /// Load a `.dt` file from disk.
+    #[staticmethod]
+    pub fn load(path: &str) -> PyResult<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Load an oplog from bytes produced by `encode()`.
+    #[staticmethod]
+    pub fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        DTListOpLog::load_from(data).map(Self::wrap).map_err(parse_err)
+    }
+
+    /// Build an oplog by replaying one of the editing traces in the crdt-testdata format (a
+    /// gzipped JSON file). Every change is made by a single agent named "trace".
+    #[staticmethod]
+    pub fn from_trace(path: &str) -> PyResult<Self> {
+        let data = read_trace(path)?;
+        let mut oplog = DTListOpLog::new();
+        let agent = oplog.get_or_create_agent_id("trace");
+
+        if !data.start_content.is_empty() {
+            oplog.add_insert(agent, 0, &data.start_content);
+        }
+        for txn in &data.txns {
+            for patch in &txn.patches {
+                if patch.1 > 0 {
+                    oplog.add_delete_without_content(agent, patch.0..patch.0 + patch.1);
+                }
+                if !patch.2.is_empty() {
+                    oplog.add_insert(agent, patch.0, &patch.2);
+                }
+            }
+        }
+        Ok(Self::wrap(oplog))
+    }
+
+    /// Save the oplog to a `.dt` file.
+    #[pyo3(signature = (path, opts=None))]
+    pub fn save(&self, path: &str, opts: Option<PyRef<EncodeOptions>>) -> PyResult<()> {
+        let bytes = self.encode_opts(opts, &[])?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    #[pyo3(signature = (opts=None))]
+    pub fn encode<'py>(&self, py: Python<'py>, opts: Option<PyRef<EncodeOptions>>) -> PyResult<Bound<'py, PyBytes>> {
+        Ok(PyBytes::new(py, &self.encode_opts(opts, &[])?))
+    }
+
+    /// Encode the changes made since `version`. Uses `EncodeOptions.patch()` by default.
+    #[pyo3(signature = (version, opts=None))]
+    pub fn encode_from<'py>(&self, py: Python<'py>, version: Vec<LV>, opts: Option<PyRef<EncodeOptions>>) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = match opts {
+            Some(opts) => self.encode_opts(Some(opts), &version)?,
+            None => {
+                let version = local_version(&self.inner, &version)?;
+                self.inner.encode_from(&DTEncodeOptions::patch(), version.as_ref())
+            }
+        };
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    /// Merge changes from bytes produced by `encode()` or `encode_from()`. Returns the new local
+    /// version.
+    pub fn decode_and_add(&mut self, data: &[u8]) -> PyResult<Vec<LV>> {
+        self.inner.decode_and_add(data)
+            .map(|v| v.as_ref().to_vec())
+            .map_err(parse_err)
+    }
+
+    /// Insert `content` at `pos`. The change is made on top of `parents` (the current version by
+    /// default). Returns the local version of the last inserted character.
+    #[pyo3(signature = (agent, pos, content, parents=None))]
+    pub fn add_insert(&mut self, agent: &str, pos: usize, content: &str, parents: Option<Vec<LV>>) -> PyResult<LV> {
+        let parents = self.parents_or_tip(parents)?;
+        check_range(&(pos..pos), self.len_at(parents.as_ref()))?;
+        let agent = self.inner.get_or_create_agent_id(agent);
+        Ok(self.inner.add_insert_at(agent, parents.as_ref(), pos, content))
+    }
+
+    /// Delete the characters from `start` to `end`. The change is made on top of `parents` (the
+    /// current version by default).
+    #[pyo3(signature = (agent, start, end, parents=None))]
+    pub fn add_delete(&mut self, agent: &str, start: usize, end: usize, parents: Option<Vec<LV>>) -> PyResult<LV> {
+        let parents = self.parents_or_tip(parents)?;
+        check_range(&(start..end), self.len_at(parents.as_ref()))?;
+        let agent = self.inner.get_or_create_agent_id(agent);
+        Ok(self.inner.add_delete_at(agent, parents.as_ref(), start..end))
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// The current version of the oplog.
+    #[getter]
+    pub fn local_version(&self) -> Vec<LV> {
+        self.inner.local_frontier_ref().to_vec()
+    }
+
+    /// The current version, named by (agent, seq) pairs which mean the same thing on every peer.
+    pub fn remote_frontier(&self) -> Vec<(String, usize)> {
+        self.inner.remote_frontier().iter()
+            .map(|RemoteVersion(agent, seq)| (agent.to_string(), *seq))
+            .collect()
+    }
+
+    pub fn local_to_remote_version(&self, version: Vec<LV>) -> PyResult<Vec<(String, usize)>> {
+        let version = local_version(&self.inner, &version)?;
+        Ok(self.inner.cg.agent_assignment.local_to_remote_frontier(version.as_ref()).iter()
+            .map(|RemoteVersion(agent, seq)| (agent.to_string(), *seq))
+            .collect())
+    }
+
+    pub fn remote_to_local_version(&self, version: Vec<(String, usize)>) -> PyResult<Vec<LV>> {
+        let mut result = Vec::with_capacity(version.len());
+        for (agent, seq) in &version {
+            let lv = self.inner.cg.agent_assignment.try_remote_to_local_version(RemoteVersion(agent, *seq))
+                .map_err(|e| PyValueError::new_err(format!("Unknown version ({agent}, {seq}): {:?}", e)))?;
+            result.push(lv);
+        }
+        Ok(self.inner.cg.graph.find_dominators(&result).as_ref().to_vec())
+    }
+
+    /// Every operation in the oplog, as dicts with `kind` ("ins" or "del"), `start`, `end`, `fwd`
+    /// and `content` keys.
+    pub fn iter_ops<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.inner.iter_ops().map(|op| op_to_py(py, &op)).collect()
+    }
+
+    /// The causal graph, as dicts with `start`, `end` and `parents` keys.
+    pub fn iter_history<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.inner.iter_history().map(|entry| {
+            let dict = PyDict::new(py);
+            dict.set_item("start", entry.span.start)?;
+            dict.set_item("end", entry.span.end)?;
+            dict.set_item("parents", entry.parents.as_ref().to_vec())?;
+            Ok(dict)
+        }).collect()
+    }
+
+    /// The operations needed to move a document at version `from` to include the changes in
+    /// `merging` (the current version by default). Yields `(start, end, op)` tuples, where `start`
+    /// and `end` are the local versions of the operation and `op` is `None` if the operation has
+    /// no effect on the document.
+    #[pyo3(signature = (from_version, merging=None))]
+    pub fn iter_xf_operations_from<'py>(&self, py: Python<'py>, from_version: Vec<LV>, merging: Option<Vec<LV>>) -> PyResult<Vec<XfOp<'py>>> {
+        let from = local_version(&self.inner, &from_version)?;
+        let merging = self.parents_or_tip(merging)?;
+        self.inner.iter_xf_operations_from(from.as_ref(), merging.as_ref())
+            .map(|(span, op)| {
+                let op = op.map(|op| op_to_py(py, &op)).transpose()?;
+                Ok((span.start, span.end, op))
+            })
+            .collect()
+    }
+
+    /// The document at `version` (the current version by default).
+    #[pyo3(signature = (version=None))]
+    pub fn checkout(&self, version: Option<Vec<LV>>) -> PyResult<ListBranch> {
+        let version = self.parents_or_tip(version)?;
+        Ok(ListBranch(self.inner.checkout(version.as_ref())))
+    }
+
+    pub fn checkout_tip(&self) -> ListBranch {
+        ListBranch(self.inner.checkout_tip())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<ListOpLog len={} version={:?}>", self.inner.len(), self.inner.local_frontier_ref())
+    }
+}
+
+/// The contents of a document at some version.
+#[pyclass(module = "diamond_types", unsendable)]
+#[derive(Debug, Default)]
+pub struct ListBranch(pub DTListBranch);
+
+#[pymethods]
+impl ListBranch {
+    #[new]
+    pub fn new() -> Self {
+        Self(DTListBranch::new())
+    }
+
+    #[getter]
+    pub fn content(&self) -> String {
+        self.0.content().to_string()
+    }
+
+    #[getter]
+    pub fn local_version(&self) -> Vec<LV> {
+        self.0.local_frontier_ref().to_vec()
+    }
+
+    pub fn remote_frontier(&self, oplog: &ListOpLog) -> Vec<(String, usize)> {
+        self.0.remote_frontier(&oplog.inner).iter()
+            .map(|RemoteVersion(agent, seq)| (agent.to_string(), *seq))
+            .collect()
+    }
+
+    /// Merge changes from the oplog, up to `version` (the oplog's current version by default).
+    #[pyo3(signature = (oplog, version=None))]
+    pub fn merge(&mut self, oplog: &ListOpLog, version: Option<Vec<LV>>) -> PyResult<()> {
+        let version = oplog.parents_or_tip(version)?;
+        self.0.merge(&oplog.inner, version.as_ref());
+        Ok(())
+    }
+
+    /// Insert `content` at `pos`, recording the change in the oplog.
+    pub fn insert(&mut self, oplog: &mut ListOpLog, agent: &str, pos: usize, content: &str) -> PyResult<LV> {
+        check_range(&(pos..pos), self.0.len())?;
+        let agent = oplog.inner.get_or_create_agent_id(agent);
+        Ok(self.0.insert(&mut oplog.inner, agent, pos, content))
+    }
+
+    /// Delete the characters from `start` to `end`, recording the change in the oplog.
+    pub fn delete(&mut self, oplog: &mut ListOpLog, agent: &str, start: usize, end: usize) -> PyResult<LV> {
+        check_range(&(start..end), self.0.len())?;
+        let agent = oplog.inner.get_or_create_agent_id(agent);
+        Ok(self.0.delete(&mut oplog.inner, agent, start..end))
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn __str__(&self) -> String {
+        self.content()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<ListBranch len={} version={:?}>", self.0.len(), self.0.local_frontier_ref())
+    }
+}
+
+/// Load an editing trace in the crdt-testdata format (a gzipped JSON file) as a dict with
+/// `start_content`, `end_content` and `txns` keys. Each txn is a list of `(pos, del, ins)` patches.
+#[pyfunction]
+pub fn load_trace<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    let data = read_trace(path)?;
+    let dict = PyDict::new(py);
+    dict.set_item("start_content", &data.start_content)?;
+    dict.set_item("end_content", &data.end_content)?;
+    let txns: Vec<Vec<(usize, usize, &str)>> = data.txns.iter()
+        .map(|txn| txn.patches.iter().map(|p| (p.0, p.1, p.2.as_str())).collect())
+        .collect();
+    dict.set_item("txns", txns)?;
+    Ok(dict)
+}
+
+#[pymodule]
+#[pyo3(name = "diamond_types")]
+fn py_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<ListOpLog>()?;
+    m.add_class::<ListBranch>()?;
+    m.add_class::<EncodeOptions>()?;
+    m.add_function(wrap_pyfunction!(load_trace, m)?)?;
+    m.add("ParseError", m.py().get_type::<ParseError>())?;
+    Ok(())
+}
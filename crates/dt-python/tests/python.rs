@@ -0,0 +1,36 @@
+//! Run tests/test_diamond_types.py against the compiled module. This needs a python interpreter,
+//! found through $PYTHON (or `python3`).
+
+#![cfg(target_os = "linux")]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn python_tests() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    // Integration tests live in target/<profile>/deps, alongside the shared library. (The copy in
+    // target/<profile> is only refreshed by `cargo build`.)
+    let lib = env::current_exe().unwrap()
+        .parent().unwrap()
+        .join("libdt_python.so");
+
+    // Python only imports extension modules named after the module.
+    let module_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("dt_python");
+    fs::create_dir_all(&module_dir).unwrap();
+    fs::copy(&lib, module_dir.join("diamond_types.so")).unwrap();
+
+    let python = env::var("PYTHON").unwrap_or_else(|_| "python3".into());
+    let output = Command::new(&python)
+        .arg(crate_dir.join("tests/test_diamond_types.py"))
+        .env("PYTHONPATH", &module_dir)
+        .env("DT_BENCHMARK_DATA", crate_dir.join("../../benchmark_data"))
+        .output()
+        .expect("Could not run python");
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "Python tests failed");
+}
@@ -0,0 +1,134 @@
+"""Tests for the python bindings. Run through `cargo test -p dt-python`, which builds the module
+and puts it on PYTHONPATH. DT_BENCHMARK_DATA points to the repository's benchmark_data folder."""
+
+import os
+import tempfile
+import unittest
+
+import diamond_types as dt
+
+DATA = os.environ.get("DT_BENCHMARK_DATA", os.path.join(os.path.dirname(__file__), "../../../benchmark_data"))
+
+
+class ListOpLogTest(unittest.TestCase):
+    def test_edit_and_checkout(self):
+        oplog = dt.ListOpLog()
+        oplog.add_insert("seph", 0, "hi there")
+        oplog.add_delete("seph", 2, 8)
+        self.assertEqual(len(oplog), 14)
+        self.assertEqual(oplog.local_version, [13])
+        self.assertEqual(oplog.checkout().content, "hi")
+        self.assertEqual(str(oplog.checkout([7])), "hi there")
+        self.assertEqual(oplog.remote_frontier(), [("seph", 13)])
+
+    def test_concurrent_edits(self):
+        oplog = dt.ListOpLog()
+        oplog.add_insert("a", 0, "aaa")
+        oplog.add_insert("b", 0, "bbb", parents=[])
+        self.assertEqual(sorted(oplog.local_version), [2, 5])
+        self.assertEqual(oplog.local_to_remote_version([2]), [("a", 2)])
+        self.assertEqual(oplog.remote_to_local_version([("a", 2), ("b", 2)]), [2, 5])
+
+        history = oplog.iter_history()
+        self.assertEqual(history, [
+            {"start": 0, "end": 3, "parents": []},
+            {"start": 3, "end": 6, "parents": []},
+        ])
+
+        ops = oplog.iter_ops()
+        self.assertEqual([op["kind"] for op in ops], ["ins", "ins"])
+        self.assertEqual(ops[1]["content"], "bbb")
+
+        # Transform b's insert so it applies on top of a's document.
+        xf = oplog.iter_xf_operations_from([2])
+        self.assertEqual(len(xf), 1)
+        start, end, op = xf[0]
+        self.assertEqual((start, end), (3, 6))
+        self.assertEqual(op["kind"], "ins")
+        self.assertEqual(len(oplog.checkout().content), 6)
+
+    def test_errors(self):
+        oplog = dt.ListOpLog()
+        oplog.add_insert("seph", 0, "abc")
+        with self.assertRaises(IndexError):
+            oplog.add_insert("seph", 4, "x")
+        with self.assertRaises(IndexError):
+            oplog.add_delete("seph", 2, 1)
+        with self.assertRaises(ValueError):
+            oplog.checkout([3])
+        with self.assertRaises(ValueError):
+            oplog.remote_to_local_version([("nobody", 0)])
+        with self.assertRaises(dt.ParseError):
+            dt.ListOpLog.from_bytes(b"not a diamond types file")
+
+    def test_encode_and_merge(self):
+        a = dt.ListOpLog()
+        a.add_insert("a", 0, "hello")
+        b = dt.ListOpLog.from_bytes(a.encode())
+        self.assertEqual(b.checkout().content, "hello")
+
+        v = b.local_version
+        b.add_insert("b", 5, " world")
+        patch = b.encode_from(v)
+        self.assertEqual(a.decode_and_add(patch), [10])
+        self.assertEqual(a.checkout().content, "hello world")
+
+        opts = dt.EncodeOptions(user_data=b"hi", store_deleted_content=True)
+        self.assertEqual(opts.user_data, b"hi")
+        self.assertFalse(dt.EncodeOptions.patch().store_start_branch_content)
+        self.assertEqual(dt.ListOpLog.from_bytes(a.encode(opts)).checkout().content, "hello world")
+
+    def test_load_and_save(self):
+        oplog = dt.ListOpLog.load(os.path.join(DATA, "friendsforever.dt"))
+        with tempfile.TemporaryDirectory() as d:
+            path = os.path.join(d, "doc.dt")
+            oplog.save(path)
+            loaded = dt.ListOpLog.load(path)
+        self.assertEqual(len(loaded), len(oplog))
+        self.assertEqual(loaded.checkout().content, oplog.checkout().content)
+
+    def test_trace(self):
+        path = os.path.join(DATA, "friendsforever_flat.json.gz")
+        trace = dt.load_trace(path)
+        oplog = dt.ListOpLog.from_trace(path)
+        self.assertEqual(oplog.checkout().content, trace["end_content"])
+
+    def test_trace_errors(self):
+        with tempfile.TemporaryDirectory() as d:
+            with self.assertRaises(OSError):
+                dt.load_trace(os.path.join(d, "missing.json.gz"))
+            path = os.path.join(d, "bad.json.gz")
+            with open(path, "wb") as f:
+                f.write(b"not a trace")
+            with self.assertRaises(ValueError):
+                dt.ListOpLog.from_trace(path)
+
+
+class ListBranchTest(unittest.TestCase):
+    def test_edit(self):
+        oplog = dt.ListOpLog()
+        branch = dt.ListBranch()
+        branch.insert(oplog, "seph", 0, "hi 😀 there")
+        branch.delete(oplog, "seph", 2, 4)
+        self.assertEqual(str(branch), "hi there")
+        self.assertEqual(len(branch), 8)
+        self.assertEqual(branch.local_version, oplog.local_version)
+        self.assertEqual(branch.remote_frontier(oplog), [("seph", 11)])
+        with self.assertRaises(IndexError):
+            branch.insert(oplog, "seph", 9, "x")
+
+    def test_merge(self):
+        oplog = dt.ListOpLog()
+        oplog.add_insert("seph", 0, "abc")
+        oplog.add_insert("seph", 3, "def")
+        branch = dt.ListBranch()
+        branch.merge(oplog, [2])
+        self.assertEqual(branch.content, "abc")
+        branch.merge(oplog)
+        self.assertEqual(branch.content, "abcdef")
+        with self.assertRaises(ValueError):
+            branch.merge(oplog, [100])
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,114 +1,667 @@
-use diamond_types::AgentId;
-use diamond_types::list::{ListCRDT as InnerListCRDT};
-use diamond_types::list::encoding::ENCODE_FULL;
+//! Swift bindings for diamond types, generated by swift-bridge.
+//!
+//! All document positions passed to and from swift are in UTF-16 code units (the same units used
+//! by `NSString` and `UITextView`).
+
+use std::io::ErrorKind;
+use diamond_types::{AgentId, Frontier, LV};
+use diamond_types::causalgraph::agent_assignment::remote_ids::{RemoteVersion as DTRemoteVersion, RemoteVersionOwned, VersionConversionError};
+use diamond_types::list::{ListBranch, ListCRDT as InnerListCRDT, ListOpLog};
+use diamond_types::list::encoding::{ENCODE_FULL, ENCODE_PATCH, ParseError};
+use diamond_types::list::operation::ListOpKind;
+use diamond_types::list::pos_unit::PosUnit;
 use rand::{distributions::Alphanumeric, Rng};
+use crate::ffi::DTError;
 
 #[swift_bridge::bridge]
 mod ffi {
+    // Errors thrown to swift. Most of these mirror the variants of diamond types' ParseError.
+    // (Swift code needs `extension DTError: Error {}` to be able to catch these - see
+    // swift/DTError.swift.)
+    #[derive(Debug)]
+    enum DTError {
+        InvalidMagic,
+        UnsupportedProtocolVersion,
+        DocIdMismatch,
+        BaseVersionUnknown,
+        UnknownChunk,
+        LZ4DecoderNeeded,
+        LZ4DecompressionError,
+        CompressedDataMissing,
+        InvalidChunkHeader,
+        MissingChunk,
+        InvalidLength,
+        UnexpectedEOF,
+        InvalidUTF8,
+        InvalidRemoteID,
+        InvalidVarInt,
+        InvalidContent,
+        GenericInvalidData,
+        ChecksumFailed,
+        DataMissing,
+        OperationRejected,
+        OperationOutOfBounds,
+        InvalidSignature,
+        MissingSignature,
+        Equivocation,
+        IdReused,
+
+        // An edit named a position past the end of the document.
+        OutOfBounds,
+        // Reading or writing a file failed. Contains the message from the OS.
+        IOError(String),
+        // A local version names an operation which isn't in the oplog.
+        InvalidVersion,
+    }
+
+    // A document with its oplog and a checkout of the current version.
     extern "Rust" {
         type ListCRDT;
 
         #[swift_bridge(init)]
         fn new() -> ListCRDT;
-        // #[swift_bridge(init)]
-        // fn new(agent_name: Option<&str>) -> ListCRDT;
+        #[swift_bridge(init)]
+        fn new_with_agent(agent_name: &str) -> ListCRDT;
+
+        pub fn set_agent(&mut self, agent_name: &str);
 
-        pub fn replace_wchar(&mut self, wchar_pos: usize, remove: usize, ins: &str);
+        pub fn replace_wchar(&mut self, wchar_pos: usize, remove: usize, ins: &str) -> Result<(), DTError>;
+        pub fn len_wchars(&self) -> usize;
 
         pub fn encode(&self) -> Vec<u8>;
-        pub fn save(&self, path: &str);
+        pub fn save(&self, path: &str) -> Result<(), DTError>;
 
         pub fn to_string(&self) -> String;
 
-        fn decode(bytes: &[u8]) -> ListCRDT;
-        fn load_or_new(path: &str) -> ListCRDT;
+        pub fn local_version(&self) -> Vec<usize>;
+        pub fn remote_version(&self) -> RemoteVersion;
+        pub fn local_to_remote_version(&self, version: &[usize]) -> Result<RemoteVersion, DTError>;
+        pub fn remote_to_local_version(&self, version: &RemoteVersion) -> Option<Vec<usize>>;
+
+        pub fn get_patch_since(&self, version: &[usize]) -> Option<Vec<u8>>;
+        pub fn merge_bytes(&mut self, bytes: &[u8]) -> Result<(), DTError>;
+        pub fn xf_since(&self, version: &[usize]) -> Option<Vec<TextOp>>;
+
+        fn decode(bytes: &[u8]) -> Result<ListCRDT, DTError>;
+        fn load_or_new(path: &str) -> Result<ListCRDT, DTError>;
     }
 
-}
+    // The set of operations in a document, without a checkout of its contents.
+    extern "Rust" {
+        type OpLog;
+
+        #[swift_bridge(init)]
+        fn new() -> OpLog;
+        #[swift_bridge(init)]
+        fn new_with_agent(agent_name: &str) -> OpLog;
+        #[swift_bridge(associated_to = OpLog)]
+        fn from_bytes(bytes: &[u8]) -> Result<OpLog, DTError>;
 
+        pub fn set_agent(&mut self, agent_name: &str);
+        pub fn len(&self) -> usize;
+
+        pub fn ins(&mut self, wchar_pos: usize, content: &str) -> Result<usize, DTError>;
+        pub fn del(&mut self, wchar_pos: usize, len: usize) -> Result<usize, DTError>;
+
+        pub fn checkout(&self) -> Branch;
+
+        pub fn local_version(&self) -> Vec<usize>;
+        pub fn remote_version(&self) -> RemoteVersion;
+        pub fn local_to_remote_version(&self, version: &[usize]) -> Result<RemoteVersion, DTError>;
+        pub fn remote_to_local_version(&self, version: &RemoteVersion) -> Option<Vec<usize>>;
+        pub fn merge_versions(&self, a: &[usize], b: &[usize]) -> Option<Vec<usize>>;
+
+        pub fn encode(&self) -> Vec<u8>;
+        pub fn get_patch_since(&self, version: &[usize]) -> Option<Vec<u8>>;
+        pub fn add_from_bytes(&mut self, bytes: &[u8]) -> Result<(), DTError>;
+        pub fn xf_since(&self, version: &[usize]) -> Option<Vec<TextOp>>;
+    }
+
+    // A checkout of an oplog at some version.
+    extern "Rust" {
+        type Branch;
+
+        #[swift_bridge(init)]
+        fn new() -> Branch;
+
+        pub fn merge(&mut self, oplog: &OpLog);
+        pub fn merge_at(&mut self, oplog: &OpLog, version: &[usize]) -> Result<(), DTError>;
+
+        pub fn to_string(&self) -> String;
+        pub fn len_wchars(&self) -> usize;
+        pub fn local_version(&self) -> Vec<usize>;
+        pub fn remote_version(&self, oplog: &OpLog) -> RemoteVersion;
+    }
+
+    // A version named by (agent, seq) pairs. Unlike local versions, remote versions mean the same
+    // thing on every peer.
+    extern "Rust" {
+        type RemoteVersion;
+
+        #[swift_bridge(init)]
+        fn new() -> RemoteVersion;
+
+        pub fn push(&mut self, agent: &str, seq: usize);
+        pub fn len(&self) -> usize;
+        pub fn agent(&self, idx: usize) -> String;
+        pub fn seq(&self, idx: usize) -> usize;
+    }
+
+    // A transformed operation, which can be applied in order to the document at the version the
+    // operations were requested from. Positions are in UTF-16 code units. Inserts replace the
+    // (empty) range start..start with content. Deletes remove start..end.
+    extern "Rust" {
+        type TextOp;
+
+        pub fn is_insert(&self) -> bool;
+        pub fn start(&self) -> usize;
+        pub fn end(&self) -> usize;
+        pub fn content(&self) -> String;
+    }
+}
 
 pub struct ListCRDT {
     inner: InnerListCRDT,
     agent_id: AgentId,
 }
 
+pub struct OpLog {
+    inner: ListOpLog,
+    agent_id: AgentId,
+
+    /// A checkout of the oplog, used to convert UTF-16 positions in local edits. This is brought
+    /// up to date on demand.
+    tip: ListBranch,
+}
+
+pub struct Branch(ListBranch);
+
+pub struct RemoteVersion(Vec<RemoteVersionOwned>);
+
+pub struct TextOp {
+    is_insert: bool,
+    start: usize,
+    end: usize,
+    content: String,
+}
+
+impl From<ParseError> for DTError {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::InvalidMagic => DTError::InvalidMagic,
+            ParseError::UnsupportedProtocolVersion => DTError::UnsupportedProtocolVersion,
+            ParseError::DocIdMismatch => DTError::DocIdMismatch,
+            ParseError::BaseVersionUnknown => DTError::BaseVersionUnknown,
+            ParseError::UnknownChunk => DTError::UnknownChunk,
+            ParseError::LZ4DecoderNeeded => DTError::LZ4DecoderNeeded,
+            ParseError::LZ4DecompressionError => DTError::LZ4DecompressionError,
+            ParseError::CompressedDataMissing => DTError::CompressedDataMissing,
+            ParseError::InvalidChunkHeader => DTError::InvalidChunkHeader,
+            ParseError::MissingChunk(_) => DTError::MissingChunk,
+            ParseError::InvalidLength => DTError::InvalidLength,
+            ParseError::UnexpectedEOF => DTError::UnexpectedEOF,
+            ParseError::InvalidUTF8 => DTError::InvalidUTF8,
+            ParseError::InvalidRemoteID(_) => DTError::InvalidRemoteID,
+            ParseError::InvalidVarInt => DTError::InvalidVarInt,
+            ParseError::InvalidContent => DTError::InvalidContent,
+            ParseError::GenericInvalidData => DTError::GenericInvalidData,
+            ParseError::ChecksumFailed => DTError::ChecksumFailed,
+            ParseError::DataMissing => DTError::DataMissing,
+            ParseError::OperationRejected => DTError::OperationRejected,
+            ParseError::OperationOutOfBounds => DTError::OperationOutOfBounds,
+            ParseError::InvalidSignature => DTError::InvalidSignature,
+            ParseError::MissingSignature => DTError::MissingSignature,
+            ParseError::Equivocation => DTError::Equivocation,
+            ParseError::IdReused { .. } => DTError::IdReused,
+            // ParseError is non_exhaustive. New variants should get their own DTError.
+            _ => DTError::GenericInvalidData,
+        }
+    }
+}
+
+impl From<std::io::Error> for DTError {
+    fn from(err: std::io::Error) -> Self {
+        DTError::IOError(err.to_string())
+    }
+}
+
+impl From<VersionConversionError> for DTError {
+    fn from(_err: VersionConversionError) -> Self {
+        DTError::InvalidRemoteID
+    }
+}
 
-fn create_agent(crdt: &mut InnerListCRDT) -> AgentId {
+fn create_agent(oplog: &mut ListOpLog) -> AgentId {
     let s: String = rand::thread_rng()
         .sample_iter(&Alphanumeric)
         .take(8)
         .map(char::from)
         .collect();
-    crdt.get_or_create_agent_id(&s)
-}
-// fn get_agent(crdt: &mut InnerListCRDT, agent_name: Option<&str>) -> AgentId {
-//     agent_name.map(|name| {
-//         crdt.get_or_create_agent_id(name)
-//     }).unwrap_or_else(|| {
-//         let s: String = rand::thread_rng()
-//             .sample_iter(&Alphanumeric)
-//             .take(8)
-//             .map(char::from)
-//             .collect();
-//         crdt.get_or_create_agent_id(&s)
-//     })
-// }
-
-pub fn decode(bytes: &[u8]) -> ListCRDT {
-    let mut inner = InnerListCRDT::load_from(bytes).expect("Failed to decode DT object from bytes");
-    let agent_id = create_agent(&mut inner);
-    ListCRDT { inner, agent_id }
-}
-fn load_or_new(path: &str) -> ListCRDT {
-    // TODO: Only make a new file if the error is ENOENT
-    std::fs::read(path)
-        .map(|data| decode(&data))
-        .unwrap_or_else(|err| {
-            eprintln!("Could not read file: {err}");
-            ListCRDT::new()
+    oplog.get_or_create_agent_id(&s)
+}
+
+fn check_wchar_range(branch: &ListBranch, wchar_pos: usize, len: usize) -> Result<(), DTError> {
+    let doc_len = branch.content().borrow().len_wchars();
+    if wchar_pos.checked_add(len).is_some_and(|end| end <= doc_len) {
+        Ok(())
+    } else {
+        Err(DTError::OutOfBounds)
+    }
+}
+
+// These functions are shared between ListCRDT and OpLog.
+
+/// Convert a version passed in from swift into a frontier, checking that all the named versions
+/// exist in the oplog.
+fn local_version(oplog: &ListOpLog, version: &[LV]) -> Result<Frontier, DTError> {
+    if version.iter().any(|v| *v >= oplog.len()) {
+        return Err(DTError::InvalidVersion);
+    }
+    // The frontier needs to be sorted and must not contain redundant versions.
+    Ok(oplog.cg.graph.find_dominators(version))
+}
+
+fn local_to_remote_version(oplog: &ListOpLog, version: &[LV]) -> Result<RemoteVersion, DTError> {
+    let version = local_version(oplog, version)?;
+    Ok(RemoteVersion(oplog.cg.agent_assignment.local_to_remote_frontier_owned(version.as_ref()).into_vec()))
+}
+
+fn remote_to_local_version(oplog: &ListOpLog, version: &RemoteVersion) -> Result<Vec<LV>, DTError> {
+    let version = version.0.iter()
+        .map(|rv| oplog.cg.agent_assignment.try_remote_to_local_version(DTRemoteVersion(&rv.0, rv.1)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(oplog.cg.graph.find_dominators(&version).0.into_vec())
+}
+
+fn get_patch_since(oplog: &ListOpLog, version: &[LV]) -> Result<Vec<u8>, DTError> {
+    let version = local_version(oplog, version)?;
+    Ok(oplog.encode_from(&ENCODE_PATCH, version.as_ref()))
+}
+
+/// Get the operations since some version, transformed so they can be applied in order to the
+/// document at that version. The positions in the returned operations are in UTF-16 code units.
+fn xf_since(oplog: &ListOpLog, version: &[LV]) -> Result<Vec<TextOp>, DTError> {
+    let version = local_version(oplog, version)?;
+
+    Ok(oplog.iter_xf_operations_from_in(version.as_ref(), oplog.local_frontier_ref(), PosUnit::Utf16)
+        .filter_map(|(_, op)| op)
+        .map(|op| match op.kind {
+            ListOpKind::Ins => TextOp {
+                is_insert: true,
+                start: op.start(),
+                end: op.start(),
+                // Reversed inserts store their content in the order it was typed.
+                content: match op.loc.fwd {
+                    true => op.content_as_str().unwrap_or_default().into(),
+                    false => op.content_as_str().unwrap_or_default().chars().rev().collect(),
+                },
+            },
+            ListOpKind::Del => TextOp {
+                is_insert: false,
+                start: op.start(),
+                end: op.end(),
+                content: String::new(),
+            },
         })
+        .collect())
+}
+
+pub fn decode(bytes: &[u8]) -> Result<ListCRDT, DTError> {
+    let mut inner = InnerListCRDT::load_from(bytes)?;
+    let agent_id = create_agent(&mut inner.oplog);
+    Ok(ListCRDT { inner, agent_id })
+}
+fn load_or_new(path: &str) -> Result<ListCRDT, DTError> {
+    match std::fs::read(path) {
+        Ok(data) => decode(&data),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(ListCRDT::new()),
+        Err(err) => Err(err.into()),
+    }
 }
 
 impl ListCRDT {
     pub fn new() -> Self {
         let mut inner = InnerListCRDT::new();
-        let agent_id = create_agent(&mut inner);
+        let agent_id = create_agent(&mut inner.oplog);
 
         Self { inner, agent_id }
     }
 
+    pub fn new_with_agent(agent_name: &str) -> Self {
+        let mut inner = InnerListCRDT::new();
+        let agent_id = inner.get_or_create_agent_id(agent_name);
+
+        Self { inner, agent_id }
+    }
+
+    pub fn set_agent(&mut self, agent_name: &str) {
+        self.agent_id = self.inner.get_or_create_agent_id(agent_name);
+    }
+
     // pub fn ins_unicode(&mut self, pos: usize, content: &str) -> usize {
     //     self.inner.insert(self.agent_id, pos, content)
     //     // let parents: LocalVersion = self.inner.local_version_ref().into();
     //     // self.inner.add_insert_at(self.agent_id.unwrap(), &parents, pos, content)
     // }
 
-    pub fn replace_wchar(&mut self, wchar_pos: usize, remove: usize, ins: &str) {
+    pub fn replace_wchar(&mut self, wchar_pos: usize, remove: usize, ins: &str) -> Result<(), DTError> {
+        check_wchar_range(&self.inner.branch, wchar_pos, remove)?;
+
         if remove > 0 {
             self.inner.delete_at_wchar(self.agent_id, wchar_pos..wchar_pos + remove);
         }
         if !ins.is_empty() {
             self.inner.insert_at_wchar(self.agent_id, wchar_pos, ins);
         }
+        Ok(())
+    }
+
+    pub fn len_wchars(&self) -> usize {
+        self.inner.branch.content().borrow().len_wchars()
     }
 
     pub fn encode(&self) -> Vec<u8> {
         self.inner.oplog.encode(&ENCODE_FULL)
     }
 
-    pub fn save(&self, path: &str) {
+    pub fn save(&self, path: &str) -> Result<(), DTError> {
         let data = self.encode();
-        std::fs::write(path, data).unwrap()
+        std::fs::write(path, data)?;
+        Ok(())
     }
 
     pub fn to_string(&self) -> String {
         self.inner.branch.content().to_string()
     }
+
+    pub fn local_version(&self) -> Vec<usize> {
+        self.inner.branch.local_frontier_ref().to_vec()
+    }
+
+    pub fn remote_version(&self) -> RemoteVersion {
+        RemoteVersion(self.inner.branch.remote_frontier(&self.inner.oplog).iter()
+            .map(|rv| RemoteVersionOwned(rv.0.into(), rv.1))
+            .collect())
+    }
+
+    pub fn local_to_remote_version(&self, version: &[usize]) -> Result<RemoteVersion, DTError> {
+        local_to_remote_version(&self.inner.oplog, version)
+    }
+
+    /// Returns None if the version names an agent or operation which the document doesn't know
+    /// about.
+    pub fn remote_to_local_version(&self, version: &RemoteVersion) -> Option<Vec<usize>> {
+        remote_to_local_version(&self.inner.oplog, version).ok()
+    }
+
+    /// Returns None if the version isn't known by the document.
+    pub fn get_patch_since(&self, version: &[usize]) -> Option<Vec<u8>> {
+        get_patch_since(&self.inner.oplog, version).ok()
+    }
+
+    /// Merge a patch from another peer, and update the document to include the new changes.
+    pub fn merge_bytes(&mut self, bytes: &[u8]) -> Result<(), DTError> {
+        self.inner.merge_data_and_ff(bytes)?;
+        Ok(())
+    }
+
+    /// Returns None if the version isn't known by the document.
+    pub fn xf_since(&self, version: &[usize]) -> Option<Vec<TextOp>> {
+        xf_since(&self.inner.oplog, version).ok()
+    }
+}
+
+impl OpLog {
+    pub fn new() -> Self {
+        let mut inner = ListOpLog::new();
+        let agent_id = create_agent(&mut inner);
+
+        Self { inner, agent_id, tip: ListBranch::new() }
+    }
+
+    pub fn new_with_agent(agent_name: &str) -> Self {
+        let mut inner = ListOpLog::new();
+        let agent_id = inner.get_or_create_agent_id(agent_name);
+
+        Self { inner, agent_id, tip: ListBranch::new() }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DTError> {
+        let mut inner = ListOpLog::load_from(bytes)?;
+        let agent_id = create_agent(&mut inner);
+
+        Ok(Self { inner, agent_id, tip: ListBranch::new() })
+    }
+
+    pub fn set_agent(&mut self, agent_name: &str) {
+        self.agent_id = self.inner.get_or_create_agent_id(agent_name);
+    }
+
+    /// The number of operations in the oplog.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn update_tip(&mut self) -> &mut ListBranch {
+        self.tip.merge(&self.inner, self.inner.local_frontier_ref());
+        &mut self.tip
+    }
+
+    /// Insert at the current version of the document. Returns the version of the new operation.
+    pub fn ins(&mut self, wchar_pos: usize, content: &str) -> Result<usize, DTError> {
+        self.update_tip();
+        check_wchar_range(&self.tip, wchar_pos, 0)?;
+        Ok(self.tip.insert_at_wchar(&mut self.inner, self.agent_id, wchar_pos, content))
+    }
+
+    /// Delete at the current version of the document. Returns the version of the new operation.
+    pub fn del(&mut self, wchar_pos: usize, len: usize) -> Result<usize, DTError> {
+        self.update_tip();
+        check_wchar_range(&self.tip, wchar_pos, len)?;
+        Ok(self.tip.delete_at_wchar(&mut self.inner, self.agent_id, wchar_pos..wchar_pos + len))
+    }
+
+    pub fn checkout(&self) -> Branch {
+        Branch(self.inner.checkout_tip())
+    }
+
+    pub fn local_version(&self) -> Vec<usize> {
+        self.inner.local_frontier_ref().to_vec()
+    }
+
+    pub fn remote_version(&self) -> RemoteVersion {
+        RemoteVersion(self.inner.cg.agent_assignment.local_to_remote_frontier_owned(self.inner.local_frontier_ref()).into_vec())
+    }
+
+    pub fn local_to_remote_version(&self, version: &[usize]) -> Result<RemoteVersion, DTError> {
+        local_to_remote_version(&self.inner, version)
+    }
+
+    pub fn remote_to_local_version(&self, version: &RemoteVersion) -> Option<Vec<usize>> {
+        remote_to_local_version(&self.inner, version).ok()
+    }
+
+    pub fn merge_versions(&self, a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+        let a = local_version(&self.inner, a).ok()?;
+        let b = local_version(&self.inner, b).ok()?;
+        Some(self.inner.version_union(a.as_ref(), b.as_ref()).0.into_vec())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.inner.encode(&ENCODE_FULL)
+    }
+
+    pub fn get_patch_since(&self, version: &[usize]) -> Option<Vec<u8>> {
+        get_patch_since(&self.inner, version).ok()
+    }
+
+    /// Decode bytes, and add (merge in) any missing operations.
+    pub fn add_from_bytes(&mut self, bytes: &[u8]) -> Result<(), DTError> {
+        self.inner.decode_and_add(bytes)?;
+        Ok(())
+    }
+
+    pub fn xf_since(&self, version: &[usize]) -> Option<Vec<TextOp>> {
+        xf_since(&self.inner, version).ok()
+    }
+}
+
+impl Branch {
+    pub fn new() -> Self {
+        Self(ListBranch::new())
+    }
+
+    /// Merge all changes in the oplog into the branch.
+    pub fn merge(&mut self, oplog: &OpLog) {
+        self.0.merge(&oplog.inner, oplog.inner.local_frontier_ref());
+    }
+
+    /// Merge the named version of the oplog into the branch.
+    pub fn merge_at(&mut self, oplog: &OpLog, version: &[usize]) -> Result<(), DTError> {
+        let version = local_version(&oplog.inner, version)?;
+        self.0.merge(&oplog.inner, version.as_ref());
+        Ok(())
+    }
+
+    pub fn to_string(&self) -> String {
+        self.0.content().to_string()
+    }
+
+    pub fn len_wchars(&self) -> usize {
+        self.0.content().borrow().len_wchars()
+    }
+
+    pub fn local_version(&self) -> Vec<usize> {
+        self.0.local_frontier_ref().to_vec()
+    }
+
+    pub fn remote_version(&self, oplog: &OpLog) -> RemoteVersion {
+        RemoteVersion(oplog.inner.cg.agent_assignment.local_to_remote_frontier_owned(self.0.local_frontier_ref()).into_vec())
+    }
+}
+
+impl RemoteVersion {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn push(&mut self, agent: &str, seq: usize) {
+        self.0.push(RemoteVersionOwned(agent.into(), seq));
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn agent(&self, idx: usize) -> String {
+        self.0[idx].0.to_string()
+    }
+
+    pub fn seq(&self, idx: usize) -> usize {
+        self.0[idx].1
+    }
+}
+
+impl Default for ListCRDT {
+    fn default() -> Self { Self::new() }
 }
 
-// pub struct Branch(DTBranch);
-//
-// pub struct OpLog {
-//     inner: DTOpLog,
-//     agent_id: Option<AgentId>,
-// }
+impl Default for OpLog {
+    fn default() -> Self { Self::new() }
+}
+
+impl Default for Branch {
+    fn default() -> Self { Self::new() }
+}
+
+impl Default for RemoteVersion {
+    fn default() -> Self { Self::new() }
+}
+
+impl TextOp {
+    pub fn is_insert(&self) -> bool { self.is_insert }
+    pub fn start(&self) -> usize { self.start }
+    pub fn end(&self) -> usize { self.end }
+    pub fn content(&self) -> String { self.content.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply UTF-16 operations to a string, the same way a swift client would.
+    fn apply_wchar_ops(doc: &str, ops: &[TextOp]) -> String {
+        let mut doc: Vec<u16> = doc.encode_utf16().collect();
+        for op in ops {
+            let ins: Vec<u16> = op.content.encode_utf16().collect();
+            doc.splice(op.start..op.end, ins);
+        }
+        String::from_utf16(&doc).unwrap()
+    }
+
+    #[test]
+    fn xf_since_uses_wchars() {
+        let mut a = ListCRDT::new_with_agent("a");
+        a.replace_wchar(0, 0, "😀😀 hi").unwrap();
+        let v = a.local_version();
+
+        let mut b = decode(&a.encode()).unwrap();
+        b.set_agent("b");
+
+        // Concurrent edits after the emoji, which are two UTF-16 code units each.
+        a.replace_wchar(4, 1, "!").unwrap();
+        b.replace_wchar(7, 0, " there").unwrap();
+        b.replace_wchar(2, 0, "🎉").unwrap();
+
+        let before = a.to_string();
+        let a_v = a.local_version();
+        a.merge_bytes(&b.get_patch_since(&[]).unwrap()).unwrap();
+        assert!(a.xf_since(&[100]).is_none());
+        let ops = a.xf_since(&a_v).unwrap();
+        assert_eq!(apply_wchar_ops(&before, &ops), a.to_string());
+
+        let ops = a.xf_since(&v).unwrap();
+        assert_eq!(apply_wchar_ops("😀😀 hi", &ops), a.to_string());
+        assert_eq!(a.to_string(), "😀🎉😀!hi there");
+    }
+
+    #[test]
+    fn oplog_sync() {
+        let mut a = OpLog::new_with_agent("a");
+        a.ins(0, "a😀c").unwrap();
+        a.del(1, 2).unwrap();
+        assert!(matches!(a.ins(10, "x"), Err(DTError::OutOfBounds)));
+        assert!(matches!(a.del(1, 2), Err(DTError::OutOfBounds)));
+
+        let mut b = OpLog::from_bytes(&a.encode()).unwrap();
+        let common = b.remote_version();
+        b.ins(1, "b").unwrap();
+        a.ins(2, "!").unwrap();
+
+        let common_a = a.remote_to_local_version(&common).unwrap();
+        b.add_from_bytes(&a.get_patch_since(&common_a).unwrap()).unwrap();
+        let common_b = b.remote_to_local_version(&common).unwrap();
+        a.add_from_bytes(&b.get_patch_since(&common_b).unwrap()).unwrap();
+
+        assert_eq!(a.checkout().to_string(), b.checkout().to_string());
+        assert_eq!(a.checkout().to_string(), "abc!");
+
+        let mut old = Branch::new();
+        old.merge_at(&b, &common_b).unwrap();
+        assert_eq!(old.to_string(), "ac");
+        assert!(matches!(old.merge_at(&b, &[100]), Err(DTError::InvalidVersion)));
+
+        let mut bad = RemoteVersion::new();
+        bad.push("nobody", 0);
+        assert_eq!(a.remote_to_local_version(&bad), None);
+        assert!(matches!(OpLog::from_bytes(b"not a diamond types file"), Err(DTError::InvalidMagic)));
+    }
+}
@@ -0,0 +1,3 @@
+// swift-bridge doesn't make shared enums conform to Error, so functions returning
+// Result<_, DTError> can't throw them without this.
+extension DTError: Error {}
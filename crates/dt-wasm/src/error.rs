@@ -0,0 +1,68 @@
+//! Errors thrown to javascript.
+//!
+//! Every error is a JS `Error` object with its `name` set to the kind of error. Decoding errors
+//! use the name of the [`ParseError`] variant (eg `"InvalidMagic"`, `"ChecksumFailed"`). Errors
+//! from the bindings themselves are named `"OutOfBounds"`, `"InvalidVersion"`, `"InvalidCRDT"`,
+//! `"InvalidPath"`, `"InvalidValue"` and `"AgentMissing"`.
+
+use std::fmt::Debug;
+use std::ops::Range;
+use jumprope::JumpRope;
+use wasm_bindgen::prelude::*;
+use diamond_types::list::encoding::ParseError;
+use diamond_types::list::pos_unit::{PosUnit, rope_chars_to_unit, rope_try_unit_to_chars};
+use diamond_types::LV;
+use crate::WasmResult;
+
+pub(crate) fn named_err(name: &str, msg: &str) -> serde_wasm_bindgen::Error {
+    let err = js_sys::Error::new(msg);
+    err.set_name(name);
+    JsValue::from(err).into()
+}
+
+pub(crate) fn err<T>(name: &str, msg: String) -> WasmResult<T> {
+    Err(named_err(name, &msg))
+}
+
+/// The name of an enum variant, from its Debug representation (`MissingChunk(3)` ->
+/// `MissingChunk`).
+fn variant_name<E: Debug>(e: &E) -> String {
+    let s = format!("{:?}", e);
+    match s.find(|c: char| !c.is_alphanumeric()) {
+        Some(idx) => s[..idx].into(),
+        None => s,
+    }
+}
+
+pub(crate) fn parse_err(e: ParseError) -> serde_wasm_bindgen::Error {
+    named_err(&variant_name(&e), &e.to_string())
+}
+
+pub(crate) fn check_bounds(pos: usize, len: usize, doc_len: usize) -> WasmResult<()> {
+    match pos.checked_add(len) {
+        Some(end) if end <= doc_len => Ok(()),
+        _ => err("OutOfBounds", format!("Range {pos}..{} is out of bounds (document length {doc_len})", pos.saturating_add(len))),
+    }
+}
+
+/// Convert the range `pos..pos+len` (measured in `unit`) to a character range in the document,
+/// checking that it's in bounds and doesn't split a character.
+pub(crate) fn check_range_in(content: &JumpRope, pos: usize, len: usize, unit: PosUnit) -> WasmResult<Range<usize>> {
+    let start = rope_try_unit_to_chars(content, unit, pos);
+    let end = pos.checked_add(len).and_then(|end| rope_try_unit_to_chars(content, unit, end));
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(start..end),
+        _ => {
+            let doc_len = rope_chars_to_unit(content, unit, content.len_chars());
+            err("OutOfBounds", format!("Range {pos}..{} is out of bounds or splits a character (document length {doc_len})", pos.saturating_add(len)))
+        }
+    }
+}
+
+/// Check that every version in a version passed in from javascript is known by the oplog.
+pub(crate) fn check_version(version: &[LV], oplog_len: usize) -> WasmResult<()> {
+    match version.iter().find(|v| **v >= oplog_len) {
+        Some(v) => err("InvalidVersion", format!("Unknown version {v} (oplog length {oplog_len})")),
+        None => Ok(()),
+    }
+}
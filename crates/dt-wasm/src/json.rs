@@ -0,0 +1,348 @@
+//! Wrappers for the JSON-style document types ([`diamond_types::OpLog`] and
+//! [`diamond_types::Branch`]). These documents contain nested maps, primitive values and text
+//! CRDTs.
+//!
+//! CRDTs inside the document are named by their ID (the local version when they were created).
+//! IDs can be looked up from a path of map keys using `crdtAtPath` / `textAtPath`. The root map
+//! has the ID returned by `rootCrdtId()`.
+
+use std::ops::Range;
+use jumprope::JumpRope;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use diamond_types::{AgentId, Branch as DTBranch, CRDTKind, CreateValue, Frontier, LV, OpLog as DTOpLog, Primitive, RegisterValue, ROOT_CRDT_ID, SerializedOpsOwned};
+use diamond_types::list::operation::TextOperation;
+use diamond_types::list::pos_unit::PosUnit;
+use crate::{parse_pos_unit, unwrap_agentid, utils, WasmResult};
+use crate::error::{check_range_in, check_version, err, parse_err};
+
+/// Serialize values with maps as plain JS objects (rather than Map objects).
+fn to_js<T: Serialize + ?Sized>(val: &T) -> WasmResult {
+    val.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+}
+
+fn path_ref(path: &[String]) -> Vec<&str> {
+    path.iter().map(|s| s.as_str()).collect()
+}
+
+fn kind_name(kind: CRDTKind) -> &'static str {
+    match kind {
+        CRDTKind::Map => "map",
+        CRDTKind::Register => "register",
+        CRDTKind::Collection => "collection",
+        CRDTKind::Text => "text",
+    }
+}
+
+fn js_to_primitive(val: &JsValue) -> WasmResult<Primitive> {
+    if val.is_null() || val.is_undefined() {
+        Ok(Primitive::Nil)
+    } else if let Some(b) = val.as_bool() {
+        Ok(Primitive::Bool(b))
+    } else if let Some(n) = val.as_f64() {
+        // Only integers are supported for now.
+        if n.fract() != 0.0 || n.abs() > 9007199254740991.0 {
+            return err("InvalidValue", format!("Cannot store number {n}. Only (safe) integers are supported"));
+        }
+        Ok(Primitive::I64(n as i64))
+    } else if let Some(s) = val.as_string() {
+        Ok(Primitive::Str(s.into()))
+    } else {
+        err("InvalidValue", "Only null, booleans, integers and strings can be stored in a register".into())
+    }
+}
+
+fn kind_from_str(kind: &str) -> WasmResult<CRDTKind> {
+    match kind {
+        "map" => Ok(CRDTKind::Map),
+        "text" => Ok(CRDTKind::Text),
+        _ => err("InvalidValue", format!("Unknown CRDT kind {kind}. Expected 'map' or 'text'")),
+    }
+}
+
+#[derive(Serialize)]
+struct CRDTRef {
+    kind: &'static str,
+    id: LV,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RegisterJs<'a> {
+    Primitive(&'a Primitive),
+    Crdt(CRDTRef),
+}
+
+fn register_to_js(val: &RegisterValue) -> WasmResult {
+    let val = match val {
+        RegisterValue::Primitive(p) => RegisterJs::Primitive(p),
+        RegisterValue::OwnedCRDT(kind, id) => RegisterJs::Crdt(CRDTRef { kind: kind_name(*kind), id: *id }),
+    };
+    to_js(&val)
+}
+
+/// The ID of the root map in every document.
+#[wasm_bindgen(js_name = rootCrdtId)]
+pub fn root_crdt_id() -> LV {
+    ROOT_CRDT_ID
+}
+
+#[wasm_bindgen]
+pub struct JsonOpLog {
+    inner: DTOpLog,
+    agent_id: Option<AgentId>,
+
+    /// A checkout of the oplog, used to check text positions before adding operations. This is
+    /// brought up to date on demand.
+    tip: DTBranch,
+
+    /// The unit for text positions passed to and from javascript. See `setPosUnit`.
+    unit: PosUnit,
+}
+
+impl JsonOpLog {
+    fn local_version(&self, version: &[LV]) -> WasmResult<Frontier> {
+        check_version(version, self.inner.cg.len())?;
+        Ok(self.inner.cg.graph.find_dominators(version))
+    }
+
+    fn check_crdt(&self, crdt: LV, expect_kind: CRDTKind) -> WasmResult<()> {
+        match self.inner.crdt_kind(crdt) {
+            Some(kind) if kind == expect_kind => Ok(()),
+            Some(kind) => err("InvalidCRDT", format!("Expected {} for CRDT {crdt}, found {}", kind_name(expect_kind), kind_name(kind))),
+            None => err("InvalidCRDT", format!("No {} with ID {crdt} in the document", kind_name(expect_kind))),
+        }
+    }
+
+    /// Texts can be read even after they have been deleted.
+    fn check_text_readable(&self, text: LV) -> WasmResult<()> {
+        if self.inner.has_text(text) { Ok(()) } else {
+            err("InvalidCRDT", format!("No text with ID {text} in the document"))
+        }
+    }
+
+    /// Convert the range `pos..pos+len` in the current content of the named text to characters.
+    fn text_range(&mut self, text: LV, pos: usize, len: usize) -> WasmResult<Range<usize>> {
+        self.check_crdt(text, CRDTKind::Text)?;
+        self.tip.merge_changes_to_tip(&self.inner);
+        match self.tip.texts.get(&text) {
+            Some(content) => check_range_in(&content.borrow(), pos, len, self.unit),
+            // Texts don't show up in the branch until they have content.
+            None => check_range_in(&JumpRope::new(), pos, len, self.unit),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl JsonOpLog {
+    #[wasm_bindgen(constructor)]
+    pub fn new(agent_name: Option<String>) -> Self {
+        utils::set_panic_hook();
+
+        let mut inner = DTOpLog::new();
+        let agent_id = agent_name.map(|name| {
+            inner.cg.get_or_create_agent_id(name.as_str())
+        });
+
+        Self { inner, agent_id, tip: DTBranch::new(), unit: PosUnit::Chars }
+    }
+
+    #[wasm_bindgen(js_name = setAgent)]
+    pub fn set_agent(&mut self, agent: &str) {
+        self.agent_id = Some(self.inner.cg.get_or_create_agent_id(agent));
+    }
+
+    /// Set the unit text positions are measured in, for `textIns`, `textDel` and
+    /// `getTextXFSince`. One of `"chars"` (unicode characters, the default), `"utf16"`
+    /// (javascript string indexes) or `"utf8"` (bytes).
+    #[wasm_bindgen(js_name = setPosUnit)]
+    pub fn set_pos_unit(&mut self, unit: &str) -> WasmResult<()> {
+        self.unit = parse_pos_unit(unit)?;
+        Ok(())
+    }
+
+    /// Find the CRDT at the named path of map keys. Returns `{kind, id}`.
+    #[wasm_bindgen(js_name = crdtAtPath)]
+    pub fn crdt_at_path(&self, path: Vec<String>) -> WasmResult {
+        match self.inner.try_crdt_at_path(&path_ref(&path)) {
+            Some((kind, id)) => to_js(&CRDTRef { kind: kind_name(kind), id }),
+            None => err("InvalidPath", format!("No CRDT found at path {:?}", path)),
+        }
+    }
+
+    /// Find the ID of the text CRDT at the named path of map keys.
+    #[wasm_bindgen(js_name = textAtPath)]
+    pub fn text_at_path(&self, path: Vec<String>) -> WasmResult<LV> {
+        match self.inner.try_crdt_at_path(&path_ref(&path)) {
+            Some((CRDTKind::Text, id)) => Ok(id),
+            Some((kind, _)) => err("InvalidPath", format!("Expected text at path {:?}, found {}", path, kind_name(kind))),
+            None => err("InvalidPath", format!("No CRDT found at path {:?}", path)),
+        }
+    }
+
+    /// Set key in the map with the named ID to a primitive value (null, a boolean, an integer or a
+    /// string). Returns the version of the new operation.
+    #[wasm_bindgen(js_name = mapSet)]
+    pub fn map_set(&mut self, map: LV, key: &str, value: JsValue) -> WasmResult<LV> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        self.check_crdt(map, CRDTKind::Map)?;
+        let value = js_to_primitive(&value)?;
+        Ok(self.inner.local_map_set(agent, map, key, CreateValue::Primitive(value)))
+    }
+
+    /// Create a new, empty CRDT ("map" or "text") at key in the map with the named ID. Returns
+    /// the ID of the new CRDT.
+    #[wasm_bindgen(js_name = mapCreate)]
+    pub fn map_create(&mut self, map: LV, key: &str, kind: &str) -> WasmResult<LV> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        self.check_crdt(map, CRDTKind::Map)?;
+        let kind = kind_from_str(kind)?;
+        Ok(self.inner.local_map_set(agent, map, key, CreateValue::NewCRDT(kind)))
+    }
+
+    /// Insert into the text CRDT with the named ID. Returns the version of the last inserted
+    /// character.
+    #[wasm_bindgen(js_name = textIns)]
+    pub fn text_insert(&mut self, text: LV, pos: usize, content: &str) -> WasmResult<LV> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        let range = self.text_range(text, pos, 0)?;
+        Ok(self.inner.local_text_op(agent, text, TextOperation::new_insert(range.start, content)).last())
+    }
+
+    #[wasm_bindgen(js_name = textDel)]
+    pub fn text_delete(&mut self, text: LV, pos: usize, len: usize) -> WasmResult<LV> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        let range = self.text_range(text, pos, len)?;
+        Ok(self.inner.local_text_op(agent, text, TextOperation::new_delete(range)).last())
+    }
+
+    /// Get the current state of the whole document as a JS object.
+    #[wasm_bindgen]
+    pub fn checkout(&self) -> WasmResult {
+        to_js(&self.inner.checkout())
+    }
+
+    #[wasm_bindgen(js_name = checkoutText)]
+    pub fn checkout_text(&self, text: LV) -> WasmResult<String> {
+        self.check_text_readable(text)?;
+        Ok(self.inner.checkout_text(text).to_string())
+    }
+
+    #[wasm_bindgen(js_name = checkoutTextAt)]
+    pub fn checkout_text_at(&self, text: LV, version: &[LV]) -> WasmResult<String> {
+        self.check_text_readable(text)?;
+        let version = self.local_version(version)?;
+        Ok(self.inner.checkout_text_at(text, version.as_ref()).to_string())
+    }
+
+    #[wasm_bindgen(js_name = checkoutBranch)]
+    pub fn checkout_branch(&self) -> JsonBranch {
+        JsonBranch(self.inner.checkout_tip())
+    }
+
+    /// Get the (transformed) changes to the named text CRDT since some version.
+    #[wasm_bindgen(js_name = getTextXFSince)]
+    pub fn get_text_xf_since(&self, text: LV, version: &[LV]) -> WasmResult {
+        self.check_text_readable(text)?;
+        let version = self.local_version(version)?;
+        let xf = self.inner.xf_text_changes_since_in(text, version.as_ref(), self.unit)
+            .into_iter()
+            .filter_map(|(_v, op)| op)
+            .collect::<Vec<_>>();
+        to_js(&xf)
+    }
+
+    #[wasm_bindgen(js_name = getLocalVersion)]
+    pub fn get_local_frontier(&self) -> Box<[LV]> {
+        self.inner.cg.version.as_ref().into()
+    }
+
+    #[wasm_bindgen(js_name = getRemoteVersion)]
+    pub fn get_remote_version(&self) -> WasmResult {
+        let version = self.inner.cg.agent_assignment.local_to_remote_frontier(self.inner.cg.version.as_ref());
+        to_js(&version)
+    }
+
+    /// Get all operations since the named version, in a form that can be passed to `mergeOps` on
+    /// another oplog.
+    #[wasm_bindgen(js_name = opsSince)]
+    pub fn ops_since(&self, version: &[LV]) -> WasmResult {
+        let version = self.local_version(version)?;
+        to_js(&self.inner.ops_since(version.as_ref()))
+    }
+
+    /// Merge in operations from `opsSince`. Returns the range of local versions which were added.
+    #[wasm_bindgen(js_name = mergeOps)]
+    pub fn merge_ops(&mut self, ops: JsValue) -> WasmResult {
+        let ops: SerializedOpsOwned = serde_wasm_bindgen::from_value(ops)?;
+        match self.inner.merge_ops(ops.as_ops()) {
+            Ok(range) => to_js(&range),
+            Err(e) => Err(parse_err(e)),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct JsonBranch(DTBranch);
+
+#[wasm_bindgen]
+impl JsonBranch {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        utils::set_panic_hook();
+
+        Self(DTBranch::new())
+    }
+
+    /// Merge all changes in the oplog into the branch.
+    #[wasm_bindgen]
+    pub fn merge(&mut self, oplog: &JsonOpLog) {
+        self.0.merge_changes_to_tip(&oplog.inner);
+    }
+
+    #[wasm_bindgen(js_name = crdtAtPath)]
+    pub fn crdt_at_path(&self, path: Vec<String>) -> WasmResult {
+        match self.0.try_crdt_at_path(&path_ref(&path)) {
+            Some((kind, id)) => to_js(&CRDTRef { kind: kind_name(kind), id }),
+            None => err("InvalidPath", format!("No CRDT found at path {:?}", path)),
+        }
+    }
+
+    /// Get the value at key in the map at the named path. Primitive values are returned directly.
+    /// CRDTs are returned as `{kind, id}`. Returns undefined if the key is not set.
+    #[wasm_bindgen]
+    pub fn get(&self, path: Vec<String>, key: &str) -> WasmResult {
+        let path = path_ref(&path);
+        match self.0.try_crdt_at_path(&path) {
+            Some((CRDTKind::Map, _)) => {
+                match self.0.register_in_map(&path, key) {
+                    Some(val) => register_to_js(val),
+                    None => Ok(JsValue::UNDEFINED),
+                }
+            }
+            _ => err("InvalidPath", format!("No map found at path {:?}", path)),
+        }
+    }
+
+    /// Get the content of the text CRDT at the named path.
+    #[wasm_bindgen(js_name = getText)]
+    pub fn get_text(&self, path: Vec<String>) -> WasmResult<String> {
+        match self.0.try_crdt_at_path(&path_ref(&path)) {
+            Some((CRDTKind::Text, id)) => {
+                Ok(self.0.texts.get(&id).map(|t| t.to_string()).unwrap_or_default())
+            }
+            _ => err("InvalidPath", format!("No text found at path {:?}", path)),
+        }
+    }
+
+    #[wasm_bindgen(js_name = getLocalVersion)]
+    pub fn get_local_frontier(&self) -> Box<[LV]> {
+        self.0.frontier.as_ref().into()
+    }
+}
+
+impl Default for JsonBranch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,12 +1,17 @@
 mod utils;
+mod error;
+pub mod json;
 
+use std::ops::Range;
 use wasm_bindgen::prelude::*;
 // use serde_wasm_bindgen::Serializer;
 // use serde::{Serialize};
-use diamond_types::{AgentId, LV};
+use diamond_types::{AgentId, Frontier, LV};
 use diamond_types::list::{ListBranch as DTBranch, ListCRDT, ListOpLog as DTOpLog};
 use diamond_types::list::encoding::{ENCODE_FULL, ENCODE_PATCH};
 use diamond_types::list::operation::TextOperation;
+use diamond_types::list::pos_unit::PosUnit;
+use crate::error::{check_bounds, check_range_in, check_version, err, parse_err};
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -27,6 +32,13 @@ pub struct Branch(DTBranch);
 pub struct OpLog {
     inner: DTOpLog,
     agent_id: Option<AgentId>,
+
+    /// A checkout of the oplog, used to check positions before adding operations. This is
+    /// created lazily, and brought up to date on demand.
+    tip: Option<DTBranch>,
+
+    /// The unit for positions passed to and from javascript. See `setPosUnit`.
+    unit: PosUnit,
 }
 
 // pub fn checkout(&self) -> Branch {
@@ -41,8 +53,17 @@ pub fn get_ops(oplog: &DTOpLog) -> WasmResult {
     serde_wasm_bindgen::to_value(&ops)
 }
 
+/// Convert a version passed in from javascript into a frontier, checking that all the named
+/// versions exist in the oplog.
+pub fn local_version(oplog: &DTOpLog, version: &[LV]) -> WasmResult<Frontier> {
+    check_version(version, oplog.len())?;
+    // The frontier needs to be sorted and must not contain redundant versions.
+    Ok(oplog.cg.graph.find_dominators(version))
+}
+
 pub fn get_ops_since(oplog: &DTOpLog, version: &[LV]) -> WasmResult {
-    let ops = oplog.iter_range_since(version)
+    let version = local_version(oplog, version)?;
+    let ops = oplog.iter_range_since(version.as_ref())
         .collect::<Box<[TextOperation]>>();
     serde_wasm_bindgen::to_value(&ops)
 }
@@ -63,8 +84,9 @@ pub fn get_history(oplog: &DTOpLog) -> WasmResult {
 //     let remote_time = oplog.time_to_remote_id(time);
 //     serde_wasm_bindgen::to_value(&remote_time)
 // }
-pub fn local_to_remote_version(oplog: &DTOpLog, local_version: &[LV]) -> WasmResult {
-    let remote_version = oplog.cg.agent_assignment.local_to_remote_frontier(local_version);
+pub fn local_to_remote_version(oplog: &DTOpLog, version: &[LV]) -> WasmResult {
+    let version = local_version(oplog, version)?;
+    let remote_version = oplog.cg.agent_assignment.local_to_remote_frontier(version.as_ref());
     serde_wasm_bindgen::to_value(&remote_version)
 }
 
@@ -80,10 +102,9 @@ pub fn to_bytes(oplog: &DTOpLog) -> Vec<u8> {
     bytes
 }
 
-pub fn get_patch_since(oplog: &DTOpLog, from_version: &[LV]) -> Vec<u8> {
-    // let from_version = map_parents(&version);
-    let bytes = oplog.encode_from(&ENCODE_PATCH, from_version);
-    bytes
+pub fn get_patch_since(oplog: &DTOpLog, from_version: &[LV]) -> WasmResult<Vec<u8>> {
+    let from_version = local_version(oplog, from_version)?;
+    Ok(oplog.encode_from(&ENCODE_PATCH, from_version.as_ref()))
 }
 
 pub fn decode_and_add(oplog: &mut DTOpLog, bytes: &[u8]) -> WasmResult {
@@ -91,29 +112,41 @@ pub fn decode_and_add(oplog: &mut DTOpLog, bytes: &[u8]) -> WasmResult {
         Ok(version) => {
             serde_wasm_bindgen::to_value(&version)
         },
-        Err(e) => {
-            let s = format!("Error merging {:?}", e);
-            let js: JsValue = s.into();
-            Err(js.into())
-        }
+        Err(e) => Err(parse_err(e)),
     }
 }
 
-pub fn xf_since(oplog: &DTOpLog, version: &[LV]) -> WasmResult {
-    let xf = oplog.iter_xf_operations_from(version, &oplog.local_frontier_ref())
+pub fn xf_since(oplog: &DTOpLog, version: &[LV], unit: PosUnit) -> WasmResult {
+    let version = local_version(oplog, version)?;
+    let xf = oplog.iter_xf_operations_from_in(version.as_ref(), oplog.local_frontier_ref(), unit)
         .filter_map(|(_v, op)| op)
         .collect::<Vec<_>>();
 
     serde_wasm_bindgen::to_value(&xf)
 }
 
-pub fn merge_versions(oplog: &DTOpLog, a: &[LV], b: &[LV]) -> Box<[LV]> {
-    let result = oplog.version_union(a, b);
-    result.as_ref().into()
+pub fn merge_versions(oplog: &DTOpLog, a: &[LV], b: &[LV]) -> WasmResult<Box<[LV]>> {
+    let a = local_version(oplog, a)?;
+    let b = local_version(oplog, b)?;
+    let result = oplog.version_union(a.as_ref(), b.as_ref());
+    Ok(result.as_ref().into())
 }
 
-fn unwrap_agentid(agent_id: Option<AgentId>) -> AgentId {
-    agent_id.expect_throw("Agent missing. Set agent before modifying oplog.")
+/// Parse the name of a position unit passed in from javascript.
+pub(crate) fn parse_pos_unit(unit: &str) -> WasmResult<PosUnit> {
+    match unit {
+        "chars" => Ok(PosUnit::Chars),
+        "utf16" => Ok(PosUnit::Utf16),
+        "utf8" => Ok(PosUnit::Utf8),
+        _ => err("InvalidValue", format!("Unknown position unit {unit:?}. Expected \"chars\", \"utf16\" or \"utf8\"")),
+    }
+}
+
+fn unwrap_agentid(agent_id: Option<AgentId>) -> WasmResult<AgentId> {
+    match agent_id {
+        Some(agent_id) => Ok(agent_id),
+        None => err("AgentMissing", "Agent missing. Set agent before modifying oplog.".into()),
+    }
 }
 
 
@@ -140,12 +173,14 @@ impl Branch {
 
     /// Merge in from some named point in time
     #[wasm_bindgen]
-    pub fn merge(&mut self, ops: &OpLog, branch: Option<Box<[LV]>>) {
+    pub fn merge(&mut self, ops: &OpLog, branch: Option<Box<[LV]>>) -> WasmResult<()> {
         if let Some(branch) = branch {
-            self.0.merge(&ops.inner, &branch);
+            let branch = local_version(&ops.inner, &branch)?;
+            self.0.merge(&ops.inner, branch.as_ref());
         } else {
-            self.0.merge(&ops.inner, &ops.inner.local_frontier_ref());
+            self.0.merge(&ops.inner, ops.inner.local_frontier_ref());
         }
+        Ok(())
     }
 
     #[wasm_bindgen(js_name = getLocalVersion)]
@@ -154,13 +189,17 @@ impl Branch {
     }
 
     #[wasm_bindgen(js_name = wCharsToChars)]
-    pub fn wchars_to_chars(&self, pos_wchars: usize) -> usize {
-        self.0.content().borrow().wchars_to_chars(pos_wchars)
+    pub fn wchars_to_chars(&self, pos_wchars: usize) -> WasmResult<usize> {
+        let content = self.0.content().borrow();
+        check_bounds(pos_wchars, 0, content.len_wchars())?;
+        Ok(content.wchars_to_chars(pos_wchars))
     }
 
     #[wasm_bindgen(js_name = charsToWchars)]
-    pub fn chars_to_wchars(&self, pos_chars: usize) -> usize {
-        self.0.content().borrow().chars_to_wchars(pos_chars)
+    pub fn chars_to_wchars(&self, pos_chars: usize) -> WasmResult<usize> {
+        let content = self.0.content().borrow();
+        check_bounds(pos_chars, 0, content.len_chars())?;
+        Ok(content.chars_to_wchars(pos_chars))
     }
 }
 
@@ -175,7 +214,7 @@ impl OpLog {
             inner.get_or_create_agent_id(name.as_str())
         });
 
-        Self { inner, agent_id }
+        Self { inner, agent_id, tip: None, unit: PosUnit::Chars }
     }
 
     #[wasm_bindgen(js_name = setAgent)]
@@ -183,6 +222,15 @@ impl OpLog {
         self.agent_id = Some(self.inner.get_or_create_agent_id(agent));
     }
 
+    /// Set the unit positions are measured in, for `ins`, `del` and the transformed operations
+    /// from `getXF` / `getXFSince`. One of `"chars"` (unicode characters, the default), `"utf16"`
+    /// (javascript string indexes) or `"utf8"` (bytes).
+    #[wasm_bindgen(js_name = setPosUnit)]
+    pub fn set_pos_unit(&mut self, unit: &str) -> WasmResult<()> {
+        self.unit = parse_pos_unit(unit)?;
+        Ok(())
+    }
+
     #[wasm_bindgen(js_name = clone)]
     pub fn js_clone(&self) -> Self {
         // We can't trust the .clone() process to preserve the agent_id.
@@ -192,32 +240,43 @@ impl OpLog {
 
         Self {
             inner: new_oplog,
-            agent_id
+            agent_id,
+            tip: self.tip.clone(),
+            unit: self.unit,
         }
     }
 
+    /// Get the parents for a new operation, and convert the range `pos..pos+len` in the document
+    /// at that version to characters.
+    fn op_parents(&mut self, parents_in: Option<Box<[LV]>>, pos: usize, len: usize) -> WasmResult<(Frontier, Range<usize>)> {
+        if let Some(parents) = parents_in {
+            let parents = local_version(&self.inner, &parents)?;
+            if parents.as_ref() != self.inner.local_frontier_ref() {
+                // Slow path. The document needs to be checked out at the named version.
+                let branch = self.inner.checkout(parents.as_ref());
+                let range = check_range_in(&branch.content().borrow(), pos, len, self.unit)?;
+                return Ok((parents, range));
+            }
+        }
+
+        let tip = self.tip.get_or_insert_with(DTBranch::new);
+        tip.merge(&self.inner, self.inner.local_frontier_ref());
+        let range = check_range_in(&tip.content().borrow(), pos, len, self.unit)?;
+        Ok((tip.local_frontier(), range))
+    }
+
     #[wasm_bindgen(js_name = ins)]
-    pub fn add_insert(&mut self, pos: usize, content: &str, parents_in: Option<Box<[usize]>>) -> usize {
-        // let parents = parents_in.map_or_else(|| self.inner.local_version(), |p| {
-        //     js_to_internal_version(&p)
-        // });
-
-        let parents = parents_in.unwrap_or_else(|| {
-            // Its gross here - I'm converting the frontier into a smallvec then immediately
-            // converting it to a slice again :p
-            self.inner.local_frontier_ref().into()
-        });
-        // Safe because we're just adding [ROOT] if its set.
-        self.inner.add_insert_at(unwrap_agentid(self.agent_id), &parents, pos, content)
+    pub fn add_insert(&mut self, pos: usize, content: &str, parents_in: Option<Box<[usize]>>) -> WasmResult<usize> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        let (parents, range) = self.op_parents(parents_in, pos, 0)?;
+        Ok(self.inner.add_insert_at(agent, parents.as_ref(), range.start, content))
     }
 
     #[wasm_bindgen(js_name = del)]
-    pub fn add_delete(&mut self, pos: usize, len: usize, parents_in: Option<Box<[usize]>>) -> usize {
-        let parents = parents_in.unwrap_or_else(|| {
-            // And here :p
-            self.inner.local_frontier_ref().into()
-        });
-        self.inner.add_delete_at(unwrap_agentid(self.agent_id), &parents, pos..pos + len)
+    pub fn add_delete(&mut self, pos: usize, len: usize, parents_in: Option<Box<[usize]>>) -> WasmResult<usize> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        let (parents, range) = self.op_parents(parents_in, pos, len)?;
+        Ok(self.inner.add_delete_at(agent, parents.as_ref(), range))
     }
 
     // This adds like 70kb of size to the WASM binary.
@@ -286,21 +345,21 @@ impl OpLog {
     }
 
     #[wasm_bindgen(js_name = getPatchSince)]
-    pub fn get_patch_since(&self, from_version: &[usize]) -> Vec<u8> {
+    pub fn get_patch_since(&self, from_version: &[usize]) -> WasmResult<Vec<u8>> {
         get_patch_since(&self.inner, from_version)
     }
 
     // This method adds 17kb to the wasm bundle, or 5kb after brotli.
     #[wasm_bindgen(js_name = fromBytes)]
-    pub fn from_bytes(bytes: &[u8], agent_name: Option<String>) -> Self {
+    pub fn from_bytes(bytes: &[u8], agent_name: Option<String>) -> WasmResult<OpLog> {
         utils::set_panic_hook();
 
-        let mut inner = DTOpLog::load_from(bytes).unwrap();
+        let mut inner = DTOpLog::load_from(bytes).map_err(parse_err)?;
         let agent_id = agent_name.map(|name| {
             inner.get_or_create_agent_id(name.as_str())
         });
 
-        Self { inner, agent_id }
+        Ok(Self { inner, agent_id, tip: None, unit: PosUnit::Chars })
     }
 
     /// Decode bytes, and add (merge in) any missing operations.
@@ -312,16 +371,16 @@ impl OpLog {
     // pub fn xf_since(&self, from_version: &[usize]) -> WasmResult {
     #[wasm_bindgen(js_name = getXF)]
     pub fn get_xf(&self) -> WasmResult {
-        xf_since(&self.inner, &[])
+        xf_since(&self.inner, &[], self.unit)
     }
 
     #[wasm_bindgen(js_name = getXFSince)]
     pub fn get_xf_since(&self, from_version: &[LV]) -> WasmResult {
-        xf_since(&self.inner, from_version)
+        xf_since(&self.inner, from_version, self.unit)
     }
 
     #[wasm_bindgen(js_name = mergeVersions)]
-    pub fn merge_versions(&self, a: &[LV], b: &[LV]) -> Box<[LV]> {
+    pub fn merge_versions(&self, a: &[LV], b: &[LV]) -> WasmResult<Box<[LV]>> {
         merge_versions(&self.inner, a, b)
     }
 
@@ -332,6 +391,9 @@ impl OpLog {
 pub struct Doc {
     inner: ListCRDT,
     agent_id: Option<AgentId>,
+
+    /// The unit for positions passed to and from javascript. See `setPosUnit`.
+    unit: PosUnit,
 }
 
 
@@ -354,23 +416,37 @@ impl Doc {
             inner.get_or_create_agent_id(name.as_str())
         });
 
-        Doc { inner, agent_id }
+        Doc { inner, agent_id, unit: PosUnit::Chars }
+    }
+
+    /// Set the unit positions are measured in, for `ins`, `del`, `len` and the transformed
+    /// operations from `xfSince`. One of `"chars"` (unicode characters, the default), `"utf16"`
+    /// (javascript string indexes) or `"utf8"` (bytes).
+    #[wasm_bindgen(js_name = setPosUnit)]
+    pub fn set_pos_unit(&mut self, unit: &str) -> WasmResult<()> {
+        self.unit = parse_pos_unit(unit)?;
+        Ok(())
     }
 
     #[wasm_bindgen]
-    pub fn ins(&mut self, pos: usize, content: &str) {
-        // let id = self.0.get_or_create_agent_id("seph");
-        self.inner.insert(unwrap_agentid(self.agent_id), pos, content);
+    pub fn ins(&mut self, pos: usize, content: &str) -> WasmResult<()> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        let range = check_range_in(&self.inner.branch.content().borrow(), pos, 0, self.unit)?;
+        self.inner.insert(agent, range.start, content);
+        Ok(())
     }
 
     #[wasm_bindgen]
-    pub fn del(&mut self, pos: usize, del_span: usize) {
-        self.inner.delete(unwrap_agentid(self.agent_id), pos .. pos + del_span);
+    pub fn del(&mut self, pos: usize, del_span: usize) -> WasmResult<()> {
+        let agent = unwrap_agentid(self.agent_id)?;
+        let range = check_range_in(&self.inner.branch.content().borrow(), pos, del_span, self.unit)?;
+        self.inner.delete(agent, range);
+        Ok(())
     }
 
     #[wasm_bindgen]
     pub fn len(&self) -> usize {
-        self.inner.branch.len()
+        self.inner.len_in(self.unit)
     }
 
     #[wasm_bindgen]
@@ -384,8 +460,10 @@ impl Doc {
     }
 
     #[wasm_bindgen]
-    pub fn merge(&mut self, branch: &[LV]) {
-        self.inner.branch.merge(&self.inner.oplog, &branch);
+    pub fn merge(&mut self, branch: &[LV]) -> WasmResult<()> {
+        let branch = local_version(&self.inner.oplog, branch)?;
+        self.inner.branch.merge(&self.inner.oplog, branch.as_ref());
+        Ok(())
     }
 
     #[wasm_bindgen(js_name = toBytes)]
@@ -394,37 +472,31 @@ impl Doc {
     }
 
     #[wasm_bindgen(js_name = getPatchSince)]
-    pub fn get_patch_since(&self, from_version: &[LV]) -> Vec<u8> {
+    pub fn get_patch_since(&self, from_version: &[LV]) -> WasmResult<Vec<u8>> {
         get_patch_since(&self.inner.oplog, from_version)
     }
 
-    // TODO: Do better error handling here.
-    // pub fn from_bytes(bytes: &[u8], agent_name: Option<String>) -> WasmResult<Doc> {
     #[wasm_bindgen(js_name = fromBytes)]
-    pub fn from_bytes(bytes: &[u8], agent_name: Option<String>) -> Self {
+    pub fn from_bytes(bytes: &[u8], agent_name: Option<String>) -> WasmResult<Doc> {
         utils::set_panic_hook();
 
-        // let mut inner = ListCRDT::load_from(bytes).map_err(|e| e.into())?;
-        let mut inner = ListCRDT::load_from(bytes).unwrap();
+        let mut inner = ListCRDT::load_from(bytes).map_err(parse_err)?;
         let agent_id = agent_name.map(|name| {
             inner.get_or_create_agent_id(name.as_str())
         });
 
-        Self {
+        Ok(Self {
             inner,
-            agent_id
-        }
+            agent_id,
+            unit: PosUnit::Chars,
+        })
     }
 
     #[wasm_bindgen(js_name = mergeBytes)]
     pub fn merge_bytes(&mut self, bytes: &[u8]) -> WasmResult<Box<[usize]>> {
     // pub fn merge_bytes(&mut self, bytes: &[u8]) -> WasmResult {
         match self.inner.merge_data_and_ff(bytes) {
-            Err(e) => {
-                let s = format!("Error merging {:?}", e);
-                let js: JsValue = s.into();
-                Err(js.into())
-            },
+            Err(e) => Err(parse_err(e)),
             Ok(frontier) => Ok(frontier.into_iter().collect())
         }
     }
@@ -463,7 +535,7 @@ impl Doc {
 
     #[wasm_bindgen(js_name = xfSince)]
     pub fn xf_since(&self, from_version: &[usize]) -> WasmResult {
-        xf_since(&self.inner.oplog, from_version)
+        xf_since(&self.inner.oplog, from_version, self.unit)
     }
 
     #[wasm_bindgen(js_name = getHistory)]
@@ -472,18 +544,22 @@ impl Doc {
     }
 
     #[wasm_bindgen(js_name = mergeVersions)]
-    pub fn merge_versions(&self, a: &[usize], b: &[usize]) -> Box<[usize]> {
+    pub fn merge_versions(&self, a: &[usize], b: &[usize]) -> WasmResult<Box<[usize]>> {
         merge_versions(&self.inner.oplog, a, b)
     }
 
     #[wasm_bindgen(js_name = wCharsToChars)]
-    pub fn wchars_to_chars(&self, pos_wchars: usize) -> usize {
-        self.inner.branch.content().borrow().wchars_to_chars(pos_wchars)
+    pub fn wchars_to_chars(&self, pos_wchars: usize) -> WasmResult<usize> {
+        let content = self.inner.branch.content().borrow();
+        check_bounds(pos_wchars, 0, content.len_wchars())?;
+        Ok(content.wchars_to_chars(pos_wchars))
     }
 
     #[wasm_bindgen(js_name = charsToWchars)]
-    pub fn chars_to_wchars(&self, pos_chars: usize) -> usize {
-        self.inner.branch.content().borrow().chars_to_wchars(pos_chars)
+    pub fn chars_to_wchars(&self, pos_chars: usize) -> WasmResult<usize> {
+        let content = self.inner.branch.content().borrow();
+        check_bounds(pos_chars, 0, content.len_chars())?;
+        Ok(content.chars_to_wchars(pos_chars))
     }
 
     // #[wasm_bindgen]
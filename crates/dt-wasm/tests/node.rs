@@ -0,0 +1,123 @@
+//! Tests for the javascript bindings, run under nodejs:
+//!
+//! CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner cargo test -p dt-wasm --target wasm32-unknown-unknown
+
+#![cfg(target_arch = "wasm32")]
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::*;
+use dt_wasm::{Doc, OpLog};
+use dt_wasm::json::{JsonOpLog, root_crdt_id};
+
+fn err_name<T>(result: Result<T, serde_wasm_bindgen::Error>) -> String {
+    let Err(e) = result else { panic!("Expected an error"); };
+    let e: js_sys::Error = JsValue::from(e).dyn_into().expect("Expected a JS Error object");
+    e.name().into()
+}
+
+#[wasm_bindgen_test]
+fn oplog_positions_are_checked() {
+    let mut oplog = OpLog::new(Some("seph".into()));
+    oplog.add_insert(0, "hi there", None).unwrap();
+
+    assert_eq!(err_name(oplog.add_insert(9, "x", None)), "OutOfBounds");
+    assert_eq!(err_name(oplog.add_delete(5, 4, None)), "OutOfBounds");
+    assert_eq!(err_name(oplog.add_delete(usize::MAX, 2, None)), "OutOfBounds");
+    oplog.add_delete(5, 3, None).unwrap();
+
+    // Positions are checked against the named parents.
+    assert_eq!(err_name(oplog.add_insert(0, "x", Some(Box::new([100])))), "InvalidVersion");
+    assert_eq!(err_name(oplog.add_insert(8, "x", Some(Box::new([2])))), "OutOfBounds");
+    oplog.add_insert(3, "x", Some(Box::new([2]))).unwrap();
+
+    assert_eq!(oplog.checkout().get().len(), 6);
+}
+
+#[wasm_bindgen_test]
+fn agent_is_required() {
+    let mut oplog = OpLog::new(None);
+    assert_eq!(err_name(oplog.add_insert(0, "x", None)), "AgentMissing");
+    oplog.set_agent("seph");
+    oplog.add_insert(0, "x", None).unwrap();
+}
+
+#[wasm_bindgen_test]
+fn decode_errors_are_named() {
+    assert_eq!(err_name(OpLog::from_bytes(b"not a diamond types file", None)), "InvalidMagic");
+    assert_eq!(err_name(Doc::from_bytes(b"", None)), "UnexpectedEOF");
+
+    let mut oplog = OpLog::new(Some("seph".into()));
+    oplog.add_insert(0, "hi", None).unwrap();
+    let mut bytes = oplog.to_bytes();
+    let len = bytes.len();
+    bytes[len - 1] ^= 0xff;
+    assert_eq!(err_name(oplog.add_from_bytes(&bytes)), "ChecksumFailed");
+}
+
+#[wasm_bindgen_test]
+fn versions_are_checked() {
+    let mut doc = Doc::new(Some("seph".into()));
+    doc.ins(0, "abc").unwrap();
+    assert_eq!(err_name(doc.ins(4, "x")), "OutOfBounds");
+    assert_eq!(err_name(doc.merge(&[3])), "InvalidVersion");
+    assert_eq!(err_name(doc.get_patch_since(&[7])), "InvalidVersion");
+    assert_eq!(err_name(doc.wchars_to_chars(4)), "OutOfBounds");
+    doc.merge(&[1]).unwrap();
+}
+
+#[wasm_bindgen_test]
+fn json_oplog_checks_crdts() {
+    let mut doc = JsonOpLog::new(Some("seph".into()));
+    let text = doc.map_create(root_crdt_id(), "text", "text").unwrap();
+    let num = doc.map_set(root_crdt_id(), "n", JsValue::from(1)).unwrap();
+
+    assert_eq!(err_name(doc.text_insert(text, 1, "x")), "OutOfBounds");
+    doc.text_insert(text, 0, "hi").unwrap();
+    assert_eq!(err_name(doc.text_delete(text, 1, 2)), "OutOfBounds");
+    doc.text_delete(text, 1, 1).unwrap();
+    assert_eq!(doc.checkout_text(text).unwrap(), "h");
+
+    assert_eq!(err_name(doc.text_insert(num, 0, "x")), "InvalidCRDT");
+    assert_eq!(err_name(doc.map_set(text, "k", JsValue::NULL)), "InvalidCRDT");
+    assert_eq!(err_name(doc.map_set(root_crdt_id(), "k", JsValue::from(1.5))), "InvalidValue");
+    assert_eq!(err_name(doc.text_at_path(vec!["n".into()])), "InvalidPath");
+    assert_eq!(err_name(doc.ops_since(&[1000])), "InvalidVersion");
+
+    let mut doc2 = JsonOpLog::new(None);
+    assert_eq!(err_name(doc2.merge_ops(JsValue::from(1))), "Error");
+    doc2.merge_ops(doc.ops_since(&[]).unwrap()).unwrap();
+    assert_eq!(doc2.checkout_text(text).unwrap(), "h");
+}
+
+#[wasm_bindgen_test]
+fn position_units() {
+    let mut doc = Doc::new(Some("seph".into()));
+    doc.set_pos_unit("utf16").unwrap();
+    doc.ins(0, "a😀c").unwrap();
+    assert_eq!(doc.len(), 4);
+    assert_eq!(err_name(doc.ins(2, "x")), "OutOfBounds");
+    assert_eq!(err_name(doc.del(1, 1)), "OutOfBounds");
+    doc.ins(3, "b").unwrap();
+    doc.del(1, 2).unwrap();
+    assert_eq!(doc.get(), "abc");
+
+    doc.set_pos_unit("utf8").unwrap();
+    doc.ins(3, "😀").unwrap();
+    assert_eq!(doc.len(), 7);
+    assert_eq!(err_name(doc.set_pos_unit("bytes")), "InvalidValue");
+
+    let mut oplog = OpLog::new(Some("seph".into()));
+    oplog.set_pos_unit("utf16").unwrap();
+    oplog.add_insert(0, "😀😀", None).unwrap();
+    assert_eq!(err_name(oplog.add_insert(1, "x", None)), "OutOfBounds");
+    oplog.add_insert(2, "x", Some(Box::new([1]))).unwrap();
+    assert_eq!(oplog.checkout().get(), "😀x😀");
+
+    let mut json = JsonOpLog::new(Some("seph".into()));
+    json.set_pos_unit("utf8").unwrap();
+    let text = json.map_create(root_crdt_id(), "text", "text").unwrap();
+    json.text_insert(text, 0, "😀").unwrap();
+    assert_eq!(err_name(json.text_insert(text, 1, "x")), "OutOfBounds");
+    json.text_insert(text, 4, "x").unwrap();
+    assert_eq!(json.checkout_text(text).unwrap(), "😀x");
+}
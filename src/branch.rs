@@ -229,32 +229,29 @@ impl Branch {
     }
 
     pub fn crdt_at_path(&self, path: &[&str]) -> (CRDTKind, LVKey) {
+        self.try_crdt_at_path(path).expect("Invalid path in document")
+    }
+
+    /// Find the CRDT at the named path. Returns None if the path doesn't name a CRDT in the
+    /// branch.
+    pub fn try_crdt_at_path(&self, path: &[&str]) -> Option<(CRDTKind, LVKey)> {
         let mut kind = CRDTKind::Map;
         let mut key = ROOT_CRDT_ID;
 
         for p in path {
-            match kind {
-                CRDTKind::Map => {
-                    let obj = self.maps.get(&key).unwrap();
-                    let state = obj.get(*p).unwrap();
-
-                    match state.value {
-                        RegisterValue::Primitive(_) => {
-                            panic!("Found primitive, not CRDT");
-                        }
-                        RegisterValue::OwnedCRDT(new_kind, new_key) => {
-                            kind = new_kind;
-                            key = new_key;
-                        }
-                    }
-                }
-                _ => {
-                    panic!("Invalid path in document");
+            if kind != CRDTKind::Map { return None; }
+
+            let state = self.maps.get(&key)?.get(*p)?;
+            match state.value {
+                RegisterValue::Primitive(_) => { return None; }
+                RegisterValue::OwnedCRDT(new_kind, new_key) => {
+                    kind = new_kind;
+                    key = new_key;
                 }
             }
         }
 
-        (kind, key)
+        Some((kind, key))
     }
 
     pub fn text_at_path(&self, path: &[&str]) -> LVKey {
@@ -3,13 +3,15 @@ use smallvec::SmallVec;
 use rle::{HasLength, MergableSpan, SplitableSpan};
 use rle::zip::rle_zip;
 
-use crate::{AgentId, CausalGraph, LV};
+use crate::{AgentId, CausalGraph, DTRange, Frontier, LV};
+use crate::encoding::parseerror::ParseError;
 use crate::causalgraph::*;
 use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteFrontierOwned};
 use crate::causalgraph::agent_span::AgentSpan;
 use crate::causalgraph::entry::CGEntry;
 use crate::causalgraph::graph::GraphEntrySimple;
-use crate::rle::{RleSpanHelpers, RleVec};
+use crate::rle::{KVPair, RleSpanHelpers, RleVec};
+use crate::rle::RleKeyedAndSplitable;
 
 impl CausalGraph {
     pub fn new() -> Self {
@@ -202,6 +204,52 @@ impl CausalGraph {
         }
     }
 
+    /// Like [`merge_and_assign`](Self::merge_and_assign), but if any of the operations in `span`
+    /// are already known, this checks that they were stored with the same parents. If they
+    /// weren't, the same (agent, seq) IDs have been reused for different operations and this
+    /// returns [`ParseError::IdReused`] without modifying the causal graph.
+    pub fn merge_and_assign_checked(&mut self, parents: &[LV], span: AgentSpan) -> Result<DTRange, ParseError> {
+        self.check_known_span(parents, span)?;
+        Ok(self.merge_and_assign(parents, span))
+    }
+
+    /// Check that any operations in `span` which are already known have the named parents.
+    pub(crate) fn check_known_span(&self, parents: &[LV], span: AgentSpan) -> Result<(), ParseError> {
+        let Some(client_data) = self.agent_assignment.client_data.get(span.agent as usize) else {
+            return Ok(());
+        };
+
+        let mut parents = Frontier::from_unsorted(parents);
+        let mut seq = span.seq_range.start;
+        while seq < span.seq_range.end {
+            let (entry, offset) = client_data.lv_for_seq.find_sparse(seq);
+            let end = match entry {
+                Err(empty) => empty.end.min(span.seq_range.end),
+                Ok(KVPair(_, lv_range)) => {
+                    let lv_start = lv_range.start + offset;
+                    let known_len = (lv_range.len() - offset).min(span.seq_range.end - seq);
+
+                    if !self.graph.has_entry_with_parents(parents.as_ref(), (lv_start..lv_start + known_len).into()) {
+                        return Err(ParseError::IdReused {
+                            agent: span.agent,
+                            seq_range: (seq..seq + known_len).into(),
+                        });
+                    }
+                    seq + known_len
+                }
+            };
+
+            // The next operation's parent is the one before it.
+            parents = match client_data.try_seq_to_lv(end - 1) {
+                Some(lv) => Frontier::new_1(lv),
+                // The incoming operation isn't known, so it can't match any stored parents.
+                None => Frontier::new_1(usize::MAX),
+            };
+            seq = end;
+        }
+        Ok(())
+    }
+
     /// Iterate through history entries
     pub fn iter_parents(&self) -> impl Iterator<Item=GraphEntrySimple> + '_ {
         self.graph.iter()
@@ -265,6 +313,88 @@ impl CausalGraph {
         result
     }
 
+    /// Discard all entries in the causal graph with local versions >= len. The remaining entries
+    /// must form a causal prefix of the graph - which they always do, because local versions are
+    /// assigned in causal order.
+    ///
+    /// This leaves the graph's version and known agents untouched. Callers need to fix them up
+    /// themselves.
+    pub(crate) fn truncate_entries(&mut self, len: usize) {
+        // This would be nicer with an RleVec iterator, but the iter implementation doesn't
+        // support iterating backwards.
+        while let Some(last) = self.agent_assignment.client_with_lv.0.last_mut() {
+            debug_assert!(len <= last.end());
+            if len == last.end() { break; }
+            else {
+                // Truncate!
+                let KVPair(_, removed) = if len <= last.0 {
+                    // Drop entire entry
+                    self.agent_assignment.client_with_lv.0.pop().unwrap()
+                } else {
+                    last.truncate(len - last.0)
+                };
+
+                let client_data = &mut self.agent_assignment.client_data[removed.agent as usize];
+                client_data.lv_for_seq.remove_ctx(removed.seq_range, &());
+            }
+        }
+
+        // Trim history
+        let hist_entries = &mut self.graph.entries;
+        let history_length = hist_entries.end();
+        if history_length > len {
+            // We can't use entries.remove because HistoryEntry doesn't support SplitableSpan.
+            // And also because we need to update child_indexes.
+            let del_span_start = len;
+
+            let first_idx = hist_entries.find_index(len).unwrap();
+
+            let e = &mut hist_entries.0[first_idx];
+            let first_truncated_idx = if del_span_start > e.span.start {
+                // The first entry just needs to be trimmed down.
+                e.span.truncate_from(del_span_start);
+                first_idx + 1
+            } else {
+                first_idx
+            };
+
+            let mut idx = first_truncated_idx;
+
+            // Go through and unwind from idx.
+            while idx < hist_entries.num_entries() {
+                // Cloning here is an ugly and kinda slow hack to work around the borrow
+                // checker. But this whole case is rare anyway, so idk.
+                let parents = hist_entries.0[idx].parents.clone();
+
+                for p in parents {
+                    if p < len { // If p >= len, the target will be discarded anyway.
+                        let parent_entry = hist_entries.find_mut(p).unwrap().0;
+                        while let Some(&c_idx) = parent_entry.child_indexes.last() {
+                            if c_idx >= first_truncated_idx {
+                                parent_entry.child_indexes.pop();
+                            } else { break; }
+                        }
+                    }
+                }
+
+                idx += 1;
+            }
+
+            if first_truncated_idx > first_idx {
+                // Children of the trimmed entry might have named a version in its discarded tail.
+                hist_entries.0[first_idx].child_indexes.retain(|c| *c < first_truncated_idx);
+            }
+
+            self.graph.entries.0.truncate(first_truncated_idx);
+
+            while let Some(&last_idx) = self.graph.root_child_indexes.last() {
+                if last_idx >= self.graph.entries.num_entries() {
+                    self.graph.root_child_indexes.pop();
+                } else { break; }
+            }
+        }
+    }
+
     pub fn diff_since_rev(&self, frontier: &[LV]) -> SmallVec<DTRange, 4> {
         let (only_a, only_b) = self.graph.diff_rev(frontier, self.version.as_ref());
         debug_assert!(only_a.is_empty());
@@ -275,6 +405,7 @@ impl CausalGraph {
 #[cfg(test)]
 mod tests {
     use crate::CausalGraph;
+    use crate::encoding::parseerror::ParseError;
 
     #[test]
     fn merge_and_assign_updates_version() {
@@ -291,4 +422,25 @@ mod tests {
         cg.merge_and_assign(&[4], (agent, 5..15).into());
         cg.dbg_check(true);
     }
+
+    #[test]
+    fn merge_and_assign_checked() {
+        let mut cg = CausalGraph::new();
+        let seph = cg.get_or_create_agent_id("seph");
+        let mike = cg.get_or_create_agent_id("mike");
+        cg.merge_and_assign(&[], (seph, 0..10).into());
+        cg.merge_and_assign(&[], (mike, 0..2).into());
+
+        assert_eq!(cg.merge_and_assign_checked(&[4], (seph, 5..10).into()), Ok((12..12).into()));
+        assert_eq!(cg.merge_and_assign_checked(&[3], (seph, 5..10).into()),
+                   Err(ParseError::IdReused { agent: seph, seq_range: (5..10).into() }));
+        assert_eq!(cg.merge_and_assign_checked(&[11], (seph, 0..3).into()),
+                   Err(ParseError::IdReused { agent: seph, seq_range: (0..3).into() }));
+        assert_eq!(cg.len(), 12);
+
+        assert_eq!(cg.merge_and_assign_checked(&[4], (seph, 5..15).into()), Ok((12..17).into()));
+        assert_eq!(cg.merge_and_assign_checked(&[9, 11], (seph, 10..11).into()),
+                   Err(ParseError::IdReused { agent: seph, seq_range: (10..11).into() }));
+        cg.dbg_check(true);
+    }
 }
\ No newline at end of file
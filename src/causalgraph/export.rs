@@ -0,0 +1,329 @@
+//! Built-in exporters for the causal graph. Unlike the DOT output in `dot.rs`, these don't need
+//! graphviz (or any other external tool) to be useful, so they work in CI and from wasm.
+//!
+//! The graph is exported with one node per run of versions from the same agent. Edges point from
+//! each node to its parents, the same way they do in the DOT output.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use rle::HasLength;
+use smartstring::alias::String as SmartString;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{CausalGraph, DTRange, LV};
+use crate::rle::KVPair;
+
+/// Node fill colours, picked by agent. The first two match the green and blue used in the DOT
+/// merge graphs.
+const AGENT_COLORS: [&str; 6] = ["#98ea79", "#84a7e8", "#f7b267", "#e88484", "#c49be8", "#7fd6cf"];
+
+/// Fill colour used when nodes aren't coloured by agent. (The same as `DotColor::Grey`).
+const DEFAULT_COLOR: &str = "#eeeeee";
+
+/// A run of versions from a single agent, with the same parents.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct GraphExportNode {
+    /// The node's ID. This is the last local version in the span, which is what the node's
+    /// children name in their parents.
+    pub id: LV,
+    /// The local versions in this node.
+    pub span: DTRange,
+    /// The name of the agent which made these changes.
+    pub agent: SmartString,
+    /// The agent's sequence numbers for the changes.
+    pub seq_range: DTRange,
+    /// A fill colour for the node, picked based on the agent.
+    pub color: &'static str,
+}
+
+/// An edge from a node to one of its parents. Both ends name node IDs.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct GraphExportEdge {
+    pub from: LV,
+    pub to: LV,
+}
+
+/// A node / edge list describing (part of) a causal graph. This is generated by
+/// [`CausalGraph::export_graph`], and can be written out as JSON, GraphML or SVG.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct GraphExport {
+    /// Nodes in local version order. Parents always come before their children.
+    pub nodes: Vec<GraphExportNode>,
+    pub edges: Vec<GraphExportEdge>,
+}
+
+impl CausalGraph {
+    /// Export the causal graph (up to version `v`, or the whole graph if `v` is None) as a list of
+    /// nodes and edges.
+    pub fn export_graph(&self, v: Option<&[LV]>) -> GraphExport {
+        let subgraph = self.graph.make_simple_graph(v.unwrap_or(self.version.as_ref()));
+        let mut result = GraphExport::default();
+
+        for entry in subgraph.iter() {
+            let mut start = entry.span.start;
+            let mut prev: Option<LV> = None;
+
+            // Split each entry up by agent.
+            for KVPair(_, agent_span) in self.agent_assignment.client_with_lv.iter_range(entry.span) {
+                let span: DTRange = (start..start + agent_span.len()).into();
+                let id = span.last();
+
+                result.nodes.push(GraphExportNode {
+                    id,
+                    span,
+                    agent: self.agent_assignment.get_agent_name(agent_span.agent).into(),
+                    seq_range: agent_span.seq_range,
+                    color: AGENT_COLORS[agent_span.agent as usize % AGENT_COLORS.len()],
+                });
+
+                if let Some(prev) = prev {
+                    result.edges.push(GraphExportEdge { from: id, to: prev });
+                } else {
+                    for &p in entry.parents.iter() {
+                        result.edges.push(GraphExportEdge { from: id, to: p });
+                    }
+                }
+
+                prev = Some(id);
+                start = span.end;
+            }
+        }
+
+        result
+    }
+}
+
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { write!(out, "\\u{:04x}", c as u32).unwrap(); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Same as the labels in the DOT output.
+fn node_label(node: &GraphExportNode) -> String {
+    format!("{} (Len {})", node.span.start, node.span.len())
+}
+
+impl GraphExport {
+    /// Write the graph as JSON, in the form `{"nodes": [...], "edges": [...]}`. This is the same
+    /// format serde produces, and it can be used directly by d3 (or the `vis/` tool).
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\"nodes\":[");
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i > 0 { out.push(','); }
+            write!(&mut out, "{{\"id\":{},\"span\":[{},{}],\"agent\":", node.id, node.span.start, node.span.end).unwrap();
+            write_json_str(&mut out, &node.agent);
+            write!(&mut out, ",\"seq_range\":[{},{}],\"color\":\"{}\"}}", node.seq_range.start, node.seq_range.end, node.color).unwrap();
+        }
+        out.push_str("],\"edges\":[");
+        for (i, edge) in self.edges.iter().enumerate() {
+            if i > 0 { out.push(','); }
+            write!(&mut out, "{{\"from\":{},\"to\":{}}}", edge.from, edge.to).unwrap();
+        }
+        out.push_str("]}");
+        out
+    }
+
+    /// Write the graph as GraphML. Each node has its label, agent, sequence numbers and colour
+    /// attached as data.
+    pub fn to_graphml(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        out.push_str("  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n");
+        out.push_str("  <key id=\"agent\" for=\"node\" attr.name=\"agent\" attr.type=\"string\"/>\n");
+        out.push_str("  <key id=\"seq_start\" for=\"node\" attr.name=\"seq_start\" attr.type=\"long\"/>\n");
+        out.push_str("  <key id=\"seq_end\" for=\"node\" attr.name=\"seq_end\" attr.type=\"long\"/>\n");
+        out.push_str("  <key id=\"color\" for=\"node\" attr.name=\"color\" attr.type=\"string\"/>\n");
+        out.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+
+        for node in &self.nodes {
+            writeln!(&mut out, "    <node id=\"n{}\">", node.id).unwrap();
+            writeln!(&mut out, "      <data key=\"label\">{}</data>", node_label(node)).unwrap();
+            writeln!(&mut out, "      <data key=\"agent\">{}</data>", xml_escape(&node.agent)).unwrap();
+            writeln!(&mut out, "      <data key=\"seq_start\">{}</data>", node.seq_range.start).unwrap();
+            writeln!(&mut out, "      <data key=\"seq_end\">{}</data>", node.seq_range.end).unwrap();
+            writeln!(&mut out, "      <data key=\"color\">{}</data>", node.color).unwrap();
+            out.push_str("    </node>\n");
+        }
+        for edge in &self.edges {
+            writeln!(&mut out, "    <edge source=\"n{}\" target=\"n{}\"/>", edge.from, edge.to).unwrap();
+        }
+
+        out.push_str("  </graph>\n");
+        out.push_str("</graphml>\n");
+        out
+    }
+
+    /// Render the graph as an SVG image. Nodes are placed in rows based on their longest path
+    /// from the root, with the oldest changes at the bottom (like the DOT output).
+    ///
+    /// The layout is very simple. Its fine for small graphs (up to a few hundred nodes), but edges
+    /// will cross each other a lot in bigger or more complex graphs. Use graphviz for those.
+    pub fn to_svg(&self, color_by_agent: bool) -> String {
+        const NODE_HEIGHT: usize = 24;
+        const ROW_HEIGHT: usize = 64;
+        const GAP: usize = 16;
+        const MARGIN: usize = 10;
+        const CHAR_WIDTH: usize = 7;
+
+        let index: HashMap<LV, usize> = self.nodes.iter().enumerate()
+            .map(|(i, node)| (node.id, i))
+            .collect();
+
+        // Nodes are in topological order, so we can figure out the rows in one pass.
+        let mut rows = vec![0usize; self.nodes.len()];
+        for edge in &self.edges {
+            let (Some(&from), Some(&to)) = (index.get(&edge.from), index.get(&edge.to)) else { continue; };
+            rows[from] = rows[from].max(rows[to] + 1);
+        }
+        let num_rows = rows.iter().max().map_or(0, |r| r + 1);
+
+        let widths: Vec<usize> = self.nodes.iter()
+            .map(|node| node_label(node).len() * CHAR_WIDTH + 2 * GAP)
+            .collect();
+
+        // Place nodes left to right in each row, in local version order.
+        let mut row_x = vec![MARGIN; num_rows];
+        let mut positions = vec![(0, 0); self.nodes.len()];
+        for (i, &row) in rows.iter().enumerate() {
+            let y = MARGIN + (num_rows - 1 - row) * ROW_HEIGHT;
+            positions[i] = (row_x[row], y);
+            row_x[row] += widths[i] + GAP;
+        }
+
+        let width = row_x.iter().copied().max().unwrap_or(MARGIN) + MARGIN;
+        let height = num_rows.saturating_sub(1) * ROW_HEIGHT + NODE_HEIGHT + 2 * MARGIN;
+
+        let mut out = String::new();
+        writeln!(&mut out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">").unwrap();
+        out.push_str("<g stroke=\"#333333\" stroke-width=\"1\">\n");
+        for edge in &self.edges {
+            let (Some(&from), Some(&to)) = (index.get(&edge.from), index.get(&edge.to)) else { continue; };
+            let (fx, fy) = positions[from];
+            let (tx, ty) = positions[to];
+            writeln!(&mut out, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>",
+                fx + widths[from] / 2, fy + NODE_HEIGHT, tx + widths[to] / 2, ty).unwrap();
+        }
+        out.push_str("</g>\n");
+
+        out.push_str("<g font-family=\"monospace\" font-size=\"12\" text-anchor=\"middle\">\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let (x, y) = positions[i];
+            let fill = if color_by_agent { node.color } else { DEFAULT_COLOR };
+            writeln!(&mut out, "<g><title>{} {}..{}</title>", xml_escape(&node.agent), node.seq_range.start, node.seq_range.end).unwrap();
+            writeln!(&mut out, "<rect x=\"{x}\" y=\"{y}\" width=\"{}\" height=\"{NODE_HEIGHT}\" fill=\"{fill}\" stroke=\"black\"/>", widths[i]).unwrap();
+            writeln!(&mut out, "<text x=\"{}\" y=\"{}\">{}</text></g>", x + widths[i] / 2, y + NODE_HEIGHT / 2 + 4, node_label(node)).unwrap();
+        }
+        out.push_str("</g>\n");
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::CausalGraph;
+    use crate::causalgraph::export::{GraphExportEdge, GraphExportNode, AGENT_COLORS};
+
+    fn example_graph() -> CausalGraph {
+        let mut cg = CausalGraph::new();
+        let seph = cg.get_or_create_agent_id("seph");
+        let mike = cg.get_or_create_agent_id("mike \"m\" <&>");
+
+        cg.assign_local_op_with_parents(&[], seph, 2);
+        cg.assign_local_op_with_parents(&[1], mike, 2);
+        cg.assign_local_op_with_parents(&[1], seph, 1);
+        cg.assign_local_op_with_parents(&[3, 4], seph, 1);
+        cg
+    }
+
+    #[test]
+    fn export_nodes_and_edges() {
+        let cg = example_graph();
+        let graph = cg.export_graph(None);
+
+        assert_eq!(graph.nodes, vec![
+            GraphExportNode { id: 1, span: (0..2).into(), agent: "seph".into(), seq_range: (0..2).into(), color: AGENT_COLORS[0] },
+            GraphExportNode { id: 3, span: (2..4).into(), agent: "mike \"m\" <&>".into(), seq_range: (0..2).into(), color: AGENT_COLORS[1] },
+            GraphExportNode { id: 4, span: (4..5).into(), agent: "seph".into(), seq_range: (2..3).into(), color: AGENT_COLORS[0] },
+            GraphExportNode { id: 5, span: (5..6).into(), agent: "seph".into(), seq_range: (3..4).into(), color: AGENT_COLORS[0] },
+        ]);
+        assert_eq!(graph.edges, vec![
+            GraphExportEdge { from: 3, to: 1 },
+            GraphExportEdge { from: 4, to: 1 },
+            GraphExportEdge { from: 5, to: 3 },
+            GraphExportEdge { from: 5, to: 4 },
+        ]);
+
+        // Truncating the graph only exports the history of the named version.
+        assert_eq!(cg.export_graph(Some(&[3])).nodes.len(), 2);
+    }
+
+    #[test]
+    fn export_json() {
+        let json = example_graph().export_graph(Some(&[3])).to_json();
+        assert_eq!(json, concat!(
+            r##"{"nodes":[{"id":1,"span":[0,2],"agent":"seph","seq_range":[0,2],"color":"#98ea79"},"##,
+            r##"{"id":3,"span":[2,4],"agent":"mike \"m\" <&>","seq_range":[0,2],"color":"#84a7e8"}],"##,
+            r##""edges":[{"from":3,"to":1}]}"##,
+        ));
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn export_json_matches_serde() {
+        let graph = example_graph().export_graph(None);
+        let from_serde: serde_json::Value = serde_json::to_value(&graph).unwrap();
+        let ours: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        assert_eq!(from_serde, ours);
+    }
+
+    #[test]
+    fn export_graphml_and_svg() {
+        let graph = example_graph().export_graph(None);
+
+        let graphml = graph.to_graphml();
+        assert!(graphml.contains("<node id=\"n5\">"));
+        assert!(graphml.contains("<edge source=\"n5\" target=\"n4\"/>"));
+        assert!(graphml.contains("mike &quot;m&quot; &lt;&amp;&gt;"));
+
+        let svg = graph.to_svg(true);
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<rect").count(), 4);
+        assert_eq!(svg.matches("<line").count(), 4);
+        assert!(svg.contains(AGENT_COLORS[1]));
+        assert!(!graph.to_svg(false).contains(AGENT_COLORS[1]));
+    }
+}
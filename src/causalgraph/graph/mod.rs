@@ -2,7 +2,7 @@
 /// parents information.
 
 pub(crate) mod tools;
-mod scope;
+pub(crate) mod scope;
 mod check;
 mod subgraph;
 mod simple;
@@ -60,6 +60,20 @@ impl Graph {
         entry.clone_parents_at_version(v)
     }
 
+    /// Returns true if the graph stores the span of versions with the named (sorted) parents. That
+    /// is, the first version in the span has `parents`, and each subsequent version's parent is the
+    /// version before it.
+    pub(crate) fn has_entry_with_parents(&self, parents: &[LV], span: DTRange) -> bool {
+        let mut expected = parents;
+        let mut prev;
+        for e in self.iter_range(span) {
+            if e.parents.as_ref() != expected { return false; }
+            prev = [e.span.last()];
+            expected = &prev;
+        }
+        true
+    }
+
     pub fn with_parents<F: FnOnce(&[LV]) -> G, G>(&self, v: LV, f: F) -> G {
         let entry = self.entries.find_packed(v);
         entry.with_parents(v, f)
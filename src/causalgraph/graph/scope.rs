@@ -1,4 +1,11 @@
-//! TODO: This code is not currently used. Use it or remove it!
+//! Scopes track the part of the causal graph which belongs to a single CRDT. They let us
+//! project document versions down onto one CRDT's history, so merging or diffing a single text
+//! only transforms that text's operations.
+//!
+//! The projection itself isn't free. When a version doesn't land directly on one of the CRDT's
+//! operations, [`Graph::version_in_scope`] walks the document's causal graph backwards until it
+//! does. So operations on other CRDTs still cost something when they're interleaved with the
+//! CRDT's own history.
 
 use std::collections::BinaryHeap;
 use smallvec::smallvec;
@@ -8,21 +15,45 @@ use crate::causalgraph::graph::tools::DiffFlag::{OnlyA, OnlyB, Shared};
 use crate::frontier::debug_assert_sorted;
 
 /// A scope is a part of history attached to a specific CRDT
-
-// TODO: Move me!
 #[derive(Debug, Clone)]
 pub(crate) struct ScopedParents {
+    /// The version of the operation which created this CRDT, or [`ROOT_SCOPE_TIME`] if the CRDT
+    /// has existed since the start of time.
     pub(crate) created_at: LV,
 
     /// This isn't a real Version. Its a list of times at which this CRDT was deleted.
-    ///
-    /// (What do we need this for??)
     pub(crate) deleted_at: Frontier,
 
+    /// The versions of all the operations which modified this CRDT.
     pub(crate) owned_times: RleVec<DTRange>,
 }
 
+impl Default for ScopedParents {
+    fn default() -> Self {
+        Self::new(ROOT_SCOPE_TIME)
+    }
+}
+
 impl ScopedParents {
+    pub(crate) fn new(created_at: LV) -> Self {
+        Self {
+            created_at,
+            deleted_at: Frontier::root(),
+            owned_times: RleVec::new(),
+        }
+    }
+
+    /// Mark the (local) versions in `range` as belonging to this scope. Ranges must be pushed in
+    /// order.
+    pub(crate) fn push_owned(&mut self, range: DTRange) {
+        self.owned_times.push(range);
+    }
+
+    /// Record that this CRDT was deleted (or overwritten) by the operation at version `v`.
+    pub(crate) fn mark_deleted(&mut self, v: LV) {
+        self.deleted_at.insert(v);
+    }
+
     pub(crate) fn exists_at(&self, graph: &Graph, version: &[LV]) -> bool {
         // If the item has not been created yet, return None.
         if !graph.frontier_contains_version(version, self.created_at) {
@@ -42,20 +73,21 @@ impl ScopedParents {
     }
 }
 
-// TODO: Remove this.
-const OLD_INVALID_ROOT_TIME: usize = usize::MAX;
+/// Creation time used for scopes which aren't created by any operation (like the root CRDT).
+pub(crate) const ROOT_SCOPE_TIME: LV = usize::MAX;
 
 impl Graph {
+    /// Project `version` onto the history of the named scope. The result is the frontier of the
+    /// scope's own operations which are included in `version`. Returns `None` if `version` is
+    /// entirely before the CRDT was created.
     pub(crate) fn version_in_scope(&self, version: &[LV], info: &ScopedParents) -> Option<Frontier> {
-        debug_assert_ne!(info.created_at, OLD_INVALID_ROOT_TIME);
-
         // If v == creation time, its a bit hacky but I still consider that a valid version, because
         // the CRDT has a value then (the default value for the CRDT).
         debug_assert_sorted(version);
 
         let Some(&highest_time) = version.last() else {
             // The root item has a creation time at the root time. But nothing else exists then.
-            return if info.created_at == OLD_INVALID_ROOT_TIME {
+            return if info.created_at == ROOT_SCOPE_TIME {
                 Some(Frontier::root())
             } else {
                 None
@@ -63,7 +95,7 @@ impl Graph {
         };
 
         // let info = &oplog.items[item];
-        if info.created_at != OLD_INVALID_ROOT_TIME && highest_time < info.created_at {
+        if info.created_at != ROOT_SCOPE_TIME && highest_time < info.created_at {
             // If the version exists entirely before this root was created, there is no common
             // ancestor.
             return None;
@@ -95,7 +127,7 @@ impl Graph {
 
         for &t in version {
             // Append children so long as they aren't earlier than the item's ctime.
-            if info.created_at == OLD_INVALID_ROOT_TIME || t >= info.created_at {
+            if info.created_at == ROOT_SCOPE_TIME || t >= info.created_at {
                 queue.push((t, OnlyA));
             }
         }
@@ -156,6 +188,8 @@ impl Graph {
 
             containing_txn.with_parents(base, |parents| {
                 for &p in parents {
+                    // Nothing before the CRDT was created can be in its scope.
+                    if info.created_at != ROOT_SCOPE_TIME && p < info.created_at { continue; }
                     queue.push((p, flag));
                     if flag == Shared { num_shared_entries += 1; }
                 }
@@ -169,5 +203,36 @@ impl Graph {
         debug_assert_sorted(&result);
         Some(Frontier(result))
     }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::causalgraph::graph::random_graphs::with_random_cgs;
+    use crate::causalgraph::graph::scope::ScopedParents;
+    use crate::Frontier;
+
+    #[test]
+    fn version_in_scope_matches_projection() {
+        with_random_cgs(4321, (100, 10), |(_i, _k), cg, frontiers| {
+            let graph = &cg.graph;
+            let len = cg.len();
+
+            for created_at in [0, len / 3, len / 2] {
+                // Own every third operation which comes causally after the creation time.
+                let mut scope = ScopedParents::new(created_at);
+                for v in created_at + 1..len {
+                    if v % 3 == 0 && graph.frontier_contains_version(&[v], created_at) {
+                        scope.push_owned((v..v + 1).into());
+                    }
+                }
 
-}
\ No newline at end of file
+                for f in frontiers {
+                    let expected = graph.project_onto_subgraph(&scope.owned_times.0, f.as_ref());
+                    let actual = graph.version_in_scope(f.as_ref(), &scope)
+                        .unwrap_or_else(Frontier::root);
+                    assert_eq!(actual, expected);
+                }
+            }
+        });
+    }
+}
@@ -10,6 +10,8 @@ pub mod graph;
 mod eq;
 pub mod entry;
 pub mod summary;
+pub mod query;
+pub mod export;
 pub mod agent_span;
 pub mod agent_assignment;
 
@@ -0,0 +1,275 @@
+//! Read-only queries about the shape of a causal graph. These are useful for tools which need to
+//! explain a merge to users - like which changes were concurrent, and where two versions diverged.
+
+use std::cmp::Ordering;
+
+use rle::{AppendRle, HasLength};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{CausalGraph, DTRange, Frontier, LV};
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionSpan;
+use crate::causalgraph::graph::GraphEntrySimple;
+use crate::causalgraph::graph::tools::DiffFlag;
+
+/// Which side of a conflict a span of changes is on. See [`CausalGraph::conflict_report`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum ConflictSide {
+    /// The changes are only in the first version (`a`).
+    OnlyA,
+    /// The changes are only in the second version (`b`).
+    OnlyB,
+    /// The changes are in both versions, but they're after the common ancestor.
+    Shared,
+}
+
+impl From<DiffFlag> for ConflictSide {
+    fn from(flag: DiffFlag) -> Self {
+        match flag {
+            DiffFlag::OnlyA => ConflictSide::OnlyA,
+            DiffFlag::OnlyB => ConflictSide::OnlyB,
+            DiffFlag::Shared => ConflictSide::Shared,
+        }
+    }
+}
+
+/// A run of changes from a single agent inside a conflict zone.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ConflictSpan<'a> {
+    pub side: ConflictSide,
+    /// The local versions of the changes.
+    pub span: DTRange,
+    /// The agent which made the changes, and their sequence numbers.
+    pub remote: RemoteVersionSpan<'a>,
+}
+
+/// A description of the region of history which needs to be merged to combine two versions.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ConflictReport<'a> {
+    /// Every change in the conflict zone comes after this version.
+    pub common_ancestor: Frontier,
+    /// All the changes in the conflict zone, in local version order.
+    pub spans: Vec<ConflictSpan<'a>>,
+}
+
+fn ranges_contain(ranges: &[DTRange], v: LV) -> bool {
+    ranges.binary_search_by(|r| {
+        if r.end <= v { Ordering::Less }
+        else if r.start > v { Ordering::Greater }
+        else { Ordering::Equal }
+    }).is_ok()
+}
+
+impl CausalGraph {
+    /// Returns true if `ancestor` is in the history of `v`. Like `git merge-base --is-ancestor`, a
+    /// version counts as its own ancestor.
+    pub fn is_ancestor(&self, ancestor: LV, v: LV) -> bool {
+        matches!(self.graph.version_cmp(ancestor, v), Some(Ordering::Less | Ordering::Equal))
+    }
+
+    /// Returns true if every version in the `ancestor` frontier is in the history of `v`.
+    pub fn frontier_is_ancestor(&self, ancestor: &[LV], v: &[LV]) -> bool {
+        self.graph.frontier_contains_frontier(v, ancestor)
+    }
+
+    /// Find the lowest common ancestors of two frontiers. This is the frontier naming exactly the
+    /// set of versions which are in the history of both `a` and `b`.
+    ///
+    /// If `a` is an ancestor of `b`, this returns `a`. If `a` and `b` share no history, this
+    /// returns the root version.
+    pub fn lowest_common_ancestors(&self, a: &[LV], b: &[LV]) -> Frontier {
+        // Every maximal common version is either named directly in a or b, or its the parent of
+        // some change which is only in one of the two versions.
+        let (only_a, only_b) = self.graph.diff(a, b);
+
+        let mut candidates: Vec<LV> = a.iter().chain(b.iter()).copied().collect();
+        for range in only_a.iter().chain(only_b.iter()) {
+            for entry in self.graph.iter_range(*range) {
+                candidates.extend_from_slice(entry.parents.as_ref());
+            }
+        }
+
+        candidates.retain(|v| {
+            self.graph.frontier_contains_version(a, *v) && self.graph.frontier_contains_version(b, *v)
+        });
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        self.graph.find_dominators(&candidates)
+    }
+
+    /// List all the versions in the graph which are concurrent with `v`. That is, all the versions
+    /// which are neither ancestors nor descendants of `v`. The result is in ascending order.
+    pub fn concurrent_with(&self, v: LV) -> Vec<DTRange> {
+        let (not_in_v, _) = self.graph.diff(self.version.as_ref(), &[v]);
+
+        // All descendants of v are outside v's history. Local versions are always in causal
+        // order, so we can find them with a single forward scan.
+        let mut descendants: Vec<DTRange> = vec![];
+        let mut result: Vec<DTRange> = vec![];
+
+        for range in not_in_v {
+            for entry in self.graph.iter_range(range) {
+                let is_descendant = entry.parents.iter()
+                    .any(|p| *p == v || ranges_contain(&descendants, *p));
+
+                if is_descendant { descendants.push_rle(entry.span); }
+                else { result.push_rle(entry.span); }
+            }
+        }
+
+        result
+    }
+
+    /// Iterate through all the changes in `to` which aren't in `from`, along with their parents.
+    /// Changes are yielded in a topological order (every change comes after all of its parents).
+    pub fn iter_between(&self, from: &[LV], to: &[LV]) -> impl Iterator<Item = GraphEntrySimple> + '_ {
+        let (_, only_to) = self.graph.diff(from, to);
+        only_to.into_iter().flat_map(move |range| self.graph.iter_range(range))
+    }
+
+    /// Describe the conflict zone which needs to be merged to combine versions `a` and `b`, with
+    /// each span of changes attributed to the agent which made it.
+    pub fn conflict_report(&self, a: &[LV], b: &[LV]) -> ConflictReport<'_> {
+        let mut rev_spans: Vec<(DTRange, DiffFlag)> = vec![];
+        let common_ancestor = self.graph.find_conflicting(a, b, |span, flag| {
+            rev_spans.push((span, flag));
+        });
+
+        let mut spans: Vec<ConflictSpan> = vec![];
+        for (range, flag) in rev_spans.into_iter().rev() {
+            let mut start = range.start;
+            for remote in self.agent_assignment.iter_remote_mappings_range(range) {
+                let len = remote.1.len();
+                spans.push(ConflictSpan {
+                    side: flag.into(),
+                    span: (start..start + len).into(),
+                    remote,
+                });
+                start += len;
+            }
+        }
+
+        ConflictReport { common_ancestor, spans }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rle::AppendRle;
+    use crate::{CausalGraph, DTRange, Frontier, LV};
+    use crate::causalgraph::graph::random_graphs::with_random_cgs;
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionSpan;
+    use crate::causalgraph::graph::GraphEntrySimple;
+    use crate::causalgraph::query::{ConflictReport, ConflictSide, ConflictSpan};
+
+    // 0-1 (seph) and 2-3 (mike) are concurrent. 4 (seph) merges them, 5 (mike) follows. 6 (kaarina)
+    // only knows about 0-1.
+    fn example_graph() -> CausalGraph {
+        let mut cg = CausalGraph::new();
+        let seph = cg.get_or_create_agent_id("seph");
+        let mike = cg.get_or_create_agent_id("mike");
+        let kaarina = cg.get_or_create_agent_id("kaarina");
+
+        cg.assign_local_op_with_parents(&[], seph, 2);
+        cg.assign_local_op_with_parents(&[], mike, 2);
+        cg.assign_local_op_with_parents(&[1, 3], seph, 1);
+        cg.assign_local_op_with_parents(&[4], mike, 1);
+        cg.assign_local_op_with_parents(&[1], kaarina, 1);
+        cg
+    }
+
+    #[test]
+    fn ancestry() {
+        let cg = example_graph();
+        assert!(cg.is_ancestor(1, 4));
+        assert!(cg.is_ancestor(4, 4));
+        assert!(!cg.is_ancestor(4, 1));
+        assert!(!cg.is_ancestor(3, 6));
+
+        assert!(cg.frontier_is_ancestor(&[1, 3], &[5]));
+        assert!(cg.frontier_is_ancestor(&[], &[6]));
+        assert!(!cg.frontier_is_ancestor(&[3, 6], &[5]));
+    }
+
+    #[test]
+    fn lowest_common_ancestors() {
+        let cg = example_graph();
+        assert_eq!(cg.lowest_common_ancestors(&[1], &[3]), Frontier::root());
+        assert_eq!(cg.lowest_common_ancestors(&[4], &[6]), Frontier::new_1(1));
+        assert_eq!(cg.lowest_common_ancestors(&[5], &[4]), Frontier::new_1(4));
+        assert_eq!(cg.lowest_common_ancestors(&[5], &[1, 3]), Frontier::from_sorted(&[1, 3]));
+        assert_eq!(cg.lowest_common_ancestors(&[5], &[3, 6]), Frontier::from_sorted(&[1, 3]));
+        assert_eq!(cg.lowest_common_ancestors(&[0], &[2]), Frontier::root());
+    }
+
+    #[test]
+    fn fuzz_lowest_common_ancestors() {
+        with_random_cgs(432, (100, 10), |_i, cg, frontiers| {
+            for a in frontiers {
+                for b in frontiers {
+                    // Brute force: find every version in both histories.
+                    let common: Vec<LV> = (0..cg.len())
+                        .filter(|v| cg.graph.frontier_contains_version(a.as_ref(), *v)
+                            && cg.graph.frontier_contains_version(b.as_ref(), *v))
+                        .collect();
+                    let expected = cg.graph.find_dominators(&common);
+                    assert_eq!(cg.lowest_common_ancestors(a.as_ref(), b.as_ref()), expected);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn fuzz_concurrent_with() {
+        with_random_cgs(321, (30, 10), |_i, cg, _frontiers| {
+            for v in 0..cg.len() {
+                let mut expected: Vec<DTRange> = vec![];
+                for w in 0..cg.len() {
+                    if cg.graph.version_cmp(v, w).is_none() { expected.push_rle(w.into()); }
+                }
+                assert_eq!(cg.concurrent_with(v), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn concurrent_with() {
+        let cg = example_graph();
+        assert_eq!(cg.concurrent_with(6), vec![DTRange::from(2..6)]);
+        assert_eq!(cg.concurrent_with(0), vec![DTRange::from(2..4)]);
+        assert_eq!(cg.concurrent_with(3), vec![DTRange::from(0..2), DTRange::from(6..7)]);
+        assert_eq!(cg.concurrent_with(5), vec![DTRange::from(6..7)]);
+    }
+
+    #[test]
+    fn iter_between() {
+        let cg = example_graph();
+        let entries: Vec<_> = cg.iter_between(&[1], &[5]).collect();
+        assert_eq!(entries, vec![
+            GraphEntrySimple { span: (2..4).into(), parents: Frontier::root() },
+            // 5 directly follows 4, so they're stored in the same entry.
+            GraphEntrySimple { span: (4..6).into(), parents: Frontier::from_sorted(&[1, 3]) },
+        ]);
+
+        assert_eq!(cg.iter_between(&[5], &[1]).count(), 0);
+    }
+
+    #[test]
+    fn conflict_report() {
+        let cg = example_graph();
+        let report = cg.conflict_report(&[4], &[6]);
+        assert_eq!(report, ConflictReport {
+            common_ancestor: Frontier::root(),
+            spans: vec![
+                ConflictSpan { side: ConflictSide::Shared, span: (0..2).into(), remote: RemoteVersionSpan("seph", (0..2).into()) },
+                ConflictSpan { side: ConflictSide::OnlyA, span: (2..4).into(), remote: RemoteVersionSpan("mike", (0..2).into()) },
+                ConflictSpan { side: ConflictSide::OnlyA, span: (4..5).into(), remote: RemoteVersionSpan("seph", (2..3).into()) },
+                ConflictSpan { side: ConflictSide::OnlyB, span: (6..7).into(), remote: RemoteVersionSpan("kaarina", (0..1).into()) },
+            ],
+        });
+    }
+}
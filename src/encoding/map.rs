@@ -156,7 +156,7 @@ impl WriteMap {
         })
     }
 
-    pub(super) fn seq_delta(&mut self, agent: AgentId, span: DTRange, persist: bool) -> isize {
+    pub(crate) fn seq_delta(&mut self, agent: AgentId, span: DTRange, persist: bool) -> isize {
         let agent = agent as usize;
         self.ensure_capacity(agent + 1);
 
@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use crate::causalgraph::agent_assignment::remote_ids::VersionConversionError;
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersionSpan, VersionConversionError};
+use crate::{AgentId, CausalGraph, DTRange};
 
 
 // #[derive(Debug)]
@@ -49,6 +50,45 @@ pub enum ParseError {
     /// I'd like to explicitly support this case, and allow the oplog to contain a somewhat- sparse
     /// set of data, and load more as needed.
     DataMissing,
+
+    /// The operations were rejected by an [`OpValidator`](crate::validate::OpValidator).
+    OperationRejected,
+
+    /// An operation's position is outside the document at the operation's parent version. This is
+    /// only checked when [`DecodeOptions::check_positions`](crate::list::encoding::DecodeOptions::check_positions)
+    /// is set.
+    OperationOutOfBounds,
+
+    /// A signature didn't match the agent's key or the operations it covers. The operations may
+    /// have been forged.
+    InvalidSignature,
+
+    /// New operations from an agent in the keyring weren't signed.
+    MissingSignature,
+
+    /// An agent signed two different sets of operations with the same (agent, seq) IDs.
+    Equivocation,
+
+    /// The same (agent, seq) IDs were used for operations which don't match the operations we
+    /// already have with those IDs. Merging them would make peers silently diverge.
+    ///
+    /// `agent` is an agent ID local to the oplog which returned the error, so it means nothing to
+    /// other peers. Use [`ParseError::reused_remote_span`] to look up the agent's name.
+    IdReused { agent: AgentId, seq_range: DTRange },
+}
+
+impl ParseError {
+    /// For [`ParseError::IdReused`] errors, returns the reused IDs as an (agent name, seq range)
+    /// pair which is meaningful to other peers. `cg` must be the causal graph of the oplog which
+    /// returned the error. Returns None for other errors.
+    pub fn reused_remote_span<'a>(&self, cg: &'a CausalGraph) -> Option<RemoteVersionSpan<'a>> {
+        match *self {
+            ParseError::IdReused { agent, seq_range } => {
+                Some(RemoteVersionSpan(cg.agent_assignment.get_agent_name(agent), seq_range))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Display for ParseError {
@@ -237,8 +237,11 @@ mod oplog;
 #[cfg(feature = "storage")]
 mod storage;
 mod simple_checkout;
-// mod listmerge2;
+#[cfg(feature = "listmerge2")]
+mod listmerge2;
 mod stats;
+mod selective_sync;
+pub mod validate;
 
 pub type AgentId = u32;
 
@@ -481,6 +484,23 @@ pub struct SerializedOpsOwned {
     text_context: ListOperationCtx,
 }
 
+impl SerializedOpsOwned {
+    /// Borrow the owned operations so they can be passed to [`OpLog::merge_ops`]. This is useful
+    /// when the operations were deserialized into owned values.
+    pub fn as_ops(&self) -> SerializedOps<'_> {
+        SerializedOps {
+            cg_changes: self.cg_changes.clone(),
+            map_ops: self.map_ops.iter().map(|(crdt_name, rv, key, val)| {
+                (crdt_name.into(), rv.into(), key.as_str(), val.clone())
+            }).collect(),
+            text_ops: self.text_ops.iter().map(|(crdt_name, rv, metrics)| {
+                (crdt_name.into(), rv.into(), metrics.clone())
+            }).collect(),
+            text_context: self.text_context.clone(),
+        }
+    }
+}
+
 /// This is used for checkouts. This is a value tree.
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
@@ -8,6 +8,7 @@ use crate::list::operation::{TextOperation, ListOpKind};
 use crate::dtrange::DTRange;
 use crate::{AgentId, Frontier, LV};
 use crate::causalgraph::agent_assignment::remote_ids::RemoteFrontier;
+use crate::list::pos_unit::{PosUnit, rope_chars_to_unit, rope_try_unit_to_chars, rope_unit_range_to_chars, rope_unit_to_chars};
 
 impl ListBranch {
     /// Create a new (empty) branch at the start of history. The branch will be an empty list.
@@ -15,6 +16,7 @@ impl ListBranch {
         Self {
             version: Frontier::root(),
             content: JumpRopeBuf::new(),
+            merge_ctx: None,
         }
     }
 
@@ -122,17 +124,53 @@ impl ListBranch {
 
     #[cfg(feature = "wchar_conversion")]
     pub fn insert_at_wchar(&mut self, oplog: &mut ListOpLog, agent: AgentId, wchar_pos: usize, ins_content: &str) -> LV {
-        let char_pos = self.content.borrow().wchars_to_chars(wchar_pos);
-        self.insert(oplog, agent, char_pos, ins_content)
+        self.insert_in(oplog, agent, wchar_pos, ins_content, PosUnit::Utf16)
     }
 
     #[cfg(feature = "wchar_conversion")]
     pub fn delete_at_wchar(&mut self, oplog: &mut ListOpLog, agent: AgentId, del_span_wchar: Range<usize>) -> LV {
-        let c = self.content.borrow();
-        let start_pos = c.wchars_to_chars(del_span_wchar.start);
-        let end_pos = c.wchars_to_chars(del_span_wchar.end);
-        drop(c);
-        apply_local_operations(oplog, self, agent, &[self.make_delete_op(start_pos .. end_pos)])
+        self.delete_in(oplog, agent, del_span_wchar, PosUnit::Utf16)
+    }
+
+    /// Returns the document's content length, measured in `unit`.
+    pub fn len_in(&self, unit: PosUnit) -> usize {
+        match unit {
+            PosUnit::Chars => self.content.len_chars(),
+            PosUnit::Utf8 => self.content.len_bytes(),
+            PosUnit::Utf16 => rope_chars_to_unit(&self.content.borrow(), unit, self.content.len_chars()),
+        }
+    }
+
+    /// Convert a character position in the document to a position measured in `unit`.
+    pub fn chars_to_unit(&self, char_pos: usize, unit: PosUnit) -> usize {
+        rope_chars_to_unit(&self.content.borrow(), unit, char_pos)
+    }
+
+    /// Convert a position measured in `unit` to a character position in the document.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is past the end of the document or in the middle of a character.
+    pub fn unit_to_chars(&self, pos: usize, unit: PosUnit) -> usize {
+        rope_unit_to_chars(&self.content.borrow(), unit, pos)
+    }
+
+    /// Convert a position measured in `unit` to a character position in the document. Returns
+    /// `None` if the position is past the end of the document or in the middle of a character.
+    pub fn try_unit_to_chars(&self, pos: usize, unit: PosUnit) -> Option<usize> {
+        rope_try_unit_to_chars(&self.content.borrow(), unit, pos)
+    }
+
+    /// Insert content at a position measured in `unit`. See [`insert`](Self::insert).
+    pub fn insert_in(&mut self, oplog: &mut ListOpLog, agent: AgentId, pos: usize, ins_content: &str, unit: PosUnit) -> LV {
+        let char_pos = self.unit_to_chars(pos, unit);
+        self.insert(oplog, agent, char_pos, ins_content)
+    }
+
+    /// Delete a range measured in `unit`. See [`delete`](Self::delete).
+    pub fn delete_in(&mut self, oplog: &mut ListOpLog, agent: AgentId, del_span: Range<usize>, unit: PosUnit) -> LV {
+        let char_span = rope_unit_range_to_chars(&self.content.borrow(), unit, del_span);
+        self.delete(oplog, agent, char_span)
     }
 
     /// Consume the Branch and return the contained rope content.
@@ -147,6 +185,15 @@ impl Default for ListBranch {
     }
 }
 
+// The merge context is just a cache, so it's ignored when comparing branches.
+impl PartialEq for ListBranch {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version && self.content == other.content
+    }
+}
+
+impl Eq for ListBranch {}
+
 impl From<ListBranch> for JumpRope {
     fn from(branch: ListBranch) -> Self {
         branch.into_inner()
@@ -191,4 +238,54 @@ mod test {
 
         oplog.dbg_check(true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn merge_context_rebuilt_for_other_oplog() {
+        // Both oplogs contain "hi" after two operations, but typed in a different order. The merge
+        // context built from the first oplog mustn't be used to merge the second.
+        let mut oplog_a = ListOpLog::new();
+        let a = oplog_a.get_or_create_agent_id("a");
+        let v = oplog_a.add_insert_at(a, &[], 0, "i");
+        oplog_a.add_insert_at(a, &[v], 0, "h");
+
+        let mut oplog_b = ListOpLog::new();
+        let b = oplog_b.get_or_create_agent_id("b");
+        let v = oplog_b.add_insert_at(b, &[], 0, "h");
+        oplog_b.add_insert_at(b, &[v], 1, "i");
+        // Concurrently delete the "h".
+        oplog_b.add_delete_at(b, &[v], 0..1);
+
+        let mut branch = ListBranch::new();
+        branch.enable_merge_context();
+        branch.merge(&oplog_a, oplog_a.local_frontier_ref());
+        assert_eq!(branch.content, "hi");
+
+        branch.merge(&oplog_b, oplog_b.local_frontier_ref());
+        assert_eq!(branch, oplog_b.checkout_tip());
+        assert_eq!(branch.content, "i");
+    }
+
+    #[test]
+    fn merge_context_rebuilt_when_oplog_replaced() {
+        // Replacing the oplog in place keeps its address, but it's still a different oplog.
+        let mut oplog = ListOpLog::new();
+        let a = oplog.get_or_create_agent_id("a");
+        let v = oplog.add_insert_at(a, &[], 0, "i");
+        oplog.add_insert_at(a, &[v], 0, "h");
+
+        let mut branch = ListBranch::new();
+        branch.enable_merge_context();
+        branch.merge(&oplog, oplog.local_frontier_ref());
+        assert_eq!(branch.content, "hi");
+
+        oplog = ListOpLog::new();
+        let b = oplog.get_or_create_agent_id("b");
+        let v = oplog.add_insert_at(b, &[], 0, "h");
+        oplog.add_insert_at(b, &[v], 1, "i");
+        oplog.add_delete_at(b, &[v], 0..1);
+
+        branch.merge(&oplog, oplog.local_frontier_ref());
+        assert_eq!(branch, oplog.checkout_tip());
+        assert_eq!(branch.content, "i");
+    }
+}
@@ -1,6 +1,7 @@
 use smallvec::{smallvec, SmallVec};
+use smartstring::alias::String as SmartString;
 use crate::list::encoding::*;
-use crate::list::{ListOpLog, switch};
+use crate::list::{ListOpLog, OpLogId, switch};
 use crate::frontier::*;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::ListOpKind::{Del, Ins};
@@ -19,6 +20,11 @@ use crate::rle::{KVPair, RleKeyedAndSplitable, RleSpanHelpers, RleVec};
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::tools::calc_checksum;
 use crate::list::encoding::leb::num_decode_zigzag_isize_old;
+use crate::validate::OpValidator;
+use crate::list::encoding::verify::check_op_positions;
+#[cfg(feature = "signing")]
+use crate::list::signing::{KeyRing, Signature, SignedSpan};
+use std::fmt::{Debug, Formatter};
 
 // If this is set to false, the compiler can optimize out the verbose printing code. This makes the
 // compiled output slightly smaller.
@@ -26,7 +32,7 @@ const ALLOW_VERBOSE: bool = false;
 // const ALLOW_VERBOSE: bool = true;
 
 impl<'a> BufReader<'a> {
-    fn read_next_agent_assignment(&mut self, map: &mut [(AgentId, usize)]) -> Result<Option<AgentSpan>, ParseError> {
+    pub(super) fn read_next_agent_assignment(&mut self, map: &mut [(AgentId, usize)]) -> Result<Option<AgentSpan>, ParseError> {
         // Agent assignments are almost always (but not always) linear. They can have gaps, and
         // they can be reordered if the same agent ID is used to contribute to multiple branches.
         //
@@ -56,9 +62,8 @@ impl<'a> BufReader<'a> {
         let entry = &mut map[inner_agent];
         let agent = entry.0;
 
-        // TODO: Error if this overflows.
-        let start = (entry.1 as isize + jump) as usize;
-        let end = start + len;
+        let start = entry.1.checked_add_signed(jump).ok_or(ParseError::InvalidLength)?;
+        let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
         entry.1 = end;
 
         Ok(Some(AgentSpan {
@@ -76,7 +81,8 @@ impl<'a> BufReader<'a> {
             let seq = self.next_usize()?; // Bleh. Skip me when root!
             if mapped_agent == 0 { break; } // Root.
 
-            let agent = agent_map[mapped_agent - 1].0;
+            let agent = agent_map.get(mapped_agent - 1)
+                .ok_or(ParseError::InvalidLength)?.0;
 
             let time = oplog.try_crdt_id_to_time((agent, seq))
                 .ok_or(ParseError::BaseVersionUnknown)?;
@@ -104,7 +110,7 @@ impl<'a> BufReader<'a> {
                     // The parents list is empty (ie, our parent is ROOT).
                     break;
                 } else {
-                    let agent = agent_map[n - 1].0;
+                    let agent = agent_map.get(n - 1).ok_or(ParseError::InvalidLength)?.0;
                     let seq = self.next_usize()?;
                     // dbg!((agent, seq));
                     if let Some(c) = oplog.cg.agent_assignment.client_data.get(agent as usize) {
@@ -118,6 +124,7 @@ impl<'a> BufReader<'a> {
             } else {
                 // Local parents (parents inside this chunk of data) are stored using their
                 // local time offset.
+                if n == 0 || n > next_time { return Err(ParseError::InvalidLength); }
                 next_time - n
             };
 
@@ -173,7 +180,7 @@ impl<'a> ChunkReader<'a> {
         }
     }
 
-    fn expect_content_str(&mut self, compressed: Option<&mut BufReader<'a>>) -> Result<&'a str, ParseError> {
+    pub(super) fn expect_content_str(&mut self, compressed: Option<&mut BufReader<'a>>) -> Result<&'a str, ParseError> {
         let (c, mut r) = self.expect_chunk_pred(|c| c == Content || c == ContentCompressed, Content)?;
 
         if c == Content {
@@ -287,7 +294,14 @@ impl<'a> ReadPatchesIter<'a> {
     // The actual next function. The only reason I did it like this is so I can take advantage of
     // the ergonomics of try?.
     fn next_internal(&mut self) -> Result<ListOpMetrics, ParseError> {
-        let mut n = self.buf.next_usize()?;
+        self.buf.next_op(&mut self.last_cursor_pos)
+    }
+}
+
+impl<'a> BufReader<'a> {
+    /// Read an operation written by `write_op`. The cursor is updated the same way.
+    pub(super) fn next_op(&mut self, last_cursor_pos: &mut usize) -> Result<ListOpMetrics, ParseError> {
+        let mut n = self.next_usize()?;
         // This is in the opposite order from write_op.
         let has_length = strip_bit_usize_2(&mut n);
         let diff_not_zero = strip_bit_usize_2(&mut n);
@@ -300,7 +314,7 @@ impl<'a> ReadPatchesIter<'a> {
             } else { true };
 
             let diff = if diff_not_zero {
-                self.buf.next_zigzag_isize()?
+                self.next_zigzag_isize()?
             } else { 0 };
 
             (n, diff, fwd)
@@ -311,20 +325,26 @@ impl<'a> ReadPatchesIter<'a> {
         };
 
         // dbg!(self.last_cursor_pos, diff);
-        let raw_start = isize::wrapping_add(self.last_cursor_pos as isize, diff) as usize;
+        // Corrupt data can send the cursor out of range. All the arithmetic here is checked so
+        // that shows up as an error rather than a panic.
+        let raw_start = last_cursor_pos.checked_add_signed(diff)
+            .ok_or(ParseError::InvalidLength)?;
 
         let (start, raw_end) = match (tag, fwd) {
-            (Ins, true) => (raw_start, raw_start + len),
+            (Ins, true) => (raw_start, raw_start.checked_add(len).ok_or(ParseError::InvalidLength)?),
             (Ins, false) | (Del, true) => (raw_start, raw_start), // Weird symmetry!
-            (Del, false) => (raw_start - len, raw_start - len),
+            (Del, false) => {
+                let start = raw_start.checked_sub(len).ok_or(ParseError::InvalidLength)?;
+                (start, start)
+            },
         };
         // dbg!((raw_start, tag, fwd, len, start, raw_end));
 
-        let end = start + len;
+        let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
 
         // dbg!(pos);
-        self.last_cursor_pos = raw_end;
-        // dbg!(self.last_cursor_pos);
+        *last_cursor_pos = raw_end;
+        // dbg!(last_cursor_pos);
 
         Ok(ListOpMetrics {
             loc: RangeRev { // TODO: Probably a nicer way to construct this.
@@ -425,37 +445,116 @@ impl<'a> Iterator for ReadPatchContentIter<'a> {
 }
 
 
-#[derive(Debug, Clone)]
-pub struct DecodeOptions {
+#[derive(Clone)]
+pub struct DecodeOptions<'a> {
     /// Ignore CRC check failures. This is mostly used for debugging.
     pub ignore_crc: bool,
 
     pub verbose: bool,
+
+    /// If set, every new operation is passed to the validator before the merge is committed. If
+    /// any operation is rejected, the merge is unwound and returns
+    /// [`ParseError::OperationRejected`]. See [`crate::validate`].
+    pub validator: Option<&'a dyn OpValidator>,
+
+    /// Compare any operations we already have against the copies in the incoming data. If they
+    /// have different content or parents, the same (agent, seq) IDs have been reused and decoding
+    /// fails with [`ParseError::IdReused`]. This is off by default because it makes merging
+    /// overlapping data slower.
+    pub check_id_reuse: bool,
+
+    /// Check that every new operation's position is inside the document at its parent version.
+    /// Checking out or merging an operation which isn't would panic, so this should be set when
+    /// the data comes from an untrusted source. If any operation is out of bounds, decoding fails
+    /// with [`ParseError::OperationOutOfBounds`]. This is off by default because it replays the
+    /// new operations.
+    pub check_positions: bool,
+
+    /// If set, signatures on incoming operations are checked against these keys. See
+    /// [`crate::list::signing`].
+    #[cfg(feature = "signing")]
+    pub keys: Option<&'a KeyRing>,
+}
+
+impl<'a> Debug for DecodeOptions<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DecodeOptions")
+            .field("ignore_crc", &self.ignore_crc)
+            .field("verbose", &self.verbose)
+            .field("validator", &self.validator.is_some())
+            .field("check_id_reuse", &self.check_id_reuse)
+            .field("check_positions", &self.check_positions)
+            .finish_non_exhaustive()
+    }
 }
 
 #[allow(clippy::derivable_impls)]
-impl Default for DecodeOptions {
+impl<'a> Default for DecodeOptions<'a> {
     fn default() -> Self {
         Self {
             ignore_crc: false,
             verbose: false,
+            validator: None,
+            check_id_reuse: false,
+            check_positions: false,
+            #[cfg(feature = "signing")]
+            keys: None,
         }
     }
 }
 
+/// The state needed to unwind an oplog after a failed merge.
+#[derive(Debug)]
+pub(crate) struct OpLogCheckpoint {
+    len: usize,
+    doc_id: Option<SmartString>,
+    version: Frontier,
+    num_agents: usize,
+    ins_content_length: usize,
+    del_content_length: usize,
+}
+
+/// Describes where decoding stopped when salvaging operations from a damaged file. See
+/// [`ListOpLog::load_salvage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SalvageStop {
+    /// The name of the chunk containing the first damaged data (eg `"OpParents"`).
+    pub chunk: String,
+    /// The error encountered reading that chunk.
+    pub error: ParseError,
+    /// The number of operations (in file order) which were kept. Everything after this point in
+    /// the file was discarded.
+    pub ops_kept: usize,
+}
+
 impl ListOpLog {
     pub fn load_from(data: &[u8]) -> Result<Self, ParseError> {
         let mut oplog = Self::new();
-        oplog.decode_internal(data, DecodeOptions::default())?;
+        oplog.decode_internal(data, DecodeOptions::default(), None)?;
         Ok(oplog)
     }
 
     pub fn load_from_opts(data: &[u8], opts: DecodeOptions) -> Result<Self, ParseError> {
         let mut oplog = Self::new();
-        oplog.decode_internal(data, opts)?;
+        oplog.decode_and_add_opts(data, opts)?;
         Ok(oplog)
     }
 
+    /// Load as much as possible from a (possibly damaged) file.
+    ///
+    /// Rather than failing when the patches in the file are corrupt, this keeps the longest valid
+    /// causal prefix of operations which could be read. If anything was discarded, the returned
+    /// [`SalvageStop`] describes where reading stopped. The checksum is not checked.
+    ///
+    /// Errors in the file header (before any patches) still fail the load, since there's nothing
+    /// we can recover from the file in that case.
+    pub fn load_salvage(data: &[u8]) -> Result<(Self, Option<SalvageStop>), ParseError> {
+        let mut oplog = Self::new();
+        let mut stop = None;
+        oplog.decode_internal(data, DecodeOptions { ignore_crc: true, ..Default::default() }, Some(&mut stop))?;
+        Ok((oplog, stop))
+    }
+
     /// Add all operations from a binary chunk into this document.
     ///
     /// Any duplicate operations are ignored.
@@ -471,8 +570,9 @@ impl ListOpLog {
     /// If successful, returns the version of the loaded data (which could be different from the
     /// local version!)
     ///
-    /// This method takes an options object, which for now doesn't do much. Most users should just
-    /// call [`OpLog::decode_and_add`](OpLog::decode_and_add)
+    /// This method takes an options object, which can be used to validate incoming operations
+    /// before they're merged. Most users should just call
+    /// [`OpLog::decode_and_add`](OpLog::decode_and_add)
     pub fn decode_and_add_opts(&mut self, data: &[u8], opts: DecodeOptions) -> Result<Frontier, ParseError> {
         // In order to merge data safely, when an error happens we need to unwind all the merged
         // operations before returning. Otherwise self is in an invalid state.
@@ -480,106 +580,79 @@ impl ListOpLog {
         // The merge_data method is append-only, so really we just need to trim back all the data
         // that has been (partially) added.
 
-        // Total (unmerged) number of operations before this data is merged in.
-        let len = self.len();
-
-        // We could regenerate the frontier, but this is much lazier.
-        let doc_id = self.doc_id.clone();
-        let old_frontier = self.cg.version.clone();
-        let num_known_agents = self.cg.agent_assignment.client_data.len();
-        let ins_content_length = self.operation_ctx.ins_content.len();
-        let del_content_length = self.operation_ctx.del_content.len();
-
-        let result = self.decode_internal(data, opts);
-
-        if result.is_err() {
-            // Unwind changes back to len.
-            // This would be nicer with an RleVec iterator, but the iter implementation doesn't
-            // support iterating backwards.
-            self.doc_id = doc_id;
-
-            while let Some(last) = self.cg.agent_assignment.client_with_lv.0.last_mut() {
-                debug_assert!(len <= last.end());
-                if len == last.end() { break; }
-                else {
-                    // Truncate!
-                    let KVPair(_, removed) = if len <= last.0 {
-                        // Drop entire entry
-                        self.cg.agent_assignment.client_with_lv.0.pop().unwrap()
-                    } else {
-                        last.truncate(len - last.0)
-                    };
-
-                    let client_data = &mut self.cg.agent_assignment.client_data[removed.agent as usize];
-                    client_data.lv_for_seq.remove_ctx(removed.seq_range, &());
-                }
+        let checkpoint = self.checkpoint();
+        let validator = opts.validator;
+        let check_positions = opts.check_positions;
+        let result = self.decode_internal(data, opts, None).and_then(|frontier| {
+            if check_positions && check_op_positions(self, (checkpoint.len..self.len()).into()).is_some() {
+                return Err(ParseError::OperationOutOfBounds);
             }
-
-            let num_operations = self.operations.end();
-            if num_operations > len {
-                self.operations.remove_ctx((len..num_operations).into(), &self.operation_ctx);
+            if let Some(validator) = validator {
+                self.validate_ops((checkpoint.len..self.len()).into(), validator)?;
             }
+            Ok(frontier)
+        });
 
-            // Trim history
-            let hist_entries = &mut self.cg.graph.entries;
-            let history_length = hist_entries.end();
-            if history_length > len {
-                // We can't use entries.remove because HistoryEntry doesn't support SplitableSpan.
-                // And also because we need to update child_indexes.
-                let del_span_start = len;
-
-                let first_idx = hist_entries.find_index(len).unwrap();
-
-                let e = &mut hist_entries.0[first_idx];
-                let first_truncated_idx = if del_span_start > e.span.start {
-                    // The first entry just needs to be trimmed down.
-                    e.span.truncate_from(del_span_start);
-                    first_idx + 1
-                } else {
-                    first_idx
-                };
-
-                let mut idx = first_truncated_idx;
-
-                // Go through and unwind from idx.
-                while idx < hist_entries.num_entries() {
-                    // Cloning here is an ugly and kinda slow hack to work around the borrow
-                    // checker. But this whole case is rare anyway, so idk.
-                    let parents = hist_entries.0[idx].parents.clone();
-
-                    for p in parents {
-                        if p < len { // If p >= len, the target will be discarded anyway.
-                            let parent_entry = hist_entries.find_mut(p).unwrap().0;
-                            while let Some(&c_idx) = parent_entry.child_indexes.last() {
-                                if c_idx >= first_truncated_idx {
-                                    parent_entry.child_indexes.pop();
-                                } else { break; }
-                            }
-                        }
-                    }
+        if result.is_err() {
+            self.restore_checkpoint(checkpoint);
+        }
 
-                    idx += 1;
-                }
+        result
+    }
+
+    /// Record the current state of the oplog, so operations merged after this point can be
+    /// discarded with [`restore_checkpoint`](ListOpLog::restore_checkpoint).
+    pub(crate) fn checkpoint(&self) -> OpLogCheckpoint {
+        OpLogCheckpoint {
+            len: self.len(),
+            // We could regenerate the frontier, but this is much lazier.
+            doc_id: self.doc_id.clone(),
+            version: self.cg.version.clone(),
+            num_agents: self.cg.agent_assignment.client_data.len(),
+            ins_content_length: self.operation_ctx.ins_content.len(),
+            del_content_length: self.operation_ctx.del_content.len(),
+        }
+    }
 
-                self.cg.graph.entries.0.truncate(first_truncated_idx);
+    /// Unwind all changes made since the checkpoint was taken.
+    pub(crate) fn restore_checkpoint(&mut self, checkpoint: OpLogCheckpoint) {
+        self.doc_id = checkpoint.doc_id;
+        self.truncate_ops(checkpoint.len);
 
-                while let Some(&last_idx) = self.cg.graph.root_child_indexes.last() {
-                    if last_idx >= self.cg.graph.entries.num_entries() {
-                        self.cg.graph.root_child_indexes.pop();
-                    } else { break; }
-                }
-            }
+        // Remove excess agents
+        self.cg.agent_assignment.client_data.truncate(checkpoint.num_agents);
 
-            // Remove excess agents
-            self.cg.agent_assignment.client_data.truncate(num_known_agents);
+        self.operation_ctx.ins_content.truncate(checkpoint.ins_content_length);
+        self.operation_ctx.del_content.truncate(checkpoint.del_content_length);
 
-            self.operation_ctx.ins_content.truncate(ins_content_length);
-            self.operation_ctx.del_content.truncate(del_content_length);
+        self.cg.version = checkpoint.version;
+    }
 
-            self.cg.version = old_frontier;
+    /// Discard all operations with local versions >= len. The remaining operations must form a
+    /// causal prefix of the oplog - which they always do, because local versions are assigned in
+    /// causal order.
+    ///
+    /// This leaves the oplog's version untouched. Callers need to fix it up themselves.
+    fn truncate_ops(&mut self, len: usize) {
+        // Local versions from here on may be reused by different operations.
+        self.id = OpLogId::next();
+        self.cg.truncate_entries(len);
+        #[cfg(feature = "signing")]
+        self.retain_signatures();
+
+        let num_operations = self.operations.end();
+        if num_operations > len {
+            self.operations.remove_ctx((len..num_operations).into(), &self.operation_ctx);
         }
+    }
 
-        result
+    /// Discard all operations from len onwards, and recalculate the oplog's version from what's
+    /// left.
+    pub(super) fn truncate_to_causal_prefix(&mut self, len: usize) {
+        self.truncate_ops(len);
+        self.cg.version = self.cg.graph.find_dominators(&self.cg.graph.entries.iter()
+            .map(|e| e.span.last())
+            .collect::<Vec<_>>());
     }
 
     /// Merge data from the remote source into our local document state.
@@ -587,7 +660,7 @@ impl ListOpLog {
     /// NOTE: This code is quite new.
     /// TODO: Currently if this method returns an error, the local state is undefined & invalid.
     /// Until this is fixed, the signature of the method will stay kinda weird to prevent misuse.
-    fn decode_internal(&mut self, data: &[u8], opts: DecodeOptions) -> Result<Frontier, ParseError> {
+    fn decode_internal<'a>(&mut self, data: &'a [u8], opts: DecodeOptions, mut salvage: Option<&mut Option<SalvageStop>>) -> Result<Frontier, ParseError> {
         // Written to be symmetric with encode functions.
         let mut reader = BufReader(data);
 
@@ -663,6 +736,15 @@ impl ListOpLog {
             // TODO! Attach start_content if we're empty and start_version != ROOT.
         }
 
+        // *** EndBranch ***
+        // Files can optionally store the document's content at the end of the patches. We don't use
+        // it when loading, but it still needs to be parsed to consume any compressed bytes.
+        if let Some(end_branch) = reader.read_chunk_if_eq(ListChunkType::ExperimentalEndBranch)? {
+            let mut end_branch = end_branch.chunks();
+            end_branch.read_chunk_if_eq(ListChunkType::Version)?;
+            end_branch.expect_content_str(compressed_chunk.as_mut())?;
+        }
+
         // Usually the version data will be strictly separated. Either we're loading data into an
         // empty document, or we've been sent catchup data from a remote peer. If the data set
         // overlaps, we need to actively filter out operations & txns from that data set.
@@ -670,16 +752,33 @@ impl ListOpLog {
         let patches_overlap = !local_frontier_eq(start_version.as_ref(), self.cg.version.as_ref());
         // dbg!(patches_overlap);
 
+        #[cfg(feature = "signing")]
+        let first_new_time = self.len();
+
         // *** Patches ***
         let file_frontier = {
             // This chunk contains the actual set of edits to the document.
-            let mut patch_chunk = reader.expect_chunk(ListChunkType::Patches)?
+            // Salvaging data from a truncated file is common enough that its worth handling
+            // specially. We read what we can from chunks which have been cut short.
+            let salvaging = salvage.is_some();
+            let read_chunk_if_eq = |reader: &mut ChunkReader<'a>, chunk_type: ListChunkType| {
+                if salvaging { reader.read_chunk_if_eq_truncated(chunk_type) }
+                else { reader.read_chunk_if_eq(chunk_type) }
+            };
+            let expect_chunk = |reader: &mut ChunkReader<'a>, chunk_type: ListChunkType| {
+                if salvaging {
+                    reader.read_chunk_if_eq_truncated(chunk_type)?
+                        .ok_or(ParseError::MissingChunk(chunk_type as _))
+                } else { reader.expect_chunk(chunk_type) }
+            };
+
+            let mut patch_chunk = expect_chunk(&mut reader, ListChunkType::Patches)?
                 .chunks();
 
             let mut ins_content = None;
             let mut del_content = None;
 
-            while let Some(chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::PatchContent)? {
+            while let Some(chunk) = read_chunk_if_eq(&mut patch_chunk, ListChunkType::PatchContent)? {
                 let (tag, content_chunk) = ReadPatchContentIter::new(chunk, compressed_chunk.as_mut())?;
                 // let iter = content_chunk.take_max();
                 let iter = content_chunk.buffered();
@@ -692,9 +791,9 @@ impl ListOpLog {
             // So note that the file we're loading from may contain changes we already have locally.
             // We (may) need to filter out operations from the patch stream, which we read from
             // below. To do that without extra need to read both the agent assignments and patches together.
-            let mut agent_assignment_chunk = patch_chunk.expect_chunk(ListChunkType::OpVersions)?;
-            let pos_patches_chunk = patch_chunk.expect_chunk(ListChunkType::OpTypeAndPosition)?;
-            let mut history_chunk = patch_chunk.expect_chunk(ListChunkType::OpParents)?;
+            let mut agent_assignment_chunk = expect_chunk(&mut patch_chunk, ListChunkType::OpVersions)?;
+            let pos_patches_chunk = expect_chunk(&mut patch_chunk, ListChunkType::OpTypeAndPosition)?;
+            let mut history_chunk = expect_chunk(&mut patch_chunk, ListChunkType::OpParents)?;
 
             // We need an insert ctx in some situations, though it'll never be accessed.
             let dummy_ctx = ListOperationCtx::new();
@@ -728,12 +827,15 @@ impl ListOpLog {
             let mut version_map = RleVec::new();
 
             // Take and merge the next exactly n patches
-            let mut parse_next_patches = |oplog: &mut ListOpLog, mut n: usize, keep: bool| -> Result<(), ParseError> {
+            // If known is set, the patches are already in the oplog from that local version and
+            // (when checking for ID reuse) they're compared against the stored operations.
+            let check_id_reuse = opts.check_id_reuse;
+            let mut parse_next_patches = |oplog: &mut ListOpLog, mut n: usize, keep: bool, mut known: Option<(LV, AgentSpan)>| -> Result<(), (ListChunkType, ParseError)> {
                 while n > 0 {
                     let mut max_len = n;
 
                     if let Some(op) = patches_iter.next() {
-                        let mut op = op?;
+                        let mut op = op.map_err(|e| (OpTypeAndPosition, e))?;
                         // dbg!((n, &op));
                         max_len = max_len.min(op.len());
 
@@ -741,7 +843,7 @@ impl ListOpLog {
                         let content_here = if let Some(iter) = switch(op.kind, &mut ins_content, &mut del_content) {
                             // There's probably a way to compact with Option helpers magic but ??
                             if let Some(content) = iter.next() {
-                                let mut content = content?;
+                                let mut content = content.map_err(|e| (PatchContent, e))?;
                                 max_len = max_len.min(content.len);
                                 // Put the rest (if any) back into the iterator.
                                 if let Some(r) = content.trim(max_len) {
@@ -749,11 +851,12 @@ impl ListOpLog {
                                 }
                                 content.content
                             } else {
-                                return Err(ParseError::InvalidLength);
+                                return Err((PatchContent, ParseError::InvalidLength));
                             }
                         } else { None };
 
-                        assert!(max_len > 0);
+                        // Zero length operations or content can only come from corrupt data.
+                        if max_len == 0 { return Err((OpTypeAndPosition, ParseError::InvalidLength)); }
                         n -= max_len;
 
                         let remainder = op.trim_ctx(max_len, &dummy_ctx);
@@ -764,94 +867,141 @@ impl ListOpLog {
                         if keep {
                             oplog.push_op_internal(next_patch_time, op.loc, op.kind, content_here);
                             next_patch_time += max_len;
+                        } else if let Some((lv, span)) = known.as_mut().filter(|_| check_id_reuse) {
+                            if !oplog.known_op_matches(*lv, (&op, content_here).into()) {
+                                return Err((OpTypeAndPosition, ParseError::IdReused {
+                                    agent: span.agent,
+                                    seq_range: span.seq_range,
+                                }));
+                            }
+                            *lv += max_len;
                         }
 
                         if let Some(r) = remainder {
                             patches_iter.push_back(Ok(r));
                         }
                     } else {
-                        return Err(ParseError::InvalidLength);
+                        return Err((OpTypeAndPosition, ParseError::InvalidLength));
                     }
                 }
 
                 Ok(())
             };
 
-            while let Some(mut crdt_span) = agent_assignment_chunk.read_next_agent_assignment(&mut agent_map)? {
-                // let mut crdt_span = crdt_span; // TODO: Remove me. Blerp clion.
-                // dbg!(crdt_span);
-                if crdt_span.agent as usize >= self.cg.agent_assignment.client_data.len() {
-                    return Err(ParseError::InvalidLength);
+            // When salvaging, errors in the data stop decoding rather than failing the whole load.
+            // Errors are tagged with the chunk they came from so we can report it.
+            let stop = |salvage: &mut Option<&mut Option<SalvageStop>>, (chunk, error): (ListChunkType, ParseError)| -> Result<(), ParseError> {
+                match salvage.as_deref_mut() {
+                    Some(s) if s.is_none() => {
+                        *s = Some(SalvageStop { chunk: format!("{:?}", chunk), error, ops_kept: 0 });
+                        Ok(())
+                    }
+                    Some(_) => Ok(()),
+                    None => Err(error),
                 }
+            };
 
-                if patches_overlap {
-                    // Sooo, if the current document overlaps with the data we're loading, we need
-                    // to filter out all the operations we already have from the stream.
-                    while !crdt_span.seq_range.is_empty() {
-                        // dbg!(&crdt_span);
-                        let client = &self.cg.agent_assignment.client_data[crdt_span.agent as usize];
-                        let (span, offset) = client.lv_for_seq.find_sparse(crdt_span.seq_range.start);
-                        // dbg!((crdt_span.seq_range, span, offset));
-                        let (span_end, overlap_start) = match span {
-                            // Skip the entry.
-                            Ok(entry) => (entry.end(), Some(entry.1.start + offset)),
-                            // Consume the entry
-                            Err(empty_span) => (empty_span.end, None),
-                        };
-
-                        let end = crdt_span.seq_range.end.min(span_end);
-                        let consume_here = crdt_span.seq_range.truncate_keeping_right_from(end);
-                        let len = consume_here.len();
-
-                        let keep = if let Some(overlap_start) = overlap_start {
-                            let overlap = (overlap_start .. overlap_start + len).into();
-                            // There's overlap. We'll filter out this item.
-                            version_map.push_rle(KVPair(next_file_time, overlap));
-                            // println!("push overlap {:?}", KVPair(next_file_time, overlap));
-                            false
-                        } else {
-                            self.assign_time_to_crdt_span(next_assignment_time, AgentSpan {
-                                agent: crdt_span.agent,
-                                seq_range: consume_here,
-                            });
-
-                            // println!("push to end {:?}", KVPair(
-                            //     next_file_time,
-                            //     TimeSpan::from(next_assignment_time..next_assignment_time + len),
-                            // ));
-                            version_map.push_rle(KVPair(
-                                next_file_time,
-                                (next_assignment_time..next_assignment_time + len).into(),
-                            ));
-                            next_assignment_time += len;
-                            true
-                        };
-                        next_file_time += len;
+            let ops_result = (|| {
+                while let Some(mut crdt_span) = agent_assignment_chunk.read_next_agent_assignment(&mut agent_map).map_err(|e| (OpVersions, e))? {
+                    // let mut crdt_span = crdt_span; // TODO: Remove me. Blerp clion.
+                    // dbg!(crdt_span);
+                    if crdt_span.agent as usize >= self.cg.agent_assignment.client_data.len() {
+                        return Err((OpVersions, ParseError::InvalidLength));
+                    }
 
-                        // dbg!(&file_to_local_version_map);
+                    if patches_overlap {
+                        // Sooo, if the current document overlaps with the data we're loading, we need
+                        // to filter out all the operations we already have from the stream.
+                        while !crdt_span.seq_range.is_empty() {
+                            // dbg!(&crdt_span);
+                            let client = &self.cg.agent_assignment.client_data[crdt_span.agent as usize];
+                            let (span, offset) = client.lv_for_seq.find_sparse(crdt_span.seq_range.start);
+                            // dbg!((crdt_span.seq_range, span, offset));
+                            let (span_end, overlap_start) = match span {
+                                // Skip the entry.
+                                Ok(entry) => (entry.end(), Some(entry.1.start + offset)),
+                                // Consume the entry
+                                Err(empty_span) => (empty_span.end, None),
+                            };
+
+                            let end = crdt_span.seq_range.end.min(span_end);
+                            let consume_here = crdt_span.seq_range.truncate_keeping_right_from(end);
+                            let len = consume_here.len();
+
+                            let known = overlap_start.map(|overlap_start| {
+                                (overlap_start, AgentSpan { agent: crdt_span.agent, seq_range: consume_here })
+                            });
+                            let keep = if let Some(overlap_start) = overlap_start {
+                                let overlap = (overlap_start .. overlap_start + len).into();
+                                // There's overlap. We'll filter out this item.
+                                version_map.push_rle(KVPair(next_file_time, overlap));
+                                // println!("push overlap {:?}", KVPair(next_file_time, overlap));
+                                false
+                            } else {
+                                self.assign_time_to_crdt_span(next_assignment_time, AgentSpan {
+                                    agent: crdt_span.agent,
+                                    seq_range: consume_here,
+                                });
+
+                                // println!("push to end {:?}", KVPair(
+                                //     next_file_time,
+                                //     TimeSpan::from(next_assignment_time..next_assignment_time + len),
+                                // ));
+                                version_map.push_rle(KVPair(
+                                    next_file_time,
+                                    (next_assignment_time..next_assignment_time + len).into(),
+                                ));
+                                next_assignment_time += len;
+                                true
+                            };
+                            next_file_time += len;
+
+                            // dbg!(&file_to_local_version_map);
+
+                            parse_next_patches(self, len, keep, known)?;
+
+                            // And deal with history.
+                            // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, keep)?;
+                        }
+                        // dbg!(span);
+                    } else {
+                        // Optimization - don't bother with the filtering code above if loaded changes
+                        // follow local changes. Most calls to this function load into an empty
+                        // document, and this is the case.
+
+                        // A valid file never assigns the same (agent, seq) pair twice.
+                        // If it does, the IDs have been reused. This lookup is only done when
+                        // we've been asked to check for damage, to keep normal loads fast.
+                        if salvaging || opts.check_id_reuse {
+                            let reused = if opts.check_id_reuse {
+                                ParseError::IdReused { agent: crdt_span.agent, seq_range: crdt_span.seq_range }
+                            } else { ParseError::InvalidLength };
+                            let client = &self.cg.agent_assignment.client_data[crdt_span.agent as usize];
+                            if let (Err(free), _) = client.lv_for_seq.find_sparse(crdt_span.seq_range.start) {
+                                if free.end < crdt_span.seq_range.end {
+                                    return Err((OpVersions, reused));
+                                }
+                            } else {
+                                return Err((OpVersions, reused));
+                            }
+                        }
 
-                        parse_next_patches(self, len, keep)?;
+                        self.assign_time_to_crdt_span(next_assignment_time, crdt_span);
+                        let len = crdt_span.len();
+                        let timespan = (next_assignment_time..next_assignment_time+len).into();
+                        // file_to_local_version_map.push_rle((next_assignment_time..next_assignment_time + len).into());
+                        version_map.push_rle(KVPair(next_file_time, timespan));
+                        parse_next_patches(self, len, true, None)?;
+                        // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, true)?;
 
-                        // And deal with history.
-                        // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, keep)?;
+                        next_assignment_time += len;
+                        next_file_time += len;
                     }
-                    // dbg!(span);
-                } else {
-                    // Optimization - don't bother with the filtering code above if loaded changes
-                    // follow local changes. Most calls to this function load into an empty
-                    // document, and this is the case.
-                    self.assign_time_to_crdt_span(next_assignment_time, crdt_span);
-                    let len = crdt_span.len();
-                    let timespan = (next_assignment_time..next_assignment_time+len).into();
-                    // file_to_local_version_map.push_rle((next_assignment_time..next_assignment_time + len).into());
-                    version_map.push_rle(KVPair(next_file_time, timespan));
-                    parse_next_patches(self, len, true)?;
-                    // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, true)?;
-
-                    next_assignment_time += len;
-                    next_file_time += len;
                 }
-            }
+                Ok(())
+            })();
+            if let Err(e) = ops_result { stop(&mut salvage, e)?; }
+            let stopped = salvage.as_ref().is_some_and(|s| s.is_some());
 
             next_file_time = new_op_start;
             // dbg!(&version_map);
@@ -859,77 +1009,106 @@ impl ListOpLog {
 
             let mut file_frontier = start_version;
 
-            while !history_chunk.is_empty() {
-                let mut entry = history_chunk.next_history_entry(self, next_file_time, &agent_map)?;
-                // So at this point the entry has underwater entry spans, and parents are underwater
-                // when they're local to the file (and non-underwater when they refer to our items).
-                // This makes the entry safe to truncate(), but we need to map it before we can use
-                // it.
-
-                next_file_time += entry.len();
-                // dbg!(&entry);
-
-                // If patches don't overlap, this code can be simplified to this:
-                //     self.insert_history(&entry.parents, entry.span);
-                //     self.advance_frontier(&entry.parents, entry.span);
-                //     next_history_time += entry.len();
-                // But benchmarks show it doesn't make any real difference in practice, so I'm not
-                // going to sweat it.
-
-                loop {
-                    let (mut mapped, remainder)
-                        = history_entry_map_and_truncate(entry, &version_map);
-                    // dbg!(&mapped);
-                    mapped.parents.debug_check_sorted();
-                    assert!(mapped.span.start <= next_history_time);
-
-                    // We'll update merge parents even if nothing is merged.
-                    // dbg!((&file_frontier, &mapped));
-                    file_frontier.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
-                    // dbg!(&file_frontier);
-
-                    if mapped.span.end > next_history_time {
-                        // We'll merge items from mapped.
-
-                        // This is needed because the overlapping & new items aren't strictly
-                        // separated in version_map. Its kinda ugly though - I'd like a better way
-                        // to deal with this case.
-                        if mapped.span.start < next_history_time {
-                            mapped.truncate_keeping_right(next_history_time - mapped.span.start);
+            // The file time just past the last operation we have agent assignments for.
+            let version_map_end = version_map.end();
+
+            let history_result = (|| {
+                while !history_chunk.is_empty() {
+                    // When salvaging, we only need the history for the operations we managed to read.
+                    if stopped && next_file_time >= version_map_end { break; }
+
+                    let mut entry = history_chunk.next_history_entry(self, next_file_time, &agent_map)?;
+                    if entry.span.end > version_map_end {
+                        if !stopped { return Err(ParseError::InvalidLength); }
+                        entry.trim(version_map_end - entry.span.start);
+                    }
+                    // So at this point the entry has underwater entry spans, and parents are underwater
+                    // when they're local to the file (and non-underwater when they refer to our items).
+                    // This makes the entry safe to truncate(), but we need to map it before we can use
+                    // it.
+
+                    next_file_time += entry.len();
+                    // dbg!(&entry);
+
+                    // If patches don't overlap, this code can be simplified to this:
+                    //     self.insert_history(&entry.parents, entry.span);
+                    //     self.advance_frontier(&entry.parents, entry.span);
+                    //     next_history_time += entry.len();
+                    // But benchmarks show it doesn't make any real difference in practice, so I'm not
+                    // going to sweat it.
+
+                    loop {
+                        let (mut mapped, remainder)
+                            = history_entry_map_and_truncate(entry, &version_map);
+                        // dbg!(&mapped);
+                        mapped.parents.debug_check_sorted();
+                        if mapped.span.start > next_history_time {
+                            // The history skips operations. Only corrupt files can do this.
+                            return Err(ParseError::InvalidLength);
                         }
 
-                        self.cg.graph.push(mapped.parents.as_ref(), mapped.span);
-                        self.cg.version.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
+                        // We'll update merge parents even if nothing is merged.
+                        // dbg!((&file_frontier, &mapped));
+                        file_frontier.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
+                        // dbg!(&file_frontier);
 
-                        next_history_time += mapped.len();
-                    } // else we already have these entries. Filter them out.
+                        if mapped.span.end > next_history_time {
+                            // We'll merge items from mapped.
 
-                    if let Some(remainder) = remainder {
-                        entry = remainder;
-                    } else {
-                        break;
+                            // This is needed because the overlapping & new items aren't strictly
+                            // separated in version_map. Its kinda ugly though - I'd like a better way
+                            // to deal with this case.
+                            if mapped.span.start < next_history_time {
+                                mapped.truncate_keeping_right(next_history_time - mapped.span.start);
+                            }
+
+                            self.cg.graph.push(mapped.parents.as_ref(), mapped.span);
+                            self.cg.version.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
+
+                            next_history_time += mapped.len();
+                        } else if opts.check_id_reuse && mapped.span.start < first_new_time {
+                            // We already have these entries. Make sure their parents match.
+                            let known: DTRange = (mapped.span.start..mapped.span.end.min(first_new_time)).into();
+                            if !self.cg.graph.has_entry_with_parents(mapped.parents.as_ref(), known) {
+                                let span = self.cg.agent_assignment.local_span_to_agent_span(known);
+                                return Err(ParseError::IdReused { agent: span.agent, seq_range: span.seq_range });
+                            }
+                        } // else we already have these entries. Filter them out.
+
+                        if let Some(remainder) = remainder {
+                            entry = remainder;
+                        } else {
+                            break;
+                        }
                     }
                 }
-            }
+                Ok(())
+            })();
+            if let Err(e) = history_result { stop(&mut salvage, (OpParents, e))?; }
 
             // We'll count the lengths in each section to make sure they all match up with each other.
-            if next_patch_time != next_assignment_time { return Err(ParseError::InvalidLength); }
-            if next_patch_time != next_history_time { return Err(ParseError::InvalidLength); }
+            if next_patch_time != next_assignment_time { stop(&mut salvage, (OpVersions, ParseError::InvalidLength))?; }
+            if next_patch_time != next_history_time { stop(&mut salvage, (OpParents, ParseError::InvalidLength))?; }
 
             // dbg!(&patch_chunk);
-            patch_chunk.expect_empty()?;
-            history_chunk.expect_empty()?;
+            if let Err(e) = patch_chunk.expect_empty() { stop(&mut salvage, (Patches, e))?; }
+            if let Err(e) = history_chunk.expect_empty() { stop(&mut salvage, (OpParents, e))?; }
 
-            if let Some(mut iter) = ins_content {
+            for iter in [ins_content, del_content].iter_mut().flatten() {
                 if iter.next().is_some() {
-                    return Err(ParseError::InvalidContent);
+                    stop(&mut salvage, (PatchContent, ParseError::InvalidContent))?;
                 }
             }
 
-            if let Some(mut iter) = del_content {
-                if iter.next().is_some() {
-                    return Err(ParseError::InvalidContent);
-                }
+            if let Some(Some(s)) = salvage.as_mut() {
+                // Keep the longest prefix of operations which has made it through every section of
+                // the file. Local versions are assigned in causal order, so this is a valid
+                // causal prefix.
+                // (next_assignment_time isn't updated if an error happens partway through a span.)
+                let ops_kept = next_patch_time.min(self.cg.len_assignment()).min(next_history_time);
+                s.ops_kept = ops_kept - first_new_time;
+                self.truncate_to_causal_prefix(ops_kept);
+                return Ok(self.cg.version.clone());
             }
 
             // dbg!(&version_map);
@@ -956,8 +1135,52 @@ impl ListOpLog {
 
         // self.frontier = end_frontier_chunk.read_full_frontier(&self)?;
 
+        // *** Signatures ***
+        // These come after the CRC chunk. Without the signing feature, they're skipped.
+        #[allow(unused_variables)]
+        let signatures_chunk = reader.read_chunk_if_eq(ListChunkType::Signatures)?;
+        #[cfg(feature = "signing")] {
+            let signatures = match signatures_chunk {
+                Some(chunk) => read_signatures(chunk, self)?,
+                None => vec![],
+            };
+            self.merge_signatures(signatures, first_new_time, opts.keys)?;
+        }
+
         Ok(file_frontier)
     }
+
+    /// Merge the body of a signatures chunk which arrived separately from the rest of its message.
+    /// All the operations in the message must already have been merged. The signatures are stored
+    /// unverified, as if the message had been decoded without a keyring.
+    #[cfg(feature = "signing")]
+    pub(super) fn merge_signatures_chunk(&mut self, chunk: &[u8]) -> Result<(), ParseError> {
+        let signatures = read_signatures(BufReader(chunk), self)?;
+        self.merge_signatures(signatures, self.len(), None)
+    }
+}
+
+/// Read the signatures in a signatures chunk. Signatures from agents we haven't seen are skipped,
+/// since we don't have any of their operations.
+#[cfg(feature = "signing")]
+fn read_signatures(mut chunk: BufReader, oplog: &ListOpLog) -> Result<Vec<SignedSpan>, ParseError> {
+    let mut result = vec![];
+    while !chunk.is_empty() {
+        let agent = oplog.get_agent_id(chunk.next_str()?);
+        let start = chunk.next_usize()?;
+        let len = chunk.next_usize()?;
+        let hash = chunk.next_n_bytes(32)?.try_into().unwrap();
+        let signature = Signature::from_bytes(chunk.next_n_bytes(64)?.try_into().unwrap());
+
+        let Some(agent) = agent else { continue; };
+        result.push(SignedSpan {
+            agent,
+            seq_range: (start..start.checked_add(len).ok_or(ParseError::InvalidLength)?).into(),
+            hash,
+            signature,
+        });
+    }
+    Ok(result)
 }
 
 #[allow(unused)]
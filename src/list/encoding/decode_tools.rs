@@ -62,8 +62,8 @@ impl<'a> BufReader<'a> {
     }
 
     pub(super) fn next_u32_le(&mut self) -> Result<u32, ParseError> {
-        // self.check_has_bytes(size_of::<u32>())?;
-        let val = u32::from_le_bytes(self.0[0..4].try_into().map_err(|_| ParseError::UnexpectedEOF)?);
+        self.check_has_bytes(size_of::<u32>())?;
+        let val = u32::from_le_bytes(self.0[0..4].try_into().unwrap());
         self.consume(size_of::<u32>());
         Ok(val)
     }
@@ -222,6 +222,18 @@ impl<'a> ChunkReader<'a> {
         Ok((chunk_type?, reader))
     }
 
+    /// Read a chunk with the named type, allowing the chunk to be cut short by the end of the data.
+    /// This is used when salvaging truncated files. Returns None if the next chunk isn't the
+    /// specified type, or we hit EOF.
+    pub(super) fn read_chunk_if_eq_truncated(&mut self, expect_chunk_type: ListChunkType) -> Result<Option<BufReader<'a>>, ParseError> {
+        if self.0.peek_u32()? != Some(expect_chunk_type as u32) {
+            return Ok(None);
+        }
+        self.0.next_u32()?;
+        let len = self.0.next_usize()?.min(self.0.len());
+        Ok(Some(BufReader(self.0.next_n_bytes(len)?)))
+    }
+
     /// Read the next chunk, skipping unknown chunks for forwards compatibility.
     pub(super) fn next_chunk(&mut self) -> Result<(ListChunkType, BufReader<'a>), ParseError> {
         loop {
@@ -17,7 +17,7 @@ use crate::listmerge::merge::TransformedResultRaw;
 const ALLOW_VERBOSE: bool = true;
 
 /// Write an operation to the passed writer.
-fn write_op(dest: &mut Vec<u8>, op: &ListOpMetrics, cursor: &mut usize) {
+pub(super) fn write_op(dest: &mut Vec<u8>, op: &ListOpMetrics, cursor: &mut usize) {
     // Note I'm relying on the operation log itself to be iter_merged, which simplifies things here
     // greatly.
 
@@ -96,10 +96,10 @@ fn write_op(dest: &mut Vec<u8>, op: &ListOpMetrics, cursor: &mut usize) {
 
 
 #[derive(Debug, Copy, Clone)]
-struct AgentAssignmentRun {
-    agent: AgentId,
-    delta: isize,
-    len: usize,
+pub(super) struct AgentAssignmentRun {
+    pub(super) agent: AgentId,
+    pub(super) delta: isize,
+    pub(super) len: usize,
 }
 
 impl MergableSpan for AgentAssignmentRun {
@@ -118,7 +118,7 @@ impl HasLength for AgentAssignmentRun {
     }
 }
 
-fn write_assignment_run(dest: &mut Vec<u8>, run: AgentAssignmentRun) {
+pub(super) fn write_assignment_run(dest: &mut Vec<u8>, run: AgentAssignmentRun) {
     // Its rare, but possible for the agent assignment sequence to jump around a little.
     // This can happen when:
     // - The sequence numbers are shared with other documents, and hence the seqs are sparse
@@ -358,6 +358,12 @@ impl ListOpLog {
     /// Encode the data stored in the OpLog into a (custom) compact binary form suitable for saving
     /// to disk, or sending over the network.
     pub fn encode_from(&self, opts: &EncodeOptions, from_version: &[LV]) -> Vec<u8> {
+        self.encode_range(opts, from_version, self.cg.version.as_ref())
+    }
+
+    /// Encode the operations in `to_version` which are not in `from_version`. `encode_from` is
+    /// `encode_range` up to the current version of the oplog.
+    pub(crate) fn encode_range(&self, opts: &EncodeOptions, from_version: &[LV], to_version: &[LV]) -> Vec<u8> {
         // if !frontier_is_root(from_frontier) {
         //     unimplemented!("Encoding from a non-root frontier is not implemented");
         // }
@@ -551,9 +557,9 @@ impl ListOpLog {
         // let iter = self.cg.history.optimized_txns_between(from_frontier, &self.frontier);
         if !opts.sort {
             assert_eq!(opts.store_xf, false);
-            let (_, new_ranges) = self.cg.graph.diff(from_version, self.cg.version.as_ref());
+            let (_, new_ranges) = self.cg.graph.diff(from_version, to_version);
             // for walk in self.cg.graph.iter_range() {
-            // for walk in self.cg.graph.optimized_txns_between(from_version, self.cg.version.as_ref()) {
+            // for walk in self.cg.graph.optimized_txns_between(from_version, to_version) {
             for ge in new_ranges.iter().flat_map(|r| self.cg.graph.iter_range(*r)) {
                 process_ops(ge);
             }
@@ -565,7 +571,7 @@ impl ListOpLog {
                 push_leb_usize(buf, e.len);
             });
 
-            for r in self.get_xf_operations_full(from_version, self.cg.version.as_ref()) {
+            for r in self.get_xf_operations_full(from_version, to_version) {
                 let range = r.lv_range();
                 self.cg.graph.with_parents(range.start, |parents| {
                     let ge = GraphEntrySimple {
@@ -621,6 +627,22 @@ impl ListOpLog {
         ops_writer.flush();
         txns_writer.flush2(&mut agent_mapping);
 
+        // *** Signatures ***
+        // Agents are named in full, so the chunk can be read without the rest of the message.
+        #[cfg(feature = "signing")]
+        let signatures_chunk = {
+            let mut buf = Vec::new();
+            let (_, new_ranges) = self.cg.graph.diff(from_version, to_version);
+            for s in self.signatures_for_ranges(new_ranges.as_ref(), to_version) {
+                push_leb_str(&mut buf, self.get_agent_name(s.agent));
+                push_leb_usize(&mut buf, s.seq_range.start);
+                push_leb_usize(&mut buf, s.seq_range.len());
+                buf.extend_from_slice(&s.hash);
+                buf.extend_from_slice(&s.signature.to_bytes());
+            }
+            buf
+        };
+
         // This nominally needs to happen before we write out agent_mapping.
         // TODO: Support partial data sets. (from_frontier)
         let mut start_branch = Vec::new();
@@ -639,9 +661,9 @@ impl ListOpLog {
 
         let end_branch = if opts.store_end_branch_content {
             let mut end_branch = Vec::new();
-            write_local_version(&mut end_branch, self.cg.version.as_ref(), &mut agent_mapping, self);
+            write_local_version(&mut end_branch, to_version, &mut agent_mapping, self);
 
-            let branch_here = ListBranch::new_at_tip(self);
+            let branch_here = ListBranch::new_at_local_version(self, to_version);
             if verbose {
                 println!("End content length (uncompressed) {}", branch_here.content.len_bytes());
             }
@@ -765,6 +787,16 @@ impl ListOpLog {
         // write_chunk(Chunk::CRC, &mut buf);
         // push_u32(&mut result, checksum);
 
+        // Signatures go after the CRC chunk. Decoders which don't know about signatures look for
+        // the CRC straight after the patches, and would skip the checksum if anything else was
+        // there. (Signatures aren't covered by the checksum, but they're checked against the
+        // operations when they're used.)
+        #[cfg(feature = "signing")] {
+            if !signatures_chunk.is_empty() {
+                push_leb_chunk(&mut result, ListChunkType::Signatures, &signatures_chunk, verbose);
+            }
+        }
+
         if verbose {
             println!("== Total length {}", result.len());
         }
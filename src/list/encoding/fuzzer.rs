@@ -1,6 +1,7 @@
 use rand::prelude::*;
 use crate::list::{ListCRDT, ListOpLog};
-use crate::list::encoding::EncodeOptions;
+use crate::Frontier;
+use crate::list::encoding::{EncodeOptions, PatchDecoder, PatchEncoder};
 use crate::list::old_fuzzer_tools::old_make_random_change;
 use crate::list_fuzzer_tools::{choose_2, make_random_change};
 use crate::listmerge::simple_oplog::{SimpleBranch, SimpleOpLog};
@@ -115,3 +116,79 @@ fn encode_decode_multi_fuzz_forever() {
         fuzz_encode_decode_multi(seed, false);
     }
 }
+
+// This fuzzer makes 3 oplogs, and merges patches between them using patch bundles. Every bundle
+// is checked against merging the same operations using the full format.
+fn fuzz_patch_bundles(seed: u64, verbose: bool) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+
+    for (i, doc) in docs.iter_mut().enumerate() {
+        doc.get_or_create_agent_id(agent_name(i).as_str());
+    }
+
+    // encoders[a][b] sends from a to b. decoders[b][a] receives them. sent[a][b] is the version
+    // of a which was last sent to b.
+    let mut encoders: [[PatchEncoder; 3]; 3] = Default::default();
+    let mut decoders: [[PatchDecoder; 3]; 3] = Default::default();
+    let mut sent: [[Frontier; 3]; 3] = Default::default();
+
+    for _i in 0..50 {
+        if verbose { println!("\n\ni {}", _i); }
+        for _j in 0..2 {
+            let idx = rng.gen_range(0..docs.len());
+            old_make_random_change(&mut docs[idx], None, 0, &mut rng, true);
+        }
+
+        for _j in 0..2 {
+            let a = rng.gen_range(0..docs.len());
+            let b = (a + rng.gen_range(1..docs.len())) % docs.len();
+
+            let from = sent[a][b].clone();
+            let data = encoders[a][b].encode_from(&docs[a].oplog, from.as_ref());
+            let full_data = docs[a].oplog.encode_from(&EncodeOptions::patch(), from.as_ref());
+            sent[a][b] = docs[a].oplog.local_frontier();
+
+            let mut expect = docs[b].oplog.clone();
+            expect.decode_and_add(&full_data).unwrap();
+
+            let doc = &mut docs[b];
+            decoders[b][a].decode_and_add(&mut doc.oplog, &data).unwrap();
+            doc.branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+
+            if doc.oplog != expect {
+                dbg!(&doc.oplog, &expect);
+                panic!("Patch bundle does not match full encoding");
+            }
+        }
+    }
+
+    // Sync everything, and check the documents converge.
+    for a in 0..docs.len() {
+        for b in 0..docs.len() {
+            if a == b { continue; }
+            let data = encoders[a][b].encode_from(&docs[a].oplog, sent[a][b].as_ref());
+            sent[a][b] = docs[a].oplog.local_frontier();
+            let doc = &mut docs[b];
+            decoders[b][a].decode_and_add(&mut doc.oplog, &data).unwrap();
+            doc.branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+        }
+    }
+    for doc in &docs[1..] {
+        assert_eq!(doc.branch.content, docs[0].branch.content);
+    }
+}
+
+#[test]
+fn patch_bundle_fuzz_once() {
+    fuzz_patch_bundles(4, false);
+}
+
+#[test]
+#[ignore]
+fn patch_bundle_fuzz_forever() {
+    for seed in 0.. {
+        if seed % 20 == 0 { println!("seed {seed}"); }
+        fuzz_patch_bundles(seed, false);
+    }
+}
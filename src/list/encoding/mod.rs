@@ -15,11 +15,19 @@ pub mod save_transformed;
 pub(crate) mod leb;
 pub(crate) mod txn_trace;
 mod encode_options;
+mod verify;
+pub mod stream;
+pub mod patch_bundle;
 
 use rle::MergableSpan;
 use crate::encoding::varint::*;
 use num_enum::TryFromPrimitive;
 pub use encode_options::{EncodeOptions, EncodeOptionsBuilder, ENCODE_FULL, ENCODE_PATCH};
+pub use decode_oplog::{DecodeOptions, SalvageStop};
+pub use verify::{ChunkInfo, read_chunk_tree, VerifyProblem, VerifyReport};
+pub use stream::{DecodeProgress, StreamDecoder, StreamError};
+pub use patch_bundle::{PatchDecoder, PatchEncoder};
+pub use crate::encoding::parseerror::ParseError;
 
 const MAGIC_BYTES: [u8; 8] = *b"DMNDTYPS";
 
@@ -61,6 +69,9 @@ enum ListChunkType {
     /// A chunk specifying the position deltas for operations when transformed in the stored order
     TransformedPositions = 28,
 
+    /// Signatures over spans of operations. See [`crate::list::signing`].
+    Signatures = 30,
+
     Crc = 100,
 }
 
@@ -0,0 +1,326 @@
+//! Patch bundles are a lightweight wire encoding for sending small, frequent changes (like
+//! individual keystrokes) between two peers over a long lived connection.
+//!
+//! The full `.dt` format is optimized for large files. Every message starts with magic bytes, a
+//! file info chunk naming every agent involved and ends with a CRC. For a single keystroke that
+//! header is most of the message. Patch bundles skip all of that:
+//!
+//! - Agent names are sent at most once per connection. The first time an agent is referenced, its
+//!   name is appended to the message's agent table and both peers assign it the next index. Later
+//!   messages refer to the agent by that index.
+//! - Sequence numbers are delta encoded against the last sequence number sent for each agent, so
+//!   consecutive edits from the same user cost a single byte.
+//! - Operations are stored interleaved with their versions and parents rather than in separate
+//!   columns. This compresses worse for large histories, but there's no chunk framing to pay for.
+//!
+//! Because both sides track what has been sent, a [`PatchEncoder`] / [`PatchDecoder`] pair must be
+//! used with a reliable, ordered transport (eg a websocket), and each bundle must be decoded
+//! exactly once, in the order it was encoded. Each encoder must also always be used with the same
+//! oplog. If a connection is dropped, start again with a new encoder and decoder.
+//!
+//! A bundle contains:
+//!
+//! - The number of new agent names, then each name as a length prefixed string
+//! - A list of entries until the end of the message. Each entry has:
+//!   - Agent assignment (mapped agent, length and optional sequence jump), as in the `OpVersions`
+//!     chunk
+//!   - Parents, as in the `OpParents` chunk. Local parents are relative to the start of the bundle.
+//!   - Operations, as in the `OpTypeAndPosition` chunk, until the entry's length is consumed. Each
+//!     insert is followed by its content as a length prefixed string. (Length 0 means the content
+//!     is unknown.) Deleted content is never sent.
+
+use rle::HasLength;
+use smallvec::SmallVec;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::causalgraph::agent_assignment::ClientData;
+use crate::encoding::map::{ReadMap, WriteMap};
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::varint::{mix_bit_usize, strip_bit_usize_2};
+use crate::frontier::sort_frontier;
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::encoding::encode_oplog::{AgentAssignmentRun, write_assignment_run, write_op};
+use crate::list::encoding::encode_tools::{push_leb_str, push_leb_usize};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind::Ins;
+use crate::list::operation::TextOperation;
+use crate::rle::{KVPair, RleVec};
+use crate::unicount::count_chars;
+
+/// Encodes patch bundles to send to a single remote peer. See the [module
+/// documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct PatchEncoder {
+    /// Agents (and their last sent seq) which the remote peer already knows about.
+    map: WriteMap,
+}
+
+impl Default for PatchEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn map_agent<'c>(map: &mut WriteMap, client_data: &'c [ClientData], agent: AgentId, new_names: &mut Vec<&'c str>) -> AgentId {
+    map.map_mut(client_data, agent, true).unwrap_or_else(|name| {
+        new_names.push(name);
+        map.map(client_data, agent).unwrap()
+    })
+}
+
+impl PatchEncoder {
+    pub fn new() -> Self {
+        Self { map: WriteMap::new() }
+    }
+
+    /// Encode all the operations in the oplog which are not in `from_version` into a bundle.
+    pub fn encode_from(&mut self, oplog: &ListOpLog, from_version: &[LV]) -> Vec<u8> {
+        let client_data = &oplog.cg.agent_assignment.client_data[..];
+
+        // Names of agents the remote peer hasn't seen yet. These go at the start of the bundle,
+        // but we only find out what they are as we write the entries.
+        let mut new_names = Vec::new();
+        let mut entries = Vec::new();
+
+        // Map from local versions to versions in the bundle. Used for local parents.
+        let mut txn_map = RleVec::<KVPair<DTRange>>::new();
+        let mut next_output_time = 0;
+        let mut last_cursor_pos = 0;
+
+        let (_, new_ranges) = oplog.cg.graph.diff(from_version, oplog.cg.version.as_ref());
+        for ge in new_ranges.iter().flat_map(|r| oplog.cg.graph.iter_range(*r)) {
+            for KVPair(lv, span) in oplog.cg.agent_assignment.client_with_lv.iter_range_ctx(ge.span, &()) {
+                let len = span.len();
+                let range: DTRange = (lv..lv + len).into();
+                let output_range: DTRange = (next_output_time..next_output_time + len).into();
+
+                // 1. Agent assignment. The mapped agent is offset by 1 because 0 means ROOT.
+                let mapped_agent = map_agent(&mut self.map, client_data, span.agent, &mut new_names);
+                write_assignment_run(&mut entries, AgentAssignmentRun {
+                    agent: mapped_agent + 1,
+                    delta: self.map.seq_delta(span.agent, span.seq_range, true),
+                    len,
+                });
+
+                // 2. Parents. Only the first run in each graph entry has interesting parents.
+                if lv == ge.span.start && ge.parents.is_root() {
+                    // n = 0, has_more = false, is_foreign = true.
+                    push_leb_usize(&mut entries, 1);
+                } else {
+                    let prev = [lv - 1];
+                    let parents = if lv == ge.span.start { ge.parents.as_ref() } else { &prev[..] };
+
+                    let mut iter = parents.iter().peekable();
+                    while let Some(&p) = iter.next() {
+                        let has_more = iter.peek().is_some();
+
+                        let mut write_parent_diff = |mut n: usize, is_foreign: bool| {
+                            n = mix_bit_usize(n, has_more);
+                            n = mix_bit_usize(n, is_foreign);
+                            push_leb_usize(&mut entries, n);
+                        };
+
+                        if let Some((map, offset)) = txn_map.find_with_offset(p) {
+                            // The parent is earlier in this bundle.
+                            write_parent_diff(output_range.start - (map.1.start + offset), false);
+                        } else {
+                            let (agent, seq) = oplog.lv_to_agent_version(p);
+                            let mapped = map_agent(&mut self.map, client_data, agent, &mut new_names);
+                            write_parent_diff(mapped as usize + 1, true);
+                            push_leb_usize(&mut entries, seq);
+                        }
+                    }
+                }
+
+                txn_map.insert(KVPair(lv, output_range));
+                next_output_time = output_range.end;
+
+                // 3. Operations, with inserted content inline.
+                for (op, content) in oplog.iter_range_simple(range) {
+                    let op = op.1;
+                    write_op(&mut entries, &op, &mut last_cursor_pos);
+
+                    if op.kind == Ins {
+                        if let Some(content) = content {
+                            push_leb_str(&mut entries, content);
+                        } else {
+                            push_leb_usize(&mut entries, 0);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut result = Vec::new();
+        push_leb_usize(&mut result, new_names.len());
+        for name in new_names {
+            push_leb_str(&mut result, name);
+        }
+        result.extend_from_slice(&entries);
+        result
+    }
+}
+
+/// Decodes patch bundles sent by a single remote [`PatchEncoder`]. See the [module
+/// documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct PatchDecoder {
+    /// Map from the remote peer's agent indexes to local agent IDs, and the last seq received.
+    map: ReadMap,
+}
+
+impl PatchDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a bundle and merge its operations into the oplog. Operations the oplog already has
+    /// are skipped. Returns the version of the operations in the bundle.
+    ///
+    /// If the bundle is invalid, neither the oplog nor the decoder are modified.
+    pub fn decode_and_add(&mut self, oplog: &mut ListOpLog, data: &[u8]) -> Result<Frontier, ParseError> {
+        let checkpoint = oplog.checkpoint();
+        let agent_map = self.map.agent_map.clone();
+
+        let result = self.decode_internal(oplog, data);
+
+        if result.is_err() {
+            oplog.restore_checkpoint(checkpoint);
+            self.map.agent_map = agent_map;
+        }
+
+        result
+    }
+
+    fn decode_internal(&mut self, oplog: &mut ListOpLog, data: &[u8]) -> Result<Frontier, ParseError> {
+        let mut reader = BufReader(data);
+
+        let num_names = reader.next_usize()?;
+        for _ in 0..num_names {
+            let name = reader.next_str()?;
+            let agent = oplog.get_or_create_agent_id(name);
+            self.map.agent_map.push((agent, 0));
+        }
+
+        // The (bundle start, agent, seq start) of each entry we've read, so local parents can be
+        // found even when some of the entry's operations were already known.
+        let mut entries: Vec<(usize, AgentId, usize)> = Vec::new();
+        let mut next_bundle_time = 0;
+        let mut last_cursor_pos = 0;
+        let mut version: SmallVec<LV, 2> = SmallVec::new();
+
+        while let Some(span) = reader.read_next_agent_assignment(&mut self.map.agent_map)? {
+            let len = span.len();
+            if len == 0 { return Err(ParseError::InvalidLength); }
+
+            // Parents
+            let mut parents: SmallVec<LV, 2> = SmallVec::new();
+            loop {
+                let mut n = reader.next_usize()?;
+                let is_foreign = strip_bit_usize_2(&mut n);
+                let has_more = strip_bit_usize_2(&mut n);
+
+                let (agent, seq) = if is_foreign {
+                    if n == 0 { break; } // ROOT.
+                    let agent = self.map.agent_map.get(n - 1).ok_or(ParseError::InvalidLength)?.0;
+                    (agent, reader.next_usize()?)
+                } else {
+                    if n == 0 || n > next_bundle_time { return Err(ParseError::InvalidLength); }
+                    let time = next_bundle_time - n;
+                    let idx = entries.partition_point(|e| e.0 <= time) - 1;
+                    let (start, agent, seq) = entries[idx];
+                    (agent, seq + (time - start))
+                };
+
+                parents.push(oplog.try_crdt_id_to_time((agent, seq)).ok_or(ParseError::InvalidLength)?);
+                if !has_more { break; }
+            }
+            sort_frontier(&mut parents);
+
+            // Operations
+            let mut ops = Vec::new();
+            let mut remaining = len;
+            while remaining > 0 {
+                let op = reader.next_op(&mut last_cursor_pos)?;
+                let op_len = op.len();
+                if op_len == 0 || op_len > remaining { return Err(ParseError::InvalidLength); }
+                remaining -= op_len;
+
+                let content = if op.kind == Ins {
+                    let content_len = reader.next_usize()?;
+                    if content_len == 0 { None } else {
+                        let bytes = reader.next_n_bytes(content_len)?;
+                        let content = std::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)?;
+                        if count_chars(content) != op_len { return Err(ParseError::InvalidContent); }
+                        Some(content.into())
+                    }
+                } else { None };
+
+                ops.push(TextOperation { loc: op.loc, kind: op.kind, content });
+            }
+
+            oplog.add_operations_remote(span.agent, &parents, span.seq_range.start, &ops);
+            version.push(oplog.try_crdt_id_to_time((span.agent, span.seq_range.last())).unwrap());
+
+            entries.push((next_bundle_time, span.agent, span.seq_range.start));
+            next_bundle_time += len;
+        }
+
+        Ok(oplog.cg.graph.find_dominators(&version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::list::ListOpLog;
+    use super::*;
+
+    #[test]
+    fn bundle_smoke_test() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hi");
+        let v = oplog.add_insert(mike, 2, " there");
+
+        let mut enc = PatchEncoder::new();
+        let mut dec = PatchDecoder::new();
+        let mut remote = ListOpLog::new();
+
+        let data = enc.encode_from(&oplog, &[]);
+        assert_eq!(dec.decode_and_add(&mut remote, &data).unwrap(), Frontier::new_1(v));
+        assert_eq!(remote, oplog);
+
+        // Agent names are only sent once.
+        let from = oplog.local_frontier();
+        oplog.add_insert(seph, 0, "x");
+        let data = enc.encode_from(&oplog, from.as_ref());
+        assert!(!data.windows(4).any(|w| w == b"seph"));
+        assert!(data.len() < 10);
+
+        dec.decode_and_add(&mut remote, &data).unwrap();
+        assert_eq!(remote, oplog);
+
+        // Resending known operations is a no-op.
+        let data = enc.encode_from(&oplog, from.as_ref());
+        dec.decode_and_add(&mut remote, &data).unwrap();
+        assert_eq!(remote, oplog);
+    }
+
+    #[test]
+    fn invalid_bundle_is_rolled_back() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi there");
+
+        let data = PatchEncoder::new().encode_from(&oplog, &[]);
+
+        let mut dec = PatchDecoder::new();
+        let mut remote = ListOpLog::new();
+        assert!(dec.decode_and_add(&mut remote, &data[..data.len() - 1]).is_err());
+        assert!(remote.is_empty());
+
+        // The decoder hasn't learned about seph, so the whole bundle still decodes.
+        dec.decode_and_add(&mut remote, &data).unwrap();
+        assert_eq!(remote, oplog);
+    }
+}
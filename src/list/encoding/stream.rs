@@ -0,0 +1,556 @@
+//! Streaming decoding. This lets an oplog be loaded from a [`Read`] (or an `AsyncRead` with the
+//! `async` feature) without reading all the data into memory first.
+//!
+//! A stream is a sequence of encoded messages, each of which is exactly what
+//! [`ListOpLog::encode_from`] returns. Each message is merged into the oplog as soon as it has
+//! been read, and its bytes are discarded. A `.dt` file is a stream containing a single message.
+//!
+//! Each message stores its operations column by column (versions, positions, parents, etc), so
+//! none of a message's operations can be applied until the whole message has been read. A
+//! message is buffered in full before it is decoded. So reading an ordinary `.dt` file through
+//! the streaming decoder uses as much memory as [`ListOpLog::load_from`].
+//!
+//! To load a large oplog incrementally, write it with [`ListOpLog::encode_stream`], which splits
+//! the operations across many small messages. Existing files can be converted with
+//! `dt repack --stream <ops per message>`.
+//!
+//! Messages normally end with a CRC chunk, followed by a signatures chunk if any of the
+//! message's operations are signed. Messages written without a checksum end where the next
+//! message's magic bytes start (or at the end of the stream).
+//!
+//! A message is merged as soon as its CRC chunk has been read. If its signatures arrive in a later
+//! push, they're merged on their own. Signatures read from a stream aren't verified (see
+//! [`crate::list::signing`]).
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::{EncodeOptions, ListChunkType, MAGIC_BYTES};
+use crate::list::encoding::decode_oplog::DecodeOptions;
+use crate::list::encoding::leb::decode_leb_usize;
+#[cfg(feature = "signing")]
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::ListOpLog;
+use crate::Frontier;
+
+/// How many bytes we ask a reader for at a time.
+const READ_SIZE: usize = 64 * 1024;
+
+/// How much of a stream has been decoded so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeProgress {
+    /// The number of bytes read from the stream.
+    pub bytes_read: usize,
+    /// The number of messages which have been merged into the oplog.
+    pub messages: usize,
+    /// The number of operations added to the oplog. Operations the oplog already had aren't
+    /// counted.
+    pub ops_added: usize,
+}
+
+/// An error reading a stream. Either the reader failed, or the data was invalid.
+#[derive(Debug)]
+pub enum StreamError {
+    Io(std::io::Error),
+    Parse(ParseError),
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "IO error reading stream: {}", e),
+            StreamError::Parse(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+impl Error for StreamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StreamError::Io(e) => Some(e),
+            StreamError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for StreamError {
+    fn from(e: std::io::Error) -> Self { StreamError::Io(e) }
+}
+
+impl From<ParseError> for StreamError {
+    fn from(e: ParseError) -> Self { StreamError::Parse(e) }
+}
+
+/// Read a varint at the start of buf. Returns None if buf ends partway through the varint.
+fn peek_leb_usize(buf: &[u8]) -> Result<Option<(usize, usize)>, ParseError> {
+    // Varints are at most 10 bytes long, and end with a byte with the high bit clear.
+    if buf.iter().take(10).all(|b| b & 0x80 != 0) && buf.len() < 10 {
+        Ok(None)
+    } else {
+        decode_leb_usize(buf).map(Some)
+    }
+}
+
+/// Read the type and total length of the chunk at the start of buf. Returns None if the chunk
+/// hasn't been read in full.
+fn peek_chunk(buf: &[u8]) -> Result<Option<(usize, usize)>, ParseError> {
+    let Some((chunk_type, type_len)) = peek_leb_usize(buf)? else { return Ok(None); };
+    let Some((chunk_len, len_len)) = peek_leb_usize(&buf[type_len..])? else { return Ok(None); };
+
+    let end = type_len.checked_add(len_len)
+        .and_then(|l| l.checked_add(chunk_len))
+        .ok_or(ParseError::InvalidLength)?;
+    Ok(if end > buf.len() { None } else { Some((chunk_type, end)) })
+}
+
+/// A push-based decoder for a stream of encoded messages. Feed it bytes as they arrive with
+/// [`push`](StreamDecoder::push). Each message is merged into the oplog once all of its bytes have
+/// been pushed.
+///
+/// If a message fails to decode, none of that message's operations are kept (just like
+/// [`ListOpLog::decode_and_add`]), but operations from earlier messages in the stream are. The
+/// decoder can't be used after an error.
+#[derive(Debug, Clone, Default)]
+pub struct StreamDecoder {
+    /// Bytes of the message currently being read.
+    buf: Vec<u8>,
+    /// Bytes in buf which have been scanned for the end of the message. 0 if we haven't read the
+    /// message's header yet.
+    scanned: usize,
+    /// Set once we've read the message's CRC chunk. The message ends there, unless a signatures
+    /// chunk follows.
+    after_crc: bool,
+    progress: DecodeProgress,
+    error: Option<ParseError>,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn progress(&self) -> DecodeProgress {
+        self.progress
+    }
+
+    /// Returns true if we're partway through reading a message.
+    pub fn in_message(&self) -> bool {
+        !self.buf.is_empty()
+    }
+
+    /// Find the length of the message at the start of buf, if it has been read in full. Messages
+    /// end with a CRC chunk (and the signatures chunk after it, if it has been read), a
+    /// signatures chunk, or just before the next message's magic bytes.
+    ///
+    /// If buf starts with the signatures chunk of the previous message instead, returns the
+    /// chunk's length and true.
+    fn scan(&mut self) -> Result<Option<(usize, bool)>, ParseError> {
+        if self.scanned == 0 {
+            if self.buf.first() == Some(&(ListChunkType::Signatures as u8)) {
+                return Ok(peek_chunk(&self.buf)?.map(|(_, len)| (len, true)));
+            }
+            if self.buf.len() < MAGIC_BYTES.len() { return Ok(None); }
+            if self.buf[..MAGIC_BYTES.len()] != MAGIC_BYTES { return Err(ParseError::InvalidMagic); }
+            let Some((_version, len)) = peek_leb_usize(&self.buf[MAGIC_BYTES.len()..])? else {
+                return Ok(None);
+            };
+            self.scanned = MAGIC_BYTES.len() + len;
+        }
+
+        loop {
+            let rest = &self.buf[self.scanned..];
+
+            // The magic bytes start with 'D' (68), which isn't a valid chunk type. So if we see
+            // them where the next chunk should be, a new message has started.
+            if rest.first() == Some(&MAGIC_BYTES[0]) {
+                if rest.len() < MAGIC_BYTES.len() { return Ok(None); }
+                if rest[..MAGIC_BYTES.len()] == MAGIC_BYTES { return Ok(Some((self.scanned, false))); }
+            }
+
+            if self.after_crc && rest.first() != Some(&(ListChunkType::Signatures as u8)) {
+                // Don't wait for signatures which might never come.
+                return Ok(Some((self.scanned, false)));
+            }
+
+            let Some((chunk_type, end)) = peek_chunk(rest)? else { return Ok(None); };
+            self.scanned += end;
+            if chunk_type == ListChunkType::Signatures as usize {
+                return Ok(Some((self.scanned, false)));
+            }
+            if chunk_type == ListChunkType::Crc as usize {
+                self.after_crc = true;
+            }
+        }
+    }
+
+    fn decode_message(&mut self, oplog: &mut ListOpLog, len: usize) -> Result<(), ParseError> {
+        let ops_before = oplog.len();
+        oplog.decode_and_add_opts(&self.buf[..len], DecodeOptions::default())?;
+        self.buf.drain(..len);
+        self.scanned = 0;
+        self.after_crc = false;
+        self.progress.messages += 1;
+        self.progress.ops_added += oplog.len() - ops_before;
+        Ok(())
+    }
+
+    /// Merge a signatures chunk which was split from the end of its message.
+    fn decode_late_signatures(&mut self, _oplog: &mut ListOpLog, len: usize) -> Result<(), ParseError> {
+        #[cfg(feature = "signing")] {
+            let mut chunk = BufReader(&self.buf[..len]).chunks();
+            _oplog.merge_signatures_chunk(chunk.expect_chunk(ListChunkType::Signatures)?.0)?;
+        }
+        self.buf.drain(..len);
+        Ok(())
+    }
+
+    fn push_internal(&mut self, oplog: &mut ListOpLog, data: &[u8]) -> Result<(), ParseError> {
+        self.buf.extend_from_slice(data);
+        self.progress.bytes_read += data.len();
+
+        while let Some((len, late_signatures)) = self.scan()? {
+            if late_signatures {
+                self.decode_late_signatures(oplog, len)?;
+            } else {
+                self.decode_message(oplog, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add more bytes from the stream. Any messages which are now complete are merged into the
+    /// oplog.
+    pub fn push(&mut self, oplog: &mut ListOpLog, data: &[u8]) -> Result<DecodeProgress, ParseError> {
+        if let Some(e) = self.error { return Err(e); }
+        self.push_internal(oplog, data).inspect_err(|&e| self.error = Some(e))?;
+        Ok(self.progress)
+    }
+
+    /// Call this once the stream has ended. Errors if the stream ended partway through a message.
+    ///
+    /// Messages normally end with a checksum. If the last message in the stream doesn't have one,
+    /// it is merged here.
+    pub fn finish(mut self, oplog: &mut ListOpLog) -> Result<DecodeProgress, ParseError> {
+        if let Some(e) = self.error { return Err(e); }
+        if !self.buf.is_empty() {
+            let len = self.buf.len();
+            self.decode_message(oplog, len)?;
+        }
+        Ok(self.progress)
+    }
+}
+
+fn read_retrying<R: Read>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        match r.read(buf) {
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+impl ListOpLog {
+    /// Merge all the messages read from `r` into this oplog. `progress` is called each time more
+    /// data has been read.
+    ///
+    /// See the [stream module](crate::list::encoding::stream) for details.
+    pub fn decode_and_add_from_reader<R: Read, F: FnMut(DecodeProgress)>(&mut self, mut r: R, mut progress: F) -> Result<DecodeProgress, StreamError> {
+        let mut decoder = StreamDecoder::new();
+        let mut buf = vec![0; READ_SIZE];
+        loop {
+            let n = read_retrying(&mut r, &mut buf)?;
+            if n == 0 { break; }
+            progress(decoder.push(self, &buf[..n])?);
+        }
+        Ok(decoder.finish(self)?)
+    }
+
+    /// Load an oplog from a reader. This is the streaming equivalent of
+    /// [`load_from`](ListOpLog::load_from).
+    ///
+    /// Each message is buffered in full before it is decoded, so loading an ordinary `.dt` file
+    /// (which is a single message) this way doesn't save any memory.
+    pub fn load_from_reader<R: Read>(r: R) -> Result<Self, StreamError> {
+        let mut oplog = Self::new();
+        oplog.decode_and_add_from_reader(r, |_| {})?;
+        Ok(oplog)
+    }
+
+    /// The async equivalent of
+    /// [`decode_and_add_from_reader`](ListOpLog::decode_and_add_from_reader).
+    #[cfg(feature = "async")]
+    pub async fn decode_and_add_from_async_reader<R, F>(&mut self, mut r: R, mut progress: F) -> Result<DecodeProgress, StreamError>
+        where R: futures_io::AsyncRead + Unpin, F: FnMut(DecodeProgress)
+    {
+        use std::pin::Pin;
+
+        let mut decoder = StreamDecoder::new();
+        let mut buf = vec![0; READ_SIZE];
+        loop {
+            let n = std::future::poll_fn(|cx| Pin::new(&mut r).poll_read(cx, &mut buf)).await;
+            let n = match n {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                n => n?,
+            };
+            if n == 0 { break; }
+            progress(decoder.push(self, &buf[..n])?);
+        }
+        Ok(decoder.finish(self)?)
+    }
+
+    /// Encode the operations after `from_version` as a stream of messages, each containing at most
+    /// `ops_per_message` operations. Unlike a single message, a stream can be merged incrementally
+    /// by [`StreamDecoder`] as it is read.
+    ///
+    /// Only the first message stores start branch content (if `opts` asks for it).
+    pub fn encode_stream<W: Write>(&self, mut w: W, opts: &EncodeOptions, from_version: &[crate::LV], ops_per_message: usize) -> std::io::Result<()> {
+        assert!(ops_per_message > 0);
+        let (_, new_ranges) = self.cg.graph.diff(from_version, self.cg.version.as_ref());
+
+        let mut from: Frontier = from_version.into();
+        let mut to = from.clone();
+        let later_opts = opts.clone().store_start_branch_content(false);
+        let mut first = true;
+
+        for mut range in new_ranges.iter().copied() {
+            while !range.is_empty() {
+                let end = range.end.min(range.start + ops_per_message);
+                to.advance(&self.cg.graph, (range.start..end).into());
+                range.start = end;
+
+                let opts = if first { opts } else { &later_opts };
+                w.write_all(&self.encode_range(opts, from.as_ref(), to.as_ref()))?;
+                from.replace(to.as_ref());
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use rand::prelude::*;
+    use crate::list::encoding::{EncodeOptions, ParseError};
+    use crate::list::{ListBranch, ListOpLog};
+    use crate::list::old_fuzzer_tools::old_make_random_change_raw;
+    use super::*;
+
+    /// Make an oplog with random concurrent edits from two agents.
+    fn gen_oplog(seed: u64, steps: usize, use_unicode: bool) -> ListOpLog {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut oplog = ListOpLog::new();
+        let agents = [oplog.get_or_create_agent_id("a"), oplog.get_or_create_agent_id("b")];
+        let mut branches = [ListBranch::new(), ListBranch::new()];
+
+        for _ in 0..steps {
+            let i = rng.gen_range(0..2);
+            let v = old_make_random_change_raw(&mut oplog, &branches[i], None, agents[i], &mut rng, use_unicode);
+            branches[i].merge(&oplog, &[v]);
+            if rng.gen_bool(0.2) {
+                branches[i].merge(&oplog, oplog.local_frontier_ref());
+            }
+        }
+        oplog
+    }
+
+    /// A reader which returns at most `max` bytes from each read call.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        max: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.data.len().min(self.max).min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn load_single_message() {
+        let oplog = gen_oplog(123, 50, true);
+        let data = oplog.encode(&EncodeOptions::full().store_deleted_content(true));
+
+        for max in [1, 7, 1000, usize::MAX] {
+            let mut updates = 0;
+            let mut result = ListOpLog::new();
+            let progress = result.decode_and_add_from_reader(Trickle { data: &data, max }, |_| updates += 1).unwrap();
+            assert_eq!(result, oplog);
+            assert_eq!(progress, DecodeProgress { bytes_read: data.len(), messages: 1, ops_added: oplog.len() });
+            assert!(updates >= 1);
+        }
+    }
+
+    #[test]
+    fn stream_applies_messages_incrementally() {
+        let oplog = gen_oplog(321, 100, true);
+        let mut data = Vec::new();
+        oplog.encode_stream(&mut data, &EncodeOptions::full(), &[], 10).unwrap();
+
+        let mut decoder = StreamDecoder::new();
+        let mut result = ListOpLog::new();
+        let mut last_len = 0;
+        let mut grew = 0;
+        for chunk in data.chunks(13) {
+            decoder.push(&mut result, chunk).unwrap();
+            assert_eq!(decoder.progress().ops_added, result.len());
+            if result.len() > last_len {
+                grew += 1;
+                last_len = result.len();
+            }
+        }
+        let progress = decoder.finish(&mut result).unwrap();
+
+        assert_eq!(result.checkout_tip().content(), oplog.checkout_tip().content());
+        assert_eq!(result.len(), oplog.len());
+        assert_eq!(progress.bytes_read, data.len());
+        assert_eq!(progress.messages, oplog.len().div_ceil(10));
+        assert!(grew > 1);
+    }
+
+    #[test]
+    fn stream_from_version() {
+        let oplog = gen_oplog(5, 60, false);
+        let base = ListOpLog::load_from(&oplog.encode_range(&EncodeOptions::full(), &[], &[20])).unwrap();
+
+        let mut data = Vec::new();
+        oplog.encode_stream(&mut data, &EncodeOptions::patch(), &[20], 7).unwrap();
+        let mut result = base.clone();
+        result.decode_and_add_from_reader(&data[..], |_| {}).unwrap();
+        assert_eq!(result.checkout_tip().content(), oplog.checkout_tip().content());
+        assert_eq!(result.len(), oplog.len());
+    }
+
+    #[test]
+    fn truncated_stream_keeps_complete_messages() {
+        let oplog = gen_oplog(9, 40, false);
+        let mut data = Vec::new();
+        oplog.encode_stream(&mut data, &EncodeOptions::full(), &[], 10).unwrap();
+
+        let mut result = ListOpLog::new();
+        let err = result.decode_and_add_from_reader(&data[..data.len() - 3], |_| {}).unwrap_err();
+        assert!(matches!(err, StreamError::Parse(ParseError::UnexpectedEOF) | StreamError::Parse(ParseError::InvalidLength)));
+        assert!(!result.is_empty() && result.len() < oplog.len());
+    }
+
+    #[test]
+    #[cfg(feature = "signing")]
+    fn signatures_split_from_message() {
+        use crate::list::signing::SigningKey;
+
+        let mut oplog = gen_oplog(45, 20, false);
+        oplog.sign_ops(0, &SigningKey::from_bytes(&[1; 32])).unwrap();
+        let mut data = oplog.encode(&EncodeOptions::full());
+        data.extend_from_slice(&data.clone());
+
+        // Wherever the stream is split, the signatures end up with the operations.
+        for split in 1..data.len() {
+            let mut decoder = StreamDecoder::new();
+            let mut result = ListOpLog::new();
+            decoder.push(&mut result, &data[..split]).unwrap();
+            decoder.push(&mut result, &data[split..]).unwrap();
+            assert_eq!(decoder.finish(&mut result).unwrap().messages, 2);
+            assert_eq!(result.checkout_tip().content(), oplog.checkout_tip().content());
+            assert_eq!(result.signatures, oplog.signatures);
+        }
+    }
+
+    #[test]
+    fn messages_without_checksums() {
+        /// Collects each message written by encode_stream separately.
+        struct Messages(Vec<Vec<u8>>);
+        impl Write for Messages {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let oplog = gen_oplog(44, 60, true);
+        let mut messages = Messages(vec![]);
+        oplog.encode_stream(&mut messages, &EncodeOptions::full(), &[], 10).unwrap();
+
+        // Strip the CRC chunk (type, length and 4 byte checksum) off the end of each message.
+        let mut data = Vec::new();
+        for m in messages.0 {
+            assert_eq!(m[m.len() - 6], ListChunkType::Crc as u8);
+            data.extend_from_slice(&m[..m.len() - 6]);
+        }
+
+        for size in [1, 5, 100] {
+            let mut decoder = StreamDecoder::new();
+            let mut result = ListOpLog::new();
+            for chunk in data.chunks(size) {
+                decoder.push(&mut result, chunk).unwrap();
+            }
+            let progress = decoder.finish(&mut result).unwrap();
+            assert_eq!(progress.messages, oplog.len().div_ceil(10));
+            assert_eq!(result.checkout_tip().content(), oplog.checkout_tip().content());
+        }
+    }
+
+    #[test]
+    fn garbage_errors() {
+        let mut decoder = StreamDecoder::new();
+        let mut oplog = ListOpLog::new();
+        assert_eq!(decoder.push(&mut oplog, b"not a dt file").unwrap_err(), ParseError::InvalidMagic);
+        assert_eq!(decoder.push(&mut oplog, b"more").unwrap_err(), ParseError::InvalidMagic);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_reader() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        /// Returns Pending before every read.
+        struct SlowReader<'a> {
+            data: &'a [u8],
+            ready: bool,
+        }
+
+        impl<'a> futures_io::AsyncRead for SlowReader<'a> {
+            fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+                if !self.ready {
+                    self.ready = true;
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                self.ready = false;
+                let n = self.data.len().min(buf.len()).min(100);
+                buf[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+                Poll::Ready(Ok(n))
+            }
+        }
+
+        struct NoopWake;
+        impl Wake for NoopWake {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let oplog = gen_oplog(77, 50, true);
+        let mut data = Vec::new();
+        oplog.encode_stream(&mut data, &EncodeOptions::full(), &[], 8).unwrap();
+
+        let mut result = ListOpLog::new();
+        let mut fut = Box::pin(result.decode_and_add_from_async_reader(SlowReader { data: &data, ready: false }, |_| {}));
+        let waker = Waker::from(Arc::new(NoopWake));
+        let mut cx = Context::from_waker(&waker);
+        let progress = loop {
+            if let Poll::Ready(r) = fut.as_mut().poll(&mut cx) { break r.unwrap(); }
+        };
+        drop(fut);
+
+        assert_eq!(progress.bytes_read, data.len());
+        assert_eq!(result.checkout_tip().content(), oplog.checkout_tip().content());
+    }
+}
@@ -96,6 +96,95 @@ fn merge_future_patch_errors() {
     assert_eq!(err, ParseError::BaseVersionUnknown);
 }
 
+#[test]
+fn out_of_bounds_ops_rejected() {
+    let check = DecodeOptions { check_positions: true, ..Default::default() };
+
+    let mut src = ListOpLog::new();
+    let seph = src.get_or_create_agent_id("seph");
+    src.add_insert(seph, 0, "hi");
+    let v = src.cg.version.clone();
+    // The oplog doesn't check positions when operations are added.
+    src.add_delete_at(seph, v.as_ref(), 1..5);
+
+    let data = src.encode(&ENCODE_FULL);
+    assert_eq!(ListOpLog::load_from_opts(&data, check.clone()).unwrap_err(), ParseError::OperationOutOfBounds);
+
+    // A failed merge is unwound.
+    let mut dest = ListOpLog::new();
+    let seph = dest.get_or_create_agent_id("seph");
+    dest.add_insert(seph, 0, "hi");
+    let expected = dest.clone();
+    assert_eq!(dest.decode_and_add_opts(&data, check.clone()).unwrap_err(), ParseError::OperationOutOfBounds);
+    assert_eq!(dest, expected);
+
+    // Without the option, the data loads.
+    assert!(ListOpLog::load_from(&data).is_ok());
+}
+
+#[test]
+fn id_reuse_detected_when_decoding() {
+    let check = DecodeOptions { check_id_reuse: true, ..Default::default() };
+
+    let mut a = ListOpLog::new();
+    let alice = a.get_or_create_agent_id("alice");
+    a.add_insert(alice, 0, "hello");
+
+    // A buggy client reuses alice's IDs for different content.
+    let mut b = ListOpLog::new();
+    let alice_b = b.get_or_create_agent_id("alice");
+    b.add_insert(alice_b, 0, "world");
+
+    // And another reuses them with the same content but different parents.
+    let mut c = ListOpLog::new();
+    let bob = c.get_or_create_agent_id("bob");
+    let alice_c = c.get_or_create_agent_id("alice");
+    c.add_insert(bob, 0, "x");
+    c.add_insert(alice_c, 0, "hello");
+
+    let mut dest = ListOpLog::load_from(&a.encode(&ENCODE_FULL)).unwrap();
+    let expected = dest.clone();
+    let reused = Err(ParseError::IdReused { agent: 0, seq_range: (0..5).into() });
+
+    assert_eq!(dest.decode_and_add_opts(&b.encode(&ENCODE_FULL), check.clone()).map(|_| ()), reused);
+    assert_eq!(dest, expected);
+    assert_eq!(dest.decode_and_add_opts(&c.encode(&ENCODE_FULL), check.clone()).map(|_| ()), reused);
+    assert_eq!(dest, expected);
+
+    // Resending the real operations is fine.
+    dest.decode_and_add_opts(&a.encode(&ENCODE_FULL), check.clone()).unwrap();
+    assert_eq!(dest, expected);
+
+    // Without the check, the reused operations are silently dropped.
+    dest.decode_and_add(&b.encode(&ENCODE_FULL)).unwrap();
+    assert_eq!(dest.checkout_tip().content(), "hello");
+}
+
+#[test]
+fn id_reuse_detected_when_adding_remote_ops() {
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionSpan;
+    use crate::list::operation::TextOperation;
+
+    let mut oplog = ListOpLog::new();
+    let alice = oplog.get_or_create_agent_id("alice");
+    oplog.add_insert(alice, 0, "hello");
+
+    let ops = [TextOperation::new_insert(0, "hel"), TextOperation::new_insert(3, "lo!")];
+    assert_eq!(oplog.add_operations_remote_checked(alice, &[], 0, &ops), Ok((5..6).into()));
+
+    let ops = [TextOperation::new_insert(0, "help")];
+    let err = oplog.add_operations_remote_checked(alice, &[], 0, &ops).unwrap_err();
+    assert_eq!(err, ParseError::IdReused { agent: alice, seq_range: (0..4).into() });
+    assert_eq!(err.reused_remote_span(&oplog.cg), Some(RemoteVersionSpan("alice", (0..4).into())));
+
+    let ops = [TextOperation::new_delete(0..2)];
+    assert_eq!(oplog.add_operations_remote_checked(alice, &[2], 5, &ops),
+               Err(ParseError::IdReused { agent: alice, seq_range: (5..6).into() }));
+    assert_eq!(ParseError::InvalidLength.reused_remote_span(&oplog.cg), None);
+    assert_eq!(oplog.len(), 6);
+    assert_eq!(oplog.checkout_tip().content(), "hello!");
+}
+
 // This test is ignored because it errors (arguably correctly) when reading the base version at
 // an unknown point in time. TODO: Rewrite this to make it work.
 #[test]
@@ -198,6 +287,7 @@ fn check_unroll_works(dest: &ListOpLog, src: &ListOpLog) {
         let result = actual_output.decode_and_add_opts(&corrupted, DecodeOptions {
             ignore_crc: false,
             verbose: true,
+            ..Default::default()
         });
 
         if let Err(_err) = result {
@@ -387,4 +477,53 @@ fn compat_simple_doc() {
         let bytes2_compressed_full = &[68, 77, 78, 68, 84, 89, 80, 83, 0, 5, 11, 9, 144, 104, 105, 32, 116, 104, 101, 114, 101, 109, 1, 7, 3, 5, 4, 115, 101, 112, 104, 10, 0, 20, 24, 24, 8, 0, 14, 2, 4, 9, 25, 1, 19, 21, 2, 2, 13, 22, 4, 65, 79, 11, 0, 23, 2, 13, 1, 100, 4, 128, 32, 8, 191];
         assert_eq!(ListOpLog::load_from(bytes2_compressed_full).unwrap(), doc.oplog);
     }
-}
\ No newline at end of file
+}
+
+/// The checksum check done by decoders from before signatures were added. They read the chunks
+/// they know about in order, then check the CRC chunk if it comes next. Returns false if the
+/// checksum would be skipped.
+#[cfg(feature = "signing")]
+fn pre_signing_crc_check(data: &[u8]) -> Result<bool, ParseError> {
+    use crate::encoding::tools::calc_checksum;
+    use crate::list::encoding::decode_tools::BufReader;
+
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    reader.next_usize()?;
+    let mut reader = reader.chunks();
+    reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)?;
+    reader.expect_chunk(ListChunkType::FileInfo)?;
+    reader.expect_chunk(ListChunkType::StartBranch)?;
+    reader.expect_chunk(ListChunkType::Patches)?;
+
+    let reader_len = reader.0.len();
+    let Some(mut crc_reader) = reader.read_chunk_if_eq(ListChunkType::Crc)? else { return Ok(false); };
+    if calc_checksum(&data[..data.len() - reader_len]) != crc_reader.next_u32_le()? {
+        return Err(ParseError::ChecksumFailed);
+    }
+    Ok(true)
+}
+
+#[test]
+#[cfg(feature = "signing")]
+fn signed_files_checksummed_by_old_decoders() {
+    use crate::list::signing::SigningKey;
+
+    let mut oplog = ListOpLog::new();
+    let seph = oplog.get_or_create_agent_id("seph");
+    oplog.add_insert(seph, 0, "hi there");
+    oplog.sign_ops(seph, &SigningKey::from_bytes(&[1; 32])).unwrap();
+
+    let data = oplog.encode(&EncodeOptions::full());
+    assert_eq!(pre_signing_crc_check(&data), Ok(true));
+
+    // Flip a byte of the inserted content. The file still parses, so only the checksum notices.
+    let mut corrupt = data.clone();
+    let pos = corrupt.windows(8).position(|w| w == b"hi there").unwrap();
+    corrupt[pos] = b'H';
+    assert_eq!(pre_signing_crc_check(&corrupt), Err(ParseError::ChecksumFailed));
+    assert_eq!(ListOpLog::load_from(&corrupt).unwrap_err(), ParseError::ChecksumFailed);
+
+    // And the signatures are still loaded by new decoders.
+    assert_eq!(ListOpLog::load_from(&data).unwrap().signatures, oplog.signatures);
+}
@@ -0,0 +1,505 @@
+//! This module contains code to check the integrity of encoded oplogs, and to salvage what we can
+//! from damaged files.
+//!
+//! Verification happens in layers. First the raw chunk structure and checksum are checked. Then the
+//! patches are decoded (stopping at the first damaged operation), and the resulting causal graph
+//! and agent assignments are sanity checked. Finally the operations are replayed and compared with
+//! the end content stored in the file, if any.
+
+use std::fmt::{Display, Formatter};
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::calc_checksum;
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::encoding::ListChunkType;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::ListOpKind;
+use rle::HasLength;
+use crate::{DTRange, LV};
+use crate::rle::KVPair;
+
+/// A chunk found in the file. Chunks containing other chunks are followed by their children, with
+/// a depth one greater than their parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// The name of the chunk type, or `Unknown(n)` for chunk types this version doesn't know about.
+    pub name: String,
+    /// Byte offset of the start of the chunk's header in the file.
+    pub offset: usize,
+    /// Length of the chunk's body, in bytes.
+    pub len: usize,
+    pub depth: usize,
+}
+
+/// A single problem found while verifying a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VerifyProblem {
+    /// The file's chunk structure can't be read at the given byte offset.
+    Structure { offset: usize, error: ParseError },
+
+    /// The file has no checksum. This is allowed, but it means corruption may go undetected.
+    MissingChecksum,
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    /// The file could not be read at all. Nothing can be recovered.
+    Unreadable(ParseError),
+
+    /// The patches could not be fully decoded. Operations from `ops_kept` onwards (in file order)
+    /// are missing.
+    Decode { chunk: String, error: ParseError, ops_kept: usize },
+
+    /// A causal graph entry is invalid. The range names the local versions of the entry.
+    CausalGraph { range: DTRange, reason: &'static str },
+
+    /// An agent's sequence numbers go backwards relative to the causal order of its operations.
+    AgentSeq { agent: String, seq_range: DTRange, reason: &'static str },
+
+    /// Replaying the operations failed. Only the first `ops_ok` operations could be replayed.
+    ReplayFailed { ops_ok: usize },
+
+    /// The document content after replaying all operations doesn't match the end content stored in
+    /// the file. Lengths are in unicode characters.
+    ContentMismatch { expected_len: usize, actual_len: usize },
+}
+
+impl Display for VerifyProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyProblem::Structure { offset, error } =>
+                write!(f, "Invalid chunk structure at byte {offset}: {error}"),
+            VerifyProblem::MissingChecksum =>
+                write!(f, "File has no checksum"),
+            VerifyProblem::ChecksumMismatch { expected, actual } =>
+                write!(f, "Checksum mismatch (expected {expected:#010x}, actual {actual:#010x})"),
+            VerifyProblem::Unreadable(error) =>
+                write!(f, "Could not read file: {error}"),
+            VerifyProblem::Decode { chunk, error, ops_kept } =>
+                write!(f, "Damaged {chunk} chunk ({error}). Only the first {ops_kept} operations are readable"),
+            VerifyProblem::CausalGraph { range, reason } =>
+                write!(f, "Invalid causal graph entry at versions {}..{}: {reason}", range.start, range.end),
+            VerifyProblem::AgentSeq { agent, seq_range, reason } =>
+                write!(f, "Agent '{agent}' seq {}..{}: {reason}", seq_range.start, seq_range.end),
+            VerifyProblem::ReplayFailed { ops_ok } =>
+                write!(f, "Replay failed. Only the first {ops_ok} operations can be replayed"),
+            VerifyProblem::ContentMismatch { expected_len, actual_len } =>
+                write!(f, "Replayed content ({actual_len} chars) does not match stored end content ({expected_len} chars)"),
+        }
+    }
+}
+
+/// The result of [`ListOpLog::verify_encoded`].
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub file_len: usize,
+    pub chunks: Vec<ChunkInfo>,
+    /// The number of operations which could be decoded from the file.
+    pub ops_read: usize,
+    /// The length of the longest valid causal prefix of operations. If the file is intact, this is
+    /// the same as ops_read.
+    pub valid_ops: usize,
+    pub problems: Vec<VerifyProblem>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found. A missing checksum isn't considered a problem here.
+    pub fn is_ok(&self) -> bool {
+        self.problems.iter().all(|p| *p == VerifyProblem::MissingChecksum)
+    }
+}
+
+fn chunk_name(chunk_type: u32) -> String {
+    match ListChunkType::try_from(chunk_type) {
+        Ok(c) => format!("{:?}", c),
+        Err(_) => format!("Unknown({chunk_type})"),
+    }
+}
+
+/// Walk the chunk tree in data. Returns the offset of the CRC chunk (if any).
+fn read_chunks(data: &[u8], chunks: &mut Vec<ChunkInfo>) -> Result<Option<usize>, (usize, ParseError)> {
+    let mut reader = BufReader(data);
+    let offset = |r: &BufReader| data.len() - r.len();
+
+    reader.read_magic().map_err(|e| (0, e))?;
+    reader.next_usize().map_err(|e| (offset(&reader), e))?;
+
+    // base is the offset of reader's bytes in the file.
+    fn read_level(base: usize, mut reader: BufReader, depth: usize, chunks: &mut Vec<ChunkInfo>, crc_offset: &mut Option<usize>) -> Result<(), (usize, ParseError)> {
+        let total_len = reader.len();
+        while !reader.is_empty() {
+            let start = base + total_len - reader.len();
+            let err = |e| (start, e);
+            let chunk_type = reader.next_u32().map_err(err)?;
+            let len = reader.next_usize().map_err(err)?;
+            if len > reader.len() { return Err(err(ParseError::InvalidLength)); }
+            let body_offset = base + total_len - reader.len();
+            let body = BufReader(reader.next_n_bytes(len).map_err(err)?);
+
+            chunks.push(ChunkInfo { name: chunk_name(chunk_type), offset: start, len, depth });
+
+            match ListChunkType::try_from(chunk_type) {
+                Ok(ListChunkType::FileInfo | ListChunkType::StartBranch
+                   | ListChunkType::ExperimentalEndBranch | ListChunkType::Patches) => {
+                    read_level(body_offset, body, depth + 1, chunks, crc_offset)?;
+                }
+                Ok(ListChunkType::Crc) if depth == 0 => {
+                    *crc_offset = Some(start);
+                    // The CRC chunk must be the last thing in the file, apart from signatures.
+                    let mut rest = reader.clone().chunks();
+                    let trailing_ok = rest.read_chunk_if_eq(ListChunkType::Signatures).is_ok()
+                        && rest.is_empty();
+                    if !trailing_ok {
+                        return Err((base + total_len - reader.len(), ParseError::InvalidLength));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    let mut crc_offset = None;
+    read_level(offset(&reader), reader, 0, chunks, &mut crc_offset)?;
+    Ok(crc_offset)
+}
+
+/// List the chunks in an encoded oplog, along with their sizes. This is useful for figuring out
+/// where the bytes in a file are going.
+pub fn read_chunk_tree(data: &[u8]) -> Result<Vec<ChunkInfo>, ParseError> {
+    let mut chunks = vec![];
+    read_chunks(data, &mut chunks).map_err(|(_offset, e)| e)?;
+    Ok(chunks)
+}
+
+fn check_crc(data: &[u8], crc_offset: usize) -> Result<Option<VerifyProblem>, ParseError> {
+    let mut reader = BufReader(&data[crc_offset..]).chunks();
+    let mut crc_chunk = reader.expect_chunk(ListChunkType::Crc)?;
+    let expected = crc_chunk.next_u32_le()?;
+    let actual = calc_checksum(&data[..crc_offset]);
+    Ok(if expected != actual {
+        Some(VerifyProblem::ChecksumMismatch { expected, actual })
+    } else { None })
+}
+
+/// Read the experimental end branch content out of the file, if its been stored.
+fn read_end_content(data: &[u8]) -> Result<Option<String>, ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    reader.next_usize()?;
+    let mut reader = reader.chunks();
+
+    let _compressed_chunk_raw: Option<Vec<u8>>;
+    let mut compressed_chunk: Option<BufReader>;
+    #[cfg(not(feature = "lz4"))] {
+        _compressed_chunk_raw = None;
+        compressed_chunk = None;
+        if reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)?.is_some() {
+            return Err(ParseError::LZ4DecoderNeeded);
+        }
+    }
+    #[cfg(feature = "lz4")] {
+        _compressed_chunk_raw = if let Some(mut c) = reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)? {
+            let uncompressed_len = c.next_usize()?;
+            Some(lz4_flex::decompress(c.0, uncompressed_len)
+                .map_err(|_e| ParseError::LZ4DecompressionError)?)
+        } else { None };
+        compressed_chunk = _compressed_chunk_raw.as_ref().map(|b| BufReader(b));
+    }
+
+    reader.expect_chunk(ListChunkType::FileInfo)?;
+
+    // The start branch content (if any) comes first in the compressed data, so it needs to be read
+    // even though we don't use it.
+    let mut start_branch = reader.expect_chunk(ListChunkType::StartBranch)?.chunks();
+    start_branch.read_chunk_if_eq(ListChunkType::Version)?;
+    if !start_branch.is_empty() {
+        start_branch.expect_content_str(compressed_chunk.as_mut())?;
+    }
+
+    Ok(if let Some(end_branch) = reader.read_chunk_if_eq(ListChunkType::ExperimentalEndBranch)? {
+        let mut end_branch = end_branch.chunks();
+        end_branch.read_chunk_if_eq(ListChunkType::Version)?;
+        Some(end_branch.expect_content_str(compressed_chunk.as_mut())?.into())
+    } else { None })
+}
+
+/// Check the causal graph entries. Returns the first local version which isn't valid, if any.
+fn check_graph(oplog: &ListOpLog, problems: &mut Vec<VerifyProblem>) -> Option<LV> {
+    let graph = &oplog.cg.graph;
+    let mut expect_start = 0;
+    for e in graph.entries.iter() {
+        let reason = if e.span.start != expect_start {
+            Some("Entries are not contiguous")
+        } else if e.parents.iter().any(|p| *p >= e.span.start) {
+            Some("Parents must come before the entry")
+        } else if e.parents.0.windows(2).any(|w| w[0] >= w[1]) {
+            Some("Parents are not sorted")
+        } else if graph.find_dominators(e.parents.as_ref()) != e.parents {
+            Some("Parents are not a minimal frontier")
+        } else { None };
+
+        if let Some(reason) = reason {
+            problems.push(VerifyProblem::CausalGraph { range: e.span, reason });
+            return Some(e.span.start);
+        }
+        expect_start = e.span.end;
+    }
+    None
+}
+
+/// Check that each agent's sequence numbers are consistent with the causal graph. Returns the first
+/// invalid local version, if any.
+fn check_agents(oplog: &ListOpLog, problems: &mut Vec<VerifyProblem>) -> Option<LV> {
+    let mut first_bad = None;
+    for client in oplog.cg.agent_assignment.client_data.iter() {
+        for w in client.lv_for_seq.0.windows(2) {
+            let (a, b) = (&w[0], &w[1]);
+            let reason = if b.0 < a.0 + a.1.len() {
+                Some("Sequence numbers assigned twice")
+            } else if oplog.cg.graph.frontier_contains_version(&[a.1.last()], b.1.start) {
+                Some("Sequence numbers go backwards in causal order")
+            } else { None };
+
+            if let Some(reason) = reason {
+                problems.push(VerifyProblem::AgentSeq {
+                    agent: client.name.to_string(),
+                    seq_range: (b.0..b.0 + b.1.len()).into(),
+                    reason,
+                });
+                let bad_lv = b.1.start.max(a.1.start);
+                first_bad = Some(first_bad.map_or(bad_lv, |v: LV| v.min(bad_lv)));
+                break;
+            }
+        }
+    }
+    first_bad
+}
+
+/// Check that every operation in `range` has a position which is in bounds in the document at the
+/// operation's parent version. Operations are checked in local version order. Everything before
+/// `range.start` must already be valid, so when we look at an operation, all of its parents are
+/// known to be valid and the document at those parents can be safely checked out.
+/// Returns the first local version which can't be replayed, if any.
+pub(crate) fn check_op_positions(oplog: &ListOpLog, range: DTRange) -> Option<LV> {
+    let mut branch = ListBranch::new();
+
+    let entries = oplog.cg.graph.entries.iter()
+        .skip_while(|e| e.span.end <= range.start)
+        .take_while(|e| e.span.start < range.end);
+    for entry in entries {
+        let parents = entry.parents.as_ref();
+        if branch.local_frontier_ref() != parents {
+            if oplog.cg.graph.frontier_contains_frontier(parents, branch.local_frontier_ref()) {
+                branch.merge(oplog, parents);
+            } else {
+                branch = ListBranch::new_at_local_version(oplog, parents);
+            }
+        }
+
+        // Within an entry each operation's parent is the previous operation, so we can track the
+        // length of the document directly.
+        let mut doc_len = branch.len();
+        let span: DTRange = (entry.span.start..entry.span.end.min(range.end)).into();
+        for (KVPair(lv, op), _) in oplog.iter_range_simple(span) {
+            match op.kind {
+                ListOpKind::Ins if op.loc.span.start <= doc_len => doc_len += op.len(),
+                ListOpKind::Del if op.loc.span.end <= doc_len => doc_len -= op.len(),
+                _ => return Some(lv),
+            }
+        }
+    }
+    None
+}
+
+impl ListOpLog {
+    /// Find the first operation whose position is out of bounds in the document at its parent
+    /// version. Checking out or merging a version containing such an operation panics. This is
+    /// useful for checking operations built from untrusted input before using them.
+    ///
+    /// Returns None if every operation can be replayed.
+    pub fn find_out_of_bounds_op(&self) -> Option<LV> {
+        check_op_positions(self, (0..self.len()).into())
+    }
+
+    /// Check the integrity of an encoded oplog, and salvage as much of it as possible.
+    ///
+    /// Along with the report, this returns an oplog containing the longest valid causal prefix of
+    /// operations in the file. If the file is intact, this is simply the loaded oplog. This is None
+    /// if the file header is unreadable.
+    ///
+    /// Operations are only replayed once their positions have been checked against the document at
+    /// their parent version, so damaged operations are reported rather than panicking.
+    pub fn verify_encoded(data: &[u8]) -> (VerifyReport, Option<ListOpLog>) {
+        let mut report = VerifyReport {
+            file_len: data.len(),
+            ..Default::default()
+        };
+
+        // *** Chunk structure & checksum ***
+        match read_chunks(data, &mut report.chunks) {
+            Ok(Some(crc_offset)) => {
+                match check_crc(data, crc_offset) {
+                    Ok(Some(p)) => report.problems.push(p),
+                    Ok(None) => {},
+                    Err(error) => report.problems.push(VerifyProblem::Structure { offset: crc_offset, error }),
+                }
+            }
+            Ok(None) => report.problems.push(VerifyProblem::MissingChecksum),
+            Err((offset, error)) => report.problems.push(VerifyProblem::Structure { offset, error }),
+        }
+
+        // *** Decoding ***
+        let mut oplog = match ListOpLog::load_salvage(data) {
+            Ok((oplog, stop)) => {
+                if let Some(stop) = stop {
+                    report.problems.push(VerifyProblem::Decode {
+                        chunk: stop.chunk,
+                        error: stop.error,
+                        ops_kept: stop.ops_kept,
+                    });
+                }
+                oplog
+            }
+            Err(e) => {
+                report.problems.push(VerifyProblem::Unreadable(e));
+                return (report, None);
+            }
+        };
+        report.ops_read = oplog.len();
+
+        // *** Causal graph & agent assignment ***
+        let mut valid = oplog.len();
+        if let Some(v) = check_graph(&oplog, &mut report.problems) { valid = valid.min(v); }
+        if let Some(v) = check_agents(&oplog, &mut report.problems) { valid = valid.min(v); }
+
+        // *** Replay ***
+        if let Some(v) = check_op_positions(&oplog, (0..valid).into()) {
+            report.problems.push(VerifyProblem::ReplayFailed { ops_ok: v });
+            valid = v;
+        }
+
+        let end_content = read_end_content(data).ok().flatten();
+        if let Some(expected) = end_content {
+            if valid == report.ops_read {
+                let content = oplog.checkout_tip().content().to_string();
+                if content != expected {
+                    report.problems.push(VerifyProblem::ContentMismatch {
+                        expected_len: expected.chars().count(),
+                        actual_len: content.chars().count(),
+                    });
+                }
+            }
+        }
+
+        if valid < oplog.len() {
+            oplog.truncate_to_causal_prefix(valid);
+        }
+        report.valid_ops = valid;
+
+        (report, Some(oplog))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::{ListBranch, ListOpLog};
+    use crate::list::encoding::verify::VerifyProblem;
+    use crate::list::old_fuzzer_tools::old_make_random_change_raw;
+
+    /// Make an oplog with concurrent changes from a few agents.
+    fn gen_oplog(seed: u64, steps: usize) -> ListOpLog {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut oplog = ListOpLog::new();
+        let mut branches = [ListBranch::new(), ListBranch::new(), ListBranch::new()];
+        for name in ["a", "b", "c"] {
+            oplog.get_or_create_agent_id(name);
+        }
+
+        for _i in 0..steps {
+            let idx = rng.gen_range(0..branches.len());
+            let v = old_make_random_change_raw(&mut oplog, &branches[idx], None, idx as _, &mut rng, true);
+            branches[idx].merge(&oplog, &[v]);
+            if rng.gen_bool(0.2) {
+                branches[idx].merge(&oplog, oplog.local_frontier_ref());
+            }
+        }
+        oplog
+    }
+
+    #[test]
+    fn verify_intact_file() {
+        let oplog = gen_oplog(123, 50);
+        let bytes = oplog.encode(&EncodeOptions::full().experimentally_store_end_branch_content(true));
+
+        let (report, salvaged) = ListOpLog::verify_encoded(&bytes);
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert_eq!(report.valid_ops, oplog.len());
+        let salvaged = salvaged.unwrap();
+        assert_eq!(salvaged.len(), oplog.len());
+        assert_eq!(salvaged.checkout_tip().content(), oplog.checkout_tip().content());
+    }
+
+    #[test]
+    fn verify_corrupt_files() {
+        let oplog = gen_oplog(321, 20);
+        let bytes = oplog.encode(&EncodeOptions::full().store_deleted_content(true));
+
+        for i in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] = !corrupted[i];
+
+            let (report, salvaged) = ListOpLog::verify_encoded(&corrupted);
+            // Every single byte is covered by the checksum or the chunk structure.
+            assert!(!report.is_ok(), "corruption at byte {i} was not detected");
+
+            if let Some(salvaged) = salvaged {
+                assert_eq!(salvaged.len(), report.valid_ops);
+                salvaged.dbg_check(true);
+                // The salvaged data must re-encode and load cleanly.
+                let bytes_2 = salvaged.encode(&EncodeOptions::full());
+                let loaded = ListOpLog::load_from(&bytes_2).unwrap();
+                assert_eq!(loaded.len(), salvaged.len());
+                assert_eq!(loaded.checkout_tip().content(), salvaged.checkout_tip().content());
+            }
+        }
+    }
+
+    #[test]
+    fn salvage_truncated_file() {
+        let oplog = gen_oplog(99, 50);
+        let bytes = oplog.encode(&EncodeOptions::full());
+
+        let (report, salvaged) = ListOpLog::verify_encoded(&bytes[..bytes.len() - 20]);
+        assert!(report.problems.iter().any(|p| matches!(p, VerifyProblem::Structure { .. })));
+        let salvaged = salvaged.unwrap();
+        salvaged.dbg_check(true);
+        assert!(!salvaged.is_empty() && salvaged.len() < oplog.len());
+        assert_eq!(report.valid_ops, salvaged.len());
+
+        // The salvaged operations are a prefix of the original document's operations.
+        assert_eq!(salvaged.checkout_tip().content(), oplog.checkout(salvaged.local_frontier_ref()).content());
+    }
+
+    #[test]
+    fn salvage_out_of_bounds_ops() {
+        // The oplog doesn't check positions, so its possible to write a file with a valid checksum
+        // containing operations which can't be replayed.
+        let mut oplog = ListOpLog::new();
+        let a = oplog.get_or_create_agent_id("a");
+        let b = oplog.get_or_create_agent_id("b");
+        oplog.add_insert(a, 0, "hi there");
+        let v = oplog.add_insert_at(b, &[3], 2, "xx");
+        oplog.add_insert_at(a, &[7], 8, "!");
+        oplog.add_delete_at(b, &[v], 100..105);
+        oplog.add_insert(a, 0, "yo ");
+        let bytes = oplog.encode(&EncodeOptions::full());
+
+        let (report, salvaged) = ListOpLog::verify_encoded(&bytes);
+        assert_eq!(report.problems, vec![VerifyProblem::ReplayFailed { ops_ok: 11 }]);
+        assert_eq!(report.valid_ops, 11);
+        let salvaged = salvaged.unwrap();
+        salvaged.dbg_check(true);
+        assert_eq!(salvaged.checkout_tip().content(), "hixx there!");
+    }
+}
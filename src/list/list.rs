@@ -8,6 +8,7 @@ use crate::list::operation::{ListOpKind, TextOperation};
 use crate::dtrange::DTRange;
 use crate::encoding::parseerror::ParseError;
 use crate::unicount::count_chars;
+use crate::list::pos_unit::PosUnit;
 
 // For local changes to a branch, we take the checkout's frontier as the new parents list.
 fn insert_history_local(oplog: &mut ListOpLog, frontier: &mut Frontier, range: DTRange) {
@@ -203,6 +204,21 @@ impl ListCRDT {
         self.branch.delete_at_wchar(&mut self.oplog, agent, wchar_range)
     }
 
+    /// Returns the document's length, measured in `unit`.
+    pub fn len_in(&self, unit: PosUnit) -> usize {
+        self.branch.len_in(unit)
+    }
+
+    /// Insert content at a position measured in `unit`.
+    pub fn insert_in(&mut self, agent: AgentId, pos: usize, ins_content: &str, unit: PosUnit) -> LV {
+        self.branch.insert_in(&mut self.oplog, agent, pos, ins_content, unit)
+    }
+
+    /// Delete a range measured in `unit`.
+    pub fn delete_in(&mut self, agent: AgentId, range: Range<usize>, unit: PosUnit) -> LV {
+        self.branch.delete_in(&mut self.oplog, agent, range, unit)
+    }
+
     pub fn print_stats(&self, detailed: bool) {
         println!("Document of length {}", self.branch.len());
 
@@ -5,16 +5,51 @@ use crate::frontier::FrontierRef;
 use crate::list::{ListBranch, ListOpLog};
 use crate::list::op_metrics::ListOpMetrics;
 use crate::list::operation::{ListOpKind, TextOperation};
+use crate::list::pos_unit::{apply_xf_op_in, PosUnit};
 use crate::listmerge::merge::{reverse_str, TransformedOpsIterRaw, TransformedResultRaw, TransformedSimpleOp, TransformedSimpleOpsIter};
 use crate::listmerge::merge::TransformedResult::{BaseMoved, DeleteAlreadyHappened};
+use crate::listmerge::merge_context::MergeContext;
 use crate::listmerge::plan::M1PlanAction;
 use crate::rle::KVPair;
 
+/// The algorithm used to merge concurrent changes into a [`ListBranch`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum MergeAlgorithm {
+    /// Replay the changes through a single CRDT tracker, retreating and advancing it as we move
+    /// through the causal graph. This is the default.
+    #[default]
+    Tracker,
+
+    /// Fork a separate index for each concurrent branch in the causal graph, and merge the indexes
+    /// back together afterwards. This avoids retreating and advancing through long runs of
+    /// concurrent changes. (See `src/listmerge2`).
+    #[cfg(feature = "listmerge2")]
+    IndexForking,
+
+    /// The same as `Tracker`, but independent regions of concurrent changes (separated by runs of
+    /// linear history) are transformed in parallel using rayon. This is faster for long histories
+    /// with lots of separate concurrent regions.
+    #[cfg(feature = "parallel")]
+    Parallel,
+}
+
 impl ListOpLog {
     pub fn dbg_bench_make_plan(&self) {
         self.cg.graph.make_m1_plan(Some(&self.operations), &[], self.cg.version.as_ref(), false);
     }
 
+    fn simple_op_to_text(&self, op: TransformedSimpleOp) -> (DTRange, Option<TextOperation>) {
+        match op {
+            TransformedSimpleOp::Apply(KVPair(start, op)) => {
+                let content = op.get_content(&self.operation_ctx);
+                let len = op.len();
+                let text_op: TextOperation = (op, content).into();
+                ((start..start + len).into(), Some(text_op))
+            }
+            TransformedSimpleOp::DeleteAlreadyHappened(range) => (range, None)
+        }
+    }
+
     pub(crate) fn get_xf_operations_full(&self, from: FrontierRef, merging: FrontierRef) -> TransformedOpsIterRaw<'_> {
         TransformedOpsIterRaw::new(&self.cg.graph, &self.cg.agent_assignment,
                                 &self.operation_ctx, &self.operations,
@@ -31,16 +66,27 @@ impl ListOpLog {
     pub fn iter_xf_operations_from(&self, from: FrontierRef, merging: FrontierRef) -> impl Iterator<Item=(DTRange, Option<TextOperation>)> + '_ {
         let iter: TransformedSimpleOpsIter = self.get_xf_operations_full(from, merging).into();
 
-        iter.map(|result| {
-            match result {
-                TransformedSimpleOp::Apply(KVPair(start, op)) => {
-                    let content = op.get_content(&self.operation_ctx);
-                    let len = op.len();
-                    let text_op: TextOperation = (op, content).into();
-                    ((start..start + len).into(), Some(text_op))
-                }
-                TransformedSimpleOp::DeleteAlreadyHappened(range) => (range, None)
-            }
+        iter.map(|result| self.simple_op_to_text(result))
+    }
+
+    /// Like [`iter_xf_operations_from`](Self::iter_xf_operations_from), but the positions in
+    /// the returned operations are measured in `unit`. Each operation's span is its range in the
+    /// document as it is when the operation is applied, in order.
+    ///
+    /// For units other than [`PosUnit::Chars`] this checks out the document at `from` and replays
+    /// the operations on top of it to convert positions.
+    pub fn iter_xf_operations_from_in(&self, from: FrontierRef, merging: FrontierRef, unit: PosUnit) -> impl Iterator<Item=(DTRange, Option<TextOperation>)> + '_ {
+        let mut content = match unit {
+            PosUnit::Chars => None,
+            _ => Some(self.checkout(from).into_inner()),
+        };
+
+        self.iter_xf_operations_from(from, merging).map(move |(range, op)| {
+            let op = match (op, content.as_mut()) {
+                (Some(op), Some(content)) => Some(apply_xf_op_in(content, unit, op)),
+                (op, _) => op,
+            };
+            (range, op)
         })
     }
 
@@ -54,6 +100,24 @@ impl ListOpLog {
         self.iter_xf_operations_from(&[], self.cg.version.as_ref())
     }
 
+    /// Like [`iter_xf_operations_from`](Self::iter_xf_operations_from), but independent regions
+    /// of concurrent changes are transformed in parallel. All the operations are transformed
+    /// before this method returns.
+    #[cfg(feature = "parallel")]
+    pub fn par_iter_xf_operations_from(&self, from: FrontierRef, merging: FrontierRef) -> impl Iterator<Item=(DTRange, Option<TextOperation>)> + '_ {
+        let xf_ops = crate::listmerge::parallel::par_xf_operations(&self.cg.graph, &self.cg.agent_assignment,
+            &self.operation_ctx, &self.operations, from, merging);
+        let iter = TransformedSimpleOpsIter::new(xf_ops.into_iter(), &self.operations, &self.operation_ctx);
+        iter.map(|result| self.simple_op_to_text(result))
+    }
+
+    /// Get all transformed operations from the start of time, transforming independent regions of
+    /// concurrent changes in parallel. See [`par_iter_xf_operations_from`](Self::par_iter_xf_operations_from).
+    #[cfg(feature = "parallel")]
+    pub fn par_iter_xf_operations(&self) -> impl Iterator<Item=(DTRange, Option<TextOperation>)> + '_ {
+        self.par_iter_xf_operations_from(&[], self.cg.version.as_ref())
+    }
+
     #[cfg(feature = "merge_conflict_checks")]
     pub fn has_conflicts_when_merging(&self) -> bool {
         let mut iter = TransformedOpsIterRaw::new(&self.cg.graph, &self.cg.agent_assignment,
@@ -73,17 +137,7 @@ impl ListOpLog {
                                                                               plan)
             .into();
 
-        iter.map(|result| {
-            match result {
-                TransformedSimpleOp::Apply(KVPair(start, op)) => {
-                    let content = op.get_content(&self.operation_ctx);
-                    let len = op.len();
-                    let text_op: TextOperation = (op, content).into();
-                    ((start..start + len).into(), Some(text_op))
-                }
-                TransformedSimpleOp::DeleteAlreadyHappened(range) => (range, None)
-            }
-        })
+        iter.map(|result| self.simple_op_to_text(result))
 
         // // allow_ff: false!
         // let (plan, common) = self.cg.graph.make_m1_plan(Some(&self.operations), &[], self.cg.version.as_ref(), false);
@@ -149,33 +203,99 @@ impl ListBranch {
         }
     }
 
-    pub fn merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
-        // let mut iter = oplog.get_xf_operations_full_raw(self.version.as_ref(), merge_frontier).merge_spans();
-        let iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
-        // println!("merge '{}' at {:?} + {:?}", self.content.to_string(), self.version, merge_frontier);
+    fn apply_xf(&mut self, oplog: &ListOpLog, xf: TransformedResultRaw) {
+        // dbg!(&xf);
+        match xf {
+            TransformedResultRaw::Apply { xf_pos, op: KVPair(_, mut op) } => {
+                // dbg!(&op);
+                op.transpose_to(xf_pos);
+                self.apply_op_at(oplog, op);
+            }
 
-        for xf in iter {
-            // dbg!(&xf);
-            // dbg!(_lv, &origin_op, &xf);
-            match xf {
-                TransformedResultRaw::Apply { xf_pos, op: KVPair(_, mut op) } => {
+            TransformedResultRaw::FF(range) => {
+                // Activate *SUPER FAST MODE*.
+                for KVPair(_, op) in oplog.operations.iter_range_ctx(range, &oplog.operation_ctx) {
                     // dbg!(&op);
-                    op.transpose_to(xf_pos);
                     self.apply_op_at(oplog, op);
                 }
+            }
+
+            TransformedResultRaw::DeleteAlreadyHappened(_) => {} // Discard.
+        }
+    }
+
+    /// Keep the merge state around between calls to [`merge`](Self::merge). Subsequent merges only
+    /// need to process the operations they haven't seen before, which makes repeatedly merging
+    /// small changes into a branch much faster.
+    ///
+    /// The tradeoff is memory. The cached state grows with every operation merged, and it's only
+    /// freed when the context is disabled or the branch is dropped. If the branch moves somewhere
+    /// the cached state can't follow, it is rebuilt at the next merge.
+    ///
+    /// The cached state is only valid for the oplog it was built from. It is rebuilt if the branch
+    /// is merged from a different oplog (including a clone of the original), or if operations have
+    /// been removed from the oplog since the last merge.
+    pub fn enable_merge_context(&mut self) {
+        if self.merge_ctx.is_none() {
+            self.merge_ctx = Some(Box::new(MergeContext::new()));
+        }
+    }
+
+    /// Discard the cached merge state (if any). See
+    /// [`enable_merge_context`](Self::enable_merge_context).
+    pub fn disable_merge_context(&mut self) {
+        self.merge_ctx = None;
+    }
+
+    /// Returns true if the branch keeps its merge state between merges.
+    pub fn has_merge_context(&self) -> bool {
+        self.merge_ctx.is_some()
+    }
+
+    pub fn merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
+        self.merge_with(oplog, merge_frontier, MergeAlgorithm::default());
+    }
+
+    /// Merge the named version into the branch using a specific merge algorithm. All algorithms
+    /// produce the same document.
+    pub fn merge_with(&mut self, oplog: &ListOpLog, merge_frontier: &[LV], algorithm: MergeAlgorithm) {
+        // println!("merge '{}' at {:?} + {:?}", self.content.to_string(), self.version, merge_frontier);
+        match algorithm {
+            MergeAlgorithm::Tracker if self.merge_ctx.is_some() => {
+                let mut ctx = self.merge_ctx.take().unwrap();
+                let from = self.version.clone();
+                ctx.merge(oplog, from.as_ref(), merge_frontier, |xf| self.apply_xf(oplog, xf));
+                self.merge_ctx = Some(ctx);
+            }
+
+            MergeAlgorithm::Tracker => {
+                // let mut iter = oplog.get_xf_operations_full_raw(self.version.as_ref(), merge_frontier).merge_spans();
+                let iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+                for xf in iter {
+                    self.apply_xf(oplog, xf);
+                }
+            }
 
-                TransformedResultRaw::FF(range) => {
-                    // Activate *SUPER FAST MODE*.
-                    for KVPair(_, op) in oplog.operations.iter_range_ctx(range, &oplog.operation_ctx) {
-                        // dbg!(&op);
-                        self.apply_op_at(oplog, op);
-                    }
+            #[cfg(feature = "parallel")]
+            MergeAlgorithm::Parallel => {
+                let xf_ops = crate::listmerge::parallel::par_xf_operations(&oplog.cg.graph, &oplog.cg.agent_assignment,
+                    &oplog.operation_ctx, &oplog.operations,
+                    self.version.as_ref(), merge_frontier);
+                for xf in xf_ops {
+                    self.apply_xf(oplog, xf);
                 }
+            }
 
-                TransformedResultRaw::DeleteAlreadyHappened(_) => {} // Discard.
+            #[cfg(feature = "listmerge2")]
+            MergeAlgorithm::IndexForking => {
+                let from = self.version.clone();
+                crate::listmerge2::xf_operations_between(&oplog.cg.graph, &oplog.cg.agent_assignment,
+                    &oplog.operation_ctx, &oplog.operations,
+                    from.as_ref(), merge_frontier,
+                    |xf| self.apply_xf(oplog, xf));
             }
         }
-        
+
         self.version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
     }
 }
\ No newline at end of file
@@ -5,11 +5,14 @@
 //! Currently this code only supports lists of unicode characters (text documents). Support for
 //! more data types will be added over time.
 
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use smartstring::alias::String as SmartString;
 
 use crate::list::operation::ListOpKind;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
-use crate::{CausalGraph, Frontier};
+use crate::{AgentId, CausalGraph, Frontier};
+use crate::listmerge::merge_context::MergeContext;
 use crate::rle::{KVPair, RleVec};
 
 pub mod operation;
@@ -22,6 +25,7 @@ pub mod oplog;
 mod branch;
 pub mod encoding;
 pub mod op_metrics;
+pub mod pos_unit;
 mod eq;
 mod oplog_merge;
 
@@ -33,6 +37,9 @@ mod oplog_merge_fuzzer;
 pub(crate) mod buffered_iter;
 mod stochastic_summary;
 mod merge;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub use merge::MergeAlgorithm;
 
 #[cfg(feature = "gen_test_data")]
 mod gen_random;
@@ -67,7 +74,7 @@ pub use gen_random::gen_oplog;
 /// Branches also provide a simple way to edit documents, via the [`insert`](Branch::insert) and
 /// [`delete`](Branch::delete) methods. These methods append new operations to the oplog, and modify
 /// the branch to contain the named changes.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct ListBranch {
     /// The version the branch is currently at. This is used to track which changes the branch has
     /// or has not locally merged.
@@ -78,6 +85,10 @@ pub struct ListBranch {
 
     /// The document's content.
     content: jumprope::JumpRopeBuf,
+
+    /// Optional cached merge state, reused between merges. See
+    /// [`enable_merge_context`](ListBranch::enable_merge_context).
+    merge_ctx: Option<Box<MergeContext>>,
 }
 
 /// An OpLog is a collection of Diamond Types operations, stored in a super fancy compact way. Each
@@ -121,6 +132,13 @@ pub struct ListOpLog {
     // TODO: Replace me with a compact form of this data.
     pub(crate) operations: RleVec<KVPair<ListOpMetrics>>,
 
+    /// Signatures over spans of operations, keyed by (agent, first seq).
+    #[cfg(feature = "signing")]
+    pub(crate) signatures: BTreeMap<(AgentId, usize), signing::SignedSpan>,
+
+    /// Identifies this oplog to state cached from it, like a branch's merge context.
+    pub(crate) id: OpLogId,
+
     // /// This is the LocalVersion for the entire oplog. So, if you merged every change we store into
     // /// a branch, this is the version of that branch.
     // ///
@@ -130,6 +148,33 @@ pub struct ListOpLog {
     // version: Frontier,
 }
 
+/// A process-wide unique ID for an oplog. Local versions only mean something in the oplog they came
+/// from, so anything which caches local versions between calls checks this to make sure it's still
+/// looking at the same oplog.
+///
+/// Every new oplog gets a fresh ID, and so does every clone (since the clones can diverge). The ID
+/// also changes whenever operations are removed from an oplog, because local versions can then be
+/// reassigned to different operations.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct OpLogId(u64);
+
+impl OpLogId {
+    pub(crate) fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Clone for OpLogId {
+    fn clone(&self) -> Self {
+        Self::next()
+    }
+}
+
 /// This is a simple helper structure which wraps an [`OpLog`](OpLog) and [`Branch`](Branch)
 /// together into a single structure to make edits easy.
 ///
@@ -1,7 +1,7 @@
 use std::ops::Range;
 use rle::{HasLength, SplitableSpan};
 use crate::{AgentId, Frontier, LV};
-use crate::list::{ListBranch, ListOpLog};
+use crate::list::{ListBranch, ListOpLog, OpLogId};
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::{TextOperation, ListOpKind};
@@ -11,6 +11,11 @@ use crate::causalgraph::agent_span::*;
 use crate::rev_range::RangeRev;
 use crate::rle::KVPair;
 use crate::unicount::{chars_to_bytes, count_chars};
+use crate::list::pos_unit::PosUnit;
+use crate::encoding::parseerror::ParseError;
+use crate::validate::OpValidator;
+#[cfg(feature = "parallel")]
+use crate::list::MergeAlgorithm;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -29,6 +34,9 @@ impl ListOpLog {
             cg: Default::default(),
             operation_ctx: ListOperationCtx::new(),
             operations: Default::default(),
+            #[cfg(feature = "signing")]
+            signatures: Default::default(),
+            id: OpLogId::next(),
             // inserted_content: "".to_string(),
         }
     }
@@ -45,6 +53,22 @@ impl ListOpLog {
         branch
     }
 
+    /// Like [`checkout`](Self::checkout), but independent regions of concurrent changes are
+    /// merged in parallel. See [`MergeAlgorithm::Parallel`].
+    #[cfg(feature = "parallel")]
+    pub fn par_checkout(&self, local_version: &[LV]) -> ListBranch {
+        let mut branch = ListBranch::new();
+        branch.merge_with(self, local_version, MergeAlgorithm::Parallel);
+        branch
+    }
+
+    /// Like [`checkout_tip`](Self::checkout_tip), but independent regions of concurrent changes
+    /// are merged in parallel. See [`MergeAlgorithm::Parallel`].
+    #[cfg(feature = "parallel")]
+    pub fn par_checkout_tip(&self) -> ListBranch {
+        self.par_checkout(self.cg.version.as_ref())
+    }
+
     pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
         self.cg.agent_assignment.get_or_create_agent_id(name)
     }
@@ -182,6 +206,62 @@ impl ListOpLog {
         }));
     }
 
+    /// Check that any of the named operations which are already in the oplog were stored with the
+    /// same parents and content.
+    pub(crate) fn check_known_ops(&self, agent: AgentId, parents: &[LV], start_seq: usize, ops: &[TextOperation]) -> Result<(), ParseError> {
+        let len: usize = ops.iter().map(|op| op.len()).sum();
+        self.cg.check_known_span(parents, AgentSpan {
+            agent,
+            seq_range: (start_seq..start_seq + len).into()
+        })?;
+
+        let Some(client) = self.cg.agent_assignment.client_data.get(agent as usize) else {
+            return Ok(());
+        };
+
+        let mut seq = start_seq;
+        for op in ops {
+            let mut next = Some(op.clone());
+            while let Some(mut op) = next.take() {
+                let (entry, offset) = client.lv_for_seq.find_sparse(seq);
+                let here_len = match entry {
+                    Err(empty) => empty.end - seq,
+                    Ok(KVPair(_, lv_range)) => lv_range.len() - offset,
+                }.min(op.len());
+
+                if here_len < op.len() { next = Some(op.truncate(here_len)); }
+                if let Ok(KVPair(_, lv_range)) = entry {
+                    if !self.known_op_matches(lv_range.start + offset, op) {
+                        return Err(ParseError::IdReused { agent, seq_range: (seq..seq + here_len).into() });
+                    }
+                }
+
+                seq += here_len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if `op` matches the operations stored from local version `lv` onwards.
+    /// Content is only compared when its known on both sides.
+    pub(crate) fn known_op_matches(&self, lv: LV, mut op: TextOperation) -> bool {
+        let range: DTRange = (lv..lv + op.len()).into();
+        for KVPair(_, stored) in self.operations.iter_range_ctx(range, &self.operation_ctx) {
+            let rest = if stored.len() < op.len() { Some(op.truncate(stored.len())) } else { None };
+
+            if stored.kind != op.kind || stored.loc != op.loc { return false; }
+            if let (Some(a), Some(b)) = (stored.get_content(&self.operation_ctx), op.content.as_ref()) {
+                if a != b.as_str() { return false; }
+            }
+
+            match rest {
+                Some(rest) => op = rest,
+                None => break,
+            }
+        }
+        true
+    }
+
     /// Push new operations to the opset. Operation parents specified by parents parameter.
     ///
     /// Returns the single item version after merging. (The resulting LocalVersion after calling
@@ -228,8 +308,9 @@ impl ListOpLog {
                 skip -= len;
             } else if skip > 0 { // and skip < len.
                 // Skip the first (skip) items from this operation.
+                // How the range splits depends on the operation's kind.
                 let mut loc = op.loc;
-                loc.truncate_keeping_right(skip);
+                let loc = loc.truncate_tagged_span(op.kind, skip);
 
                 let content = op.content.as_ref().map(|c| {
                     let s = c.as_str();
@@ -249,6 +330,31 @@ impl ListOpLog {
         new_lv_range
     }
 
+    /// Like [`add_operations_remote`](ListOpLog::add_operations_remote), but any operations which
+    /// are already known are compared with the stored operations. If they don't match, the oplog
+    /// is left unchanged and this returns [`ParseError::IdReused`].
+    pub fn add_operations_remote_checked(&mut self, agent: AgentId, parents: &[LV], start_seq: usize, ops: &[TextOperation]) -> Result<DTRange, ParseError> {
+        self.check_known_ops(agent, parents, start_seq, ops)?;
+        Ok(self.add_operations_remote(agent, parents, start_seq, ops))
+    }
+
+    /// Like [`add_operations_remote_checked`](ListOpLog::add_operations_remote_checked), but the
+    /// new operations are also passed to the validator before being kept. If the validator
+    /// rejects them, the oplog is left unchanged and this returns
+    /// [`ParseError::OperationRejected`].
+    pub fn add_operations_remote_with_validator(&mut self, agent: AgentId, parents: &[LV], start_seq: usize, ops: &[TextOperation], validator: &dyn OpValidator) -> Result<DTRange, ParseError> {
+        self.check_known_ops(agent, parents, start_seq, ops)?;
+
+        let checkpoint = self.checkpoint();
+        let new_lv_range = self.add_operations_remote(agent, parents, start_seq, ops);
+
+        if let Err(err) = self.validate_ops(new_lv_range, validator) {
+            self.restore_checkpoint(checkpoint);
+            return Err(err);
+        }
+        Ok(new_lv_range)
+    }
+
     /// Push new operations to the opset. Operation parents specified by parents parameter.
     ///
     /// Returns the single item version after merging. (The resulting LocalVersion after calling
@@ -299,6 +405,30 @@ impl ListOpLog {
         end_time - 1
     }
 
+    /// Like [`add_insert_at`](Self::add_insert_at), but `pos` is measured in `unit` in the
+    /// document at `parents`. Converting the position needs a checkout of the document at
+    /// `parents`, so this is much slower than editing a branch.
+    pub fn add_insert_at_in(&mut self, agent: AgentId, parents: &[LV], pos: usize, ins_content: &str, unit: PosUnit) -> LV {
+        let pos = match unit {
+            PosUnit::Chars => pos,
+            _ => self.checkout(parents).unit_to_chars(pos, unit),
+        };
+        self.add_insert_at(agent, parents, pos, ins_content)
+    }
+
+    /// Like [`add_delete_at`](Self::add_delete_at), but `loc` is measured in `unit` in the
+    /// document at `parents`.
+    pub fn add_delete_at_in(&mut self, agent: AgentId, parents: &[LV], loc: Range<usize>, unit: PosUnit) -> LV {
+        let loc = match unit {
+            PosUnit::Chars => loc,
+            _ => {
+                let branch = self.checkout(parents);
+                branch.unit_to_chars(loc.start, unit)..branch.unit_to_chars(loc.end, unit)
+            }
+        };
+        self.add_delete_at(agent, parents, loc)
+    }
+
     // *** Helpers for pushing at the current version ***
 
     /// Append local operations to the oplog. This method is used to make local changes to the
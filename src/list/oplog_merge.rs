@@ -159,6 +159,8 @@ impl ListOpLog {
 #[cfg(test)]
 mod test {
     use crate::list::ListOpLog;
+    use crate::list::operation::{ListOpKind, TextOperation};
+    use crate::rev_range::RangeRev;
 
     fn merge_into_and_check(dest: &mut ListOpLog, src: &ListOpLog) {
         // dbg!(&dest);
@@ -200,4 +202,27 @@ mod test {
 
         merge_both_and_check(&mut a, &mut b);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn add_partially_known_deletes() {
+        // Re-sending a run of deletes we already have part of should only apply the rest. Forward
+        // deletes (the delete key) stay at the same position, and reversed deletes (backspace) move
+        // backwards.
+        for (first, all) in [
+            (RangeRev { span: (3..4).into(), fwd: true }, RangeRev { span: (3..6).into(), fwd: true }),
+            (RangeRev { span: (5..6).into(), fwd: true }, RangeRev { span: (3..6).into(), fwd: false }),
+        ] {
+            let mut oplog = ListOpLog::new();
+            let seph = oplog.get_or_create_agent_id("seph");
+            let mike = oplog.get_or_create_agent_id("mike");
+            oplog.add_insert(seph, 0, "abcdef");
+
+            let del = |loc| TextOperation { loc, kind: ListOpKind::Del, content: None };
+            oplog.add_operations_remote(mike, &[5], 0, &[del(first)]);
+            assert_eq!(oplog.add_operations_remote(mike, &[5], 0, &[del(all)]), (7..9).into());
+
+            oplog.dbg_check(true);
+            assert_eq!(oplog.checkout_tip().content(), "abc");
+        }
+    }
+}
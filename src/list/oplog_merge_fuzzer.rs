@@ -15,6 +15,11 @@ fn oplog_merge_fuzz(seed: u64, n: usize, verbose: bool) {
         }
     }
 
+    // The first two documents keep their merge state around between merges. The third always
+    // merges from scratch.
+    docs[0].branch.enable_merge_context();
+    docs[1].branch.enable_merge_context();
+
     for _i in 0..n {
         if verbose { println!("\n\ni {}", _i); }
 
@@ -60,6 +65,17 @@ fn oplog_merge_fuzz(seed: u64, n: usize, verbose: bool) {
         // assert_eq!(a.branch.content.to_string(), b.branch.content.to_string());
         assert_eq!(a.branch.content, b.branch.content);
 
+        // Check the merge context against a fresh merge.
+        for doc in [&*a, &*b] {
+            if doc.branch.has_merge_context() {
+                let expected = doc.oplog.checkout(doc.branch.local_frontier_ref());
+                assert_eq!(doc.branch, expected);
+            }
+        }
+
+        #[cfg(feature = "parallel")]
+        assert_eq!(a.oplog.par_checkout(a.branch.local_frontier_ref()), a.branch);
+
 
         // let mut new_oplog = ListOpLog::new();
         // for (op, graph, agent_span) in a.oplog.iter_full() {
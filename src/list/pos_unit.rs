@@ -0,0 +1,364 @@
+//! Diamond types stores and transforms positions as unicode character (scalar value) offsets. But
+//! most editors don't. Javascript, Swift's NSString, Java and the language server protocol count
+//! UTF-16 code units, and rust strings are indexed by UTF-8 bytes.
+//!
+//! [`PosUnit`] names the unit positions are measured in. The `*_in` methods on
+//! [`ListBranch`](crate::list::ListBranch), [`ListCRDT`](crate::list::ListCRDT) and
+//! [`ListOpLog`](crate::list::ListOpLog) take and return positions in the requested unit, so
+//! editors can use them directly.
+//!
+//! Converting positions needs the document content at the version the position refers to. The
+//! branch methods use the branch's content. The oplog methods check out the document at the
+//! requested version, which is much slower than editing a branch.
+//!
+//! Positions in the untransformed operations stored in the oplog (from `iter_ops()`) are always in
+//! characters, since each refers to the document at a different version.
+
+use std::ops::Range;
+use jumprope::JumpRope;
+use rle::HasLength;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::unicount::{bytes_to_chars, chars_to_bytes, count_chars};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The unit positions and lengths are measured in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PosUnit {
+    /// Unicode scalar values (rust `char`s). This is what diamond types uses internally.
+    #[default]
+    Chars,
+    /// UTF-16 code units. Characters outside the basic multilingual plane count as 2.
+    Utf16,
+    /// UTF-8 bytes.
+    Utf8,
+}
+
+impl PosUnit {
+    /// The length of a string in this unit.
+    pub fn count(self, s: &str) -> usize {
+        match self {
+            PosUnit::Chars => count_chars(s),
+            PosUnit::Utf16 => str_indices::utf16::count(s),
+            PosUnit::Utf8 => s.len(),
+        }
+    }
+
+    /// Convert a character offset within a string to this unit.
+    pub fn from_chars(self, s: &str, char_pos: usize) -> usize {
+        match self {
+            PosUnit::Chars => char_pos,
+            PosUnit::Utf16 => str_indices::utf16::from_byte_idx(s, chars_to_bytes(s, char_pos)),
+            PosUnit::Utf8 => chars_to_bytes(s, char_pos),
+        }
+    }
+
+    /// Convert an offset in this unit within a string to a character offset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is past the end of the string, or in the middle of a character.
+    pub fn to_chars(self, s: &str, pos: usize) -> usize {
+        self.try_to_chars(s, pos)
+            .unwrap_or_else(|| panic!("Position {pos} is out of bounds or inside a character"))
+    }
+
+    /// Convert an offset in this unit within a string to a character offset. Returns `None` if the
+    /// position is past the end of the string, or in the middle of a character.
+    pub fn try_to_chars(self, s: &str, pos: usize) -> Option<usize> {
+        let byte_pos = match self {
+            PosUnit::Chars => {
+                return if pos <= count_chars(s) { Some(pos) } else { None };
+            }
+            PosUnit::Utf16 => {
+                let byte_pos = str_indices::utf16::to_byte_idx(s, pos);
+                // to_byte_idx rounds down positions inside surrogate pairs and clamps positions
+                // past the end of the string.
+                if str_indices::utf16::from_byte_idx(s, byte_pos) != pos { return None; }
+                byte_pos
+            }
+            PosUnit::Utf8 => pos,
+        };
+        if s.is_char_boundary(byte_pos) {
+            Some(bytes_to_chars(s, byte_pos))
+        } else { None }
+    }
+}
+
+/// Convert a character position in the rope to `unit`.
+pub fn rope_chars_to_unit(rope: &JumpRope, unit: PosUnit, char_pos: usize) -> usize {
+    match unit {
+        PosUnit::Chars => char_pos,
+        #[cfg(feature = "wchar_conversion")]
+        PosUnit::Utf16 => rope.chars_to_wchars(char_pos),
+        _ => {
+            assert!(char_pos <= rope.len_chars(), "Position {char_pos} is past the end of the document");
+            rope.slice_substrings(0..char_pos).map(|s| unit.count(s)).sum()
+        }
+    }
+}
+
+/// Convert a position in `unit` to a character position in the rope.
+///
+/// # Panics
+///
+/// Panics if the position is past the end of the document, or in the middle of a character.
+pub fn rope_unit_to_chars(rope: &JumpRope, unit: PosUnit, pos: usize) -> usize {
+    rope_try_unit_to_chars(rope, unit, pos)
+        .unwrap_or_else(|| panic!("Position {pos} is past the end of the document or inside a character"))
+}
+
+/// Convert a position in `unit` to a character position in the rope. Returns `None` if the
+/// position is past the end of the document, or in the middle of a character.
+pub fn rope_try_unit_to_chars(rope: &JumpRope, unit: PosUnit, pos: usize) -> Option<usize> {
+    match unit {
+        PosUnit::Chars => {
+            if pos <= rope.len_chars() { Some(pos) } else { None }
+        }
+        #[cfg(feature = "wchar_conversion")]
+        PosUnit::Utf16 => {
+            if pos > rope.len_wchars() { return None; }
+            let char_pos = rope.wchars_to_chars(pos);
+            if rope.chars_to_wchars(char_pos) == pos { Some(char_pos) } else { None }
+        }
+        _ => {
+            // Scan through the rope's chunks until we find the one containing the position.
+            let mut remaining = pos;
+            let mut chars = 0;
+            for s in rope.substrings() {
+                let len = unit.count(s);
+                if remaining <= len {
+                    return unit.try_to_chars(s, remaining).map(|c| chars + c);
+                }
+                remaining -= len;
+                chars += count_chars(s);
+            }
+            if remaining == 0 { Some(chars) } else { None }
+        }
+    }
+}
+
+pub(crate) fn rope_unit_range_to_chars(rope: &JumpRope, unit: PosUnit, range: Range<usize>) -> Range<usize> {
+    rope_unit_to_chars(rope, unit, range.start)..rope_unit_to_chars(rope, unit, range.end)
+}
+
+/// Apply a transformed operation (with character positions) to `rope`, and return a copy of the
+/// operation with its positions converted to `unit`.
+///
+/// Inserts with unknown content are applied as a run of `?` characters, and their length is
+/// reported as if each character took up one unit.
+pub(crate) fn apply_xf_op_in(rope: &mut JumpRope, unit: PosUnit, mut op: TextOperation) -> TextOperation {
+    let start = op.start();
+    let start_u = rope_chars_to_unit(rope, unit, start);
+
+    let end_u = match op.kind {
+        ListOpKind::Ins => {
+            let len_u = match op.content_as_str() {
+                Some(content) => {
+                    if op.loc.fwd {
+                        rope.insert(start, content);
+                    } else {
+                        // The content of reversed inserts is stored in the order it was typed.
+                        let content: String = content.chars().rev().collect();
+                        rope.insert(start, &content);
+                    }
+                    unit.count(content)
+                }
+                None => {
+                    rope.insert(start, &"?".repeat(op.len()));
+                    op.len()
+                }
+            };
+            start_u + len_u
+        }
+        ListOpKind::Del => {
+            let end_u = rope_chars_to_unit(rope, unit, op.end());
+            rope.remove(start..op.end());
+            end_u
+        }
+    };
+
+    op.loc.span = (start_u..end_u).into();
+    op
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRope;
+    use rand::prelude::*;
+    use crate::list::ListCRDT;
+    use crate::list::old_fuzzer_tools::old_make_random_change;
+    use crate::list_fuzzer_tools::choose_2;
+    use super::*;
+
+    const UNITS: [PosUnit; 3] = [PosUnit::Chars, PosUnit::Utf16, PosUnit::Utf8];
+
+    #[test]
+    fn str_conversions() {
+        let s = "a©😀b";
+        assert_eq!(PosUnit::Chars.count(s), 4);
+        assert_eq!(PosUnit::Utf16.count(s), 5);
+        assert_eq!(PosUnit::Utf8.count(s), 8);
+
+        assert_eq!(PosUnit::Utf16.from_chars(s, 3), 4);
+        assert_eq!(PosUnit::Utf8.from_chars(s, 3), 7);
+        assert_eq!(PosUnit::Utf8.to_chars(s, 3), 2);
+        assert_eq!(PosUnit::Utf16.to_chars(s, 5), 4);
+
+        for unit in UNITS {
+            for c in 0..=4 {
+                assert_eq!(unit.to_chars(s, unit.from_chars(s, c)), c);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_positions() {
+        let s = "a😀";
+        assert_eq!(PosUnit::Chars.try_to_chars(s, 3), None);
+        assert_eq!(PosUnit::Utf16.try_to_chars(s, 2), None);
+        assert_eq!(PosUnit::Utf16.try_to_chars(s, 4), None);
+        assert_eq!(PosUnit::Utf8.try_to_chars(s, 3), None);
+        assert_eq!(PosUnit::Utf8.try_to_chars(s, 6), None);
+
+        let rope = JumpRope::from(s);
+        for unit in UNITS {
+            assert_eq!(rope_try_unit_to_chars(&rope, unit, unit.count(s)), Some(2));
+            assert_eq!(rope_try_unit_to_chars(&rope, unit, unit.count(s) + 1), None);
+        }
+        assert_eq!(rope_try_unit_to_chars(&rope, PosUnit::Utf16, 2), None);
+        assert_eq!(rope_try_unit_to_chars(&rope, PosUnit::Utf8, 2), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn utf8_inside_char_panics() {
+        PosUnit::Utf8.to_chars("😀", 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn utf16_inside_surrogate_pair_panics() {
+        PosUnit::Utf16.to_chars("😀", 1);
+    }
+
+    #[test]
+    fn rope_conversions() {
+        // Long enough to be split across several nodes.
+        let s = "a©😀b\n".repeat(200);
+        let rope = JumpRope::from(s.as_str());
+
+        for unit in UNITS {
+            for c in (0..=rope.len_chars()).step_by(7) {
+                let pos = rope_chars_to_unit(&rope, unit, c);
+                assert_eq!(pos, unit.from_chars(&s, c));
+                assert_eq!(rope_unit_to_chars(&rope, unit, pos), c);
+            }
+            assert_eq!(rope_unit_to_chars(&rope, unit, unit.count(&s)), rope.len_chars());
+        }
+    }
+
+    /// Apply xf operations with positions in `unit` to a document stored as UTF-8, UTF-16 or
+    /// chars. Each representation is indexed natively, so this doesn't share any conversion code
+    /// with the code under test.
+    fn apply_ops(unit: PosUnit, start: &str, ops: impl Iterator<Item=TextOperation>) -> String {
+        let mut utf8 = start.to_string();
+        let mut utf16: Vec<u16> = start.encode_utf16().collect();
+        let mut chars: Vec<char> = start.chars().collect();
+
+        for op in ops {
+            let range = op.start()..op.end();
+            let content: String = match (op.kind, op.loc.fwd) {
+                (ListOpKind::Ins, true) => op.content_as_str().unwrap().into(),
+                (ListOpKind::Ins, false) => op.content_as_str().unwrap().chars().rev().collect(),
+                (ListOpKind::Del, _) => String::new(),
+            };
+            if op.kind == ListOpKind::Ins {
+                assert_eq!(range.len(), unit.count(&content));
+            }
+
+            match unit {
+                PosUnit::Utf8 => utf8.replace_range(range.start..range.start + if op.kind == ListOpKind::Del { range.len() } else { 0 }, &content),
+                PosUnit::Utf16 => {
+                    let del_end = if op.kind == ListOpKind::Del { range.end } else { range.start };
+                    utf16.splice(range.start..del_end, content.encode_utf16());
+                }
+                PosUnit::Chars => {
+                    let del_end = if op.kind == ListOpKind::Del { range.end } else { range.start };
+                    chars.splice(range.start..del_end, content.chars());
+                }
+            }
+        }
+
+        match unit {
+            PosUnit::Utf8 => utf8,
+            PosUnit::Utf16 => String::from_utf16(&utf16).unwrap(),
+            PosUnit::Chars => chars.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn branch_edits_in_units() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert_in(seph, 0, "a😀c", PosUnit::Utf8);
+        assert_eq!(doc.len_in(PosUnit::Chars), 3);
+        assert_eq!(doc.len_in(PosUnit::Utf16), 4);
+        assert_eq!(doc.len_in(PosUnit::Utf8), 6);
+
+        doc.insert_in(seph, 5, "b", PosUnit::Utf8);
+        doc.delete_in(seph, 1..3, PosUnit::Utf16);
+        assert_eq!(doc.branch.content.to_string(), "abc");
+        assert_eq!(doc.branch.chars_to_unit(2, PosUnit::Utf8), 2);
+
+        let v = doc.oplog.local_frontier();
+        doc.oplog.add_insert_at_in(seph, &[2], 1, "x", PosUnit::Utf8);
+        doc.oplog.add_delete_at_in(seph, v.as_ref(), 0..1, PosUnit::Utf16);
+        doc.branch.merge(&doc.oplog, doc.oplog.cg.version.as_ref());
+        assert_eq!(doc.branch.content.to_string(), "xbc");
+    }
+
+    #[test]
+    fn xf_operations_in_units() {
+        let mut rng = SmallRng::seed_from_u64(321);
+        let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+        for doc in docs.iter_mut() {
+            for a in 0..3 {
+                doc.get_or_create_agent_id(format!("agent {a}").as_str());
+            }
+        }
+
+        for _i in 0..50 {
+            for _j in 0..2 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, true);
+            }
+
+            let (_a_idx, a, _b_idx, b) = choose_2(&mut docs, &mut rng);
+            a.oplog.add_missing_operations_from(&b.oplog);
+            b.oplog.add_missing_operations_from(&a.oplog);
+            a.branch.merge(&a.oplog, a.oplog.cg.version.as_ref());
+            b.branch.merge(&b.oplog, b.oplog.cg.version.as_ref());
+        }
+
+        for doc in &docs {
+            let oplog = &doc.oplog;
+            let expect = doc.branch.content.to_string();
+
+            for unit in UNITS {
+                let ops = oplog.iter_xf_operations_from_in(&[], oplog.cg.version.as_ref(), unit)
+                    .filter_map(|(_, op)| op);
+                assert_eq!(apply_ops(unit, "", ops), expect);
+
+                // And from some version in the middle of the history.
+                let from = [oplog.len() / 2];
+                let start = oplog.checkout(&from).content.to_string();
+                let ops = oplog.iter_xf_operations_from_in(&from, oplog.cg.version.as_ref(), unit)
+                    .filter_map(|(_, op)| op);
+                assert_eq!(apply_ops(unit, &start, ops), expect);
+            }
+        }
+    }
+}
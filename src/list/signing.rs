@@ -0,0 +1,508 @@
+//! Optional ed25519 signatures for operations. Requires the `signing` feature.
+//!
+//! Agent names are just strings, so by default any peer can create operations claiming to be from
+//! any agent. With signing, each agent is bound to a public key in a [`KeyRing`]. Agents sign
+//! their own operations with [`ListOpLog::sign_ops`], and the signatures are stored in the oplog
+//! and sent along with the operations when the oplog is encoded.
+//!
+//! When data is merged with [`DecodeOptions::keys`](crate::list::encoding::DecodeOptions::keys)
+//! set, every new operation from an agent in the keyring must be covered by a valid signature from
+//! that agent. The merge fails (and the oplog is left unchanged) with:
+//!
+//! - [`ParseError::InvalidSignature`] if a signature doesn't match the agent's key, or doesn't
+//!   match the operations it names (ie, the operations were forged or modified in transit).
+//! - [`ParseError::MissingSignature`] if new operations from a keyed agent aren't signed.
+//! - [`ParseError::Equivocation`] if an agent has signed two different sets of operations with
+//!   the same (agent, seq) IDs. Merging both would corrupt the document.
+//!
+//! **Signatures merged without a keyring aren't verified.** They're stored (so they can be passed
+//! on to other peers) as long as they match the operations they name, but anyone could have made
+//! them. This includes signatures loaded from files with [`ListOpLog::load_from`] and read through
+//! a [`StreamDecoder`](crate::list::encoding::StreamDecoder). Use [`ListOpLog::is_signed`] with a
+//! keyring to check stored signatures.
+//!
+//! A signed span is only sent once all of its operations are being sent. If an agent signs a span
+//! which is later split across multiple encoded messages (eg by
+//! [`ListOpLog::encode_stream`]), the earlier messages will fail the keyring check.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use ed25519_dalek::{Signer, Verifier};
+use rle::{HasLength, SplitableSpanCtx};
+use sha2::{Digest, Sha256};
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, DTRange, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+pub use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+
+/// Mixed into every signed message, so signatures can't be reused in other protocols.
+const SIGNING_CONTEXT: &[u8] = b"diamond-types signed ops v1";
+
+/// A signature by an agent over a contiguous range of its operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedSpan {
+    pub agent: AgentId,
+    pub seq_range: DTRange,
+    /// SHA-256 hash of the operations in the span, including their parents.
+    pub hash: [u8; 32],
+    pub signature: Signature,
+}
+
+/// The public keys of known agents.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRing {
+    keys: BTreeMap<SmartString, VerifyingKey>,
+    require_signatures: bool,
+}
+
+impl KeyRing {
+    pub fn new() -> Self { Self::default() }
+
+    /// Bind the named agent to a public key. Operations from the agent will only be accepted if
+    /// they're signed by the matching signing key.
+    pub fn add_agent(&mut self, name: &str, key: VerifyingKey) {
+        self.keys.insert(name.into(), key);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VerifyingKey> {
+        self.keys.get(name)
+    }
+
+    /// If set, operations from agents which aren't in the keyring are rejected too. By default
+    /// they're accepted without any checks.
+    pub fn set_require_signatures(&mut self, require: bool) {
+        self.require_signatures = require;
+    }
+
+    fn needs_signature(&self, name: &str) -> bool {
+        self.require_signatures || self.keys.contains_key(name)
+    }
+}
+
+fn signed_message(agent_name: &str, seq_range: DTRange, hash: &[u8; 32]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(SIGNING_CONTEXT.len() + agent_name.len() + 56);
+    msg.extend_from_slice(SIGNING_CONTEXT);
+    msg.extend_from_slice(&(agent_name.len() as u64).to_le_bytes());
+    msg.extend_from_slice(agent_name.as_bytes());
+    msg.extend_from_slice(&(seq_range.start as u64).to_le_bytes());
+    msg.extend_from_slice(&(seq_range.end as u64).to_le_bytes());
+    msg.extend_from_slice(hash);
+    msg
+}
+
+impl ListOpLog {
+    /// Hash the operations with the named agent and seq range. Returns None if the oplog doesn't
+    /// contain all of the operations (or their inserted content).
+    ///
+    /// The hash only depends on the operations themselves (not local versions, or how operations
+    /// happen to be run-length encoded), so every peer computes the same hash for the same span.
+    pub(crate) fn hash_span(&self, agent: AgentId, seq_range: DTRange) -> Option<[u8; 32]> {
+        let aa = &self.cg.agent_assignment;
+        let client = aa.client_data.get(agent as usize)?;
+        let mut hasher = Sha256::new();
+
+        let mut seq = seq_range.start;
+        while seq < seq_range.end {
+            let lv_span = client.try_seq_to_lv_span((seq..seq_range.end).into())?;
+            seq += lv_span.len();
+
+            for KVPair(mut v, mut op) in self.operations.iter_range_ctx(lv_span, &self.operation_ctx) {
+                // Hash each item separately, so the hash doesn't depend on how runs were split.
+                loop {
+                    let rest = if op.len() > 1 { Some(op.truncate_ctx(1, &self.operation_ctx)) } else { None };
+
+                    let parents = self.cg.graph.parents_at_version(v);
+                    let mut parents = aa.local_to_remote_frontier(parents.as_ref()).into_iter()
+                        .collect::<Vec<_>>();
+                    parents.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+                    hasher.update((parents.len() as u64).to_le_bytes());
+                    for p in parents {
+                        hasher.update((p.0.len() as u64).to_le_bytes());
+                        hasher.update(p.0.as_bytes());
+                        hasher.update((p.1 as u64).to_le_bytes());
+                    }
+
+                    hasher.update((op.start() as u64).to_le_bytes());
+                    // Deleted content is optional in encoded files, so it isn't part of the hash.
+                    // The position is enough to identify what was deleted.
+                    if op.kind == ListOpKind::Ins {
+                        let c = op.get_content(&self.operation_ctx)?;
+                        hasher.update([0, c.len() as u8]);
+                        hasher.update(c.as_bytes());
+                    } else {
+                        hasher.update([1]);
+                    }
+
+                    v += 1;
+                    match rest {
+                        Some(rest) => op = rest,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Some(hasher.finalize().into())
+    }
+
+    /// Sign all of the agent's operations which haven't been signed yet. The signing key should
+    /// match the agent's key in peers' [`KeyRing`]s.
+    ///
+    /// Returns the seq range which was signed, or None if there was nothing to sign.
+    pub fn sign_ops(&mut self, agent: AgentId, key: &SigningKey) -> Option<DTRange> {
+        let start = self.signatures.range((agent, 0)..=(agent, usize::MAX))
+            .map(|(_, s)| s.seq_range.end)
+            .max()
+            .unwrap_or(0);
+        let end = self.cg.agent_assignment.client_data[agent as usize].get_next_seq();
+        if start >= end { return None; }
+
+        let seq_range: DTRange = (start..end).into();
+        let hash = self.hash_span(agent, seq_range)?;
+        let name = self.cg.agent_assignment.get_agent_name(agent);
+        let signature = key.sign(&signed_message(name, seq_range, &hash));
+
+        self.signatures.insert((agent, start), SignedSpan { agent, seq_range, hash, signature });
+        Some(seq_range)
+    }
+
+    /// Returns true if the named operations are covered by stored signatures which are valid
+    /// for the agent's key in `keys`. Returns false if the agent isn't in the keyring.
+    ///
+    /// Stored signatures may not have been verified when they were merged, so they're all checked
+    /// again here.
+    pub fn is_signed(&self, agent: AgentId, seq_range: DTRange, keys: &KeyRing) -> bool {
+        let name = self.get_agent_name(agent);
+        let Some(key) = keys.get(name) else { return false; };
+
+        let valid = self.signatures.range((agent, 0)..=(agent, usize::MAX))
+            .map(|(_, s)| s)
+            .filter(|s| {
+                key.verify(&signed_message(name, s.seq_range, &s.hash), &s.signature).is_ok()
+                    && self.hash_span(agent, s.seq_range) == Some(s.hash)
+            });
+        Coverage::new(valid).covers(agent, seq_range)
+    }
+
+    /// Find the stored signatures which should be sent along with the operations in `ranges`.
+    /// Spans are skipped unless all of their operations are contained in `to_version`. Each
+    /// signature is returned at most once.
+    pub(crate) fn signatures_for_ranges(&self, ranges: &[DTRange], to_version: &[LV]) -> Vec<&SignedSpan> {
+        let aa = &self.cg.agent_assignment;
+        let mut result: Vec<&SignedSpan> = vec![];
+        let mut seen: HashSet<(AgentId, usize)> = HashSet::new();
+
+        for KVPair(_, span) in ranges.iter().flat_map(|r| aa.client_with_lv.iter_range(*r)) {
+            let client = &aa.client_data[span.agent as usize];
+            let key = (span.agent, span.seq_range.start);
+
+            // An agent's signatures don't overlap, so at most one signature starting before the
+            // span can cover part of it.
+            let prev = self.signatures.range(..key).next_back()
+                .filter(|((agent, _), _)| *agent == span.agent);
+            let rest = self.signatures.range(key..(span.agent, span.seq_range.end));
+
+            for s in prev.into_iter().chain(rest).map(|(_, s)| s) {
+                if s.seq_range.end <= span.seq_range.start { continue; }
+                if !seen.insert((s.agent, s.seq_range.start)) { continue; }
+
+                let mut seq = s.seq_range.start;
+                let contained = loop {
+                    if seq >= s.seq_range.end { break true; }
+                    let Some(lv_span) = client.try_seq_to_lv_span((seq..s.seq_range.end).into()) else { break false; };
+                    if !self.cg.graph.frontier_contains_version(to_version, lv_span.last()) { break false; }
+                    seq += lv_span.len();
+                };
+                if contained { result.push(s); }
+            }
+        }
+
+        result
+    }
+
+    /// Drop any signatures which name operations we no longer have.
+    pub(crate) fn retain_signatures(&mut self) {
+        let client_data = &self.cg.agent_assignment.client_data;
+        self.signatures.retain(|_, s| {
+            client_data.get(s.agent as usize)
+                .is_some_and(|c| s.seq_range.end <= c.get_next_seq())
+        });
+    }
+
+    /// Check the signatures read from a file, after its operations have been merged. Operations
+    /// from `first_new` onwards were added by the merge. If this returns an error, the caller
+    /// needs to unwind the merge.
+    pub(crate) fn merge_signatures(&mut self, incoming: Vec<SignedSpan>, first_new: LV, keys: Option<&KeyRing>) -> Result<(), ParseError> {
+        let aa = &self.cg.agent_assignment;
+        let mut accepted = vec![];
+
+        for s in incoming {
+            let Some(hash) = self.hash_span(s.agent, s.seq_range) else {
+                // We don't have all the named operations. The span will be sent again later.
+                continue;
+            };
+
+            let name = aa.get_agent_name(s.agent);
+            if let Some(keys) = keys {
+                if let Some(key) = keys.get(name) {
+                    key.verify(&signed_message(name, s.seq_range, &s.hash), &s.signature)
+                        .map_err(|_| ParseError::InvalidSignature)?;
+
+                    if hash != s.hash {
+                        // The agent really did sign these operations. If we already had any of
+                        // them, the agent has signed two different histories with the same IDs.
+                        return Err(if self.is_new_span(s.agent, s.seq_range, first_new) {
+                            ParseError::InvalidSignature
+                        } else {
+                            ParseError::Equivocation
+                        });
+                    }
+
+                    if let Some(existing) = self.signatures.get(&(s.agent, s.seq_range.start)) {
+                        if existing.hash != s.hash { return Err(ParseError::Equivocation); }
+                    }
+                    accepted.push(s);
+                    continue;
+                }
+            }
+
+            // Unverified signatures are only kept if they match the operations they name.
+            if hash == s.hash { accepted.push(s); }
+        }
+
+        if let Some(keys) = keys {
+            // Stored signatures only cover operations we had before this merge, so the new
+            // operations need to be covered by verified signatures which came with them. Agents
+            // without keys can't be verified, so with require_signatures they're always rejected.
+            let coverage = Coverage::new(accepted.iter()
+                .filter(|s| keys.get(aa.get_agent_name(s.agent)).is_some()));
+            for KVPair(_, span) in aa.client_with_lv.iter_range((first_new..self.len()).into()) {
+                if !keys.needs_signature(aa.get_agent_name(span.agent)) { continue; }
+                if !coverage.covers(span.agent, span.seq_range) {
+                    return Err(ParseError::MissingSignature);
+                }
+            }
+        }
+
+        for s in accepted {
+            self.signatures.entry((s.agent, s.seq_range.start)).or_insert(s);
+        }
+        Ok(())
+    }
+
+    /// Returns true if all the operations in the named span were added at or after `first_new`.
+    fn is_new_span(&self, agent: AgentId, seq_range: DTRange, first_new: LV) -> bool {
+        let client = &self.cg.agent_assignment.client_data[agent as usize];
+        client.lv_for_seq.iter_range(seq_range).all(|KVPair(_, lv)| lv.start >= first_new)
+    }
+}
+
+/// The seq ranges covered by a set of signatures, for each agent. Overlapping and adjacent ranges
+/// are merged, so each agent's ranges are sorted and disjoint.
+struct Coverage(HashMap<AgentId, Vec<DTRange>>);
+
+impl Coverage {
+    fn new<'a>(signatures: impl Iterator<Item = &'a SignedSpan>) -> Self {
+        let mut ranges: HashMap<AgentId, Vec<DTRange>> = HashMap::new();
+        for s in signatures {
+            ranges.entry(s.agent).or_default().push(s.seq_range);
+        }
+
+        for spans in ranges.values_mut() {
+            spans.sort_unstable_by_key(|r| r.start);
+            let mut merged: Vec<DTRange> = Vec::with_capacity(spans.len());
+            for r in spans.drain(..) {
+                match merged.last_mut() {
+                    Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                    _ => merged.push(r),
+                }
+            }
+            *spans = merged;
+        }
+        Self(ranges)
+    }
+
+    fn covers(&self, agent: AgentId, seq_range: DTRange) -> bool {
+        let Some(spans) = self.0.get(&agent) else { return false; };
+        // Find the last range starting at or before seq_range.
+        let idx = spans.partition_point(|r| r.start <= seq_range.start);
+        idx > 0 && spans[idx - 1].end >= seq_range.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::{DecodeOptions, ENCODE_FULL};
+    use crate::list::ListOpLog;
+    use super::*;
+
+    fn alice_key() -> SigningKey { SigningKey::from_bytes(&[1; 32]) }
+    fn mallory_key() -> SigningKey { SigningKey::from_bytes(&[2; 32]) }
+
+    fn keyring() -> KeyRing {
+        let mut keys = KeyRing::new();
+        keys.add_agent("alice", alice_key().verifying_key());
+        keys
+    }
+
+    fn decode_checked(oplog: &mut ListOpLog, data: &[u8], keys: &KeyRing) -> Result<(), ParseError> {
+        let before = oplog.clone();
+        let result = oplog.decode_and_add_opts(data, DecodeOptions { keys: Some(keys), ..Default::default() });
+        if result.is_err() {
+            assert_eq!(oplog, &before);
+            assert_eq!(oplog.signatures, before.signatures);
+        }
+        result.map(|_| ())
+    }
+
+    fn alice_doc(content: &str) -> ListOpLog {
+        let mut oplog = ListOpLog::new();
+        let alice = oplog.get_or_create_agent_id("alice");
+        oplog.add_insert(alice, 0, content);
+        oplog
+    }
+
+    #[test]
+    fn signed_ops_round_trip() {
+        let mut src = alice_doc("hi there");
+        let alice = src.get_or_create_agent_id("alice");
+        assert_eq!(src.sign_ops(alice, &alice_key()), Some((0..8).into()));
+        assert_eq!(src.sign_ops(alice, &alice_key()), None);
+
+        let keys = keyring();
+        let mut dest = ListOpLog::new();
+        decode_checked(&mut dest, &src.encode(&ENCODE_FULL), &keys).unwrap();
+        assert_eq!(dest, src);
+        assert!(dest.is_signed(alice, (0..8).into(), &keys));
+
+        // Signatures are saved and loaded along with everything else.
+        let loaded = ListOpLog::load_from(&dest.encode(&ENCODE_FULL)).unwrap();
+        assert_eq!(loaded.signatures, src.signatures);
+
+        // And incremental changes from other agents carry their own signatures.
+        let v = src.cg.version.clone();
+        let bob = src.get_or_create_agent_id("bob");
+        src.add_insert(bob, 0, "Oh, ");
+        src.add_delete_without_content(alice, 4..6);
+        src.sign_ops(alice, &alice_key()).unwrap();
+        decode_checked(&mut dest, &src.encode_from(&ENCODE_FULL, v.as_ref()), &keys).unwrap();
+        assert_eq!(dest, src);
+        assert!(dest.is_signed(alice, (0..10).into(), &keys));
+        assert!(!dest.is_signed(bob, (0..4).into(), &keys));
+
+        // Unless bob needs a key too.
+        let mut strict = keys.clone();
+        strict.set_require_signatures(true);
+        let mut dest2 = ListOpLog::new();
+        assert_eq!(decode_checked(&mut dest2, &src.encode(&ENCODE_FULL), &strict), Err(ParseError::MissingSignature));
+    }
+
+    #[test]
+    fn forged_ops_rejected() {
+        let keys = keyring();
+        let mut dest = ListOpLog::new();
+
+        // Unsigned.
+        let forged = alice_doc("evil");
+        assert_eq!(decode_checked(&mut dest, &forged.encode(&ENCODE_FULL), &keys), Err(ParseError::MissingSignature));
+
+        // Signed with the wrong key.
+        let mut forged = alice_doc("evil");
+        forged.sign_ops(0, &mallory_key());
+        assert_eq!(decode_checked(&mut dest, &forged.encode(&ENCODE_FULL), &keys), Err(ParseError::InvalidSignature));
+
+        // With a real signature for different operations.
+        let mut real = alice_doc("good");
+        real.sign_ops(0, &alice_key());
+        let mut forged = alice_doc("evil");
+        forged.signatures = real.signatures.clone();
+        assert_eq!(decode_checked(&mut dest, &forged.encode(&ENCODE_FULL), &keys), Err(ParseError::InvalidSignature));
+
+        // Without a keyring, nothing is checked.
+        dest.decode_and_add(&forged.encode(&ENCODE_FULL)).unwrap();
+        assert_eq!(dest.checkout_tip().content(), "evil");
+    }
+
+    #[test]
+    fn unverified_signatures_not_trusted() {
+        let keys = keyring();
+
+        // Loading without a keyring keeps the signature, but it isn't valid for alice's key.
+        let mut forged = alice_doc("evil");
+        forged.sign_ops(0, &mallory_key());
+        let loaded = ListOpLog::load_from(&forged.encode(&ENCODE_FULL)).unwrap();
+        assert_eq!(loaded.signatures, forged.signatures);
+        assert!(!loaded.is_signed(0, (0..4).into(), &keys));
+
+        // Signatures which don't match their operations are dropped.
+        let mut real = alice_doc("good");
+        real.sign_ops(0, &alice_key());
+        let mut mismatched = alice_doc("evil");
+        mismatched.signatures = real.signatures.clone();
+        let loaded = ListOpLog::load_from(&mismatched.encode(&ENCODE_FULL)).unwrap();
+        assert!(loaded.signatures.is_empty());
+    }
+
+    #[test]
+    fn coverage_merges_spans() {
+        let sig = |agent, start, end| SignedSpan {
+            agent,
+            seq_range: (start..end).into(),
+            hash: [0; 32],
+            signature: Signature::from_bytes(&[0; 64]),
+        };
+        let sigs = [sig(0, 5, 10), sig(0, 0, 3), sig(0, 3, 6), sig(0, 12, 20), sig(1, 0, 4)];
+        let coverage = Coverage::new(sigs.iter());
+
+        assert!(coverage.covers(0, (0..10).into()));
+        assert!(coverage.covers(0, (4..8).into()));
+        assert!(!coverage.covers(0, (8..13).into()));
+        assert!(coverage.covers(0, (12..20).into()));
+        assert!(!coverage.covers(0, (19..21).into()));
+        assert!(!coverage.covers(1, (0..5).into()));
+        assert!(!coverage.covers(2, (0..1).into()));
+    }
+
+    #[test]
+    fn equivocation_detected() {
+        let keys = keyring();
+
+        // Alice signs two different histories with the same IDs.
+        let mut a = alice_doc("good");
+        a.sign_ops(0, &alice_key());
+        let mut b = alice_doc("evil");
+        b.sign_ops(0, &alice_key());
+
+        let mut dest = ListOpLog::new();
+        decode_checked(&mut dest, &a.encode(&ENCODE_FULL), &keys).unwrap();
+        assert_eq!(decode_checked(&mut dest, &b.encode(&ENCODE_FULL), &keys), Err(ParseError::Equivocation));
+        assert_eq!(dest.checkout_tip().content(), "good");
+
+        // Resending the same operations is fine.
+        decode_checked(&mut dest, &a.encode(&ENCODE_FULL), &keys).unwrap();
+    }
+
+    #[test]
+    fn signatures_for_ranges_returned_once() {
+        let mut oplog = alice_doc("hi there");
+        let alice = oplog.get_or_create_agent_id("alice");
+        oplog.sign_ops(alice, &alice_key());
+        let bob = oplog.get_or_create_agent_id("bob");
+        oplog.add_insert(bob, 0, "Oh, ");
+        oplog.add_insert(alice, 0, "!");
+        oplog.sign_ops(alice, &alice_key());
+
+        let v = oplog.cg.version.clone();
+        // Both ranges start partway through alice's first signature.
+        let sigs = oplog.signatures_for_ranges(&[(3..5).into(), (5..13).into()], v.as_ref());
+        let spans = sigs.iter().map(|s| (s.agent, s.seq_range)).collect::<Vec<_>>();
+        assert_eq!(spans, [(alice, (0..8).into()), (alice, (8..9).into())]);
+
+        // Signatures are skipped unless all their operations are included.
+        assert!(oplog.signatures_for_ranges(&[(0..8).into()], &[6]).is_empty());
+    }
+}
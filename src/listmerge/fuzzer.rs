@@ -79,6 +79,15 @@ fn merge_fuzz(seed: u64, verbose: bool) {
 
         // dbg!(&opset);
 
+        // The index forking merge algorithm should produce exactly the same result.
+        #[cfg(feature = "listmerge2")] {
+            let mut a2 = a.clone();
+            oplog.merge_to_version_listmerge2(&mut a2, b.version.as_ref());
+            let mut a3 = a.clone();
+            oplog.merge_to_version(&mut a3, b.version.as_ref());
+            assert_eq!(a2, a3, "listmerge2 result does not match");
+        }
+
         if verbose { println!("Merge b to a: {:?} -> {:?}", &b.version, &a.version); }
         oplog.merge_to_version(a, b.version.as_ref());
         if verbose {
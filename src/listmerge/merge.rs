@@ -301,7 +301,7 @@ impl M2Tracker {
         end_pos
     }
 
-    fn apply_range(&mut self, aa: &AgentAssignment, op_ctx: &ListOperationCtx, ops: &RleVec<KVPair<ListOpMetrics>>, range: DTRange, mut to: Option<&mut JumpRopeBuf>) {
+    pub(super) fn apply_range(&mut self, aa: &AgentAssignment, op_ctx: &ListOperationCtx, ops: &RleVec<KVPair<ListOpMetrics>>, range: DTRange, mut to: Option<&mut JumpRopeBuf>) {
         if range.is_empty() { return; }
 
         let mut iter = OpMetricsIter::new(ops, op_ctx, range);
@@ -670,7 +670,7 @@ impl<'a> TransformedOpsIterRaw<'a> {
     }
 
     // Returns (remainder, item_here);
-    fn next_from(aa: &AgentAssignment, tracker: &mut M2Tracker, op_ctx: &ListOperationCtx, mut pair: KVPair<ListOpMetrics>)
+    pub(super) fn next_from(aa: &AgentAssignment, tracker: &mut M2Tracker, op_ctx: &ListOperationCtx, mut pair: KVPair<ListOpMetrics>)
         -> (Option<KVPair<ListOpMetrics>>, TransformedResultRaw)
     {
         // Ok, try to consume as much as we can from pair.
@@ -774,18 +774,28 @@ pub(crate) enum TransformedSimpleOp {
 /// transformed.
 ///
 /// TODO: Name me.
-pub(crate) struct TransformedSimpleOpsIter<'a> {
-    inner: TransformedOpsIterRaw<'a>,
+pub(crate) struct TransformedSimpleOpsIter<'a, I = TransformedOpsIterRaw<'a>> {
+    inner: I,
+    ops: &'a RleVec<KVPair<ListOpMetrics>>,
+    op_ctx: &'a ListOperationCtx,
     ff_iter: Option<(std::slice::Iter<'a, KVPair<ListOpMetrics>>, usize)>,
 }
 
+impl<'a, I: Iterator<Item = TransformedResultRaw>> TransformedSimpleOpsIter<'a, I> {
+    /// Wrap any stream of raw transformed results. FF ranges are expanded using `ops`.
+    pub(crate) fn new(inner: I, ops: &'a RleVec<KVPair<ListOpMetrics>>, op_ctx: &'a ListOperationCtx) -> Self {
+        Self { inner, ops, op_ctx, ff_iter: None }
+    }
+}
+
 impl<'a> From<TransformedOpsIterRaw<'a>> for TransformedSimpleOpsIter<'a> {
     fn from(inner: TransformedOpsIterRaw<'a>) -> Self {
-        Self { inner, ff_iter: None }
+        let (ops, op_ctx) = (inner.ops, inner.op_ctx);
+        Self::new(inner, ops, op_ctx)
     }
 }
 
-impl<'a> Iterator for TransformedSimpleOpsIter<'a> {
+impl<'a, I: Iterator<Item = TransformedResultRaw>> Iterator for TransformedSimpleOpsIter<'a, I> {
     type Item = TransformedSimpleOp;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -797,7 +807,7 @@ impl<'a> Iterator for TransformedSimpleOpsIter<'a> {
                 if item.0 < *end {
                     let item_end = item.end();
                     if item_end > *end {
-                        item.truncate_ctx(*end - item.0, self.inner.op_ctx);
+                        item.truncate_ctx(*end - item.0, self.op_ctx);
                     }
                     return Some(Apply(item));
                 }
@@ -816,13 +826,16 @@ impl<'a> Iterator for TransformedSimpleOpsIter<'a> {
             Some(TransformedResultRaw::FF(range)) => {
                 debug_assert!(!range.is_empty());
 
-                let start_idx = self.inner.ops.find_next_index(range.start);
-                let mut first = self.inner.ops[start_idx].clone();
+                let start_idx = self.ops.find_next_index(range.start);
+                let mut first = self.ops[start_idx].clone();
                 if first.0 < range.start {
-                    first.truncate_keeping_right_ctx(range.start - first.0, self.inner.op_ctx);
+                    first.truncate_keeping_right_ctx(range.start - first.0, self.op_ctx);
+                }
+                if first.end() > range.end {
+                    first.truncate_ctx(range.end - first.0, self.op_ctx);
                 }
 
-                self.ff_iter = Some((self.inner.ops.0[start_idx+1..].iter(), range.end));
+                self.ff_iter = Some((self.ops.0[start_idx+1..].iter(), range.end));
 
                 Some(Apply(first))
             },
@@ -845,11 +858,17 @@ impl TextInfo {
 
     pub(crate) fn with_xf_iter<F: FnOnce(TransformedOpsIterRaw, Frontier) -> R, R>(&self, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV], f: F) -> R {
         // This is a big dirty mess for now, but it should be correct at least.
-        let conflict = cg.graph.find_conflicting_simple(from, merge_frontier);
-
         let final_frontier = cg.graph.find_dominators_2(from, merge_frontier);
         // if final_frontier.as_ref() == from { return final_frontier; } // Nothing to do!
 
+        // Only this text's operations matter here. Projecting both versions into the text's scope
+        // first keeps other CRDTs' operations out of the conflict zone. (Projecting and finding
+        // the conflict zone still walk the document's graph.)
+        let from = self.version_in_scope(&cg.graph, from);
+        let merge_frontier = self.version_in_scope(&cg.graph, merge_frontier);
+        let conflict = cg.graph.find_conflicting_simple(from.as_ref(), merge_frontier.as_ref());
+        let scoped_frontier = cg.graph.find_dominators_2(from.as_ref(), merge_frontier.as_ref());
+
         // This looks inefficient - since after all, we only care about the operations in the
         // conflict zone. But because we scan the intersection of these operations and the conflict,
         // and scan them backwards, it works out to be efficient in practice.
@@ -864,20 +883,56 @@ impl TextInfo {
         let iter = rle_intersect_rev(op_spans, conflict.rev_spans.iter().copied())
             .map(|pair| pair.0);
 
-        let (subgraph, _ff) = cg.graph.subgraph_raw(iter.clone(), final_frontier.as_ref());
+        let (subgraph, _ff) = cg.graph.subgraph_raw(iter.clone(), scoped_frontier.as_ref());
 
         // println!("{}", subgraph.0.0.len());
         // subgraph.dbg_check_subgraph(true); // For debugging.
         // dbg!(&subgraph, ff.as_ref());
 
-        let from = cg.graph.project_onto_subgraph_raw(iter.clone(), from);
-        let merge_frontier = cg.graph.project_onto_subgraph_raw(iter.clone(), merge_frontier);
+        let from = cg.graph.project_onto_subgraph_raw(iter.clone(), from.as_ref());
+        let merge_frontier = cg.graph.project_onto_subgraph_raw(iter.clone(), merge_frontier.as_ref());
 
         // let mut iter = TransformedOpsIter::new(oplog, &self.frontier, merge_frontier);
         let iter = self.get_xf_operations_full(&subgraph, &cg.agent_assignment, from.as_ref(), merge_frontier.as_ref());
         f(iter, final_frontier)
     }
 
+    /// Like [`merge_into`](Self::merge_into), but using the index forking merge algorithm in
+    /// listmerge2.
+    #[cfg(feature = "listmerge2")]
+    pub(crate) fn merge_into_listmerge2(&self, into: &mut JumpRopeBuf, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV]) -> Frontier {
+        // Same dance as with_xf_iter above.
+        let final_frontier = cg.graph.find_dominators_2(from, merge_frontier);
+        let from = self.version_in_scope(&cg.graph, from);
+        let merge_frontier = self.version_in_scope(&cg.graph, merge_frontier);
+        let conflict = cg.graph.find_conflicting_simple(from.as_ref(), merge_frontier.as_ref());
+        let scoped_frontier = cg.graph.find_dominators_2(from.as_ref(), merge_frontier.as_ref());
+
+        let op_spans = self.ops.iter().map(|e| e.span())
+            .rev()
+            .merge_spans_rev();
+        let iter = rle_intersect_rev(op_spans, conflict.rev_spans.iter().copied())
+            .map(|pair| pair.0);
+
+        let (subgraph, _ff) = cg.graph.subgraph_raw(iter.clone(), scoped_frontier.as_ref());
+        let from = cg.graph.project_onto_subgraph_raw(iter.clone(), from.as_ref());
+        let merge_frontier = cg.graph.project_onto_subgraph_raw(iter.clone(), merge_frontier.as_ref());
+
+        crate::listmerge2::xf_operations_between(&subgraph, &cg.agent_assignment, &self.ctx, &self.ops,
+            from.as_ref(), merge_frontier.as_ref(), |xf| {
+                match xf {
+                    TransformedResultRaw::Apply { xf_pos, op: KVPair(_, mut op) } => {
+                        op.transpose_to(xf_pos);
+                        self.apply_op_to(op, into);
+                    }
+                    TransformedResultRaw::FF(_) => unreachable!(),
+                    TransformedResultRaw::DeleteAlreadyHappened(_) => {} // Discard.
+                }
+            });
+
+        final_frontier
+    }
+
     /// Iterate through all the *transformed* operations from some point in time. Internally, the
     /// OpLog stores all changes as they were when they were created. This makes a lot of sense from
     /// CRDT academic point of view (and makes signatures and all that easy). But its is rarely
@@ -986,7 +1041,7 @@ mod test {
     use rle::{MergeableIterator, SplitableSpan};
 
     use crate::dtrange::UNDERWATER_START;
-    use crate::list::ListOpLog;
+    use crate::list::{ListBranch, ListOpLog};
     use crate::listmerge::simple_oplog::SimpleOpLog;
     use crate::listmerge::yjsspan::{deleted_n_state, DELETED_ONCE, SpanState};
     use crate::stats::take_stats;
@@ -1226,6 +1281,42 @@ mod test {
     }
 
 
+    #[test]
+    fn xf_ops_cover_each_op_once() {
+        // The first op in a fast forward range shouldn't extend past the end of the range. Here
+        // "abc" is stored as a single op, but only the first 2 characters can be fast forwarded.
+        let mut oplog = ListOpLog::new();
+        let a = oplog.get_or_create_agent_id("a");
+        let b = oplog.get_or_create_agent_id("b");
+        oplog.add_insert_at(a, &[], 0, "abc");
+        oplog.add_insert_at(b, &[1], 0, "x");
+
+        let mut content = String::new();
+        let mut applied = 0;
+        for (range, op) in oplog.iter_xf_operations() {
+            applied += range.len();
+            let op = op.unwrap();
+            content.insert_str(op.start(), op.content.as_ref().unwrap());
+        }
+        assert_eq!(applied, oplog.len());
+        assert_eq!(content, oplog.checkout_tip().content().to_string());
+    }
+
+    #[test]
+    fn xf_ops_stop_at_merged_version() {
+        // Merging part of a stored op should only yield that part.
+        let mut oplog = ListOpLog::new();
+        let a = oplog.get_or_create_agent_id("a");
+        oplog.add_insert_at(a, &[], 0, "abc");
+
+        let ops = oplog.iter_xf_operations_from(&[], &[1]).collect::<Vec<_>>();
+        assert_eq!(ops, [((0..2).into(), Some(TextOperation::new_insert(0, "ab")))]);
+
+        let mut branch = ListBranch::new();
+        branch.merge(&oplog, &[1]);
+        assert_eq!(branch.content(), "ab");
+    }
+
     #[test]
     #[ignore]
     fn print_stats() {
@@ -0,0 +1,148 @@
+//! A persistent merge context keeps an [`M2Tracker`] alive between merges into the same branch.
+//!
+//! Normally every merge builds a fresh tracker starting at the common ancestor of the branch's
+//! version and the merged version, replays all the concurrent operations into it and then throws
+//! it away. If a branch is repeatedly merging small changes while its peers are editing
+//! concurrently, most of that work is repeated each time.
+//!
+//! Instead the merge context holds on to the tracker. Each merge only needs to extend it with the
+//! operations it hasn't seen yet. The tradeoff is memory: the tracker grows with every operation
+//! applied since the context was created. And the context never uses the fast-forward
+//! optimization, so the first merge can be slower than a normal merge.
+
+use rle::{HasLength, TrimCtx};
+
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::agent_assignment::AgentAssignment;
+use crate::causalgraph::graph::Graph;
+use crate::list::ListOpLog;
+use crate::list::op_iter::OpMetricsIter;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::listmerge::M2Tracker;
+use crate::listmerge::merge::{TransformedOpsIterRaw, TransformedResultRaw};
+use crate::rle::{KVPair, RleVec};
+
+#[derive(Debug, Clone)]
+pub(crate) struct MergeContext {
+    tracker: M2Tracker,
+
+    /// The version the tracker was created at. Everything in this version is "underwater" in the
+    /// tracker, so the tracker can never be moved to a version which doesn't contain it.
+    base: Frontier,
+
+    /// All the operations which have been applied to the tracker. The tracker's current state
+    /// also matches this version in between merges.
+    version: Frontier,
+
+    /// Set once the tracker has been positioned by a merge. The first merge always starts from
+    /// the common ancestor of the two versions, rather than replaying the branch's entire history.
+    ready: bool,
+
+    /// The [`OpLogId`](crate::list::OpLogId) of the oplog used for the last merge. The tracker
+    /// refers to operations by local version, so it's only valid for that oplog.
+    oplog_id: Option<u64>,
+}
+
+impl MergeContext {
+    pub(crate) fn new() -> Self {
+        Self {
+            tracker: M2Tracker::new(),
+            base: Frontier::root(),
+            version: Frontier::root(),
+            ready: false,
+            oplog_id: None,
+        }
+    }
+
+    /// Can we keep using the tracker to merge `merging` into a document at `from`? The tracker's
+    /// content must be a subset of `from` (since the transformed positions are relative to
+    /// everything the tracker has seen). And every operation we still need to visit must come
+    /// after the base version.
+    fn can_reuse(&self, oplog: &ListOpLog, from: &[LV], merging: &[LV]) -> bool {
+        let graph = &oplog.cg.graph;
+        self.ready
+            && self.oplog_id == Some(oplog.id.get())
+            && graph.frontier_contains_frontier(from, self.version.as_ref())
+            && graph.frontier_contains_frontier(graph.find_conflicting(self.version.as_ref(), from, |_, _| {}).as_ref(), self.base.as_ref())
+            && graph.frontier_contains_frontier(graph.find_conflicting(from, merging, |_, _| {}).as_ref(), self.base.as_ref())
+    }
+
+    fn reset(&mut self, graph: &Graph, from: &[LV], merging: &[LV]) {
+        let common = graph.find_conflicting(from, merging, |_, _| {});
+        self.tracker.clear();
+        self.base = common.clone();
+        self.version = common;
+        self.ready = true;
+    }
+
+    /// Move the tracker from its current version (`current`) to `target`. Every operation in
+    /// either version must already be applied.
+    fn move_tracker(&mut self, graph: &Graph, current: &[LV], target: &[LV]) {
+        let (only_current, only_target) = graph.diff_rev(current, target);
+
+        for range in only_current.iter() {
+            self.tracker.retreat_by_range(*range);
+        }
+        for range in only_target.iter().rev() {
+            self.tracker.advance_by_range(*range);
+        }
+    }
+
+    /// Apply all the operations in `target` which haven't been applied to the tracker yet. If
+    /// `emit` is passed, the transformed operations are passed to it.
+    fn advance_to<F: FnMut(TransformedResultRaw)>(&mut self, oplog: &ListOpLog, target: &[LV], mut emit: Option<&mut F>) {
+        let graph = &oplog.cg.graph;
+        let (_, new_ranges) = graph.diff(self.version.as_ref(), target);
+        if new_ranges.is_empty() { return; }
+
+        let mut current = self.version.clone();
+        for range in new_ranges.iter() {
+            for entry in graph.iter_range(*range) {
+                self.move_tracker(graph, current.as_ref(), entry.parents.as_ref());
+                self.apply_span(&oplog.cg.agent_assignment, &oplog.operation_ctx, &oplog.operations, entry.span, emit.as_deref_mut());
+                current = Frontier::new_1(entry.span.last());
+            }
+        }
+
+        let new_version = graph.find_dominators_2(self.version.as_ref(), target);
+        self.move_tracker(graph, current.as_ref(), new_version.as_ref());
+        self.version = new_version;
+    }
+
+    fn apply_span<F: FnMut(TransformedResultRaw)>(&mut self, aa: &AgentAssignment, op_ctx: &ListOperationCtx,
+        ops: &RleVec<KVPair<ListOpMetrics>>, span: DTRange, emit: Option<&mut F>)
+    {
+        let Some(emit) = emit else {
+            self.tracker.apply_range(aa, op_ctx, ops, span, None);
+            return;
+        };
+
+        for mut pair in OpMetricsIter::new(ops, op_ctx, span) {
+            loop {
+                let (remainder, result) = TransformedOpsIterRaw::next_from(aa, &mut self.tracker, op_ctx, pair);
+                emit(result);
+                if let Some(r) = remainder {
+                    pair = r;
+                } else { break; }
+            }
+        }
+    }
+
+    /// Transform the operations in `merging` which aren't in `from`, passing them to `emit` in the
+    /// order they should be applied to a document at `from`. This produces the same results as
+    /// [`TransformedOpsIterRaw`], but the tracker is kept around for the next merge.
+    pub(crate) fn merge<F: FnMut(TransformedResultRaw)>(&mut self, oplog: &ListOpLog, from: &[LV], merging: &[LV], mut emit: F) {
+        let graph = &oplog.cg.graph;
+        if graph.frontier_contains_frontier(from, merging) { return; } // Nothing to merge.
+
+        if !self.can_reuse(oplog, from, merging) {
+            self.reset(graph, from, merging);
+        }
+        self.oplog_id = Some(oplog.id.get());
+
+        // First catch the tracker up with any changes made locally to the branch since the last
+        // merge. Then apply the new operations.
+        self.advance_to::<F>(oplog, from, None);
+        self.advance_to(oplog, merging, Some(&mut emit));
+    }
+}
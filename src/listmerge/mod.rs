@@ -17,6 +17,9 @@ use crate::ost::IndexTree;
 
 pub(crate) mod yjsspan;
 pub(crate) mod merge;
+pub(crate) mod merge_context;
+#[cfg(feature = "parallel")]
+pub(crate) mod parallel;
 pub(crate) mod markers;
 mod advance_retreat;
 // pub(crate) mod txn_trace;
@@ -33,7 +36,7 @@ pub(crate) mod xf_old;
 
 type Index = IndexTree<Marker>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct M2Tracker {
     /// The index is used for 2 things:
     ///
@@ -0,0 +1,90 @@
+//! Parallel transformation of independent conflict zones.
+//!
+//! When the merge plan fast-forwards through a span on the critical path, it clears the tracker
+//! first. Nothing before a clear affects anything after it - each run of actions between clears
+//! starts from an empty tracker (full of underwater items). So we can split the plan at each clear,
+//! transform each part on its own thread and stitch the results back together in order.
+//!
+//! This only helps for histories with lots of separate concurrent regions (like node_nodecc). A
+//! single big conflict zone is still transformed on one thread.
+
+use rayon::prelude::*;
+
+use crate::LV;
+use crate::causalgraph::agent_assignment::AgentAssignment;
+use crate::causalgraph::graph::Graph;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::listmerge::merge::{TransformedOpsIterRaw, TransformedResultRaw};
+use crate::listmerge::plan::{M1Plan, M1PlanAction};
+use crate::rle::{KVPair, RleVec};
+
+impl M1Plan {
+    /// Split the plan into independent parts at each clear action. Each part after the first
+    /// starts with `BeginOutput` if the original plan had started its output by that point.
+    pub(crate) fn split_at_clears(self) -> Vec<M1Plan> {
+        let mut result = vec![];
+        let mut current = vec![];
+        let mut output_started = false;
+
+        for action in self.0 {
+            match action {
+                M1PlanAction::Clear => {
+                    if !current.is_empty() {
+                        result.push(M1Plan(std::mem::take(&mut current)));
+                    }
+                    if output_started {
+                        current.push(M1PlanAction::BeginOutput);
+                    }
+                }
+                M1PlanAction::BeginOutput => {
+                    output_started = true;
+                    current.push(action);
+                }
+                _ => { current.push(action); }
+            }
+        }
+
+        if !current.is_empty() {
+            result.push(M1Plan(current));
+        }
+        result
+    }
+}
+
+/// Transform the operations in `merging` which aren't in `from`, like [`TransformedOpsIterRaw`].
+/// The independent parts of the merge are transformed in parallel using rayon's global thread
+/// pool. The results are returned in the order they should be applied to a document at `from`.
+pub(crate) fn par_xf_operations(graph: &Graph, aa: &AgentAssignment, op_ctx: &ListOperationCtx,
+    ops: &RleVec<KVPair<ListOpMetrics>>, from: &[LV], merging: &[LV]) -> Vec<TransformedResultRaw>
+{
+    let (plan, _common) = graph.make_m1_plan(Some(ops), from, merging, true);
+
+    let parts: Vec<Vec<TransformedResultRaw>> = plan.split_at_clears()
+        .into_par_iter()
+        .map(|plan| TransformedOpsIterRaw::from_plan(aa, op_ctx, ops, plan).collect())
+        .collect();
+
+    parts.concat()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListOpLog, MergeAlgorithm};
+
+    #[test]
+    fn matches_tracker_on_benchmark_data() {
+        for name in ["S1", "C1", "node_nodecc"] {
+            let bytes = std::fs::read(format!("benchmark_data/{name}.dt")).unwrap();
+            let oplog = ListOpLog::load_from(&bytes).unwrap();
+
+            let expected = oplog.checkout_tip();
+            let mut actual = ListBranch::new();
+            actual.merge_with(&oplog, oplog.local_frontier_ref(), MergeAlgorithm::Parallel);
+            assert_eq!(expected, actual, "{name} does not match");
+
+            let expected_ops: Vec<_> = oplog.iter_xf_operations().collect();
+            let actual_ops: Vec<_> = oplog.par_iter_xf_operations().collect();
+            assert_eq!(expected_ops, actual_ops, "{name} operations do not match");
+        }
+    }
+}
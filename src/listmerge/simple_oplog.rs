@@ -87,6 +87,11 @@ impl SimpleOpLog {
         into.version = self.merge_raw(&mut into.content, into.version.as_ref(), to_version);
     }
 
+    #[cfg(feature = "listmerge2")]
+    pub(crate) fn merge_to_version_listmerge2(&self, into: &mut SimpleBranch, to_version: &[LV]) {
+        into.version = self.info.merge_into_listmerge2(&mut into.content, &self.cg, into.version.as_ref(), to_version);
+    }
+
     pub(crate) fn dbg_check(&self, deep: bool) {
         // TODO: Check the op ctx makes sense I guess?
         self.cg.dbg_check(deep);
@@ -15,6 +15,9 @@ pub(crate) struct ApplyAction {
     pub index: Index,
     pub update_other_indexes: SmallVec<Index, 2>,
     pub insert_items: bool,
+    /// True when the span is only in the version being merged in (b). The transformed positions
+    /// of these operations are emitted when the plan is run.
+    pub output: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -27,7 +30,7 @@ pub(crate) enum MergePlanAction {
     MaxIndex(Index, SmallVec<Index, 2>),
 }
 use MergePlanAction::*;
-use crate::causalgraph::graph::conflict_subgraph::ConflictSubgraph;
+use crate::causalgraph::graph::conflict_subgraph::{ConflictGraphEntry, ConflictSubgraph};
 use crate::causalgraph::graph::Graph;
 use crate::frontier::is_sorted_slice;
 
@@ -144,19 +147,61 @@ impl ConflictSubgraph<EntryState> {
         }
 
         if !self.entries.is_empty() {
-            // self.entries[0].state.children_needing_index += 1;
-            self.entries[self.a_root].state.children_needing_index += 1;
-            self.entries[self.b_root].state.children_needing_index += 1;
+            // The final entry hands its index back to the caller.
+            self.entries[0].state.children_needing_index += 1;
         }
     }
 
+    /// The plan is a traversal from a single final entry, which needs to be at index 0. The
+    /// conflict graph has separate roots for a and b, so unless b is already contained in a, we
+    /// add a merge entry for them at the front of the list.
+    ///
+    /// The merge's first parent is a_root. So everything in a is visited before anything which is
+    /// only in b.
+    fn add_final_merge(&mut self) {
+        if self.entries.is_empty() || self.a_root == self.b_root { return; }
+
+        // Entries are sorted newest first, so the root with the lower index might be a
+        // descendant of the other one. If b is an ancestor of a, there's nothing to output and a
+        // is already the final entry. But if a is an ancestor of b we still add the merge, so a's
+        // history is visited first.
+        if self.a_root < self.b_root {
+            let (hi, lo) = (self.a_root, self.b_root);
+            let mut stack = vec![hi];
+            let mut visited = vec![false; lo + 1];
+            while let Some(idx) = stack.pop() {
+                if idx == lo {
+                    debug_assert_eq!(hi, 0);
+                    return;
+                }
+                for &p in self.entries[idx].parents.iter() {
+                    if p <= lo && !visited[p] {
+                        visited[p] = true;
+                        stack.push(p);
+                    }
+                }
+            }
+        }
+
+        for e in self.entries.iter_mut() {
+            for p in e.parents.iter_mut() { *p += 1; }
+        }
+        self.a_root += 1;
+        self.b_root += 1;
+        self.entries.insert(0, ConflictGraphEntry {
+            parents: smallvec![self.a_root, self.b_root],
+            span: Default::default(),
+            state: Default::default(),
+            flag: DiffFlag::Shared,
+        });
+    }
+
     fn plan_first_pass(&mut self, b: &Bump) {
         use bumpalo::collections::Vec as BumpVec;
 
         if self.entries.is_empty() { return; }
         let mut stack = vec![];
-        let mut current_idx = self.a_root;
-        let mut done_b = false;
+        let mut current_idx = 0;
 
         #[derive(Debug, Clone, Copy, Eq, PartialEq)]
         enum Movement {
@@ -248,10 +293,6 @@ impl ConflictSubgraph<EntryState> {
                 Down => {
                     if let Some(next) = stack.pop() {
                         current_idx = next;
-                    } else if !done_b && !self.entries[self.b_root].state.visited {
-                        done_b = true;
-                        current_idx = self.b_root;
-                        last_direction = Up(usize::MAX);
                     } else { break; };
                 }
             }
@@ -288,6 +329,8 @@ impl ConflictSubgraph<EntryState> {
             return MergePlan { actions: vec![], indexes_used: 0 };
         }
 
+        self.add_final_merge();
+
         // This is so horrible.
         for i in 0..self.entries.len() {
             for j in 0..self.entries[i].parents.len() {
@@ -473,6 +516,7 @@ impl ConflictSubgraph<EntryState> {
                             index,
                             update_other_indexes,
                             insert_items: concurrency > 0,
+                            output: e.flag == DiffFlag::OnlyB,
                         }));
                         list_contains_content = true;
                     }
@@ -674,7 +718,7 @@ impl MergePlan {
 
         for action in self.actions.iter() {
             match action {
-                Apply(ApplyAction { span, index, update_other_indexes, insert_items: _ }) => {
+                Apply(ApplyAction { span, index, update_other_indexes, .. }) => {
                     if !span.is_empty() {
                         let actual_parents = graph.parents_at_version(span.start);
 
@@ -763,11 +807,11 @@ mod test {
         result.dbg_check_conflicting(graph, a, b);
 
         let plan = result.make_plan();
-        plan.simulate_plan(&graph, &[]);
+        plan.dbg_check(true);
+        plan.simulate_plan(&graph, result.base_version.as_ref());
     }
 
     #[test]
-    #[ignore] // Ignored until I rework make_plan to use a_root / b_root.
     fn test_trivial_graphs() {
         let mut g = ConflictSubgraph { entries: vec![], base_version: Frontier::root(), a_root: usize::MAX, b_root: usize::MAX };
 
@@ -799,7 +843,6 @@ mod test {
     }
 
     #[test]
-    #[ignore] // Ignored until I rework make_plan to use a_root / b_root.
     fn test_simple_graph() {
         let _graph = Graph::from_simple_items(&[
             GraphEntrySimple { span: 0.into(), parents: Frontier::root() },
@@ -848,7 +891,6 @@ mod test {
     }
 
     #[test]
-    #[ignore] // Ignored until I rework make_plan to use a_root / b_root.
     fn diamonds() {
         let mut g: ConflictSubgraph<EntryState> = ConflictSubgraph {
             entries: vec![
@@ -919,7 +961,6 @@ mod test {
     }
 
     #[test]
-    #[ignore] // Ignored until I rework make_plan to use a_root / b_root.
     fn combined_merge() {
         // let graph = Graph::from_simple_items(&[
         //     GraphEntrySimple { span: 0.into(), parents: Frontier::root() },
@@ -953,7 +994,6 @@ mod test {
     }
 
     #[test]
-    #[ignore] // Ignored until I rework make_plan to use a_root / b_root.
     fn test_from_fancy_graph() {
         let graph = fancy_graph();
         check(&graph, &[], &[]);
@@ -993,23 +1033,15 @@ mod test {
     }
 
     #[test]
-    #[ignore] // Ignored until I rework make_plan to use a_root / b_root.
     fn fuzz_action_plans() {
-        with_random_cgs(123, (1, 100), |_i, cg, _frontiers| {
+        with_random_cgs(123, (1, 100), |_i, cg, frontiers| {
             let mut subgraph = cg.graph.make_conflict_graph_between(&[], cg.version.as_ref());
             let plan = subgraph.make_plan();
             plan.simulate_plan(&cg.graph, &[]);
 
-            // TODO:
-            // for fs in frontiers.windows(2) {
-            //     let start = fs[0].as_ref();
-            //     let merge_in = fs[1].as_ref();
-            //     let mut subgraph = cg.graph.make_conflict_graph_between(start, merge_in);
-            //     let plan = subgraph.make_plan();
-            //
-            //     // let base = cg.graph.
-            //     plan.simulate_plan(&cg.graph, start);
-            // }
+            for fs in frontiers.windows(2) {
+                check(&cg.graph, fs[0].as_ref(), fs[1].as_ref());
+            }
         });
     }
 
@@ -2,13 +2,9 @@ use std::cmp::Ordering;
 use std::ops::Range;
 use rle::{HasLength, MergableSpan, merge_items, MergeIter, SplitableSpan};
 use crate::frontier::{debug_assert_sorted, is_sorted_slice};
-use crate::list::op_iter::OpMetricsIter;
-use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
-use crate::list::operation::ListOpKind;
-use crate::listmerge2::action_plan::{MergePlan, MergePlanAction};
+use crate::LV;
 use crate::listmerge2::Index;
 use crate::listmerge2::yjsspan::{YjsSpan, SpanState, YjsSpanWithState};
-use crate::rle::{KVPair, RleVec};
 
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -18,7 +14,7 @@ struct IndexInfo {
 }
 
 #[derive(Debug, Clone)]
-struct IndexGapBuffer {
+pub(super) struct IndexGapBuffer {
     // Gap buffer size = items.len().
     // == indexes[xx].len() at all times.
     items: Vec<YjsSpan>,
@@ -30,12 +26,15 @@ struct IndexGapBuffer {
     index_info: Vec<IndexInfo>, // Index -> length of gap_start for this index.
 }
 
-fn next_contains<I: Eq + Copy>(i: &mut usize, needle: I, haystack: &[I]) -> bool {
-    // TODO: I think this loop could correctly be an if() statement in cases where we check every
-    // item - and I think that is guaranteed in all cases in this code.
+fn next_contains<I: Ord + Copy>(i: &mut usize, needle: I, haystack: &[I]) -> bool {
+    // The haystack is sorted, and needle is increasing between calls. So we can skip anything
+    // smaller than the needle, but we need to stop when we overshoot.
     while *i < haystack.len() {
-        if haystack[*i] == needle { return true; }
-        *i += 1;
+        match haystack[*i].cmp(&needle) {
+            Ordering::Less => { *i += 1; }
+            Ordering::Equal => { return true; }
+            Ordering::Greater => { return false; }
+        }
     }
     false
 }
@@ -76,7 +75,7 @@ impl IndexGapBuffer {
         result
     }
 
-    fn new_with_num_indexes(num_indexes: usize) -> Self {
+    pub(super) fn new_with_num_indexes(num_indexes: usize) -> Self {
         Self::new_internal(num_indexes, 16)
         // Self::new_internal(num_indexes, 256)
     }
@@ -96,6 +95,7 @@ impl IndexGapBuffer {
 
         assert_eq!(self.states.len(), buffer_size * num_indexes);
         for (i, states) in self.states.chunks_exact(buffer_size).enumerate() {
+            if !self.index_info[i].active { continue; }
 
             let start_actual_len: usize = self.items[..self.gap_start_idx].iter()
                 .zip(states[..self.gap_start_idx].iter().copied())
@@ -201,7 +201,10 @@ impl IndexGapBuffer {
         self.move_gap(split_i + 1);
 
         // Will this little short circuit actually happen much in practice?
-        if self.gap_end_idx < self.items.len() && remainder.can_append(&self.items[self.gap_end_idx]) {
+        if self.gap_end_idx < self.items.len()
+            && remainder.can_append(&self.items[self.gap_end_idx])
+            && self.states_match(self.gap_start_idx - 1, self.gap_end_idx)
+        {
             self.items[self.gap_end_idx].prepend(remainder);
         } else {
             // We need to reinsert the remainder regardless. For that we need room:
@@ -340,16 +343,15 @@ impl IndexGapBuffer {
                 let new_gap_end = self.gap_end_idx - moved_range.len();
 
                 // The annoying part: Updating all the indexes and states.
-                let mut base = 0;
                 let items_len = self.items.len();
-                for index_info in self.index_info.iter_mut() {
+                for (index, index_info) in self.index_info.iter_mut().enumerate() {
                     if !index_info.active { continue; }
+                    let base = index * items_len;
                     let (moved_size, r2) = count_moved_size(&self.items, &self.states, moved_range.clone(), base);
                     // dbg!(moved_size);
 
                     self.states.copy_within(r2, new_gap_end+base);
                     index_info.before_gap_len -= moved_size;
-                    base += items_len;
                 }
 
                 // The easy part - move the actual items.
@@ -365,14 +367,13 @@ impl IndexGapBuffer {
                 if moved_range.is_empty() { return; } // Nothing to do!
 
                 // Update the indexes and states. Code adapted from above.
-                let mut base = 0;
                 let items_len = self.items.len();
-                for index_info in self.index_info.iter_mut() {
+                for (index, index_info) in self.index_info.iter_mut().enumerate() {
                     if !index_info.active { continue; }
+                    let base = index * items_len;
                     let (moved_size, r2) = count_moved_size(&self.items, &self.states, moved_range.clone(), base);
                     self.states.copy_within(r2, self.gap_start_idx+base);
                     index_info.before_gap_len += moved_size;
-                    base += items_len;
                 }
 
                 // Move the items themselves.
@@ -874,8 +875,235 @@ impl IndexGapBuffer {
     }
 }
 
+// These methods are used by the plan executor in merge.rs. They all work relative to the gap,
+// since edits are usually close to one another.
+impl IndexGapBuffer {
+    pub(super) fn state(&self, index: Index, i: usize) -> SpanState {
+        self.states[self.state_idx_at(index, i)]
+    }
+
+    /// The content length of the index before the gap.
+    pub(super) fn pos_at_gap(&self, index: Index) -> usize {
+        self.index_info[index].before_gap_len
+    }
+
+    fn next_i(&self, i: usize) -> usize {
+        if i + 1 == self.gap_start_idx { self.gap_end_idx } else { i + 1 }
+    }
+
+    fn first_i(&self) -> usize {
+        if self.gap_start_idx > 0 { 0 } else { self.gap_end_idx }
+    }
+
+    /// Find the item which contains the character at content_pos in the index. Returns
+    /// (i, offset).
+    pub(super) fn find_char(&self, index: Index, content_pos: usize) -> (usize, usize) {
+        debug_assert!(self.index_info[index].active);
+        let gap_pos = self.index_info[index].before_gap_len;
+
+        if content_pos < gap_pos {
+            // Scan backwards from the gap.
+            let index_start = self.start_state_idx(index);
+            let mut count = gap_pos - content_pos;
+            for i in (0..self.gap_start_idx).rev() {
+                if self.states[index_start + i] != SpanState::Inserted { continue; }
+                let len = self.items[i].len();
+                if count <= len { return (i, len - count); }
+                count -= len;
+            }
+            panic!("Content position overflowed gap_pos");
+        } else {
+            self.find_tail(index, content_pos - gap_pos, false, self.gap_end_idx)
+        }
+    }
+
+    /// Find the item containing the specified ID. This is a linear scan, but its only needed when
+    /// there are concurrent inserts at the same location.
+    fn find_id(&self, id: LV) -> Option<(usize, usize)> {
+        self.items[..self.gap_start_idx].iter().enumerate()
+            .chain(self.items[self.gap_end_idx..].iter().enumerate()
+                .map(|(i, item)| (i + self.gap_end_idx, item)))
+            .find(|(_, item)| item.id.contains(id))
+            .map(|(i, item)| (i, id - item.id.start))
+    }
+
+    /// Returns a comparable position directly after the named item. usize::MAX is the start of
+    /// the list.
+    pub(super) fn cursor_after(&self, id: LV) -> (usize, usize) {
+        if id == usize::MAX { return (self.first_i(), 0); }
+        let (i, offset) = self.find_id(id).expect("Missing origin item");
+        if offset + 1 == self.items[i].len() {
+            (self.next_i(i), 0)
+        } else {
+            (i, offset + 1)
+        }
+    }
+
+    /// Returns a comparable position directly before the named item. usize::MAX is the end of the
+    /// list.
+    pub(super) fn cursor_before(&self, id: LV) -> (usize, usize) {
+        if id == usize::MAX { return (self.items.len(), 0); }
+        self.find_id(id).expect("Missing origin item")
+    }
+
+    pub(super) fn tail_item(&self, i: usize) -> Option<&YjsSpan> {
+        debug_assert!(i >= self.gap_end_idx);
+        self.items.get(i)
+    }
+
+    pub(super) fn gap_end(&self) -> usize { self.gap_end_idx }
+
+    /// The ID of the item at (i, offset).
+    pub(super) fn id_at(&self, i: usize, offset: usize) -> LV {
+        self.items[i].id.start + offset
+    }
+
+    /// The length of the first item after the gap.
+    pub(super) fn gap_end_len(&self) -> usize {
+        self.items[self.gap_end_idx].len()
+    }
+
+    /// The state of the item directly before the gap in the named index.
+    pub(super) fn state_before_gap(&self, index: Index) -> SpanState {
+        self.state(index, self.gap_start_idx - 1)
+    }
+
+    /// Move the gap to (i, offset), splitting item i if needed. i may be in the tail. The offset
+    /// can be the length of the item, in which case the gap is moved after the item.
+    pub(super) fn move_gap_to(&mut self, i: usize, offset: usize) {
+        if offset == 0 {
+            self.move_gap(i);
+        } else {
+            debug_assert!(offset <= self.items[i].len());
+            self.move_gap_and_split(i, offset);
+        }
+    }
+
+    fn try_merge_before_gap(&mut self) {
+        let i = self.gap_start_idx;
+        if i >= 2 && self.items[i - 2].can_append(&self.items[i - 1]) && self.states_match(i - 2, i - 1) {
+            let item = self.items[i - 1];
+            self.items[i - 2].append(item);
+            self.gap_start_idx -= 1;
+        }
+    }
+
+    /// Insert a new item at the gap. The new item is Inserted in update_index and the other
+    /// indexes, and NotInsertedYet everywhere else.
+    pub(super) fn insert_at_gap(&mut self, new_item: YjsSpan, update_index: Index, other_indexes: &[Index]) {
+        if self.gap_size() == 0 { self.grow(); }
+
+        let i = self.gap_start_idx;
+        self.items[i] = new_item;
+        self.set_item_state_inserted(i, update_index, other_indexes);
+        self.gap_start_idx += 1;
+        self.add_to_gap_len(i, update_index, other_indexes, new_item.len());
+        self.try_merge_before_gap();
+    }
+
+    /// Move the first len items from the tail to the front, as a separate item.
+    pub(super) fn take_from_tail(&mut self, len: usize) {
+        if self.gap_size() == 0 { self.grow(); }
+
+        let src = self.gap_end_idx;
+        let dest = self.gap_start_idx;
+        let item_len = self.items[src].len();
+        debug_assert!(len > 0 && len <= item_len);
+
+        if len == item_len {
+            self.items[dest] = self.items[src];
+            self.gap_end_idx += 1;
+        } else {
+            let rem = self.items[src].truncate(len);
+            self.items[dest] = self.items[src];
+            self.items[src] = rem;
+        }
+        self.gap_start_idx += 1;
+
+        let items_len = self.items.len();
+        for (index, info) in self.index_info.iter_mut().enumerate() {
+            if !info.active { continue; }
+            let base = index * items_len;
+            let state = self.states[base + src];
+            self.states[base + dest] = state;
+            if state == SpanState::Inserted { info.before_gap_len += len; }
+        }
+    }
+
+    /// Mark the item directly before the gap as deleted in the named indexes.
+    pub(super) fn delete_before_gap(&mut self, update_index: Index, other_indexes: &[Index]) {
+        let i = self.gap_start_idx - 1;
+        let len = self.items[i].len();
+        for index in std::iter::once(update_index).chain(other_indexes.iter().copied()) {
+            let idx = self.state_idx_at(index, i);
+            match self.states[idx] {
+                SpanState::Inserted => {
+                    self.index_info[index].before_gap_len -= len;
+                    self.states[idx] = SpanState::Deleted;
+                }
+                SpanState::Deleted => {}
+                SpanState::NotInsertedYet => panic!("Cannot delete an item which isn't in the index"),
+            }
+        }
+        self.try_merge_before_gap();
+    }
+
+    pub(super) fn fork_index(&mut self, src: Index, dest: Index) {
+        assert!(self.index_info[src].active);
+        self.index_info[dest] = self.index_info[src];
+        let items_len = self.items.len();
+        self.states.copy_within(src * items_len..(src + 1) * items_len, dest * items_len);
+    }
+
+    pub(super) fn drop_index(&mut self, index: Index) {
+        // We don't need to clear it or anything. Just leave whatever junk was in there before.
+        self.index_info[index].active = false;
+    }
+
+    /// Set each item's state in dest to the max of its state in dest and all the from indexes.
+    pub(super) fn max_index(&mut self, dest: Index, from: &[Index]) {
+        assert!(self.index_info[dest].active);
+        let items_len = self.items.len();
+        let dest_start = dest * items_len;
+        let mut before_gap_len = 0;
+
+        for i in (0..self.gap_start_idx).chain(self.gap_end_idx..items_len) {
+            let mut state = self.states[dest_start + i];
+            for &src in from {
+                debug_assert!(self.index_info[src].active);
+                state = state.max(self.states[src * items_len + i]);
+            }
+            self.states[dest_start + i] = state;
+            if i < self.gap_start_idx && state == SpanState::Inserted {
+                before_gap_len += self.items[i].len();
+            }
+        }
+
+        self.index_info[dest].before_gap_len = before_gap_len;
+    }
+
+    /// Replace all the content with a single underwater item, inserted in every active index.
+    pub(super) fn clear(&mut self) {
+        let starting_item = YjsSpan::new_undiff_max();
+        let items_len = self.items.len();
+
+        self.items[0] = starting_item;
+        self.gap_start_idx = 1;
+        self.gap_end_idx = items_len;
+        for (index, info) in self.index_info.iter_mut().enumerate() {
+            if !info.active { continue; }
+            info.before_gap_len = starting_item.len();
+            self.states[index * items_len] = SpanState::Inserted;
+        }
+    }
+
+    pub(super) fn num_active_indexes(&self) -> usize {
+        self.index_info.iter().filter(|i| i.active).count()
+    }
+}
+
 #[derive(Debug, Clone)]
-struct GapBufferReader<'a> {
+pub(super) struct GapBufferReader<'a> {
     buffer: &'a IndexGapBuffer,
     index: Index,
     i: usize,
@@ -921,119 +1149,6 @@ impl IndexGapBuffer {
     }
 }
 
-fn run_plan(plan: &MergePlan, op_metrics: &RleVec<KVPair<ListOpMetrics>>, ctx: &ListOperationCtx) {
-    if plan.actions.is_empty() { return; } // Nothing to do anyway.
-    if plan.indexes_used == 1 {
-        // In this case, we don't actually need to set up the gap buffer. We can just copy input ->
-        // output.
-        eprintln!("TODO: Update me to do less work!");
-    }
-
-    let mut buffer = IndexGapBuffer::new_with_num_indexes(plan.indexes_used);
-    for action in plan.actions.iter() {
-        match action {
-            MergePlanAction::Apply(apply_action) => {
-                for metrics in OpMetricsIter::new(op_metrics, ctx, apply_action.span) {
-                    match metrics.1.kind {
-                        ListOpKind::Ins => {
-
-                        }
-                        ListOpKind::Del => {
-                            // For delete operations, we're marking the state certain number of ops as
-                            // deleted
-                        }
-                    }
-                    todo!()
-                }
-            }
-            MergePlanAction::ClearInsertedItems => {
-                for (i, s) in buffer.index_info.iter_mut().enumerate() {
-                    // Not sure if this check is needed. The only active index should be index 0.
-                    assert_eq!(s.active, i == 0);
-                }
-
-                buffer.items[0] = YjsSpan::new_undiff_max();
-                buffer.states[0] = SpanState::Inserted; // [0] is the start of index 0.
-                buffer.gap_start_idx = 1;
-                buffer.gap_end_idx = buffer.items.len();
-            }
-            MergePlanAction::ForkIndex { src, dest } => {
-                assert!(buffer.index_info[*src].active);
-                buffer.index_info[*dest] = buffer.index_info[*src];
-
-                // TODO: Benchmark this with std::ptr::copy_nonoverlapping.
-                let src_start = buffer.start_state_idx(*src);
-                let dest_start = buffer.start_state_idx(*dest);
-                buffer.states.copy_within(
-                    src_start..src_start+buffer.gap_start_idx,
-                    dest_start
-                );
-                buffer.states.copy_within(
-                    src_start+buffer.gap_end_idx..src_start+buffer.items.len(),
-                    dest_start+buffer.gap_end_idx
-                );
-            }
-            MergePlanAction::DropIndex(index) => {
-                buffer.index_info[*index].active = false;
-                // We don't need to clear it or anything. Just leave whatever junk was in there
-                // before.
-            }
-            MergePlanAction::MaxIndex(index, from) => {
-                assert!(buffer.index_info[*index].active);
-                let mut before_gap_len = buffer.index_info[*index].before_gap_len;
-
-                // TODO: We can reverse the loop order. Might be worth benchmarking or spending some
-                // time with godbolt to figure out which is faster.
-                let start_idx = buffer.start_state_idx(*index);
-
-                // First do the start (the bit before the gap):
-                for i in 0..buffer.gap_start_idx {
-                    let ii = i + start_idx;
-                    for &src_idx in from {
-                        let from_idx = buffer.state_idx_at(src_idx, i);
-                        let old_state = buffer.states[ii];
-                        let merge_state = buffer.states[from_idx];
-                        match (old_state, merge_state) {
-                            (a, b) if a == b => { continue; },
-                            (SpanState::NotInsertedYet, SpanState::Inserted) => {
-                                before_gap_len += buffer.items[i].len();
-                            },
-                            (SpanState::NotInsertedYet, SpanState::Deleted) => {},
-                            (SpanState::Inserted, SpanState::Deleted) => {
-                                before_gap_len -= buffer.items[i].len();
-                            },
-                            _ => { continue; },
-                        }
-                        debug_assert!(merge_state > old_state);
-                        buffer.states[ii] = merge_state;
-                    }
-                }
-
-                buffer.index_info[*index].before_gap_len = before_gap_len;
-
-                // Then do the bit after the gap. There's probably a way to merge these loops
-                // together rather than copy+pasting+changing. But there's 2 reasons I'm not doing
-                // that:
-                // 1. They're a bit different because we don't need to update the index size in each
-                //    case.
-                // 2. This is hot code. We want the compiler to unroll these loops as much as it can
-                //    anyway, so reusing code here is a non-goal.
-                for i in buffer.gap_end_idx..buffer.items.len() {
-                    let ii = i + start_idx;
-                    for &src_idx in from {
-                        let from_idx = buffer.state_idx_at(src_idx, i);
-                        let old_state = buffer.states[ii];
-                        let merge_state = buffer.states[from_idx];
-                        if merge_state > old_state {
-                            buffer.states[ii] = merge_state;
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use rand::prelude::SmallRng;
@@ -1165,3 +1280,4 @@ mod tests {
         }
     }
 }
+
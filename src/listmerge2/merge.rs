@@ -0,0 +1,326 @@
+//! This file runs a merge plan (from action_plan.rs) against an index gap buffer to figure out
+//! where each operation lands in the resulting document.
+//!
+//! Each index in the plan is a column of item states in the gap buffer. In addition to the indexes
+//! the plan uses, we keep one extra "output" index which every operation updates. Because the plan
+//! visits everything in the `from` version before anything which is only being merged in, the
+//! output index always matches the content of the document being merged into. So the transformed
+//! position of each operation is just its position in the output index.
+
+use std::cmp::Ordering;
+
+use smallvec::SmallVec;
+
+use rle::{HasLength, TrimCtx};
+
+use crate::{AgentId, DTRange, LV};
+use crate::causalgraph::agent_assignment::AgentAssignment;
+use crate::causalgraph::graph::conflict_subgraph::ConflictSubgraph;
+use crate::causalgraph::graph::Graph;
+use crate::list::op_iter::OpMetricsIter;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::list::operation::ListOpKind;
+use crate::listmerge::merge::TransformedResultRaw;
+use crate::listmerge2::action_plan::{ApplyAction, EntryState, MergePlan, MergePlanAction};
+use crate::listmerge2::Index;
+use crate::listmerge2::index_gap_buffer::IndexGapBuffer;
+use crate::listmerge2::yjsspan::{SpanState, YjsSpan};
+use crate::rle::{KVPair, RleSpanHelpers, RleVec};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ApplyResult {
+    BaseMoved(usize),
+    DeleteAlreadyHappened,
+}
+
+/// The output index is always updated along with the plan's indexes, and it's always the last
+/// (highest) index in the list.
+fn output_index(other_indexes: &[Index]) -> Index {
+    *other_indexes.last().unwrap()
+}
+
+impl IndexGapBuffer {
+    /// Integrate a new insert into the list at the gap, using the same rules as
+    /// `M2Tracker::integrate`. Items are inserted at the gap, and then the gap is moved right
+    /// past any concurrent inserts which should come before the new item.
+    fn integrate(&mut self, aa: &AgentAssignment, agent: AgentId, item: YjsSpan, index: Index) {
+        let left_cursor = (self.gap_end(), 0);
+        let mut i = self.gap_end();
+        let mut scanning = false;
+        let mut scan_i = i;
+
+        while let Some(other_entry) = self.tail_item(i).copied() {
+            // This test is almost always true. (Ie, we basically always break here).
+            let other_lv = other_entry.id.start;
+            if other_lv == item.origin_right { break; }
+
+            // We're now in the rare case there's actually concurrent inserts.
+            debug_assert_eq!(self.state(index, i), SpanState::NotInsertedYet);
+
+            let other_left_cursor = self.cursor_after(other_entry.origin_left);
+
+            // YjsMod / Fugue semantics.
+            match other_left_cursor.cmp(&left_cursor) {
+                Ordering::Less => { break; } // Top row
+                Ordering::Greater => {} // Bottom row. Continue.
+                Ordering::Equal => {
+                    if item.origin_right == other_entry.origin_right {
+                        // Origin_right matches. Items are concurrent. Order by agent names.
+                        let my_name = aa.get_agent_name(agent);
+                        let (other_agent, other_seq) = aa.local_to_agent_version(other_lv);
+                        let other_name = aa.get_agent_name(other_agent);
+
+                        let ins_here = match my_name.cmp(other_name) {
+                            Ordering::Less => true,
+                            Ordering::Equal => {
+                                let item_seq = aa.local_to_agent_version(item.id.start).1;
+                                item_seq < other_seq
+                            }
+                            Ordering::Greater => false,
+                        };
+
+                        if ins_here { break; }
+                        else { scanning = false; }
+                    } else {
+                        // Set scanning based on how the origin_right entries are ordered.
+                        let my_right_cursor = self.cursor_before(item.origin_right);
+                        let other_right_cursor = self.cursor_before(other_entry.origin_right);
+
+                        if other_right_cursor < my_right_cursor {
+                            if !scanning {
+                                scanning = true;
+                                scan_i = i;
+                            }
+                        } else {
+                            scanning = false;
+                        }
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        if scanning { i = scan_i; }
+        self.move_gap_to(i, 0);
+    }
+
+    fn apply_ins(&mut self, aa: &AgentAssignment, agent: AgentId, lv_span: DTRange, pos: usize, index: Index, other_indexes: &[Index]) -> usize {
+        // Find the item directly before the insert position. This is our origin_left. Then move
+        // the gap to just after it.
+        let origin_left = if pos == 0 {
+            self.move_gap_to(0, 0);
+            usize::MAX
+        } else {
+            let (i, offset) = self.find_char(index, pos - 1);
+            let origin_left = self.id_at(i, offset);
+            self.move_gap_to(i, offset + 1);
+            origin_left
+        };
+
+        // Origin_right is the next item which isn't in the NotInsertedYet state. The list always
+        // ends with underwater items, so we'll always find one.
+        let mut i = self.gap_end();
+        let origin_right = loop {
+            let Some(item) = self.tail_item(i) else { break usize::MAX; };
+            if self.state(index, i) != SpanState::NotInsertedYet { break item.id.start; }
+            i += 1;
+        };
+
+        let item = YjsSpan { id: lv_span, origin_left, origin_right };
+        self.integrate(aa, agent, item, index);
+
+        let xf_pos = self.pos_at_gap(output_index(other_indexes));
+        self.insert_at_gap(item, index, other_indexes);
+        xf_pos
+    }
+
+    /// Delete as much as we can from the start (or end, for reversed deletes) of the operation.
+    /// Returns the length we deleted and the transformed result.
+    fn apply_del(&mut self, op: &ListOpMetrics, index: Index, other_indexes: &[Index]) -> (usize, ApplyResult) {
+        let output = output_index(other_indexes);
+        let len = if op.loc.fwd {
+            let (i, offset) = self.find_char(index, op.start());
+            self.move_gap_to(i, offset);
+            self.gap_end_len().min(op.len())
+        } else {
+            // Delete as many items as we can from the end of the range.
+            let last = op.loc.span.last();
+            let (i, offset) = self.find_char(index, last);
+            let len = (offset + 1).min(op.len());
+            self.move_gap_to(i, offset + 1 - len);
+            len
+        };
+        debug_assert_eq!(self.state(index, self.gap_end()), SpanState::Inserted);
+
+        self.take_from_tail(len);
+        let result = match self.state_before_gap(output) {
+            SpanState::Inserted => ApplyResult::BaseMoved(self.pos_at_gap(output) - len),
+            SpanState::Deleted => ApplyResult::DeleteAlreadyHappened,
+            SpanState::NotInsertedYet => unreachable!(),
+        };
+        self.delete_before_gap(index, other_indexes);
+
+        (len, result)
+    }
+
+    /// Apply (as much as possible of) an operation. Returns the length consumed and the
+    /// transformed position.
+    fn apply(&mut self, aa: &AgentAssignment, op_pair: &KVPair<ListOpMetrics>, max_len: usize, agent: AgentId, index: Index, other_indexes: &[Index]) -> (usize, ApplyResult) {
+        let op = &op_pair.1;
+        match op.kind {
+            ListOpKind::Ins => {
+                // The characters of a reversed insert were typed backwards, so each one was
+                // inserted at the start of the span. Apply them one at a time. (Trimming a reversed
+                // insert keeps its start position, so the remainder is handled the same way.)
+                let len = if op.loc.fwd { max_len.min(op.len()) } else { 1 };
+                let mut lv_span = op_pair.span();
+                lv_span.end = lv_span.start + len;
+                let xf_pos = self.apply_ins(aa, agent, lv_span, op.start(), index, other_indexes);
+                (len, ApplyResult::BaseMoved(xf_pos))
+            }
+            ListOpKind::Del => {
+                self.apply_del(op, index, other_indexes)
+            }
+        }
+    }
+}
+
+fn apply_action<F: FnMut(TransformedResultRaw)>(buffer: &mut IndexGapBuffer, action: &ApplyAction,
+    aa: &AgentAssignment, ctx: &ListOperationCtx, ops: &RleVec<KVPair<ListOpMetrics>>,
+    output: Index, emit: &mut F)
+{
+    // The output index is always the highest index, so the list stays sorted.
+    let mut other_indexes: SmallVec<Index, 4> = action.update_other_indexes.iter().copied().collect();
+    other_indexes.push(output);
+
+    for mut pair in OpMetricsIter::new(ops, ctx, action.span) {
+        loop {
+            let span = aa.local_span_to_agent_span(pair.span());
+            let len = span.len().min(pair.len());
+
+            let (len_here, result) = buffer.apply(aa, &pair, len, span.agent, action.index, &other_indexes);
+            let remainder = pair.trim_ctx(len_here, ctx);
+
+            if action.output {
+                emit(match result {
+                    ApplyResult::BaseMoved(xf_pos) => TransformedResultRaw::Apply { xf_pos, op: pair },
+                    ApplyResult::DeleteAlreadyHappened => TransformedResultRaw::DeleteAlreadyHappened(pair.span()),
+                });
+            }
+
+            if let Some(r) = remainder {
+                pair = r;
+            } else { break; }
+        }
+    }
+}
+
+/// Run a merge plan, emitting the transformed position of all the operations marked as output.
+pub(super) fn run_plan<F: FnMut(TransformedResultRaw)>(plan: &MergePlan, aa: &AgentAssignment,
+    ctx: &ListOperationCtx, ops: &RleVec<KVPair<ListOpMetrics>>, mut emit: F)
+{
+    if plan.actions.is_empty() { return; } // Nothing to do anyway.
+
+    let output = plan.indexes_used;
+    let mut buffer = IndexGapBuffer::new_with_num_indexes(plan.indexes_used + 1);
+    buffer.fork_index(0, output);
+
+    for action in plan.actions.iter() {
+        match action {
+            MergePlanAction::Apply(apply_action) => {
+                self::apply_action(&mut buffer, apply_action, aa, ctx, ops, output, &mut emit);
+            }
+            MergePlanAction::ClearInsertedItems => {
+                // The plan only clears when there's a single index in use (plus the output index).
+                debug_assert_eq!(buffer.num_active_indexes(), 2);
+                buffer.clear();
+            }
+            MergePlanAction::ForkIndex { src, dest } => {
+                buffer.fork_index(*src, *dest);
+            }
+            MergePlanAction::DropIndex(index) => {
+                buffer.drop_index(*index);
+            }
+            MergePlanAction::MaxIndex(index, from) => {
+                buffer.max_index(*index, from);
+            }
+        }
+    }
+}
+
+/// Transform the operations in `merging` which aren't in `from`, using the listmerge2 algorithm.
+/// The results are passed to `emit` in the order they should be applied to a document at `from`.
+pub(crate) fn xf_operations_between<F: FnMut(TransformedResultRaw)>(graph: &Graph, aa: &AgentAssignment,
+    ctx: &ListOperationCtx, ops: &RleVec<KVPair<ListOpMetrics>>, from: &[LV], merging: &[LV], emit: F)
+{
+    let mut subgraph: ConflictSubgraph<EntryState> = graph.make_conflict_graph_between(from, merging);
+    let plan = subgraph.make_plan();
+    run_plan(&plan, aa, ctx, ops, emit);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListOpLog, MergeAlgorithm};
+    use crate::list::operation::{ListOpKind, TextOperation};
+    use crate::rev_range::RangeRev;
+
+    fn check_matches_tracker(name: &str) {
+        let bytes = std::fs::read(format!("benchmark_data/{name}.dt")).unwrap();
+        let oplog = ListOpLog::load_from(&bytes).unwrap();
+
+        let expected = oplog.checkout_tip();
+        let mut actual = ListBranch::new();
+        actual.merge_with(&oplog, oplog.local_frontier_ref(), MergeAlgorithm::IndexForking);
+        assert_eq!(expected.content().to_string(), actual.content().to_string(), "{name} does not match");
+    }
+
+    #[test]
+    fn matches_tracker_on_benchmark_data() {
+        // The concurrent traces are very slow in debug mode. They're checked in
+        // matches_tracker_on_all_benchmark_data.
+        check_matches_tracker("S1");
+    }
+
+    #[test]
+    #[ignore]
+    fn matches_tracker_on_all_benchmark_data() {
+        for name in ["S1", "S2", "S3", "C1", "C2", "A1", "A2"] {
+            check_matches_tracker(name);
+        }
+    }
+
+    #[test]
+    fn reversed_inserts() {
+        // The oplog never merges inserts into reversed runs itself, but they can show up in
+        // encoded data. Compare against the same edits made one character at a time.
+        let reversed = TextOperation {
+            loc: RangeRev { span: (1..4).into(), fwd: false },
+            kind: ListOpKind::Ins,
+            content: Some("cba".into()), // Content is stored in the order it was typed.
+        };
+
+        let mut oplog = ListOpLog::new();
+        let mut expected_oplog = ListOpLog::new();
+        for o in [&mut oplog, &mut expected_oplog] {
+            let a = o.get_or_create_agent_id("a");
+            let b = o.get_or_create_agent_id("b");
+            o.add_insert_at(a, &[], 0, "xy");
+            o.add_insert_at(b, &[1], 1, "ZZ");
+        }
+
+        let a = oplog.get_or_create_agent_id("a");
+        oplog.add_operations_at(a, &[1], &[reversed]);
+        let mut v = 1;
+        for c in ["c", "b", "a"] {
+            v = expected_oplog.add_insert_at(a, &[v], 1, c);
+        }
+        assert_eq!(oplog.len(), expected_oplog.len());
+
+        let expected = expected_oplog.checkout_tip().content().to_string();
+        let mut actual = ListBranch::new();
+        actual.merge_with(&oplog, oplog.local_frontier_ref(), MergeAlgorithm::IndexForking);
+        assert_eq!(actual.content().to_string(), expected);
+        assert!(expected.contains("abc"));
+    }
+}
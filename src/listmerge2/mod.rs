@@ -4,6 +4,7 @@ mod test_conversion;
 // #[cfg(feature = "dot_export")]
 mod dot;
 mod index_gap_buffer;
+mod merge;
 mod yjsspan;
 
 use std::cmp::Ordering;
@@ -13,6 +14,8 @@ use rle::SplitableSpan;
 use crate::{DTRange, Frontier, LV};
 use crate::causalgraph::graph::tools::DiffFlag;
 
+pub(crate) use merge::xf_operations_between;
+
 type Index = usize;
 
 
@@ -100,7 +100,9 @@ impl MergableSpan for YjsSpan {
 
     fn can_append(&self, other: &Self) -> bool {
         match (self.is_undiff(), other.is_undiff()) {
-            (true, true) => true,
+            // Underwater items are only ever split, never reordered. Keeping their IDs
+            // contiguous means we can still find them by ID.
+            (true, true) => self.id.end == other.id.start,
             (false, false) => {
                 self.id.can_append(&other.id)
                     && other.origin_left == other.id.start - 1
@@ -122,7 +124,7 @@ impl MergableSpan for YjsSpan {
     fn prepend(&mut self, other: Self) {
         debug_assert!(other.can_append(self));
         if self.is_undiff() {
-            self.id.end += other.len();
+            self.id.start -= other.len();
         } else {
             self.id.prepend(other.id);
             self.origin_left = other.origin_left;
@@ -7,9 +7,10 @@ use smartstring::alias::String as SmartString;
 #[cfg(feature = "serde")]
 use serde::{Serialize, Serializer};
 
-use rle::{HasLength, SplitableSpanCtx};
+use rle::{HasLength, MergeableIterator, SplitableSpanCtx};
 use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
-use crate::{AgentId, CRDTKind, CreateValue, DTRange, DTValue, OpLog, LV, LVKey, RegisterInfo, RegisterValue, ROOT_CRDT_ID, SerializedOps, ValPair};
+use crate::{AgentId, CRDTKind, CreateValue, DTRange, DTValue, Frontier, OpLog, LV, LVKey, RegisterInfo, RegisterValue, ROOT_CRDT_ID, SerializedOps, ValPair};
+use crate::causalgraph::graph::Graph;
 use crate::encoding::bufparser::BufParser;
 use crate::encoding::cg_entry::{read_cg_entry_into_cg, write_cg_entry_iter};
 use crate::encoding::map::{ReadMap, WriteMap};
@@ -18,7 +19,10 @@ use crate::branch::btree_range_for_crdt;
 use crate::frontier::{is_sorted_iter_uniq, is_sorted_slice};
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::TextOperation;
+use crate::list::pos_unit::{apply_xf_op_in, PosUnit};
 use crate::rle::{KVPair, RleSpanHelpers};
+use crate::textinfo::TextInfo;
+use crate::validate::OpValidator;
 
 #[cfg(feature = "serde")]
 impl Serialize for OpLog {
@@ -98,6 +102,10 @@ impl OpLog {
             // Check the operations are sorted
             assert!(is_sorted_iter_uniq(info.ops.iter().map(|KVPair(v, _)| *v)));
 
+            // And the scope should own exactly the text's operations.
+            assert_eq!(info.scope.created_at, *crdt);
+            assert!(info.ops.iter().map(|op| op.span()).merge_spans().eq(info.scope.owned_times.iter().copied()));
+
             for v in info.frontier.as_ref() {
                 assert!(*v < cg_len);
 
@@ -202,12 +210,19 @@ impl OpLog {
             CRDTKind::Register => {}
             CRDTKind::Collection => {}
             CRDTKind::Text => {
-                self.texts.entry(v).or_default();
+                self.texts.entry(v).or_insert_with(|| TextInfo::new(v));
             }
         }
     }
 
-    fn recursive_mark_deleted_inner(&mut self, mut to_delete: Vec<LV>) {
+    /// Record that the text CRDT was deleted by the operation at version `by`.
+    fn mark_text_deleted(texts: &mut BTreeMap<LVKey, TextInfo>, crdt: LVKey, by: LV) {
+        if let Some(info) = texts.get_mut(&crdt) {
+            info.scope.mark_deleted(by);
+        }
+    }
+
+    fn recursive_mark_deleted_inner(&mut self, mut to_delete: Vec<LV>, by: LV) {
         while let Some(crdt) = to_delete.pop() {
             for (_, info) in btree_range_for_crdt(&self.map_keys, crdt) {
                 for s in info.supremum.iter() {
@@ -215,9 +230,11 @@ impl OpLog {
                     if let CreateValue::NewCRDT(kind) = create_val {
                         assert!(self.deleted_crdts.insert(*lv));
 
-                        if *kind == CRDTKind::Map {
+                        match kind {
                             // Go through this CRDT's children.
-                            to_delete.push(*lv);
+                            CRDTKind::Map => to_delete.push(*lv),
+                            CRDTKind::Text => Self::mark_text_deleted(&mut self.texts, *lv, by),
+                            _ => {}
                         }
                     }
                 }
@@ -242,8 +259,10 @@ impl OpLog {
             let (lv, val) = &entry.ops[*idx];
             if let CreateValue::NewCRDT(kind) = val {
                 assert!(self.deleted_crdts.insert(*lv));
-                if *kind == CRDTKind::Map {
-                    to_delete.push(*lv);
+                match kind {
+                    CRDTKind::Map => to_delete.push(*lv),
+                    CRDTKind::Text => Self::mark_text_deleted(&mut self.texts, *lv, v),
+                    _ => {}
                 }
             }
 
@@ -256,7 +275,7 @@ impl OpLog {
         self.map_index.insert(v, (crdt, key.into()));
 
         // dbg!((crdt, key, &to_delete));
-        self.recursive_mark_deleted_inner(to_delete);
+        self.recursive_mark_deleted_inner(to_delete, v);
         v
     }
 
@@ -300,8 +319,10 @@ impl OpLog {
                     // old (version, value) pair.
                     if let CreateValue::NewCRDT(kind) = old_val {
                         assert!(self.deleted_crdts.insert(*old_lv));
-                        if *kind == CRDTKind::Map {
-                            to_delete.push(*old_lv);
+                        match kind {
+                            CRDTKind::Map => to_delete.push(*old_lv),
+                            CRDTKind::Text => Self::mark_text_deleted(&mut self.texts, *old_lv, v),
+                            _ => {}
                         }
                     }
                     self.map_index.remove(old_lv);
@@ -314,7 +335,7 @@ impl OpLog {
             }
         }
         entry.supremum = new_sup;
-        self.recursive_mark_deleted_inner(to_delete);
+        self.recursive_mark_deleted_inner(to_delete, v);
     }
 
     pub fn local_text_op(&mut self, agent: AgentId, crdt: LVKey, op: TextOperation) -> DTRange {
@@ -379,7 +400,7 @@ impl OpLog {
         }
     }
 
-    fn resolve_mv(&self, reg: &RegisterInfo) -> RegisterValue {
+    pub(crate) fn resolve_mv(&self, reg: &RegisterInfo) -> RegisterValue {
         let (active_idx, _) = self.tie_break_mv(reg);
 
         let (v, value) = &reg.ops[active_idx];
@@ -387,10 +408,15 @@ impl OpLog {
     }
 
     pub fn checkout_text(&self, crdt: LVKey) -> JumpRopeBuf {
+        self.checkout_text_at(crdt, self.cg.version.as_ref())
+    }
+
+    /// Checkout the named text CRDT as it was at some (possibly past) version of the document.
+    pub fn checkout_text_at(&self, crdt: LVKey, version: &[LV]) -> JumpRopeBuf {
         let info = self.texts.get(&crdt).unwrap();
 
         let mut result = JumpRopeBuf::new();
-        info.merge_into(&mut result, &self.cg, &[], self.cg.version.as_ref());
+        info.merge_into(&mut result, &self.cg, &[], version);
         result
     }
 
@@ -427,31 +453,49 @@ impl OpLog {
     }
 
     pub fn crdt_at_path(&self, path: &[&str]) -> (CRDTKind, LVKey) {
+        self.try_crdt_at_path(path).expect("Invalid path in document")
+    }
+
+    /// Find the CRDT at the named path. Returns None if the path doesn't name a CRDT in the
+    /// document.
+    pub fn try_crdt_at_path(&self, path: &[&str]) -> Option<(CRDTKind, LVKey)> {
         let mut kind = CRDTKind::Map;
         let mut key = ROOT_CRDT_ID;
 
         for p in path {
-            match kind {
-                CRDTKind::Map => {
-                    let container = self.map_keys.get(&(key, (*p).into()))
-                        .unwrap();
-                    match self.resolve_mv(container) {
-                        RegisterValue::Primitive(_) => {
-                            panic!("Found primitive, not CRDT");
-                        }
-                        RegisterValue::OwnedCRDT(new_kind, new_key) => {
-                            kind = new_kind;
-                            key = new_key;
-                        }
-                    }
-                }
-                _ => {
-                    panic!("Invalid path in document");
+            if kind != CRDTKind::Map { return None; }
+
+            let container = self.map_keys.get(&(key, (*p).into()))?;
+            match self.resolve_mv(container) {
+                RegisterValue::Primitive(_) => { return None; }
+                RegisterValue::OwnedCRDT(new_kind, new_key) => {
+                    kind = new_kind;
+                    key = new_key;
                 }
             }
         }
 
-        (kind, key)
+        Some((kind, key))
+    }
+
+    /// Look up the kind of the CRDT with the named ID. Returns None if there is no such CRDT, or
+    /// if it has been deleted or overwritten at the current version.
+    pub fn crdt_kind(&self, crdt: LVKey) -> Option<CRDTKind> {
+        if crdt == ROOT_CRDT_ID { return Some(CRDTKind::Map); }
+        if self.deleted_crdts.contains(&crdt) { return None; }
+
+        let container = self.map_keys.get(self.map_index.get(&crdt)?)?;
+        let idx = container.ops.binary_search_by_key(&crdt, |e| e.0).ok()?;
+        match container.ops[idx].1 {
+            CreateValue::NewCRDT(kind) => Some(kind),
+            CreateValue::Primitive(_) => None,
+        }
+    }
+
+    /// Returns true if a text CRDT with the named ID has ever been created. Unlike
+    /// [`crdt_kind`](Self::crdt_kind), this includes texts which have since been deleted.
+    pub fn has_text(&self, crdt: LVKey) -> bool {
+        self.texts.contains_key(&crdt)
     }
 
     pub fn text_at_path(&self, path: &[&str]) -> LVKey {
@@ -465,6 +509,48 @@ impl OpLog {
         let info = self.texts.get(&text).unwrap();
         info.xf_operations_from(&self.cg, since_frontier, self.cg.version.as_ref())
     }
+
+    /// Project a document version onto the history of a single text CRDT. The result only names
+    /// operations on that text, so it can be used with the graph from
+    /// [`text_graph`](Self::text_graph). Returns None if there is no text CRDT with that ID.
+    pub fn text_version_at(&self, text: LVKey, version: &[LV]) -> Option<Frontier> {
+        let info = self.texts.get(&text)?;
+        Some(info.version_in_scope(&self.cg.graph, version))
+    }
+
+    /// Returns true if the named text CRDT exists at `version`. That is, it has been created and
+    /// not yet deleted or overwritten.
+    pub fn text_exists_at(&self, text: LVKey, version: &[LV]) -> bool {
+        self.texts.get(&text)
+            .is_some_and(|info| info.scope.exists_at(&self.cg.graph, version))
+    }
+
+    /// Get the causal graph of a single text CRDT, along with the text's current frontier. The
+    /// graph only contains the text's own operations (with their local versions unchanged).
+    /// Returns None if there is no text CRDT with that ID.
+    pub fn text_graph(&self, text: LVKey) -> Option<(Graph, Frontier)> {
+        let info = self.texts.get(&text)?;
+        Some(self.cg.graph.subgraph(&info.scope.owned_times.0, info.frontier.as_ref()))
+    }
+
+    /// Get the raw (untransformed) operations on a single text CRDT which aren't included in
+    /// `since_frontier`. Unlike [`ops_since`](Self::ops_since), the diff is taken over the text's
+    /// own history. (Projecting `since_frontier` onto the text can still walk back through other
+    /// CRDTs' operations.) Returns None if there is no text CRDT with that ID.
+    pub fn text_ops_since(&self, text: LVKey, since_frontier: &[LV]) -> Option<Vec<(DTRange, TextOperation)>> {
+        let info = self.texts.get(&text)?;
+        let since = info.version_in_scope(&self.cg.graph, since_frontier);
+        let (graph, frontier) = self.cg.graph.subgraph(&info.scope.owned_times.0, info.frontier.as_ref());
+        debug_assert_eq!(frontier, info.frontier);
+
+        let (_, only_text) = graph.diff(since.as_ref(), frontier.as_ref());
+        Some(only_text.iter().flat_map(|r| {
+            info.ops.iter_range_ctx(*r, &info.ctx).map(|KVPair(lv, op)| {
+                let content = op.get_content(&info.ctx);
+                ((lv..lv + op.len()).into(), (op, content).into())
+            })
+        }).collect())
+    }
 }
 
 impl OpLog {
@@ -481,6 +567,14 @@ impl OpLog {
         else { self.cg.agent_assignment.remote_to_local_version(crdt_rv) }
     }
 
+    pub(crate) fn try_remote_to_crdt_name(&self, crdt_rv: RemoteVersion) -> Result<LVKey, ParseError> {
+        if crdt_rv.0 == "ROOT" { Ok(ROOT_CRDT_ID) }
+        else {
+            self.cg.agent_assignment.try_remote_to_local_version(crdt_rv)
+                .map_err(ParseError::InvalidRemoteID)
+        }
+    }
+
     // pub fn xf_text_changes_since(&self, text_item: LVKey, since_frontier: &[LV]) {
     //     let crdt = self.texts.get(&text_item).unwrap();
     //
@@ -510,13 +604,21 @@ impl OpLog {
             }
         }
 
+        self.serialize_ops_in(&diff_rev, cg_changes, map_crdts_to_send, text_crdts_to_send)
+    }
+
+    /// Serialize the map and text operations within `ranges` from the named map registers and
+    /// text CRDTs. The ranges are visited in the order they're passed in.
+    pub(crate) fn serialize_ops_in<'a, M, T>(&'a self, ranges: &[DTRange], cg_changes: Vec<u8>, map_crdts: M, text_crdts: T) -> SerializedOps<'a>
+        where M: IntoIterator<Item = (LVKey, &'a SmartString)>, T: IntoIterator<Item = LVKey>
+    {
         // Serialize map operations
         let mut map_ops = Vec::new();
-        for (crdt, key) in map_crdts_to_send {
+        for (crdt, key) in map_crdts {
             let crdt_name = self.crdt_name_to_remote(crdt);
             let entry = self.map_keys.get(&(crdt, key.clone()))
                 .unwrap();
-            for r in diff_rev.iter() {
+            for r in ranges.iter() {
                 // Find all the unknown ops.
                 // TODO: Add a flag to trim this to only the most recent ops.
                 let start_idx = entry.ops
@@ -536,10 +638,10 @@ impl OpLog {
         // Serialize text operations
         let mut text_context = ListOperationCtx::new();
         let mut text_ops = Vec::new();
-        for crdt in text_crdts_to_send {
+        for crdt in text_crdts {
             let crdt_name = self.crdt_name_to_remote(crdt);
             let info = &self.texts[&crdt];
-            for r in diff_rev.iter() {
+            for r in ranges.iter() {
                 for KVPair(lv, op) in info.ops.iter_range_ctx(*r, &info.ctx) {
                     // dbg!(&op);
 
@@ -567,17 +669,49 @@ impl OpLog {
 
 
     pub fn merge_ops(&mut self, changes: SerializedOps) -> Result<DTRange, ParseError> {
+        self.merge_ops_internal(changes, None)
+    }
+
+    /// Merge remote changes, checking each span of new operations with `validator` first. If the
+    /// validator rejects anything, nothing is merged and this returns
+    /// [`ParseError::OperationRejected`]. See [`validate`](crate::validate) for details.
+    pub fn merge_ops_with_validator(&mut self, changes: SerializedOps, validator: &dyn OpValidator) -> Result<DTRange, ParseError> {
+        self.merge_ops_internal(changes, Some(validator))
+    }
+
+    fn merge_ops_internal(&mut self, changes: SerializedOps, validator: Option<&dyn OpValidator>) -> Result<DTRange, ParseError> {
         let mut read_map = ReadMap::new();
 
         let old_end = self.cg.len();
+        let old_version = self.cg.version.clone();
+        let old_num_agents = self.cg.agent_assignment.client_data.len();
+
+        // If anything goes wrong, we unwind the causal graph so the merge is atomic. The map and
+        // text operations are only applied after everything has been checked.
+        let result = (|| {
+            let mut buf = BufParser(&changes.cg_changes);
+            while !buf.is_empty() {
+                read_cg_entry_into_cg(&mut buf, true, &mut self.cg, &mut read_map)?;
+            }
 
-        let mut buf = BufParser(&changes.cg_changes);
-        while !buf.is_empty() {
-            read_cg_entry_into_cg(&mut buf, true, &mut self.cg, &mut read_map)?;
-        }
-
-        let new_end = self.cg.len();
-        let new_range: DTRange = (old_end..new_end).into();
+            let new_range: DTRange = (old_end..self.cg.len()).into();
+            if let Some(validator) = validator {
+                if !new_range.is_empty() {
+                    self.validate_ops(&changes, new_range, validator)?;
+                }
+            }
+            Ok(new_range)
+        })();
+
+        let new_range = match result {
+            Ok(new_range) => new_range,
+            Err(e) => {
+                self.cg.truncate_entries(old_end);
+                self.cg.agent_assignment.client_data.truncate(old_num_agents);
+                self.cg.version = old_version;
+                return Err(e);
+            }
+        };
 
         // The code above will discard any operations we already know about. The new range could be empty, could
         // contain all of the new changes, or have some subset of them. We need to respect that in the code below
@@ -617,6 +751,26 @@ impl OpLog {
         let textinfo = self.texts.get(&text_crdt).unwrap();
         textinfo.xf_operations_from(&self.cg, since, textinfo.frontier.as_ref())
     }
+
+    /// Like [`xf_text_changes_since`](Self::xf_text_changes_since), but the positions in the
+    /// returned operations are measured in `unit`.
+    pub fn xf_text_changes_since_in(&self, text_crdt: LVKey, since: &[LV], unit: PosUnit) -> Vec<(DTRange, Option<TextOperation>)> {
+        let mut changes = self.xf_text_changes_since(text_crdt, since);
+
+        if unit != PosUnit::Chars {
+            // Replay the changes on top of the text at `since` to convert positions.
+            let textinfo = self.texts.get(&text_crdt).unwrap();
+            let mut content = JumpRopeBuf::new();
+            textinfo.merge_into(&mut content, &self.cg, &[], since);
+            let mut content = content.into_inner();
+
+            for (_, op) in changes.iter_mut() {
+                *op = op.take().map(|op| apply_xf_op_in(&mut content, unit, op));
+            }
+        }
+
+        changes
+    }
 }
 
 
@@ -624,10 +778,11 @@ impl OpLog {
 mod tests {
     #[cfg(feature = "serde")]
     use serde::{Deserialize, Serialize};
-    use crate::{CRDTKind, CreateValue, OpLog, Primitive, ROOT_CRDT_ID, SerializedOps};
+    use crate::{CRDTKind, CreateValue, OpLog, Primitive, ROOT_CRDT_ID, SerializedOps, SerializedOpsOwned};
     use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
     use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
     use crate::list::operation::TextOperation;
+    use crate::list::pos_unit::PosUnit;
 
     #[test]
     fn smoke() {
@@ -641,6 +796,27 @@ mod tests {
         oplog.dbg_check(true);
     }
 
+    #[test]
+    fn xf_text_changes_in_units() {
+        let mut oplog = OpLog::new();
+
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let text = oplog.local_map_set(seph, ROOT_CRDT_ID, "content", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, text, TextOperation::new_insert(0, "😀a"));
+        let v = oplog.cg.version.clone();
+        oplog.local_text_op(seph, text, TextOperation::new_insert(1, "b"));
+        oplog.local_text_op(seph, text, TextOperation::new_delete(2..3));
+
+        let spans = |unit| -> Vec<_> {
+            oplog.xf_text_changes_since_in(text, v.as_ref(), unit).into_iter()
+                .map(|(_, op)| op.unwrap().loc.span)
+                .collect()
+        };
+        assert_eq!(spans(PosUnit::Chars), vec![(1..2).into(), (2..3).into()]);
+        assert_eq!(spans(PosUnit::Utf16), vec![(2..3).into(), (3..4).into()]);
+        assert_eq!(spans(PosUnit::Utf8), vec![(4..5).into(), (5..6).into()]);
+    }
+
     #[test]
     fn text() {
         let mut oplog = OpLog::new();
@@ -709,6 +885,124 @@ mod tests {
         dbg!(oplog1.crdt_at_path(&["title"]));
     }
 
+    #[test]
+    fn checkout_text_at_version() {
+        let mut oplog = OpLog::new();
+
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let text = oplog.local_map_set(seph, ROOT_CRDT_ID, "content", CreateValue::NewCRDT(CRDTKind::Text));
+        let v1 = oplog.local_text_op(seph, text, TextOperation::new_insert(0, "hi there")).last();
+        oplog.local_text_op(seph, text, TextOperation::new_delete(0..3));
+
+        assert_eq!(oplog.checkout_text_at(text, &[v1]).to_string(), "hi there");
+        assert_eq!(oplog.checkout_text_at(text, &[text]).to_string(), "");
+        assert_eq!(oplog.checkout_text(text).to_string(), "there");
+    }
+
+    #[test]
+    fn scoped_text_history() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+
+        let a = oplog.local_map_set(seph, ROOT_CRDT_ID, "a", CreateValue::NewCRDT(CRDTKind::Text));
+        let b = oplog.local_map_set(seph, ROOT_CRDT_ID, "b", CreateValue::NewCRDT(CRDTKind::Text));
+        let a1 = oplog.local_text_op(seph, a, TextOperation::new_insert(0, "aaa"));
+        oplog.local_text_op(seph, b, TextOperation::new_insert(0, "bbb"));
+        let v = oplog.cg.version.clone();
+        oplog.local_map_set(seph, ROOT_CRDT_ID, "x", CreateValue::Primitive(Primitive::I64(1)));
+        let a2 = oplog.local_text_op(seph, a, TextOperation::new_insert(3, "!"));
+        oplog.local_text_op(seph, b, TextOperation::new_delete(0..1));
+        oplog.dbg_check(true);
+
+        // Versions are projected down onto the text's own operations.
+        assert_eq!(oplog.text_version_at(a, v.as_ref()).unwrap().as_ref(), &[a1.last()]);
+        assert_eq!(oplog.text_version_at(a, &[a]).unwrap().as_ref(), &[] as &[usize]);
+        assert_eq!(oplog.text_version_at(a, oplog.cg.version.as_ref()).unwrap().as_ref(), &[a2.last()]);
+
+        let (graph, frontier) = oplog.text_graph(a).unwrap();
+        assert_eq!(frontier.as_ref(), &[a2.last()]);
+        assert_eq!(graph.entries.iter().map(|e| e.span).collect::<Vec<_>>(), vec![a1, a2]);
+
+        let ops = oplog.text_ops_since(a, v.as_ref()).unwrap();
+        assert_eq!(ops, vec![(a2, TextOperation::new_insert(3, "!"))]);
+        assert_eq!(oplog.text_ops_since(a, &[]).unwrap().len(), 2);
+
+        // Other IDs aren't texts.
+        assert!(oplog.text_version_at(a1.start, &[]).is_none());
+        assert!(oplog.text_graph(ROOT_CRDT_ID).is_none());
+        assert!(oplog.text_ops_since(a1.start, &[]).is_none());
+
+        assert_eq!(oplog.checkout_text_at(a, v.as_ref()).to_string(), "aaa");
+        assert_eq!(oplog.checkout_text_at(b, v.as_ref()).to_string(), "bbb");
+        assert_eq!(oplog.checkout_text(b).to_string(), "bb");
+
+        // Overwriting the text removes it from the document at later versions.
+        assert!(!oplog.text_exists_at(b, &[a]));
+        assert!(oplog.text_exists_at(b, v.as_ref()));
+        let del = oplog.local_map_set(seph, ROOT_CRDT_ID, "b", CreateValue::Primitive(Primitive::Nil));
+        assert!(oplog.text_exists_at(b, v.as_ref()));
+        assert!(!oplog.text_exists_at(b, &[del]));
+        oplog.dbg_check(true);
+    }
+
+    #[test]
+    fn try_crdt_at_path() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let map = oplog.local_map_set(seph, ROOT_CRDT_ID, "obj", CreateValue::NewCRDT(CRDTKind::Map));
+        let text = oplog.local_map_set(seph, map, "text", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_map_set(seph, map, "num", CreateValue::Primitive(Primitive::I64(1)));
+
+        assert_eq!(oplog.try_crdt_at_path(&[]), Some((CRDTKind::Map, ROOT_CRDT_ID)));
+        assert_eq!(oplog.try_crdt_at_path(&["obj"]), Some((CRDTKind::Map, map)));
+        assert_eq!(oplog.try_crdt_at_path(&["obj", "text"]), Some((CRDTKind::Text, text)));
+        assert_eq!(oplog.try_crdt_at_path(&["obj", "num"]), None);
+        assert_eq!(oplog.try_crdt_at_path(&["obj", "text", "x"]), None);
+        assert_eq!(oplog.try_crdt_at_path(&["missing"]), None);
+
+        let branch = oplog.checkout_tip();
+        assert_eq!(branch.try_crdt_at_path(&["obj", "text"]), Some((CRDTKind::Text, text)));
+        assert_eq!(branch.try_crdt_at_path(&["obj", "num"]), None);
+        assert_eq!(branch.try_crdt_at_path(&["missing"]), None);
+    }
+
+    #[test]
+    fn crdt_kind() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let map = oplog.local_map_set(seph, ROOT_CRDT_ID, "obj", CreateValue::NewCRDT(CRDTKind::Map));
+        let text = oplog.local_map_set(seph, map, "text", CreateValue::NewCRDT(CRDTKind::Text));
+        let num = oplog.local_map_set(seph, map, "num", CreateValue::Primitive(Primitive::I64(1)));
+
+        assert_eq!(oplog.crdt_kind(ROOT_CRDT_ID), Some(CRDTKind::Map));
+        assert_eq!(oplog.crdt_kind(map), Some(CRDTKind::Map));
+        assert_eq!(oplog.crdt_kind(text), Some(CRDTKind::Text));
+        assert_eq!(oplog.crdt_kind(num), None);
+        assert_eq!(oplog.crdt_kind(1000), None);
+
+        // Overwriting the map deletes it and everything inside it.
+        oplog.local_map_set(seph, ROOT_CRDT_ID, "obj", CreateValue::Primitive(Primitive::Nil));
+        assert_eq!(oplog.crdt_kind(map), None);
+        assert_eq!(oplog.crdt_kind(text), None);
+        assert!(oplog.has_text(text));
+        assert!(!oplog.has_text(map));
+    }
+
+    #[test]
+    fn merge_owned_ops() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let text = oplog.local_map_set(seph, ROOT_CRDT_ID, "content", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, text, TextOperation::new_insert(0, "Oh hai!"));
+        oplog.local_map_set(seph, ROOT_CRDT_ID, "n", CreateValue::Primitive(Primitive::I64(3)));
+
+        let owned: SerializedOpsOwned = oplog.ops_since(&[]).into();
+        let mut oplog_2 = OpLog::new();
+        oplog_2.merge_ops(owned.as_ops()).unwrap();
+        oplog_2.dbg_check(true);
+        assert_eq!(oplog.checkout(), oplog_2.checkout());
+    }
+
     #[test]
     fn checkout() {
         let mut oplog = OpLog::new();
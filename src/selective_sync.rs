@@ -0,0 +1,298 @@
+//! Selective sync lets a peer send only the operations for part of a document - for example,
+//! just the `notes` and `todo` sections of a large shared workspace.
+//!
+//! The causal graph entries are projected onto the operations being sent (using
+//! [`Graph::project_onto_subgraph`]), so the receiver never needs to know about operations outside
+//! the filter. The receiver ends up with a partial replica which can be edited and synced like
+//! normal, so long as it keeps syncing with the same filter.
+
+use std::collections::BTreeSet;
+use rle::zip::rle_zip;
+use smartstring::alias::String as SmartString;
+use crate::{CRDTKind, CreateValue, DTRange, LV, LVKey, OpLog, RegisterValue, ROOT_CRDT_ID, SerializedOps};
+use crate::branch::btree_range_for_crdt;
+use crate::causalgraph::agent_span::AgentSpan;
+use crate::causalgraph::entry::CGEntry;
+use crate::causalgraph::graph::GraphEntrySimple;
+use crate::encoding::cg_entry::write_cg_entry_iter;
+use crate::encoding::map::WriteMap;
+use crate::rle::KVPair;
+
+/// The set of operations covered by a sync filter.
+#[derive(Debug, Default)]
+struct SyncScope<'a> {
+    /// Every local version in the scope. Unsorted, and may contain duplicates.
+    spans: Vec<DTRange>,
+    map_keys: BTreeSet<(LVKey, &'a SmartString)>,
+    texts: BTreeSet<LVKey>,
+}
+
+impl<'a> SyncScope<'a> {
+    fn add_register(&mut self, oplog: &'a OpLog, key: &'a (LVKey, SmartString)) {
+        let info = &oplog.map_keys[key];
+        self.spans.extend(info.ops.iter().map(|(v, _)| DTRange::from(*v..*v + 1)));
+        self.map_keys.insert((key.0, &key.1));
+    }
+
+    /// Add the named CRDT and everything which has ever been nested inside it.
+    fn add_subtree(&mut self, oplog: &'a OpLog, crdt: LVKey) {
+        let mut queue = vec![crdt];
+        while let Some(crdt) = queue.pop() {
+            if let Some(info) = oplog.texts.get(&crdt) {
+                self.spans.extend(info.scope.owned_times.iter().copied());
+                self.texts.insert(crdt);
+            }
+
+            for (key, info) in btree_range_for_crdt(&oplog.map_keys, crdt) {
+                self.add_register(oplog, key);
+                for (v, value) in info.ops.iter() {
+                    if let CreateValue::NewCRDT(_) = value {
+                        // This avoids recursion, so deeply nested documents can't smash the stack.
+                        queue.push(*v);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sort and merge the spans in the scope.
+    fn into_spans(mut self) -> (Vec<DTRange>, BTreeSet<(LVKey, &'a SmartString)>, BTreeSet<LVKey>) {
+        self.spans.sort_unstable_by_key(|r| r.start);
+        let mut spans: Vec<DTRange> = Vec::with_capacity(self.spans.len());
+        for r in self.spans {
+            match spans.last_mut() {
+                Some(last) if r.start <= last.end => { last.end = last.end.max(r.end); }
+                _ => { spans.push(r); }
+            }
+        }
+        (spans, self.map_keys, self.texts)
+    }
+}
+
+impl OpLog {
+    /// Find the map register containing the operation which created the named CRDT.
+    pub(crate) fn register_creating(&self, crdt: LVKey) -> Option<&(LVKey, SmartString)> {
+        if let Some(key) = self.map_index.get(&crdt) {
+            return self.map_keys.get_key_value(key).map(|(k, _)| k);
+        }
+
+        // The CRDT has been overwritten, so its no longer in the index. Search for it.
+        self.map_keys.iter()
+            .find(|(_, info)| info.ops.binary_search_by_key(&crdt, |e| e.0).is_ok())
+            .map(|(k, _)| k)
+    }
+
+    /// Like [`ops_since`](Self::ops_since), but only includes the operations on the named CRDTs
+    /// (and everything nested inside them). The operations which created each CRDT and the maps
+    /// containing it are included too, so the receiver knows where the CRDT lives.
+    ///
+    /// The returned causal graph changes only name the operations being sent. Merging them into
+    /// another oplog creates a partial replica of the document.
+    pub fn ops_since_for_crdts(&self, since_frontier: &[LV], crdts: &[LVKey]) -> SerializedOps<'_> {
+        let mut scope = SyncScope::default();
+        for &crdt in crdts {
+            scope.add_subtree(self, crdt);
+
+            let mut item = crdt;
+            while item != ROOT_CRDT_ID {
+                let Some(key) = self.register_creating(item) else { break; };
+                scope.spans.push((item..item + 1).into());
+                scope.map_keys.insert((key.0, &key.1));
+                item = key.0;
+            }
+        }
+        self.ops_since_in_scope(since_frontier, scope)
+    }
+
+    /// Like [`ops_since`](Self::ops_since), but only includes the operations under the named
+    /// paths in the document. For example, passing `&[&["notes"], &["todo"]]` sends the `notes`
+    /// and `todo` keys in the root map (and everything inside them). Paths which don't exist are
+    /// ignored.
+    ///
+    /// The full history of the map keys along each path is included, so the receiver can look up
+    /// the same paths in its partial replica.
+    pub fn ops_since_at_paths(&self, since_frontier: &[LV], paths: &[&[&str]]) -> SerializedOps<'_> {
+        let mut scope = SyncScope::default();
+
+        'outer: for path in paths {
+            let Some((last, prefix)) = path.split_last() else {
+                // The empty path names the whole document.
+                scope.add_subtree(self, ROOT_CRDT_ID);
+                continue;
+            };
+
+            let mut container = ROOT_CRDT_ID;
+            for p in prefix {
+                let Some((key, info)) = self.map_keys.get_key_value(&(container, (*p).into())) else {
+                    continue 'outer;
+                };
+                scope.add_register(self, key);
+                match self.resolve_mv(info) {
+                    RegisterValue::OwnedCRDT(CRDTKind::Map, child) => { container = child; }
+                    _ => { continue 'outer; }
+                }
+            }
+
+            let Some((key, info)) = self.map_keys.get_key_value(&(container, (*last).into())) else {
+                continue;
+            };
+            scope.add_register(self, key);
+            // Send the contents of everything that's been stored in this key.
+            for (v, value) in info.ops.iter() {
+                if let CreateValue::NewCRDT(_) = value {
+                    scope.add_subtree(self, *v);
+                }
+            }
+        }
+
+        self.ops_since_in_scope(since_frontier, scope)
+    }
+
+    fn ops_since_in_scope<'a>(&'a self, since_frontier: &[LV], scope: SyncScope<'a>) -> SerializedOps<'a> {
+        let (spans, map_keys, texts) = scope.into_spans();
+
+        // Project everything onto the subgraph of operations we're sending.
+        let (graph, frontier) = self.cg.graph.subgraph(&spans, self.cg.version.as_ref());
+        let since = self.cg.graph.project_onto_subgraph(&spans, since_frontier);
+        let (_, to_send) = graph.diff(since.as_ref(), frontier.as_ref());
+
+        let mut write_map = WriteMap::with_capacity_from(&self.cg.agent_assignment.client_data);
+        let mut cg_changes = Vec::new();
+        for range in to_send.iter() {
+            let parents = graph.iter_range(*range);
+            let aa = self.cg.agent_assignment.client_with_lv.iter_range(*range)
+                .map(|KVPair(_, data)| data);
+
+            let iter = rle_zip(parents, aa).map(|(parents, span): (GraphEntrySimple, AgentSpan)| {
+                CGEntry {
+                    start: parents.span.start,
+                    parents: parents.parents,
+                    span
+                }
+            });
+            write_cg_entry_iter(&mut cg_changes, iter, &mut write_map, &self.cg);
+        }
+
+        self.serialize_ops_in(&to_send, cg_changes, map_keys, texts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rle::HasLength;
+    use crate::{CRDTKind, CreateValue, Frontier, OpLog, Primitive, ROOT_CRDT_ID};
+    use crate::list::operation::TextOperation;
+
+    /// Get the version in `from` which `to` knows about.
+    fn known_version(from: &OpLog, to: &OpLog) -> Frontier {
+        let rv = to.cg.agent_assignment.local_to_remote_frontier(to.cg.version.as_ref());
+        from.cg.agent_assignment.remote_to_local_frontier(rv.into_iter())
+    }
+
+    fn make_doc() -> OpLog {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+
+        let notes = oplog.local_map_set(seph, ROOT_CRDT_ID, "notes", CreateValue::NewCRDT(CRDTKind::Text));
+        let other = oplog.local_map_set(seph, ROOT_CRDT_ID, "other", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, notes, TextOperation::new_insert(0, "hi there"));
+        oplog.local_text_op(seph, other, TextOperation::new_insert(0, "secret"));
+
+        let todo = oplog.local_map_set(seph, ROOT_CRDT_ID, "todo", CreateValue::NewCRDT(CRDTKind::Map));
+        let item = oplog.local_map_set(seph, todo, "item", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, item, TextOperation::new_insert(0, "buy milk"));
+        oplog.local_map_set(seph, todo, "done", CreateValue::Primitive(Primitive::Bool(false)));
+        oplog.local_text_op(seph, other, TextOperation::new_delete(0..3));
+        oplog
+    }
+
+    #[test]
+    fn sync_paths() {
+        let mut oplog = make_doc();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+
+        let mut partial = OpLog::new();
+        partial.merge_ops(oplog.ops_since_at_paths(&[], &[&["notes"], &["todo"]])).unwrap();
+        partial.dbg_check(true);
+
+        let full = oplog.checkout();
+        let doc = partial.checkout();
+        assert_eq!(doc.len(), 2);
+        assert_eq!(doc["notes"], full["notes"]);
+        assert_eq!(doc["todo"], full["todo"]);
+        assert!(partial.cg.len() < oplog.cg.len());
+
+        // More edits are synced incrementally, and edits outside the filter are skipped.
+        let notes = oplog.text_at_path(&["notes"]);
+        let other = oplog.text_at_path(&["other"]);
+        oplog.local_text_op(seph, other, TextOperation::new_insert(0, "xxx"));
+        oplog.local_text_op(seph, notes, TextOperation::new_insert(0, "Oh, "));
+
+        let since = known_version(&oplog, &partial);
+        let ops = oplog.ops_since_at_paths(since.as_ref(), &[&["notes"], &["todo"]]);
+        assert_eq!(partial.merge_ops(ops).unwrap().len(), 4);
+        partial.dbg_check(true);
+        assert_eq!(partial.checkout()["notes"], oplog.checkout()["notes"]);
+
+        // And the partial replica's own edits can be merged back into the full document.
+        let kaarina = partial.cg.get_or_create_agent_id("kaarina");
+        let partial_notes = partial.text_at_path(&["notes"]);
+        partial.local_text_op(kaarina, partial_notes, TextOperation::new_insert(0, "> "));
+        let since = known_version(&partial, &oplog);
+        oplog.merge_ops(partial.ops_since(since.as_ref())).unwrap();
+        oplog.dbg_check(true);
+        assert_eq!(oplog.checkout_text(notes).to_string(), "> Oh, hi there");
+    }
+
+    #[test]
+    fn sync_crdts() {
+        let oplog = make_doc();
+        let item = oplog.text_at_path(&["todo", "item"]);
+
+        let mut partial = OpLog::new();
+        partial.merge_ops(oplog.ops_since_for_crdts(&[], &[item])).unwrap();
+        partial.dbg_check(true);
+
+        // The receiver knows about the text and the creation of the map containing it, but nothing
+        // else in the map.
+        let partial_item = partial.cg.agent_assignment.remote_to_local_version(
+            oplog.cg.agent_assignment.local_to_remote_version(item)
+        );
+        assert_eq!(partial.checkout_text(partial_item).to_string(), "buy milk");
+        assert_eq!(partial.cg.len(), 2 + "buy milk".len());
+        assert_eq!(partial.try_crdt_at_path(&["todo", "item"]), Some((CRDTKind::Text, partial_item)));
+        assert_eq!(partial.checkout_register_at_path_nc(&["todo"], "done"), None);
+
+        // Filtering by the root CRDT sends everything.
+        let mut all = OpLog::new();
+        all.merge_ops(oplog.ops_since_for_crdts(&[], &[ROOT_CRDT_ID])).unwrap();
+        assert_eq!(all.checkout(), oplog.checkout());
+        assert_eq!(all.cg, oplog.cg);
+    }
+
+    #[test]
+    fn sync_concurrent_edits() {
+        let mut oplog_a = make_doc();
+        let mut oplog_b = OpLog::new();
+        oplog_b.merge_ops(oplog_a.ops_since(&[])).unwrap();
+
+        // Interleave concurrent edits to the synced text and the rest of the document.
+        let seph = oplog_a.cg.get_or_create_agent_id("seph");
+        let kaarina = oplog_b.cg.get_or_create_agent_id("kaarina");
+        for (oplog, agent) in [(&mut oplog_a, seph), (&mut oplog_b, kaarina)] {
+            let notes = oplog.text_at_path(&["notes"]);
+            let other = oplog.text_at_path(&["other"]);
+            oplog.local_text_op(agent, other, TextOperation::new_insert(0, "a"));
+            oplog.local_text_op(agent, notes, TextOperation::new_insert(0, "b"));
+            oplog.local_text_op(agent, other, TextOperation::new_insert(0, "c"));
+        }
+        oplog_a.merge_ops(oplog_b.ops_since(&[])).unwrap();
+        let notes = oplog_a.text_at_path(&["notes"]);
+        oplog_a.local_text_op(seph, notes, TextOperation::new_insert(0, "d"));
+
+        let mut partial = OpLog::new();
+        partial.merge_ops(oplog_a.ops_since_at_paths(&[], &[&["notes"]])).unwrap();
+        partial.dbg_check(true);
+        assert_eq!(partial.checkout()["notes"], oplog_a.checkout()["notes"]);
+    }
+}
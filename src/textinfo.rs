@@ -1,6 +1,7 @@
 use jumprope::JumpRopeBuf;
 use rle::HasLength;
 use crate::causalgraph::graph::Graph;
+use crate::causalgraph::graph::scope::ScopedParents;
 use crate::dtrange::DTRange;
 use crate::frontier::Frontier;
 use crate::list::ListOpLog;
@@ -17,9 +18,26 @@ pub(crate) struct TextInfo {
     pub(crate) ctx: ListOperationCtx,
     pub(crate) ops: RleVec<KVPair<ListOpMetrics>>,
     pub(crate) frontier: Frontier,
+
+    /// The part of the causal graph which belongs to this text. This lets us merge and diff the
+    /// text without transforming other CRDTs' operations.
+    pub(crate) scope: ScopedParents,
 }
 
 impl TextInfo {
+    /// Create a new (empty) text CRDT, created by the operation at version `created_at`.
+    pub(crate) fn new(created_at: LV) -> Self {
+        Self {
+            scope: ScopedParents::new(created_at),
+            ..Default::default()
+        }
+    }
+
+    /// Project a document version onto this text's own history.
+    pub(crate) fn version_in_scope(&self, graph: &Graph, version: &[LV]) -> Frontier {
+        graph.version_in_scope(version, &self.scope).unwrap_or_default()
+    }
+
     pub fn iter_metrics_range(&self, range: DTRange) -> OpMetricsIter<'_> {
         OpMetricsIter::new(&self.ops, &self.ctx, range)
     }
@@ -47,6 +65,7 @@ impl TextInfo {
             kind: op.kind,
             content_pos
         }));
+        self.scope.push_owned(v_range);
     }
 
     pub fn remote_push_op(&mut self, op: TextOperation, v_range: DTRange, parents: &[LV], graph: &Graph) {
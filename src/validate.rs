@@ -0,0 +1,324 @@
+//! Hooks for checking operations received from remote peers before they're merged.
+//!
+//! Diamond types will happily merge any well-formed operation from any agent. Servers which need
+//! access control (eg "viewers can't edit" or "only the owner may change this key") can implement
+//! [`OpValidator`] and pass it in when merging remote changes:
+//!
+//! - [`ListOpLog::decode_and_add_opts`] via [`DecodeOptions::validator`](crate::list::encoding::DecodeOptions::validator)
+//! - [`ListOpLog::add_operations_remote_with_validator`]
+//! - [`OpLog::merge_ops_with_validator`]
+//!
+//! The validator is called once for each span of new operations. If it rejects any span, the whole
+//! patch is discarded and the merge returns [`ParseError::OperationRejected`].
+
+use std::collections::BTreeMap;
+use rle::HasLength;
+use smartstring::alias::String as SmartString;
+use crate::{CreateValue, DTRange, Frontier, LV, LVKey, OpLog, ROOT_CRDT_ID, SerializedOps};
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+/// What an incoming operation modifies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpTarget<'a> {
+    /// The document's text. All operations in a [`ListOpLog`] target the text.
+    ListText,
+
+    /// A text CRDT inside an [`OpLog`].
+    Text {
+        crdt: LVKey,
+        /// The path of map keys from the root of the document to the text, if it can be found.
+        path: Option<&'a [SmartString]>,
+    },
+
+    /// A key in a map CRDT inside an [`OpLog`].
+    MapKey {
+        crdt: LVKey,
+        /// The path of map keys from the root of the document to the map, if it can be found. This
+        /// is empty for the root map.
+        path: Option<&'a [SmartString]>,
+        key: &'a str,
+    },
+}
+
+/// The kind of change an incoming operation makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncomingOpKind<'a> {
+    /// Inserting or deleting text.
+    Text(ListOpKind),
+    /// Setting a map key to a new value.
+    MapSet(&'a CreateValue),
+}
+
+/// A span of operations received from a remote peer, passed to [`OpValidator::allow`].
+#[derive(Debug, Clone, Copy)]
+pub struct IncomingOp<'a> {
+    /// The name of the agent which created the operations.
+    pub agent: &'a str,
+    /// The sequence numbers of the operations, for that agent.
+    pub seq_range: DTRange,
+    /// The local versions assigned to the operations.
+    pub version: DTRange,
+    /// The parents of the first operation in the span. Each subsequent operation's parent is the
+    /// operation before it.
+    pub parents: &'a [LV],
+    pub target: OpTarget<'a>,
+    pub kind: IncomingOpKind<'a>,
+}
+
+/// A hook for accepting or rejecting operations received from remote peers. See the
+/// [module documentation](self) for details.
+pub trait OpValidator {
+    /// Returns true if the operations should be accepted. Returning false rejects the whole
+    /// patch containing them.
+    fn allow(&self, op: &IncomingOp<'_>) -> bool;
+}
+
+impl<F: Fn(&IncomingOp<'_>) -> bool> OpValidator for F {
+    fn allow(&self, op: &IncomingOp<'_>) -> bool {
+        self(op)
+    }
+}
+
+fn check(validator: &dyn OpValidator, op: IncomingOp<'_>) -> Result<(), ParseError> {
+    if validator.allow(&op) { Ok(()) } else { Err(ParseError::OperationRejected) }
+}
+
+impl ListOpLog {
+    /// Run the validator over all the operations in the named range.
+    pub(crate) fn validate_ops(&self, range: DTRange, validator: &dyn OpValidator) -> Result<(), ParseError> {
+        for entry in self.cg.iter_range(range) {
+            let agent = self.cg.agent_assignment.get_agent_name(entry.span.agent);
+            let range = entry.time_span();
+            let mut parents = entry.parents;
+            let mut seq = entry.span.seq_range.start;
+
+            for KVPair(v, op) in self.operations.iter_range_ctx(range, &self.operation_ctx) {
+                let len = op.len();
+                check(validator, IncomingOp {
+                    agent,
+                    seq_range: (seq..seq + len).into(),
+                    version: (v..v + len).into(),
+                    parents: parents.as_ref(),
+                    target: OpTarget::ListText,
+                    kind: IncomingOpKind::Text(op.kind),
+                })?;
+
+                parents = Frontier::new_1(v + len - 1);
+                seq += len;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OpLog {
+    /// Find the path of map keys from the root of the document to the named CRDT. `pending` names
+    /// the map registers containing CRDTs which haven't been added to the oplog yet.
+    fn path_to_crdt_pending(&self, mut crdt: LVKey, pending: &BTreeMap<LV, (LVKey, &str)>) -> Option<Vec<SmartString>> {
+        let mut path = vec![];
+        while crdt != ROOT_CRDT_ID {
+            let (parent, key) = if let Some((parent, key)) = pending.get(&crdt) {
+                (*parent, SmartString::from(*key))
+            } else {
+                let register = self.register_creating(crdt)?;
+                // Make sure the operation actually created a CRDT, not a primitive value.
+                let ops = &self.map_keys[register].ops;
+                let idx = ops.binary_search_by_key(&crdt, |e| e.0).ok()?;
+                if !matches!(ops[idx].1, CreateValue::NewCRDT(_)) { return None; }
+                (register.0, register.1.clone())
+            };
+            path.push(key);
+            crdt = parent;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Find the path of map keys from the root of the document to the named CRDT. This works even
+    /// if the CRDT has since been deleted or overwritten. Returns None if the CRDT doesn't exist.
+    pub fn path_to_crdt(&self, crdt: LVKey) -> Option<Vec<SmartString>> {
+        self.path_to_crdt_pending(crdt, &BTreeMap::new())
+    }
+
+    /// Run the validator over the changes which will be merged into `new_range`. The changes'
+    /// causal graph entries must already be in the oplog.
+    pub(crate) fn validate_ops(&self, changes: &SerializedOps, new_range: DTRange, validator: &dyn OpValidator) -> Result<(), ParseError> {
+        let aa = &self.cg.agent_assignment;
+
+        // CRDTs created in this patch won't be found in the oplog yet.
+        let mut pending = BTreeMap::new();
+        for (crdt_r_name, rv, key, val) in changes.map_ops.iter() {
+            if let CreateValue::NewCRDT(_) = val {
+                let lv = aa.try_remote_to_local_version(*rv).map_err(ParseError::InvalidRemoteID)?;
+                let crdt = self.try_remote_to_crdt_name(*crdt_r_name)?;
+                pending.insert(lv, (crdt, *key));
+            }
+        }
+
+        for (crdt_r_name, rv, key, val) in changes.map_ops.iter() {
+            let lv = aa.try_remote_to_local_version(*rv).map_err(ParseError::InvalidRemoteID)?;
+            if !new_range.contains(lv) { continue; }
+
+            let crdt = self.try_remote_to_crdt_name(*crdt_r_name)?;
+            let path = self.path_to_crdt_pending(crdt, &pending);
+            let parents = self.cg.graph.parents_at_version(lv);
+            let (agent, seq) = aa.local_to_agent_version(lv);
+            check(validator, IncomingOp {
+                agent: aa.get_agent_name(agent),
+                seq_range: (seq..seq + 1).into(),
+                version: (lv..lv + 1).into(),
+                parents: parents.as_ref(),
+                target: OpTarget::MapKey { crdt, path: path.as_deref(), key },
+                kind: IncomingOpKind::MapSet(val),
+            })?;
+        }
+
+        for (crdt_r_name, rv, op_metrics) in changes.text_ops.iter() {
+            let lv = aa.try_remote_to_local_version(*rv).map_err(ParseError::InvalidRemoteID)?;
+            let v_range: DTRange = (lv..lv + op_metrics.len()).into();
+            // Only check the part of the operation we'll actually merge.
+            let start = v_range.start.max(new_range.start);
+            if start >= v_range.end.min(new_range.end) { continue; }
+            let v_range: DTRange = (start..v_range.end).into();
+
+            let crdt = self.try_remote_to_crdt_name(*crdt_r_name)?;
+            let path = self.path_to_crdt_pending(crdt, &pending);
+            let parents = self.cg.graph.parents_at_version(start);
+            let (agent, seq) = aa.local_to_agent_version(start);
+            check(validator, IncomingOp {
+                agent: aa.get_agent_name(agent),
+                seq_range: (seq..seq + v_range.len()).into(),
+                version: v_range,
+                parents: parents.as_ref(),
+                target: OpTarget::Text { crdt, path: path.as_deref() },
+                kind: IncomingOpKind::Text(op_metrics.kind),
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDTKind, CreateValue, OpLog, Primitive, ROOT_CRDT_ID};
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::{DecodeOptions, ENCODE_FULL};
+    use crate::list::ListOpLog;
+    use crate::list::operation::{ListOpKind, TextOperation};
+    use super::*;
+
+    fn no_viewers(op: &IncomingOp<'_>) -> bool {
+        op.agent != "viewer"
+    }
+
+    #[test]
+    fn list_decode_rejected() {
+        let mut src = ListOpLog::new();
+        let seph = src.get_or_create_agent_id("seph");
+        let viewer = src.get_or_create_agent_id("viewer");
+        src.add_insert(seph, 0, "hi there");
+        let data = src.encode(&ENCODE_FULL);
+        src.add_delete_without_content(viewer, 0..3);
+        let bad_data = src.encode(&ENCODE_FULL);
+
+        let opts = DecodeOptions { validator: Some(&no_viewers), ..Default::default() };
+        let mut dest = ListOpLog::load_from_opts(&data, opts.clone()).unwrap();
+        let expected = dest.clone();
+
+        assert_eq!(dest.decode_and_add_opts(&bad_data, opts.clone()), Err(ParseError::OperationRejected));
+        assert_eq!(dest, expected);
+        assert_eq!(ListOpLog::load_from_opts(&bad_data, opts).unwrap_err(), ParseError::OperationRejected);
+
+        // Without the validator everything is merged as normal.
+        dest.decode_and_add(&bad_data).unwrap();
+        assert_eq!(dest, src);
+    }
+
+    #[test]
+    fn list_remote_with_validator() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi there");
+
+        // Only allow inserts.
+        let no_deletes = |op: &IncomingOp<'_>| {
+            assert_eq!(op.target, OpTarget::ListText);
+            op.kind == IncomingOpKind::Text(ListOpKind::Ins)
+        };
+
+        let mike = oplog.get_or_create_agent_id("mike");
+        let expected = oplog.clone();
+        let ops = [TextOperation::new_insert(0, "Oh, "), TextOperation::new_delete(4..7)];
+        assert_eq!(oplog.add_operations_remote_with_validator(mike, &[7], 0, &ops, &no_deletes), Err(ParseError::OperationRejected));
+        assert_eq!(oplog, expected);
+
+        let range = oplog.add_operations_remote_with_validator(mike, &[7], 0, &ops[..1], &no_deletes).unwrap();
+        assert_eq!(range, (8..12).into());
+        assert_eq!(oplog.checkout_tip().content(), "Oh, hi there");
+
+        // Operations we already have aren't checked again.
+        let range = oplog.add_operations_remote_with_validator(mike, &[7], 0, &ops[..1], &|_: &IncomingOp<'_>| false).unwrap();
+        assert!(range.is_empty());
+    }
+
+    #[test]
+    fn oplog_merge_rejected() {
+        let mut src = OpLog::new();
+        let seph = src.cg.get_or_create_agent_id("seph");
+        let owner = src.local_map_set(seph, ROOT_CRDT_ID, "owner", CreateValue::Primitive(Primitive::Str("seph".into())));
+        let doc = src.local_map_set(seph, ROOT_CRDT_ID, "doc", CreateValue::NewCRDT(CRDTKind::Map));
+        let text = src.local_map_set(seph, doc, "text", CreateValue::NewCRDT(CRDTKind::Text));
+        src.local_text_op(seph, text, TextOperation::new_insert(0, "hi"));
+        assert_eq!(src.path_to_crdt(text).unwrap(), ["doc", "text"]);
+        assert_eq!(src.path_to_crdt(ROOT_CRDT_ID).unwrap().len(), 0);
+        assert_eq!(src.path_to_crdt(owner), None);
+
+        // Only seph can change the owner, and viewers can't change anything.
+        let validator = |op: &IncomingOp<'_>| {
+            if op.agent == "viewer" { return false; }
+            match op.target {
+                OpTarget::MapKey { crdt: ROOT_CRDT_ID, key: "owner", .. } => op.agent == "seph",
+                _ => true,
+            }
+        };
+
+        let mut dest = OpLog::new();
+        dest.merge_ops_with_validator(src.ops_since(&[]), &validator).unwrap();
+        dest.dbg_check(true);
+        let expected_len = dest.cg.len();
+        let expected_doc = dest.checkout();
+
+        let mike = src.cg.get_or_create_agent_id("mike");
+        src.local_text_op(mike, text, TextOperation::new_insert(2, "!"));
+        src.local_map_set(mike, ROOT_CRDT_ID, "owner", CreateValue::Primitive(Primitive::Str("mike".into())));
+        let since = [expected_len - 1];
+
+        assert_eq!(dest.merge_ops_with_validator(src.ops_since(&since), &validator), Err(ParseError::OperationRejected));
+        dest.dbg_check(true);
+        assert_eq!(dest.cg.len(), expected_len);
+        assert_eq!(dest.cg.agent_assignment.client_data.len(), 1);
+        assert_eq!(dest.checkout(), expected_doc);
+
+        // Text edits are checked too, along with the path to the text.
+        let viewer = src.cg.get_or_create_agent_id("viewer");
+        src.local_text_op(viewer, text, TextOperation::new_insert(0, "x"));
+        let texts_only = |op: &IncomingOp<'_>| {
+            if let OpTarget::Text { path, .. } = op.target {
+                assert_eq!(path.unwrap(), ["doc", "text"]);
+                op.agent != "viewer"
+            } else { true }
+        };
+        let mut dest2 = dest.clone();
+        assert_eq!(dest2.merge_ops_with_validator(src.ops_since(&since), &texts_only), Err(ParseError::OperationRejected));
+        dest2.dbg_check(true);
+        assert_eq!(dest2.checkout(), expected_doc);
+
+        dest.merge_ops(src.ops_since(&since)).unwrap();
+        dest.dbg_check(true);
+        assert_eq!(dest.checkout(), src.checkout());
+    }
+}